@@ -0,0 +1,588 @@
+//! Generate prompts with the templates declared by the users.
+//!
+//! A custom template wraps the content of each message with the wrapper of its role, e.g. `"<|user|>\n{content}<|end|>\n"`, and is registered under a name with [`register_custom_template`]. The registered template is used with the `custom:<name>` prompt template type.
+
+use crate::{
+    error::{PromptError, Result},
+    utils::get_image_format,
+    BuildChatPrompt, PromptTemplateType,
+};
+use endpoints::chat::{
+    ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionUserMessageContent,
+    ContentPart,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, RwLock},
+};
+
+// key: name of the custom template
+static CUSTOM_TEMPLATES: RwLock<BTreeMap<String, CustomTemplate>> = RwLock::new(BTreeMap::new());
+// names of the custom templates used by the prompt template types
+static CUSTOM_TEMPLATE_NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// A prompt template declared by the user.
+///
+/// The wrappers replace `{content}` with the content of the message, and `{bos}` and `{eos}` with the BOS and EOS strings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomTemplate {
+    /// The string at the beginning of the prompt. Defaults to empty.
+    #[serde(default)]
+    pub bos: String,
+    /// The string replacing `{eos}` in the wrappers. Defaults to empty.
+    #[serde(default)]
+    pub eos: String,
+    /// The wrapper of the system messages. If not set, the content of the system messages is put before the content of the next user message.
+    #[serde(default)]
+    pub system: Option<String>,
+    /// The wrapper of the user messages.
+    pub user: String,
+    /// The wrapper of the assistant messages.
+    pub assistant: String,
+    /// The wrapper of the tool messages. If not set, the tool messages are not supported.
+    #[serde(default)]
+    pub tool: Option<String>,
+    /// The string at the end of the prompt, which starts the answer of the assistant. Defaults to empty.
+    #[serde(default)]
+    pub generation_prompt: String,
+    /// The content of the system message used if the messages have no system message.
+    #[serde(default)]
+    pub default_system_prompt: Option<String>,
+    /// The strings at which the generation stops, e.g. the end-of-turn token. Defaults to empty.
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// The wrapper of each image of the user messages, replacing `{image}` with the image. The images are put before the text of the message. If not set, the images are not supported.
+    #[serde(default)]
+    pub image: Option<String>,
+}
+impl CustomTemplate {
+    fn wrap(&self, wrapper: &str, content: &str) -> String {
+        wrapper
+            .replace("{bos}", &self.bos)
+            .replace("{eos}", &self.eos)
+            .replace("{content}", content)
+    }
+
+    /// Appends the system message to the prompt, or keeps its content for the next user message if the template has no system wrapper.
+    fn add_system(&self, prompt: &mut String, system: &mut Option<String>, content: &str) {
+        match &self.system {
+            Some(wrapper) => prompt.push_str(&self.wrap(wrapper, content)),
+            None => match system {
+                Some(system) => {
+                    system.push_str("\n\n");
+                    system.push_str(content);
+                }
+                None => *system = Some(content.to_string()),
+            },
+        }
+    }
+
+    /// Returns the text of the user message, with the content of the pending system messages before it.
+    fn user_content(
+        &self,
+        name: &str,
+        content: &ChatCompletionUserMessageContent,
+        system: &mut Option<String>,
+    ) -> Result<String> {
+        let mut text = String::new();
+        let mut images = String::new();
+        match content {
+            ChatCompletionUserMessageContent::Text(content) => text.push_str(content),
+            ChatCompletionUserMessageContent::Parts(parts) => {
+                for part in parts {
+                    match part {
+                        ContentPart::Text(part) => {
+                            if !text.is_empty() {
+                                text.push('\n');
+                            }
+                            text.push_str(part.text());
+                        }
+                        ContentPart::Image(part) => {
+                            let wrapper = self.image.as_ref().ok_or_else(|| {
+                                PromptError::UnsupportedContent(format!(
+                                    "Image content is not supported by the custom template `{name}`."
+                                ))
+                            })?;
+                            let image = match part.image().is_url() {
+                                true => String::from("<image>"),
+                                false => {
+                                    let base64_str = part.image().url.as_str();
+                                    let format = get_image_format(base64_str)?;
+                                    format!(
+                                        r#"<img src="data:image/{format};base64,{base64_str}">"#
+                                    )
+                                }
+                            };
+                            images.push_str(&wrapper.replace("{image}", &image));
+                        }
+                        ContentPart::Audio(_) => {
+                            return Err(PromptError::UnsupportedContent(format!(
+                                "Audio content is not supported by the custom template `{name}`."
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+
+        let text = match system.take() {
+            Some(system) => format!("{system}\n\n{text}"),
+            None => text,
+        };
+
+        Ok(format!("{images}{text}"))
+    }
+
+    /// Returns the content of the assistant message, or the tool calls as JSON lines if it has no content.
+    fn assistant_content(message: &ChatCompletionAssistantMessage) -> Result<String> {
+        match (message.content(), message.tool_calls()) {
+            (Some(content), _) if !content.is_empty() => Ok(content.to_string()),
+            (_, Some(tool_calls)) => Ok(tool_calls
+                .iter()
+                .map(|tool_call| {
+                    let arguments: serde_json::Value =
+                        serde_json::from_str(&tool_call.function.arguments)
+                            .unwrap_or_else(|_| tool_call.function.arguments.clone().into());
+                    serde_json::json!({
+                        "name": tool_call.function.name,
+                        "arguments": arguments,
+                    })
+                    .to_string()
+                })
+                .collect::<Vec<_>>()
+                .join("\n")),
+            (Some(content), None) => Ok(content.to_string()),
+            (None, None) => Err(PromptError::NoAssistantMessage),
+        }
+    }
+}
+
+/// Registers the custom template under the name, replacing the template registered before with the same name, and returns the prompt template type using it.
+pub fn register_custom_template(
+    name: impl AsRef<str>,
+    template: CustomTemplate,
+) -> Result<PromptTemplateType> {
+    let name = name.as_ref();
+    if name.is_empty() || name.contains(',') || name.contains(char::is_whitespace) {
+        return Err(PromptError::InvalidCustomTemplate(format!(
+            "Invalid name: `{name}`. The name must not be empty, and must not contain commas or whitespace."
+        )));
+    }
+
+    let wrappers = [
+        ("user", Some(&template.user)),
+        ("assistant", Some(&template.assistant)),
+        ("system", template.system.as_ref()),
+        ("tool", template.tool.as_ref()),
+    ];
+    for (role, wrapper) in wrappers {
+        if let Some(wrapper) = wrapper {
+            if !wrapper.contains("{content}") {
+                return Err(PromptError::InvalidCustomTemplate(format!(
+                    "The {role} wrapper of the custom template `{name}` has no `{{content}}` placeholder."
+                )));
+            }
+        }
+    }
+    if let Some(image) = &template.image {
+        if !image.contains("{image}") {
+            return Err(PromptError::InvalidCustomTemplate(format!(
+                "The image wrapper of the custom template `{name}` has no `{{image}}` placeholder."
+            )));
+        }
+    }
+
+    CUSTOM_TEMPLATES
+        .write()
+        .map_err(|e| {
+            PromptError::InvalidCustomTemplate(format!(
+                "Failed to acquire the lock of the custom templates. {e}"
+            ))
+        })?
+        .insert(name.to_string(), template);
+
+    Ok(PromptTemplateType::Custom(intern_name(name)))
+}
+
+/// Returns the custom template registered under the name.
+pub fn custom_template(name: &str) -> Option<CustomTemplate> {
+    CUSTOM_TEMPLATES.read().ok()?.get(name).cloned()
+}
+
+/// Returns the names of the registered custom templates.
+pub fn custom_template_names() -> Vec<String> {
+    CUSTOM_TEMPLATES
+        .read()
+        .map(|templates| templates.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Returns the name with the static lifetime required by [`PromptTemplateType::Custom`]. Each name is allocated once.
+pub(crate) fn intern_name(name: &str) -> &'static str {
+    let mut names = match CUSTOM_TEMPLATE_NAMES.lock() {
+        Ok(names) => names,
+        Err(e) => e.into_inner(),
+    };
+
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+/// (De)serializes the name of [`PromptTemplateType::Custom`], which is interned to get the static lifetime.
+pub(crate) mod template_name {
+    pub(crate) fn serialize<S: serde::Serializer>(
+        name: &&'static str,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(name)
+    }
+
+    pub(crate) fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<&'static str, D::Error> {
+        let name = <String as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(super::intern_name(&name))
+    }
+}
+
+/// Generate prompts with a custom template.
+#[derive(Debug, Clone)]
+pub struct CustomPrompt {
+    name: &'static str,
+}
+impl CustomPrompt {
+    /// Creates a prompt builder with the custom template registered under the name. The template is looked up when the prompt is built.
+    pub fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+}
+impl BuildChatPrompt for CustomPrompt {
+    fn build(&self, messages: &mut Vec<ChatCompletionRequestMessage>) -> Result<String> {
+        if messages.is_empty() {
+            return Err(PromptError::NoMessages);
+        }
+
+        let template = &custom_template(self.name).ok_or_else(|| {
+            PromptError::UnknownPromptTemplateType(format!("custom:{}", self.name))
+        })?;
+        let mut prompt = template.bos.clone();
+
+        // the content of the system messages waiting for the next user message, if the template has no system wrapper
+        let mut system = None;
+
+        let has_system_message = matches!(
+            messages[0],
+            ChatCompletionRequestMessage::System(_) | ChatCompletionRequestMessage::Developer(_)
+        );
+        if let (false, Some(default_system_prompt)) =
+            (has_system_message, &template.default_system_prompt)
+        {
+            template.add_system(&mut prompt, &mut system, default_system_prompt);
+        }
+
+        for message in messages.iter() {
+            match message {
+                ChatCompletionRequestMessage::System(message) => {
+                    template.add_system(&mut prompt, &mut system, message.content())
+                }
+                ChatCompletionRequestMessage::Developer(message) => {
+                    template.add_system(&mut prompt, &mut system, message.content())
+                }
+                ChatCompletionRequestMessage::User(message) => {
+                    let content =
+                        template.user_content(self.name, message.content(), &mut system)?;
+                    prompt.push_str(&template.wrap(&template.user, &content));
+                }
+                ChatCompletionRequestMessage::Assistant(message) => {
+                    let content = CustomTemplate::assistant_content(message)?;
+                    prompt.push_str(&template.wrap(&template.assistant, &content));
+                }
+                ChatCompletionRequestMessage::Tool(message) => {
+                    let wrapper = template.tool.as_ref().ok_or_else(|| {
+                        PromptError::BadMessages(format!(
+                            "Tool messages are not supported by the custom template `{}`.",
+                            self.name
+                        ))
+                    })?;
+                    prompt.push_str(&template.wrap(wrapper, message.content()));
+                }
+            }
+        }
+
+        prompt.push_str(&template.generation_prompt);
+
+        Ok(prompt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatPrompt;
+    use endpoints::chat::{Function, Image, ImageContentPart, TextContentPart, ToolCall};
+
+    fn phi() -> CustomTemplate {
+        CustomTemplate {
+            bos: "<s>".to_string(),
+            eos: "<|end|>".to_string(),
+            system: Some("<|system|>\n{content}{eos}\n".to_string()),
+            user: "<|user|>\n{content}{eos}\n".to_string(),
+            assistant: "<|assistant|>\n{content}{eos}\n".to_string(),
+            tool: Some("<|tool|>\n{content}{eos}\n".to_string()),
+            generation_prompt: "<|assistant|>\n".to_string(),
+            stop: vec!["<|end|>".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn system(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_system_message(content, None)
+    }
+
+    fn user(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(content.to_string()),
+            None,
+        )
+    }
+
+    fn assistant(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_assistant_message(Some(content.to_string()), None, None)
+    }
+
+    fn build(
+        name: &str,
+        template: CustomTemplate,
+        messages: &[ChatCompletionRequestMessage],
+    ) -> Result<String> {
+        ChatPrompt::from(register_custom_template(name, template)?).build(&mut messages.to_vec())
+    }
+
+    #[test]
+    fn test_register_custom_template() {
+        let template_type = register_custom_template("test-register", phi()).unwrap();
+        assert_eq!(template_type, PromptTemplateType::Custom("test-register"));
+        assert_eq!(template_type.to_string(), "custom:test-register");
+        let json = serde_json::to_string(&template_type).unwrap();
+        assert_eq!(json, r#"{"Custom":"test-register"}"#);
+        assert_eq!(
+            serde_json::from_str::<PromptTemplateType>(&json).unwrap(),
+            template_type
+        );
+        assert_eq!(custom_template("test-register"), Some(phi()));
+        assert!(custom_template_names().contains(&"test-register".to_string()));
+
+        // the template registered again replaces the previous one
+        let template = CustomTemplate {
+            generation_prompt: "<|assistant|>\nSure,".to_string(),
+            ..phi()
+        };
+        register_custom_template("test-register", template.clone()).unwrap();
+        assert_eq!(custom_template("test-register"), Some(template));
+
+        assert_eq!(custom_template("test-unregistered"), None);
+        assert!(matches!(
+            CustomPrompt::new("test-unregistered").build(&mut vec![user("Hi")]),
+            Err(PromptError::UnknownPromptTemplateType(_))
+        ));
+    }
+
+    #[test]
+    fn test_register_invalid_custom_template() {
+        for name in ["", "a b", "a,b"] {
+            assert!(matches!(
+                register_custom_template(name, phi()),
+                Err(PromptError::InvalidCustomTemplate(_))
+            ));
+        }
+
+        let invalid = [
+            CustomTemplate {
+                user: "<|user|>\n".to_string(),
+                ..phi()
+            },
+            CustomTemplate {
+                assistant: "<|assistant|>\n".to_string(),
+                ..phi()
+            },
+            CustomTemplate {
+                system: Some("<|system|>\n".to_string()),
+                ..phi()
+            },
+            CustomTemplate {
+                tool: Some("<|tool|>\n".to_string()),
+                ..phi()
+            },
+            CustomTemplate {
+                image: Some("<|image|>".to_string()),
+                ..phi()
+            },
+        ];
+        for template in invalid {
+            assert!(matches!(
+                register_custom_template("test-invalid", template),
+                Err(PromptError::InvalidCustomTemplate(_))
+            ));
+        }
+        assert_eq!(custom_template("test-invalid"), None);
+    }
+
+    #[test]
+    fn test_intern_name() {
+        let name = intern_name("test-intern");
+        assert_eq!(name, "test-intern");
+        assert!(std::ptr::eq(
+            name,
+            intern_name(&String::from("test-intern"))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_custom_template() {
+        let template: CustomTemplate = serde_json::from_str(
+            r#"{"user": "[INST] {content} [/INST]", "assistant": "{content}</s>"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template,
+            CustomTemplate {
+                user: "[INST] {content} [/INST]".to_string(),
+                assistant: "{content}</s>".to_string(),
+                ..Default::default()
+            }
+        );
+
+        // the user and assistant wrappers are required
+        assert!(serde_json::from_str::<CustomTemplate>(r#"{"user": "{content}"}"#).is_err());
+    }
+
+    #[test]
+    fn test_build_with_system_wrapper() {
+        let prompt = build(
+            "test-system-wrapper",
+            phi(),
+            &[
+                system("Be brief."),
+                user("Hi"),
+                assistant("Hello!"),
+                user("Bye"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            prompt,
+            "<s><|system|>\nBe brief.<|end|>\n<|user|>\nHi<|end|>\n<|assistant|>\nHello!<|end|>\n<|user|>\nBye<|end|>\n<|assistant|>\n"
+        );
+    }
+
+    #[test]
+    fn test_build_without_system_wrapper() {
+        let template = CustomTemplate {
+            bos: "<s>".to_string(),
+            eos: "</s>".to_string(),
+            user: "[INST] {content} [/INST]".to_string(),
+            assistant: "{content}{eos}".to_string(),
+            default_system_prompt: Some("You are helpful.".to_string()),
+            ..Default::default()
+        };
+
+        // the system messages are put before the next user message
+        let prompt = build(
+            "test-no-system-wrapper",
+            template.clone(),
+            &[system("Be brief."), system("Be kind."), user("Hi")],
+        )
+        .unwrap();
+        assert_eq!(prompt, "<s>[INST] Be brief.\n\nBe kind.\n\nHi [/INST]");
+
+        // the default system prompt is used without a system message
+        let prompt = build(
+            "test-no-system-wrapper",
+            template,
+            &[user("Hi"), assistant("Hello!"), user("Bye")],
+        )
+        .unwrap();
+        assert_eq!(
+            prompt,
+            "<s>[INST] You are helpful.\n\nHi [/INST]Hello!</s>[INST] Bye [/INST]"
+        );
+    }
+
+    #[test]
+    fn test_build_with_tool_calls() {
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            ty: "function".to_string(),
+            function: Function {
+                name: "get_weather".to_string(),
+                arguments: r#"{"city":"Paris"}"#.to_string(),
+            },
+        };
+        let messages = [
+            user("What is the weather in Paris?"),
+            ChatCompletionRequestMessage::new_assistant_message(None, None, Some(vec![tool_call])),
+            ChatCompletionRequestMessage::new_tool_message("22°C", "call_1"),
+        ];
+
+        let prompt = build("test-tool", phi(), &messages).unwrap();
+        assert_eq!(
+            prompt,
+            "<s><|user|>\nWhat is the weather in Paris?<|end|>\n<|assistant|>\n{\"arguments\":{\"city\":\"Paris\"},\"name\":\"get_weather\"}<|end|>\n<|tool|>\n22°C<|end|>\n<|assistant|>\n"
+        );
+
+        // the tool messages are rejected without the tool wrapper
+        let template = CustomTemplate {
+            tool: None,
+            ..phi()
+        };
+        assert!(matches!(
+            build("test-no-tool", template, &messages),
+            Err(PromptError::BadMessages(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_with_images() {
+        let messages = [ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Parts(vec![
+                ContentPart::Text(TextContentPart::new("What is in the image?")),
+                ContentPart::Image(ImageContentPart::new(Image {
+                    url: "https://example.com/image.png".to_string(),
+                    detail: None,
+                })),
+            ]),
+            None,
+        )];
+
+        let template = CustomTemplate {
+            image: Some("{image}\n".to_string()),
+            ..phi()
+        };
+        let prompt = build("test-image", template, &messages).unwrap();
+        assert_eq!(
+            prompt,
+            "<s><|user|>\n<image>\nWhat is in the image?<|end|>\n<|assistant|>\n"
+        );
+
+        // the images are rejected without the image wrapper
+        assert!(matches!(
+            build("test-no-image", phi(), &messages),
+            Err(PromptError::UnsupportedContent(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_without_messages() {
+        assert!(matches!(
+            build("test-no-messages", phi(), &[]),
+            Err(PromptError::NoMessages)
+        ));
+    }
+}
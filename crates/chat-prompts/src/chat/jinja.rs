@@ -0,0 +1,550 @@
+//! Generate prompts by rendering the chat templates of the models.
+//!
+//! The chat templates on Hugging Face are written in Jinja, and `transformers` renders them with `trim_blocks` and `lstrip_blocks` enabled, adding `raise_exception`, `strftime_now` and the `tojson` filter. The templates are rendered by `minijinja`, with the Python methods of strings, lists and dicts provided by `minijinja-contrib`.
+
+use crate::{
+    error::{PromptError, Result},
+    BuildChatPrompt,
+};
+use endpoints::chat::{
+    ChatCompletionRequestMessage, ChatCompletionUserMessageContent, ContentPart, Tool,
+};
+use minijinja::{context, value::Kwargs, Environment, ErrorKind};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+/// The name of the chat template in the environment.
+const TEMPLATE_NAME: &str = "chat";
+
+/// The chat template of a model, written in the Jinja dialect of Hugging Face.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChatTemplate {
+    /// The source of the template.
+    pub source: String,
+    /// The text passed to the template as `bos_token`.
+    pub bos_token: String,
+    /// The text passed to the template as `eos_token`.
+    pub eos_token: String,
+}
+impl ChatTemplate {
+    /// Creates a chat template with empty `bos_token` and `eos_token`.
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the text passed to the template as `bos_token`.
+    pub fn with_bos_token(mut self, bos_token: impl Into<String>) -> Self {
+        self.bos_token = bos_token.into();
+        self
+    }
+
+    /// Sets the text passed to the template as `eos_token`.
+    pub fn with_eos_token(mut self, eos_token: impl Into<String>) -> Self {
+        self.eos_token = eos_token.into();
+        self
+    }
+}
+
+/// Generate prompts by rendering the chat template of the model.
+#[derive(Debug, Default, Clone)]
+pub struct JinjaPrompt {
+    /// The environment holding the parsed chat template.
+    env: Option<Arc<Environment<'static>>>,
+    bos_token: String,
+    eos_token: String,
+    add_generation_prompt: bool,
+}
+impl JinjaPrompt {
+    /// Creates a prompt builder rendering the chat template. The template is parsed once here.
+    pub fn new(template: ChatTemplate) -> Result<Self> {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", raise_exception);
+        env.add_function("strftime_now", strftime_now);
+        env.add_filter("tojson", tojson);
+        env.add_template_owned(TEMPLATE_NAME, template.source)
+            .map_err(|e| {
+                PromptError::Operation(format!("Failed to parse the chat template. {e}"))
+            })?;
+
+        Ok(Self {
+            env: Some(Arc::new(env)),
+            bos_token: template.bos_token,
+            eos_token: template.eos_token,
+            add_generation_prompt: true,
+        })
+    }
+
+    /// Sets whether the template appends the header of the assistant turn. Enabled by default.
+    ///
+    /// The header is never appended if the last message is an assistant message, which the model continues instead.
+    pub fn with_add_generation_prompt(mut self, add_generation_prompt: bool) -> Self {
+        self.add_generation_prompt = add_generation_prompt;
+        self
+    }
+
+    fn render(
+        &self,
+        messages: &[ChatCompletionRequestMessage],
+        tools: Option<&[Tool]>,
+    ) -> Result<String> {
+        let env = match &self.env {
+            Some(env) => env,
+            None => {
+                return Err(PromptError::Operation(
+                    "The `jinja` prompt template requires the chat template of the model."
+                        .to_string(),
+                ))
+            }
+        };
+
+        let last = match messages.last() {
+            Some(last) => last,
+            None => return Err(PromptError::NoMessages),
+        };
+        let add_generation_prompt = self.add_generation_prompt
+            && !matches!(last, ChatCompletionRequestMessage::Assistant(_));
+
+        let tools = match tools {
+            Some(tools) if !tools.is_empty() => minijinja::Value::from_serialize(tools),
+            _ => minijinja::Value::from(()),
+        };
+        let messages = messages.iter().map(message_value).collect::<Vec<_>>();
+
+        env.get_template(TEMPLATE_NAME)
+            .and_then(|template| {
+                template.render(context! {
+                    messages => minijinja::Value::from_serialize(&messages),
+                    tools => tools,
+                    add_generation_prompt => add_generation_prompt,
+                    bos_token => &self.bos_token,
+                    eos_token => &self.eos_token,
+                })
+            })
+            .map_err(|e| PromptError::Operation(format!("Failed to render the chat template. {e}")))
+    }
+}
+impl BuildChatPrompt for JinjaPrompt {
+    fn build(&self, messages: &mut Vec<ChatCompletionRequestMessage>) -> Result<String> {
+        self.render(messages, None)
+    }
+
+    fn build_with_tools(
+        &self,
+        messages: &mut Vec<ChatCompletionRequestMessage>,
+        tools: Option<&[Tool]>,
+    ) -> Result<String> {
+        self.render(messages, tools)
+    }
+}
+
+/// Converts a chat message into the form expected by the chat templates.
+fn message_value(message: &ChatCompletionRequestMessage) -> Value {
+    let mut value = Map::new();
+    match message {
+        ChatCompletionRequestMessage::System(message) => {
+            value.insert("role".into(), "system".into());
+            value.insert("content".into(), message.content().into());
+        }
+        // the chat templates do not know the developer role
+        ChatCompletionRequestMessage::Developer(message) => {
+            value.insert("role".into(), "system".into());
+            value.insert("content".into(), message.content().into());
+        }
+        ChatCompletionRequestMessage::User(message) => {
+            let content = match message.content() {
+                ChatCompletionUserMessageContent::Text(text) => text.clone(),
+                ChatCompletionUserMessageContent::Parts(parts) => parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text(text) => Some(text.text()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            value.insert("role".into(), "user".into());
+            value.insert("content".into(), content.into());
+        }
+        ChatCompletionRequestMessage::Assistant(message) => {
+            value.insert("role".into(), "assistant".into());
+            value.insert(
+                "content".into(),
+                message.content().cloned().unwrap_or_default().into(),
+            );
+            if let Some(tool_calls) = message.tool_calls() {
+                let tool_calls = tool_calls
+                    .iter()
+                    .map(|tool_call| {
+                        // the templates expect the arguments as an object, e.g. `arguments | tojson`
+                        let arguments =
+                            serde_json::from_str::<Value>(&tool_call.function.arguments)
+                                .unwrap_or_else(|_| tool_call.function.arguments.clone().into());
+
+                        serde_json::json!({
+                            "id": tool_call.id,
+                            "type": tool_call.ty,
+                            "function": {
+                                "name": tool_call.function.name,
+                                "arguments": arguments,
+                            },
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                value.insert("tool_calls".into(), tool_calls.into());
+            }
+        }
+        ChatCompletionRequestMessage::Tool(message) => {
+            value.insert("role".into(), "tool".into());
+            value.insert("content".into(), message.content().into());
+            value.insert("tool_call_id".into(), message.tool_call_id().into());
+        }
+    }
+
+    if let Some(name) = message.name() {
+        value.insert("name".into(), name.clone().into());
+    }
+
+    Value::Object(value)
+}
+
+/// Fails the rendering with the message, e.g. `raise_exception("Conversation roles must alternate")`.
+fn raise_exception(message: String) -> std::result::Result<minijinja::Value, minijinja::Error> {
+    Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+}
+
+/// Formats the current local time, e.g. `strftime_now("%d %b %Y")`.
+fn strftime_now(format: String) -> std::result::Result<String, minijinja::Error> {
+    let mut context = tera::Context::new();
+    context.insert("format", &format);
+
+    tera::Tera::one_off("{{ now() | date(format=format) }}", &context, false).map_err(|e| {
+        minijinja::Error::new(
+            ErrorKind::InvalidOperation,
+            format!("Failed to format the current time: {e}"),
+        )
+    })
+}
+
+/// Serializes a value as `json.dumps` of Python, e.g. `tool | tojson` or `tool | tojson(indent=4)`.
+fn tojson(
+    value: minijinja::Value,
+    indent: Option<usize>,
+    kwargs: Kwargs,
+) -> std::result::Result<minijinja::Value, minijinja::Error> {
+    let indent = match indent {
+        Some(indent) => Some(indent),
+        None => kwargs.get::<Option<usize>>("indent")?,
+    };
+    kwargs.assert_all_used()?;
+
+    to_json(&value, indent)
+        .map(minijinja::Value::from_safe_string)
+        .map_err(|e| {
+            minijinja::Error::new(
+                ErrorKind::InvalidOperation,
+                format!("Failed to serialize to JSON: {e}"),
+            )
+        })
+}
+
+fn to_json(value: &impl Serialize, indent: Option<usize>) -> serde_json::Result<String> {
+    let mut buffer = vec![];
+    match indent {
+        Some(indent) => {
+            let indent = " ".repeat(indent);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            value.serialize(&mut serde_json::Serializer::with_formatter(
+                &mut buffer,
+                formatter,
+            ))?
+        }
+        None => value.serialize(&mut serde_json::Serializer::with_formatter(
+            &mut buffer,
+            PythonFormatter,
+        ))?,
+    }
+
+    // serde_json only writes valid UTF-8
+    Ok(String::from_utf8(buffer).unwrap_or_default())
+}
+
+/// Separates the items with `, ` and the keys from the values with `: ` as Python does.
+struct PythonFormatter;
+impl serde_json::ser::Formatter for PythonFormatter {
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> std::io::Result<()>
+    where
+        W: ?Sized + std::io::Write,
+    {
+        match first {
+            true => Ok(()),
+            false => writer.write_all(b", "),
+        }
+    }
+
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> std::io::Result<()>
+    where
+        W: ?Sized + std::io::Write,
+    {
+        match first {
+            true => Ok(()),
+            false => writer.write_all(b", "),
+        }
+    }
+
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: ?Sized + std::io::Write,
+    {
+        writer.write_all(b": ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use endpoints::chat::{Function, ToolCall};
+
+    const LLAMA_3: &str = "{% set loop_messages = messages %}{% for message in loop_messages %}{% set content = '<|start_header_id|>' + message['role'] + '<|end_header_id|>\n\n'+ message['content'] | trim + '<|eot_id|>' %}{% if loop.index0 == 0 %}{% set content = bos_token + content %}{% endif %}{{ content }}{% endfor %}{% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\n\n' }}{% endif %}";
+
+    const MISTRAL: &str = "{{ bos_token }}{% for message in messages %}{% if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}{{ raise_exception('Conversation roles must alternate user/assistant/user/assistant/...') }}{% endif %}{% if message['role'] == 'user' %}{{ '[INST] ' + message['content'] + ' [/INST]' }}{% elif message['role'] == 'assistant' %}{{ message['content'] + eos_token}}{% else %}{{ raise_exception('Only user and assistant roles are supported!') }}{% endif %}{% endfor %}";
+
+    const QWEN_2_5: &str = r##"{%- if tools %}
+    {{- '<|im_start|>system\n' }}
+    {%- if messages[0]['role'] == 'system' %}
+        {{- messages[0]['content'] }}
+    {%- else %}
+        {{- 'You are Qwen, created by Alibaba Cloud. You are a helpful assistant.' }}
+    {%- endif %}
+    {{- "\n\n# Tools\n\nYou may call one or more functions to assist with the user query.\n\nYou are provided with function signatures within <tools></tools> XML tags:\n<tools>" }}
+    {%- for tool in tools %}
+        {{- "\n" }}
+        {{- tool | tojson }}
+    {%- endfor %}
+    {{- "\n</tools>\n\nFor each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:\n<tool_call>\n{\"name\": <function-name>, \"arguments\": <args-json-object>}\n</tool_call><|im_end|>\n" }}
+{%- else %}
+    {%- if messages[0]['role'] == 'system' %}
+        {{- '<|im_start|>system\n' + messages[0]['content'] + '<|im_end|>\n' }}
+    {%- else %}
+        {{- '<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.<|im_end|>\n' }}
+    {%- endif %}
+{%- endif %}
+{%- for message in messages %}
+    {%- if (message.role == "user") or (message.role == "system" and not loop.first) or (message.role == "assistant" and not message.tool_calls) %}
+        {{- '<|im_start|>' + message.role + '\n' + message.content + '<|im_end|>' + '\n' }}
+    {%- elif message.role == "assistant" %}
+        {{- '<|im_start|>' + message.role }}
+        {%- if message.content %}
+            {{- '\n' + message.content }}
+        {%- endif %}
+        {%- for tool_call in message.tool_calls %}
+            {%- if tool_call.function is defined %}
+                {%- set tool_call = tool_call.function %}
+            {%- endif %}
+            {{- '\n<tool_call>\n{"name": "' }}
+            {{- tool_call.name }}
+            {{- '", "arguments": ' }}
+            {{- tool_call.arguments | tojson }}
+            {{- '}\n</tool_call>' }}
+        {%- endfor %}
+        {{- '<|im_end|>\n' }}
+    {%- elif message.role == "tool" %}
+        {%- if (loop.index0 == 0) or (messages[loop.index0 - 1].role != "tool") %}
+            {{- '<|im_start|>user' }}
+        {%- endif %}
+        {{- '\n<tool_response>\n' }}
+        {{- message.content }}
+        {{- '\n</tool_response>' }}
+        {%- if loop.last or (messages[loop.index0 + 1].role != "tool") %}
+            {{- '<|im_end|>\n' }}
+        {%- endif %}
+    {%- endif %}
+{%- endfor %}
+{%- if add_generation_prompt %}
+    {{- '<|im_start|>assistant\n' }}
+{%- endif %}
+"##;
+
+    fn system(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_system_message(content, None)
+    }
+
+    fn user(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(content.to_string()),
+            None,
+        )
+    }
+
+    fn assistant(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_assistant_message(Some(content.to_string()), None, None)
+    }
+
+    fn prompt(source: &str) -> JinjaPrompt {
+        let template = ChatTemplate::new(source)
+            .with_bos_token("<s>")
+            .with_eos_token("</s>");
+        JinjaPrompt::new(template).unwrap()
+    }
+
+    #[test]
+    fn test_render_llama_3() {
+        let template = ChatTemplate::new(LLAMA_3).with_bos_token("<|begin_of_text|>");
+        let prompt = JinjaPrompt::new(template).unwrap();
+        let mut messages = vec![system("You are a helpful assistant."), user(" Hello ")];
+
+        assert_eq!(
+            prompt.build(&mut messages).unwrap(),
+            "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nYou are a helpful assistant.<|eot_id|><|start_header_id|>user<|end_header_id|>\n\nHello<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_without_generation_prompt() {
+        let template = ChatTemplate::new(LLAMA_3).with_bos_token("<|begin_of_text|>");
+        let prompt = JinjaPrompt::new(template)
+            .unwrap()
+            .with_add_generation_prompt(false);
+        let mut messages = vec![user("Hello")];
+
+        assert_eq!(
+            prompt.build(&mut messages).unwrap(),
+            "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n\nHello<|eot_id|>"
+        );
+    }
+
+    #[test]
+    fn test_render_continues_last_assistant_message() {
+        let template = ChatTemplate::new(LLAMA_3).with_bos_token("<|begin_of_text|>");
+        let prompt = JinjaPrompt::new(template).unwrap();
+        let mut messages = vec![user("Hello"), assistant("Hi")];
+
+        assert_eq!(
+            prompt.build(&mut messages).unwrap(),
+            "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n\nHello<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\nHi<|eot_id|>"
+        );
+    }
+
+    #[test]
+    fn test_render_mistral() {
+        let prompt = prompt(MISTRAL);
+        let mut messages = vec![user("Hello"), assistant("Hi"), user("How are you?")];
+
+        assert_eq!(
+            prompt.build(&mut messages).unwrap(),
+            "<s>[INST] Hello [/INST]Hi</s>[INST] How are you? [/INST]"
+        );
+    }
+
+    #[test]
+    fn test_render_raise_exception() {
+        let prompt = prompt(MISTRAL);
+        let mut messages = vec![system("You are a helpful assistant."), user("Hello")];
+
+        let error = prompt.build(&mut messages).unwrap_err().to_string();
+        assert!(
+            error.contains("Conversation roles must alternate"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_render_qwen() {
+        let prompt = prompt(QWEN_2_5);
+        let mut messages = vec![user("Hello")];
+
+        assert_eq!(
+            prompt.build(&mut messages).unwrap(),
+            "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.<|im_end|>\n<|im_start|>user\nHello<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_render_qwen_with_tools() {
+        let prompt = prompt(QWEN_2_5);
+        let tool: Tool = serde_json::from_str(
+            r#"{"type": "function", "function": {"name": "get_weather", "description": "Get the weather", "parameters": {"properties": {"city": {"type": "string"}}, "required": ["city"], "type": "object"}}}"#,
+        )
+        .unwrap();
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            ty: "function".to_string(),
+            function: Function {
+                name: "get_weather".to_string(),
+                arguments: r#"{"city":"Paris"}"#.to_string(),
+            },
+        };
+        let mut messages = vec![
+            user("What is the weather in Paris?"),
+            ChatCompletionRequestMessage::new_assistant_message(None, None, Some(vec![tool_call])),
+            ChatCompletionRequestMessage::new_tool_message("22°C", "call_1"),
+        ];
+
+        assert_eq!(
+            prompt
+                .build_with_tools(&mut messages, Some(&[tool]))
+                .unwrap(),
+            r#"<|im_start|>system
+You are Qwen, created by Alibaba Cloud. You are a helpful assistant.
+
+# Tools
+
+You may call one or more functions to assist with the user query.
+
+You are provided with function signatures within <tools></tools> XML tags:
+<tools>
+{"type": "function", "function": {"name": "get_weather", "description": "Get the weather", "parameters": {"properties": {"city": {"type": "string"}}, "required": ["city"], "type": "object"}}}
+</tools>
+
+For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:
+<tool_call>
+{"name": <function-name>, "arguments": <args-json-object>}
+</tool_call><|im_end|>
+<|im_start|>user
+What is the weather in Paris?<|im_end|>
+<|im_start|>assistant
+<tool_call>
+{"name": "get_weather", "arguments": {"city": "Paris"}}
+</tool_call><|im_end|>
+<|im_start|>user
+<tool_response>
+22°C
+</tool_response><|im_end|>
+<|im_start|>assistant
+"#
+        );
+    }
+
+    #[test]
+    fn test_render_python_methods_and_filters() {
+        let prompt = prompt(
+            "{% set ns = namespace(found=false) %}{% for message in messages %}{% if message.content.strip().startswith('Hi') %}{% set ns.found = true %}{% break %}{% endif %}{% endfor %}{{ ns.found }}|{{ {'a': [1, 2]} | tojson(indent=2) }}|{{ strftime_now('%Y') | length }}",
+        );
+        let mut messages = vec![user("  Hi there "), assistant("Hello")];
+
+        assert_eq!(
+            prompt.build(&mut messages).unwrap(),
+            "true|{\n  \"a\": [\n    1,\n    2\n  ]\n}|4"
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = JinjaPrompt::new(ChatTemplate::new("{% if messages %}")).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Failed to parse the chat template"));
+    }
+
+    #[test]
+    fn test_missing_template() {
+        let mut messages = vec![user("Hello")];
+        assert!(JinjaPrompt::default().build(&mut messages).is_err());
+    }
+}
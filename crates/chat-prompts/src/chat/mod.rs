@@ -1,6 +1,7 @@
 pub mod baichuan;
 pub mod belle;
 pub mod chatml;
+pub mod custom;
 pub mod deepseek;
 pub mod exaone;
 pub mod falcon;
@@ -9,6 +10,7 @@ pub mod gemma;
 pub mod glm;
 pub mod groq;
 pub mod intel;
+pub mod jinja;
 pub mod llama;
 pub mod mediatek;
 pub mod megrez;
@@ -31,6 +33,9 @@ pub mod zephyr;
 pub use baichuan::*;
 pub use belle::*;
 pub use chatml::*;
+pub use custom::{
+    custom_template, custom_template_names, register_custom_template, CustomPrompt, CustomTemplate,
+};
 pub use deepseek::*;
 pub use exaone::*;
 pub use falcon::*;
@@ -39,6 +44,7 @@ pub use gemma::*;
 pub use glm::*;
 pub use groq::*;
 pub use intel::*;
+pub use jinja::{ChatTemplate, JinjaPrompt};
 pub use llama::*;
 pub use mediatek::BreezeInstructPrompt;
 pub use megrez::*;
@@ -10,7 +10,10 @@ pub mod utils;
 use agent::*;
 use chat::*;
 use clap::ValueEnum;
-use endpoints::chat::{ChatCompletionRequestMessage, Tool};
+use endpoints::chat::{
+    ChatCompletionRequestMessage, ChatCompletionUserMessageContent, ContentPart, TextContentPart,
+    Tool,
+};
 use error::Result;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
@@ -144,6 +147,17 @@ pub enum PromptTemplateType {
     Smol3NoThink,
     #[value(name = "gpt-oss")]
     GptOss,
+    #[value(name = "jinja")]
+    Jinja,
+    /// The template detected from the metadata of the model file when the model is loaded.
+    #[value(name = "auto")]
+    Auto,
+    /// The template registered with [`chat::register_custom_template`] under the name, which is given as `custom:<name>`.
+    #[value(skip)]
+    Custom(
+        // spelled out so that serde does not borrow the name from the input
+        #[serde(with = "custom::template_name")] &'static std::primitive::str,
+    ),
     #[value(name = "embedding")]
     Embedding,
     #[value(name = "tts")]
@@ -198,7 +212,11 @@ impl PromptTemplateType {
             | PromptTemplateType::SeedOssThink
             | PromptTemplateType::SeedOssNoThink
             | PromptTemplateType::Smol3NoThink
-            | PromptTemplateType::GptOss => true,
+            | PromptTemplateType::GptOss
+            | PromptTemplateType::Jinja => true,
+            PromptTemplateType::Custom(name) => {
+                custom_template(name).is_some_and(|template| template.system.is_some())
+            }
             PromptTemplateType::MistralInstruct
             | PromptTemplateType::MistralTool
             | PromptTemplateType::MistralLite
@@ -218,6 +236,7 @@ impl PromptTemplateType {
             | PromptTemplateType::SeedReasoning
             | PromptTemplateType::MoxinInstruct
             | PromptTemplateType::Smolvl
+            | PromptTemplateType::Auto
             | PromptTemplateType::Embedding
             | PromptTemplateType::Tts
             | PromptTemplateType::Null => false,
@@ -226,14 +245,19 @@ impl PromptTemplateType {
 
     /// Check if the prompt template supports image input.
     pub fn is_image_supported(&self) -> bool {
-        matches!(
-            self,
-            PromptTemplateType::MiniCPMV
-                | PromptTemplateType::Qwen2vl
-                | PromptTemplateType::VicunaLlava
-                | PromptTemplateType::Gemma3
-                | PromptTemplateType::Smolvl
-        )
+        match self {
+            PromptTemplateType::Custom(name) => {
+                custom_template(name).is_some_and(|template| template.image.is_some())
+            }
+            _ => matches!(
+                self,
+                PromptTemplateType::MiniCPMV
+                    | PromptTemplateType::Qwen2vl
+                    | PromptTemplateType::VicunaLlava
+                    | PromptTemplateType::Gemma3
+                    | PromptTemplateType::Smolvl
+            ),
+        }
     }
 }
 impl FromStr for PromptTemplateType {
@@ -305,12 +329,20 @@ impl FromStr for PromptTemplateType {
             "smol-vision" => Ok(PromptTemplateType::Smolvl),
             "smol3-no-think" => Ok(PromptTemplateType::Smol3NoThink),
             "gpt-oss" => Ok(PromptTemplateType::GptOss),
+            "jinja" => Ok(PromptTemplateType::Jinja),
+            "auto" => Ok(PromptTemplateType::Auto),
             "embedding" => Ok(PromptTemplateType::Embedding),
             "tts" => Ok(PromptTemplateType::Tts),
             "none" => Ok(PromptTemplateType::Null),
-            _ => Err(error::PromptError::UnknownPromptTemplateType(
-                template.to_string(),
-            )),
+            // the custom template may be registered after parsing the name
+            _ => match template.strip_prefix("custom:") {
+                Some(name) if !name.is_empty() => {
+                    Ok(PromptTemplateType::Custom(custom::intern_name(name)))
+                }
+                _ => Err(error::PromptError::UnknownPromptTemplateType(
+                    template.to_string(),
+                )),
+            },
         }
     }
 }
@@ -380,6 +412,9 @@ impl std::fmt::Display for PromptTemplateType {
             PromptTemplateType::Smolvl => write!(f, "smol-vision"),
             PromptTemplateType::Smol3NoThink => write!(f, "smol3-no-think"),
             PromptTemplateType::GptOss => write!(f, "gpt-oss"),
+            PromptTemplateType::Jinja => write!(f, "jinja"),
+            PromptTemplateType::Auto => write!(f, "auto"),
+            PromptTemplateType::Custom(name) => write!(f, "custom:{name}"),
             PromptTemplateType::Embedding => write!(f, "embedding"),
             PromptTemplateType::Tts => write!(f, "tts"),
             PromptTemplateType::Null => write!(f, "none"),
@@ -387,6 +422,46 @@ impl std::fmt::Display for PromptTemplateType {
     }
 }
 
+/// Parse the prompt template types given on the command line, which are the values of [`ValueEnum`] or `custom:<name>`.
+///
+/// Use it as the `value_parser` of the arguments instead of `clap::value_parser!(PromptTemplateType)`, which only accepts the values of [`ValueEnum`].
+#[derive(Clone, Debug, Default)]
+pub struct PromptTemplateTypeParser;
+impl clap::builder::TypedValueParser for PromptTemplateTypeParser {
+    type Value = PromptTemplateType;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> std::result::Result<Self::Value, clap::Error> {
+        match value
+            .to_str()
+            .and_then(|value| value.strip_prefix("custom:"))
+        {
+            Some(name) if !name.is_empty() => {
+                Ok(PromptTemplateType::Custom(custom::intern_name(name)))
+            }
+            _ => clap::builder::EnumValueParser::<PromptTemplateType>::new()
+                .parse_ref(cmd, arg, value),
+        }
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        let values = PromptTemplateType::value_variants()
+            .iter()
+            .filter_map(ValueEnum::to_possible_value)
+            .chain(std::iter::once(clap::builder::PossibleValue::new(
+                "custom:<name>",
+            )));
+
+        Some(Box::new(values))
+    }
+}
+
 #[enum_dispatch::enum_dispatch(BuildChatPrompt)]
 pub enum ChatPrompt {
     Llama2ChatPrompt,
@@ -453,6 +528,8 @@ pub enum ChatPrompt {
     SmolvlPrompt,
     Smol3NoThinkPrompt,
     GptOssPrompt,
+    JinjaPrompt,
+    CustomPrompt,
 }
 impl From<PromptTemplateType> for ChatPrompt {
     fn from(ty: PromptTemplateType) -> Self {
@@ -564,6 +641,13 @@ impl From<PromptTemplateType> for ChatPrompt {
                 ChatPrompt::SeedOssNoThinkPrompt(SeedOssNoThinkPrompt)
             }
             PromptTemplateType::GptOss => ChatPrompt::GptOssPrompt(GptOssPrompt),
+            // the chat template of the model is given by `JinjaPrompt::new`
+            PromptTemplateType::Jinja => ChatPrompt::JinjaPrompt(JinjaPrompt::default()),
+            // the custom template is looked up when the prompt is built
+            PromptTemplateType::Custom(name) => ChatPrompt::CustomPrompt(CustomPrompt::new(name)),
+            PromptTemplateType::Auto => {
+                panic!("Auto prompt template is replaced with the detected one when the model is loaded")
+            }
             PromptTemplateType::Embedding => {
                 panic!("Embedding prompt template is not used for building chat prompts")
             }
@@ -577,11 +661,14 @@ impl From<PromptTemplateType> for ChatPrompt {
     }
 }
 
+/// The default prompt put before the RAG context.
+const DEFAULT_RAG_PROMPT: &str = "Use the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------";
+
 /// Trait for merging RAG context into chat messages
 pub trait MergeRagContext: Send {
     /// Merge RAG context into chat messages.
     ///
-    /// Note that the default implementation simply merges the RAG context into the system message. That is, to use the default implementation, `has_system_prompt` should be set to `true` and `policy` set to `MergeRagContextPolicy::SystemMessage`.
+    /// Note that the default implementation merges all the chunks of the RAG context into the system message with the `MergeRagContextPolicy::SystemMessage` policy, or into the last user message with the `MergeRagContextPolicy::LastUserMessage` policy. If the chat template has no system prompt, the `MergeRagContextPolicy::LastUserMessage` policy is always used.
     ///
     /// # Arguments
     ///
@@ -592,6 +679,8 @@ pub trait MergeRagContext: Send {
     /// * `has_system_prompt` - Whether the chat template has a system prompt.
     ///
     /// * `policy` - The policy for merging RAG context into chat messages.
+    ///
+    /// * `rag_prompt` - The prompt put before the RAG context. If not set or empty, a default prompt is used.
     fn build(
         messages: &mut Vec<endpoints::chat::ChatCompletionRequestMessage>,
         context: &[String],
@@ -599,70 +688,126 @@ pub trait MergeRagContext: Send {
         policy: MergeRagContextPolicy,
         rag_prompt: Option<String>,
     ) -> error::Result<()> {
-        if (policy == MergeRagContextPolicy::SystemMessage) && has_system_prompt {
-            if messages.is_empty() {
-                return Err(error::PromptError::NoMessages);
-            }
-
-            if context.is_empty() {
-                return Err(error::PromptError::Operation(
-                    "No context provided.".to_string(),
-                ));
-            }
-
-            let context = context[0].trim_end();
-
-            // update or insert system message
-            match messages[0] {
-                ChatCompletionRequestMessage::System(ref message) => {
-                    // compose new system message content
-                    let content = match rag_prompt {
-                        Some(rag_prompt) if !rag_prompt.is_empty() => {
-                            format!(
-                                "{original_system_message}\n{rag_prompt}\n{context}",
-                                original_system_message = message.content().trim(),
-                                rag_prompt = rag_prompt.trim(),
-                                context = context.trim_end()
-                            )
-                        }
-                        _ => {
-                            format!("{original_system_message}\nUse the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------\n{context}", original_system_message=message.content().trim(), context=context.trim_end())
-                        }
-                    };
+        if messages.is_empty() {
+            return Err(error::PromptError::NoMessages);
+        }
 
-                    // create system message
-                    let system_message = ChatCompletionRequestMessage::new_system_message(
-                        content,
-                        messages[0].name().cloned(),
-                    );
+        if context.is_empty() {
+            return Err(error::PromptError::Operation(
+                "No context provided.".to_string(),
+            ));
+        }
 
-                    // replace the original system message
-                    messages[0] = system_message;
-                }
-                _ => {
-                    // compose new system message content
-                    let content = match rag_prompt {
-                        Some(rag_prompt) if !rag_prompt.is_empty() => {
-                            format!(
-                                "{rag_prompt}\n{context}",
-                                rag_prompt = rag_prompt.trim(),
-                                context = context.trim_end()
-                            )
-                        }
-                        _ => {
-                            format!("Use the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------\n{}", context.trim_end())
+        // the chunks of the context are separated by blank lines
+        let context = context
+            .iter()
+            .map(|chunk| chunk.trim())
+            .filter(|chunk| !chunk.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let rag_prompt = match rag_prompt {
+            Some(rag_prompt) if !rag_prompt.trim().is_empty() => rag_prompt.trim().to_string(),
+            _ => DEFAULT_RAG_PROMPT.to_string(),
+        };
+
+        // the chat templates without a system prompt get the context in the last user message
+        let policy = match has_system_prompt {
+            true => policy,
+            false => MergeRagContextPolicy::LastUserMessage,
+        };
+
+        match policy {
+            MergeRagContextPolicy::SystemMessage => {
+                // update or insert system message
+                match messages[0] {
+                    ChatCompletionRequestMessage::System(ref message) => {
+                        // compose new system message content
+                        let content = format!(
+                            "{original_system_message}\n{rag_prompt}\n{context}",
+                            original_system_message = message.content().trim(),
+                        );
+
+                        // create system message
+                        let system_message = ChatCompletionRequestMessage::new_system_message(
+                            content,
+                            messages[0].name().cloned(),
+                        );
+
+                        // replace the original system message
+                        messages[0] = system_message;
+                    }
+                    _ => {
+                        // compose new system message content
+                        let content = format!("{rag_prompt}\n{context}");
+
+                        // create system message
+                        let system_message = ChatCompletionRequestMessage::new_system_message(
+                            content,
+                            messages[0].name().cloned(),
+                        );
+                        // insert system message
+                        messages.insert(0, system_message);
+                    }
+                };
+            }
+            MergeRagContextPolicy::LastUserMessage => {
+                let (index, message) = messages
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .find_map(|(index, message)| match message {
+                        ChatCompletionRequestMessage::User(message) => Some((index, message)),
+                        _ => None,
+                    })
+                    .ok_or_else(|| {
+                        error::PromptError::BadMessages(
+                            "No user message to merge the RAG context into.".to_string(),
+                        )
+                    })?;
+
+                // put the context before the question of the user
+                let content = match message.content() {
+                    ChatCompletionUserMessageContent::Text(text) => {
+                        ChatCompletionUserMessageContent::Text(format!(
+                            "{rag_prompt}\n{context}\n\n{question}",
+                            question = text.trim()
+                        ))
+                    }
+                    ChatCompletionUserMessageContent::Parts(parts) => {
+                        let mut parts = parts.clone();
+                        match parts
+                            .iter()
+                            .position(|part| matches!(part, ContentPart::Text(_)))
+                        {
+                            Some(index) => {
+                                if let ContentPart::Text(part) = &parts[index] {
+                                    parts[index] =
+                                        ContentPart::Text(TextContentPart::new(format!(
+                                            "{rag_prompt}\n{context}\n\n{question}",
+                                            question = part.text().trim()
+                                        )));
+                                }
+                            }
+                            None => parts.insert(
+                                0,
+                                ContentPart::Text(TextContentPart::new(format!(
+                                    "{rag_prompt}\n{context}"
+                                ))),
+                            ),
                         }
-                    };
-
-                    // create system message
-                    let system_message = ChatCompletionRequestMessage::new_system_message(
-                        content,
-                        messages[0].name().cloned(),
-                    );
-                    // insert system message
-                    messages.insert(0, system_message);
-                }
-            };
+
+                        ChatCompletionUserMessageContent::Parts(parts)
+                    }
+                };
+
+                // replace the last user message
+                let user_message = ChatCompletionRequestMessage::new_user_message(
+                    content,
+                    message.name().cloned(),
+                );
+                messages[index] = user_message;
+            }
         }
 
         Ok(())
@@ -674,11 +819,11 @@ pub trait MergeRagContext: Send {
 pub enum MergeRagContextPolicy {
     /// Merge RAG context into the system message.
     ///
-    /// Note that this policy is only applicable when the chat template has a system message.
+    /// Note that this policy is only applicable when the chat template has a system message. Otherwise, the `LastUserMessage` policy is used.
     #[default]
     #[serde(rename = "system-message")]
     SystemMessage,
-    /// Merge RAG context into the last user message.
+    /// Merge RAG context into the last user message, before the question of the user.
     #[serde(rename = "last-user-message")]
     LastUserMessage,
 }
@@ -719,3 +864,194 @@ pub trait BuildChatPrompt: Send {
         self.build(messages)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use endpoints::chat::{Image, ImageContentPart};
+
+    struct RagPrompt;
+    impl MergeRagContext for RagPrompt {}
+
+    fn system(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_system_message(content, None)
+    }
+
+    fn user(content: ChatCompletionUserMessageContent) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_user_message(content, None)
+    }
+
+    fn text(content: &str) -> ChatCompletionUserMessageContent {
+        ChatCompletionUserMessageContent::Text(content.to_string())
+    }
+
+    fn assistant(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_assistant_message(Some(content.to_string()), None, None)
+    }
+
+    fn image() -> ContentPart {
+        ContentPart::Image(ImageContentPart::new(Image {
+            url: "https://example.com/image.png".to_string(),
+            detail: None,
+        }))
+    }
+
+    fn merge(
+        messages: &mut Vec<ChatCompletionRequestMessage>,
+        has_system_prompt: bool,
+        policy: MergeRagContextPolicy,
+    ) -> Result<()> {
+        let context = [
+            "Paris is the capital of France.\n".to_string(),
+            " ".to_string(),
+        ];
+        RagPrompt::build(
+            messages,
+            &context,
+            has_system_prompt,
+            policy,
+            Some("Context:".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_merge_rag_context_into_system_message() {
+        let mut messages = vec![system("You are a helpful assistant. "), user(text("Hi"))];
+        merge(&mut messages, true, MergeRagContextPolicy::SystemMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                system("You are a helpful assistant.\nContext:\nParis is the capital of France."),
+                user(text("Hi")),
+            ]
+        );
+
+        // the system message is inserted if there is none
+        let mut messages = vec![user(text("Hi"))];
+        merge(&mut messages, true, MergeRagContextPolicy::SystemMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                system("Context:\nParis is the capital of France."),
+                user(text("Hi")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_rag_context_into_last_user_message() {
+        let mut messages = vec![
+            system("You are a helpful assistant."),
+            user(text("Hi")),
+            assistant("Hello!"),
+            user(text(" What is the capital of France? ")),
+        ];
+        merge(&mut messages, true, MergeRagContextPolicy::LastUserMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                system("You are a helpful assistant."),
+                user(text("Hi")),
+                assistant("Hello!"),
+                user(text(
+                    "Context:\nParis is the capital of France.\n\nWhat is the capital of France?"
+                )),
+            ]
+        );
+
+        // the chat templates without a system prompt always get the context in the last user message
+        let mut messages = vec![user(text("What is the capital of France?"))];
+        merge(&mut messages, false, MergeRagContextPolicy::SystemMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![user(text(
+                "Context:\nParis is the capital of France.\n\nWhat is the capital of France?"
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_merge_rag_context_into_parts() {
+        // the context is put before the first text part
+        let mut messages = vec![user(ChatCompletionUserMessageContent::Parts(vec![
+            image(),
+            ContentPart::Text(TextContentPart::new("What is in the image?")),
+        ]))];
+        merge(&mut messages, true, MergeRagContextPolicy::LastUserMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![user(ChatCompletionUserMessageContent::Parts(vec![
+                image(),
+                ContentPart::Text(TextContentPart::new(
+                    "Context:\nParis is the capital of France.\n\nWhat is in the image?"
+                )),
+            ]))]
+        );
+
+        // a text part is inserted if there is none
+        let mut messages = vec![user(ChatCompletionUserMessageContent::Parts(vec![image()]))];
+        merge(&mut messages, true, MergeRagContextPolicy::LastUserMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![user(ChatCompletionUserMessageContent::Parts(vec![
+                ContentPart::Text(TextContentPart::new(
+                    "Context:\nParis is the capital of France."
+                )),
+                image(),
+            ]))]
+        );
+    }
+
+    #[test]
+    fn test_merge_rag_context_without_messages() {
+        // without a system prompt, the context falls back to the last user message, which is missing
+        let mut messages = vec![assistant("Hello!")];
+        assert!(matches!(
+            merge(&mut messages, false, MergeRagContextPolicy::SystemMessage),
+            Err(error::PromptError::BadMessages(_))
+        ));
+        assert_eq!(messages, vec![assistant("Hello!")]);
+
+        // with a system prompt, the system message is inserted even without a user message
+        merge(&mut messages, true, MergeRagContextPolicy::SystemMessage).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                system("Context:\nParis is the capital of France."),
+                assistant("Hello!"),
+            ]
+        );
+
+        assert!(matches!(
+            merge(&mut vec![], true, MergeRagContextPolicy::SystemMessage),
+            Err(error::PromptError::NoMessages)
+        ));
+        assert!(matches!(
+            RagPrompt::build(
+                &mut vec![user(text("Hi"))],
+                &[],
+                true,
+                MergeRagContextPolicy::SystemMessage,
+                None,
+            ),
+            Err(error::PromptError::Operation(_))
+        ));
+
+        // the default prompt is used if the prompt is empty
+        let mut messages = vec![user(text("Hi"))];
+        RagPrompt::build(
+            &mut messages,
+            &["Paris is the capital of France.".to_string()],
+            true,
+            MergeRagContextPolicy::SystemMessage,
+            Some(" ".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            messages[0],
+            system(&format!(
+                "{DEFAULT_RAG_PROMPT}\nParis is the capital of France."
+            ))
+        );
+    }
+}
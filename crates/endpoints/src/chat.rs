@@ -172,6 +172,26 @@ impl ChatCompletionRequestBuilder {
         self
     }
 
+    /// Enables returning log probabilities of the output tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - Whether to return log probabilities of the output tokens.
+    pub fn enable_logprobs(mut self, flag: bool) -> Self {
+        self.req.logprobs = Some(flag);
+        self
+    }
+
+    /// Sets the number of most likely tokens to return at each token position. Requires `logprobs` to be enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - An integer between 0 and 20.
+    pub fn with_top_logprobs(mut self, n: u8) -> Self {
+        self.req.top_logprobs = Some(n);
+        self
+    }
+
     /// Sets the user.
     ///
     /// # Arguments
@@ -212,6 +232,26 @@ impl ChatCompletionRequestBuilder {
         self
     }
 
+    /// Sets the truncation strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `truncation` - The strategy to shorten the chat history if the prompt does not fit in the context window.
+    pub fn with_truncation(mut self, truncation: TruncationStrategy) -> Self {
+        self.req.truncation = Some(truncation);
+        self
+    }
+
+    /// Sets whether to return the reasoning of thinking models.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - Whether to return the reasoning in the `reasoning_content` field of the message.
+    pub fn include_reasoning(mut self, flag: bool) -> Self {
+        self.req.include_reasoning = Some(flag);
+        self
+    }
+
     /// Builds the chat completion request.
     pub fn build(self) -> ChatCompletionRequest {
         self.req
@@ -273,6 +313,14 @@ pub struct ChatCompletionRequest {
     /// Defaults to None.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logit_bias: Option<HashMap<String, f64>>,
+    /// Whether to return log probabilities of the output tokens. If true, returns the log probabilities of each output token in the `logprobs` field of the choice.
+    /// Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// An integer between 0 and 20 specifying the number of most likely tokens to return at each token position, each with an associated log probability. `logprobs` must be set to `true` if this parameter is used.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
     /// A unique identifier representing your end-user.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
@@ -288,6 +336,13 @@ pub struct ChatCompletionRequest {
     /// Controls which (if any) function is called by the model.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<ToolChoice>,
+    /// Controls how the chat history is shortened if the prompt does not fit in the context window. Defaults to the truncation strategy of the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<TruncationStrategy>,
+    /// Whether to return the reasoning of thinking models in the `reasoning_content` field of the message. If `false`, the reasoning is dropped from the output. The reasoning tokens are counted in the usage either way.
+    /// Defaults to true.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_reasoning: Option<bool>,
 }
 #[allow(deprecated)]
 impl<'de> Deserialize<'de> for ChatCompletionRequest {
@@ -321,10 +376,14 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                 let mut presence_penalty = None;
                 let mut frequency_penalty = None;
                 let mut logit_bias = None;
+                let mut logprobs = None;
+                let mut top_logprobs = None;
                 let mut user = None;
                 let mut response_format = None;
                 let mut tools = None;
                 let mut tool_choice = None;
+                let mut truncation = None;
+                let mut include_reasoning = None;
 
                 while let Some(key) = map.next_key::<String>()? {
                     #[cfg(feature = "logging")]
@@ -343,10 +402,14 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                         "presence_penalty" => presence_penalty = map.next_value()?,
                         "frequency_penalty" => frequency_penalty = map.next_value()?,
                         "logit_bias" => logit_bias = map.next_value()?,
+                        "logprobs" => logprobs = map.next_value()?,
+                        "top_logprobs" => top_logprobs = map.next_value()?,
                         "user" => user = map.next_value()?,
                         "response_format" => response_format = map.next_value()?,
                         "tools" => tools = map.next_value()?,
                         "tool_choice" => tool_choice = map.next_value()?,
+                        "truncation" => truncation = map.next_value()?,
+                        "include_reasoning" => include_reasoning = map.next_value()?,
                         _ => {
                             // Ignore unknown fields
                             let _ = map.next_value::<IgnoredAny>()?;
@@ -393,10 +456,14 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                     presence_penalty,
                     frequency_penalty,
                     logit_bias,
+                    logprobs,
+                    top_logprobs,
                     user,
                     response_format,
                     tools,
                     tool_choice,
+                    truncation,
+                    include_reasoning,
                 })
             }
         }
@@ -414,10 +481,14 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
             "presence_penalty",
             "frequency_penalty",
             "logit_bias",
+            "logprobs",
+            "top_logprobs",
             "user",
             "response_format",
             "tools",
             "tool_choice",
+            "truncation",
+            "include_reasoning",
         ];
         deserializer.deserialize_struct(
             "ChatCompletionRequest",
@@ -443,10 +514,14 @@ impl Default for ChatCompletionRequest {
             presence_penalty: Some(0.0),
             frequency_penalty: Some(0.0),
             logit_bias: None,
+            logprobs: None,
+            top_logprobs: None,
             user: None,
             response_format: None,
             tools: None,
             tool_choice: None,
+            truncation: None,
+            include_reasoning: None,
         }
     }
 }
@@ -511,13 +586,15 @@ fn test_chat_serialize_chat_request() {
             .with_stop(vec!["stop1".to_string(), "stop2".to_string()])
             .with_presence_penalty(0.5)
             .with_frequency_penalty(0.5)
+            .enable_logprobs(true)
+            .with_top_logprobs(2)
             .with_reponse_format(ChatResponseFormat::default())
             .with_tool_choice(ToolChoice::Auto)
             .build();
         let json = serde_json::to_string(&request).unwrap();
         assert_eq!(
             json,
-            r#"{"model":"model-id","messages":[{"role":"system","content":"Hello, world!"},{"role":"user","content":"Hello, world!"},{"role":"assistant","content":"Hello, world!"}],"temperature":0.8,"top_p":1.0,"n":3,"stream":true,"stream_options":{"include_usage":true},"stop":["stop1","stop2"],"max_completion_tokens":2147483647,"presence_penalty":0.5,"frequency_penalty":0.5,"response_format":{"type":"text"},"tool_choice":"auto"}"#
+            r#"{"model":"model-id","messages":[{"role":"system","content":"Hello, world!"},{"role":"user","content":"Hello, world!"},{"role":"assistant","content":"Hello, world!"}],"temperature":0.8,"top_p":1.0,"n":3,"stream":true,"stream_options":{"include_usage":true},"stop":["stop1","stop2"],"max_completion_tokens":2147483647,"presence_penalty":0.5,"frequency_penalty":0.5,"logprobs":true,"top_logprobs":2,"response_format":{"type":"text"},"tool_choice":"auto"}"#
         );
     }
 
@@ -729,11 +806,13 @@ fn test_chat_deserialize_chat_request() {
         assert_eq!(request.max_completion_tokens, Some(i32::MAX));
         assert_eq!(request.presence_penalty, Some(0.5));
         assert_eq!(request.frequency_penalty, Some(0.5));
+        assert_eq!(request.logprobs, None);
+        assert_eq!(request.top_logprobs, None);
         assert_eq!(request.tool_choice, None);
     }
 
     {
-        let json = r#"{"model":"model-id","messages":[{"role":"system","content":"Hello, world!"},{"role":"user","content":"Hello, world!"},{"role":"assistant","content":"Hello, world!"}],"temperature":0.8,"top_p":1.0,"n":3,"stream":true,"stop":["stop1","stop2"],"max_completion_tokens":100,"presence_penalty":0.5,"frequency_penalty":0.5,"response_format":{"type":"text"},"tool_choice":"auto"}"#;
+        let json = r#"{"model":"model-id","messages":[{"role":"system","content":"Hello, world!"},{"role":"user","content":"Hello, world!"},{"role":"assistant","content":"Hello, world!"}],"temperature":0.8,"top_p":1.0,"n":3,"stream":true,"stop":["stop1","stop2"],"max_completion_tokens":100,"presence_penalty":0.5,"frequency_penalty":0.5,"logprobs":true,"top_logprobs":3,"response_format":{"type":"text"},"tool_choice":"auto"}"#;
         let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
         assert_eq!(request.model, Some("model-id".to_string()));
         assert_eq!(request.messages.len(), 3);
@@ -748,6 +827,8 @@ fn test_chat_deserialize_chat_request() {
         assert_eq!(request.max_completion_tokens, Some(100));
         assert_eq!(request.presence_penalty, Some(0.5));
         assert_eq!(request.frequency_penalty, Some(0.5));
+        assert_eq!(request.logprobs, Some(true));
+        assert_eq!(request.top_logprobs, Some(3));
         assert_eq!(request.tool_choice, Some(ToolChoice::Auto));
     }
 
@@ -879,14 +960,18 @@ fn test_chat_deserialize_chat_request() {
 /// An object specifying the format that the model must output.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatResponseFormat {
-    /// Must be one of `text`` or `json_object`. Defaults to `text`.
+    /// Must be one of `text`, `json_object` or `json_schema`. Defaults to `text`.
     #[serde(rename = "type")]
     pub ty: String,
+    /// The JSON schema the output must conform to. Only used if `type` is `json_schema`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<JsonSchemaFormat>,
 }
 impl Default for ChatResponseFormat {
     fn default() -> Self {
         Self {
             ty: "text".to_string(),
+            json_schema: None,
         }
     }
 }
@@ -895,15 +980,68 @@ impl Default for ChatResponseFormat {
 fn test_chat_serialize_response_format() {
     let response_format = ChatResponseFormat {
         ty: "text".to_string(),
+        json_schema: None,
     };
     let json = serde_json::to_string(&response_format).unwrap();
     assert_eq!(json, r#"{"type":"text"}"#);
 
     let response_format = ChatResponseFormat {
         ty: "json_object".to_string(),
+        json_schema: None,
     };
     let json = serde_json::to_string(&response_format).unwrap();
     assert_eq!(json, r#"{"type":"json_object"}"#);
+
+    let schema: JsonObject =
+        serde_json::from_str(r#"{"type":"object","properties":{"name":{"type":"string"}}}"#)
+            .unwrap();
+    let response_format = ChatResponseFormat {
+        ty: "json_schema".to_string(),
+        json_schema: Some(JsonSchemaFormat {
+            name: "person".to_string(),
+            description: None,
+            schema: Some(schema),
+            strict: Some(true),
+        }),
+    };
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"json_schema","json_schema":{"name":"person","schema":{"properties":{"name":{"type":"string"}},"type":"object"},"strict":true}}"#
+    );
+}
+
+#[test]
+fn test_chat_deserialize_response_format() {
+    let json = r#"{"type":"text"}"#;
+    let response_format: ChatResponseFormat = serde_json::from_str(json).unwrap();
+    assert_eq!(response_format.ty, "text");
+    assert!(response_format.json_schema.is_none());
+
+    let json = r#"{"type":"json_schema","json_schema":{"name":"person","description":"A person","schema":{"type":"object","properties":{"name":{"type":"string"}},"required":["name"]}}}"#;
+    let response_format: ChatResponseFormat = serde_json::from_str(json).unwrap();
+    assert_eq!(response_format.ty, "json_schema");
+    let json_schema = response_format.json_schema.unwrap();
+    assert_eq!(json_schema.name, "person");
+    assert_eq!(json_schema.description, Some("A person".to_string()));
+    assert!(json_schema.schema.unwrap().contains_key("required"));
+    assert!(json_schema.strict.is_none());
+}
+
+/// Describes the JSON schema of a `json_schema` response format.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JsonSchemaFormat {
+    /// The name of the response format. Must be a-z, A-Z, 0-9, or contain underscores and dashes, with a maximum length of 64.
+    pub name: String,
+    /// A description of what the response format is for, used by the model to determine how to respond in the format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The schema for the response format, described as a JSON Schema object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<JsonObject>,
+    /// Whether to enable strict schema adherence when generating the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 /// Options for streaming response. Only set this when you set stream: `true`.
@@ -913,6 +1051,113 @@ pub struct StreamOptions {
     pub include_usage: Option<bool>,
 }
 
+/// Controls how the chat history is shortened if the prompt does not fit in the context window of the model. The messages of the latest turn are always kept. Defaults to `auto`.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TruncationStrategy {
+    /// Drops the oldest turns until the prompt fits. The system message is kept.
+    #[serde(rename = "auto")]
+    #[default]
+    Auto,
+    /// Drops the turns in the middle of the chat history until the prompt fits, so that the first and the latest turns are kept. The system message is kept.
+    #[serde(rename = "middle_out")]
+    MiddleOut,
+    /// Replaces the oldest turns that do not fit with a summary generated by the same model. The summary is appended to the system message.
+    #[serde(rename = "summarize")]
+    Summarize,
+    /// Never drops messages. The requests of which the prompt does not fit fail.
+    #[serde(rename = "disabled")]
+    Disabled,
+    /// Keeps the system message and at most the given number of the latest turns, then drops the oldest turns until the prompt fits.
+    #[serde(untagged)]
+    LastTurns {
+        /// The maximum number of turns to keep, including the latest turn.
+        last_turns: usize,
+    },
+}
+impl std::str::FromStr for TruncationStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(TruncationStrategy::Auto),
+            "middle_out" | "middle-out" => Ok(TruncationStrategy::MiddleOut),
+            "summarize" => Ok(TruncationStrategy::Summarize),
+            "disabled" => Ok(TruncationStrategy::Disabled),
+            s => match s
+                .strip_prefix("last_turns:")
+                .or_else(|| s.strip_prefix("last-turns:"))
+                .map(str::parse::<usize>)
+            {
+                Some(Ok(last_turns)) if last_turns > 0 => {
+                    Ok(TruncationStrategy::LastTurns { last_turns })
+                }
+                _ => Err(format!(
+                    "Invalid truncation strategy: {s}. Possible values: auto, middle_out, summarize, disabled, last_turns:<N> with N > 0"
+                )),
+            },
+        }
+    }
+}
+impl fmt::Display for TruncationStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TruncationStrategy::Auto => write!(f, "auto"),
+            TruncationStrategy::MiddleOut => write!(f, "middle_out"),
+            TruncationStrategy::Summarize => write!(f, "summarize"),
+            TruncationStrategy::Disabled => write!(f, "disabled"),
+            TruncationStrategy::LastTurns { last_turns } => write!(f, "last_turns:{last_turns}"),
+        }
+    }
+}
+
+#[test]
+fn test_chat_serialize_truncation_strategy() {
+    let json = serde_json::to_string(&TruncationStrategy::MiddleOut).unwrap();
+    assert_eq!(json, r#""middle_out""#);
+
+    let json = serde_json::to_string(&TruncationStrategy::LastTurns { last_turns: 4 }).unwrap();
+    assert_eq!(json, r#"{"last_turns":4}"#);
+}
+
+#[test]
+fn test_chat_deserialize_truncation_strategy() {
+    let strategy: TruncationStrategy = serde_json::from_str(r#""summarize""#).unwrap();
+    assert_eq!(strategy, TruncationStrategy::Summarize);
+
+    let strategy: TruncationStrategy = serde_json::from_str(r#"{"last_turns":2}"#).unwrap();
+    assert_eq!(strategy, TruncationStrategy::LastTurns { last_turns: 2 });
+
+    assert!(serde_json::from_str::<TruncationStrategy>(r#""truncate""#).is_err());
+
+    let json = r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"truncation":"middle_out"}"#;
+    let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.truncation, Some(TruncationStrategy::MiddleOut));
+
+    let json = r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}]}"#;
+    let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.truncation, None);
+}
+
+#[test]
+fn test_chat_parse_truncation_strategy() {
+    assert_eq!(
+        "disabled".parse::<TruncationStrategy>(),
+        Ok(TruncationStrategy::Disabled)
+    );
+    assert_eq!(
+        "last_turns:3".parse::<TruncationStrategy>(),
+        Ok(TruncationStrategy::LastTurns { last_turns: 3 })
+    );
+    assert!("last_turns:0".parse::<TruncationStrategy>().is_err());
+    assert!("middle".parse::<TruncationStrategy>().is_err());
+
+    let strategy = TruncationStrategy::LastTurns { last_turns: 3 };
+    assert_eq!(
+        strategy.to_string().parse::<TruncationStrategy>(),
+        Ok(strategy)
+    );
+}
+
 /// Controls which (if any) function is called by the model. Defaults to `None`.
 #[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub enum ToolChoice {
@@ -2820,6 +3065,7 @@ fn test_serialize_chat_completion_object_choice() {
     };
     let message = ChatCompletionObjectMessage {
         content: None,
+        reasoning_content: None,
         tool_calls: vec![tool],
         role: ChatCompletionRole::Assistant,
         function_call: None,
@@ -2873,6 +3119,9 @@ pub struct TopLogProb {
 pub struct ChatCompletionObjectMessage {
     /// The contents of the message.
     pub content: Option<String>,
+    /// The reasoning of thinking models, generated before the contents of the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
     /// The tool calls generated by the model, such as function calls.
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub tool_calls: Vec<ToolCall>,
@@ -2901,6 +3150,7 @@ impl<'de> Deserialize<'de> for ChatCompletionObjectMessage {
                 V: MapAccess<'de>,
             {
                 let mut content = None;
+                let mut reasoning_content = None;
                 let mut tool_calls = None;
                 let mut role = None;
                 let mut function_call = None;
@@ -2908,6 +3158,7 @@ impl<'de> Deserialize<'de> for ChatCompletionObjectMessage {
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_str() {
                         "content" => content = map.next_value()?,
+                        "reasoning_content" => reasoning_content = map.next_value()?,
                         "tool_calls" => tool_calls = map.next_value()?,
                         "role" => role = map.next_value()?,
                         "function_call" => function_call = map.next_value()?,
@@ -2928,6 +3179,7 @@ impl<'de> Deserialize<'de> for ChatCompletionObjectMessage {
 
                 Ok(ChatCompletionObjectMessage {
                     content,
+                    reasoning_content,
                     tool_calls,
                     role,
                     function_call,
@@ -2935,7 +3187,13 @@ impl<'de> Deserialize<'de> for ChatCompletionObjectMessage {
             }
         }
 
-        const FIELDS: &[&str] = &["content", "tool_calls", "role", "function_call"];
+        const FIELDS: &[&str] = &[
+            "content",
+            "reasoning_content",
+            "tool_calls",
+            "role",
+            "function_call",
+        ];
         deserializer.deserialize_struct(
             "ChatCompletionObjectMessage",
             FIELDS,
@@ -2956,6 +3214,7 @@ fn test_serialize_chat_completion_object_message() {
     };
     let message = ChatCompletionObjectMessage {
         content: None,
+        reasoning_content: None,
         tool_calls: vec![tool],
         role: ChatCompletionRole::Assistant,
         function_call: None,
@@ -2984,6 +3243,17 @@ fn test_deserialize_chat_completion_object_message() {
         assert!(message.tool_calls.is_empty());
         assert_eq!(message.role, ChatCompletionRole::Assistant);
     }
+
+    {
+        let json = r#"{"content":"Paris.","reasoning_content":"The user asks for the capital of France.","role":"assistant"}"#;
+        let message: ChatCompletionObjectMessage = serde_json::from_str(json).unwrap();
+        assert_eq!(message.content, Some("Paris.".to_string()));
+        assert_eq!(
+            message.reasoning_content,
+            Some("The user asks for the capital of France.".to_string())
+        );
+        assert_eq!(serde_json::to_string(&message).unwrap(), json);
+    }
 }
 
 /// The name and arguments of a function that should be called, as generated by the model.
@@ -3026,6 +3296,7 @@ fn test_serialize_chat_completion_chunk() {
             index: 0,
             delta: ChatCompletionChunkChoiceDelta {
                 content: Some(".".to_owned()),
+                reasoning_content: None,
                 tool_calls: vec![],
                 role: ChatCompletionRole::Assistant,
             },
@@ -3094,6 +3365,9 @@ pub struct ChatCompletionChunkChoice {
 pub struct ChatCompletionChunkChoiceDelta {
     /// The contents of the chunk message.
     pub content: Option<String>,
+    /// The reasoning of thinking models in the chunk message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
     /// The name and arguments of a function that should be called, as generated by the model.
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub tool_calls: Vec<ToolCallForChunk>,
@@ -3119,12 +3393,14 @@ impl<'de> Deserialize<'de> for ChatCompletionChunkChoiceDelta {
                 V: MapAccess<'de>,
             {
                 let mut content = None;
+                let mut reasoning_content = None;
                 let mut tool_calls = None;
                 let mut role = None;
 
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_str() {
                         "content" => content = map.next_value()?,
+                        "reasoning_content" => reasoning_content = map.next_value()?,
                         "tool_calls" => tool_calls = map.next_value()?,
                         "role" => role = map.next_value()?,
                         _ => {
@@ -3142,13 +3418,14 @@ impl<'de> Deserialize<'de> for ChatCompletionChunkChoiceDelta {
                 let role = role.ok_or_else(|| de::Error::missing_field("role"))?;
                 Ok(ChatCompletionChunkChoiceDelta {
                     content,
+                    reasoning_content,
                     tool_calls,
                     role,
                 })
             }
         }
 
-        const FIELDS: &[&str] = &["content", "tool_calls", "role"];
+        const FIELDS: &[&str] = &["content", "reasoning_content", "tool_calls", "role"];
         deserializer.deserialize_struct(
             "ChatCompletionChunkChoiceDelta",
             FIELDS,
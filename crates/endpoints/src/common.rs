@@ -17,6 +17,29 @@ pub struct Usage {
     pub completion_tokens: u64,
     /// Total number of tokens used in the request (prompt + completion).
     pub total_tokens: u64,
+    /// Breakdown of the tokens in the prompt. Present only if the prompt cache of the model is enabled.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    /// Breakdown of the tokens in the completion. Present only if the prompt template of the model separates the reasoning of thinking models.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+    /// Number of messages dropped from the chat history to fit the prompt in the context window. Present only if messages were dropped.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub truncated_messages: Option<u64>,
+}
+
+/// Breakdown of the tokens in the prompt.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PromptTokensDetails {
+    /// Number of prompt tokens reused from the KV cache of the previous request on the same slot of the model instead of being evaluated again.
+    pub cached_tokens: u64,
+}
+
+/// Breakdown of the tokens in the completion.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CompletionTokensDetails {
+    /// Number of tokens generated by the model for reasoning, including the markers around the reasoning.
+    pub reasoning_tokens: u64,
 }
 
 /// The reason the model stopped generating tokens.
@@ -30,3 +53,54 @@ pub enum FinishReason {
     /// `tool_calls` if the model called a tool.
     tool_calls,
 }
+
+#[test]
+fn test_serialize_usage() {
+    {
+        let usage = Usage {
+            prompt_tokens: 9,
+            completion_tokens: 12,
+            total_tokens: 21,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&usage).unwrap();
+        assert_eq!(
+            json,
+            r#"{"prompt_tokens":9,"completion_tokens":12,"total_tokens":21}"#
+        );
+    }
+
+    {
+        let usage = Usage {
+            prompt_tokens: 1200,
+            completion_tokens: 12,
+            total_tokens: 1212,
+            prompt_tokens_details: Some(PromptTokensDetails {
+                cached_tokens: 1024,
+            }),
+            completion_tokens_details: Some(CompletionTokensDetails {
+                reasoning_tokens: 8,
+            }),
+            truncated_messages: None,
+        };
+        let json = serde_json::to_string(&usage).unwrap();
+        assert_eq!(
+            json,
+            r#"{"prompt_tokens":1200,"completion_tokens":12,"total_tokens":1212,"prompt_tokens_details":{"cached_tokens":1024},"completion_tokens_details":{"reasoning_tokens":8}}"#
+        );
+
+        let usage: Usage = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            usage.prompt_tokens_details,
+            Some(PromptTokensDetails {
+                cached_tokens: 1024
+            })
+        );
+        assert_eq!(
+            usage.completion_tokens_details,
+            Some(CompletionTokensDetails {
+                reasoning_tokens: 8
+            })
+        );
+    }
+}
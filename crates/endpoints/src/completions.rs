@@ -204,8 +204,6 @@ pub enum CompletionPrompt {
 }
 
 /// Represents a completion response from the API.
-///
-/// Note: both the streamed and non-streamed response objects share the same shape (unlike the chat endpoint).
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CompletionObject {
     /// A unique identifier for the completion.
@@ -233,6 +231,92 @@ pub struct CompletionChoice {
     pub text: String,
 }
 
+/// Represents a streamed chunk of a completion response returned by the model.
+///
+/// The chunks have the same shape as [`CompletionObject`] except that the finish reason is only set in the last chunk, and there are no usage statistics.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompletionChunk {
+    /// A unique identifier for the completion. Each chunk has the same ID.
+    pub id: String,
+    /// The list of completion choices the model generated for the input prompt.
+    pub choices: Vec<CompletionChunkChoice>,
+    /// The Unix timestamp (in seconds) of when the completion was created. Each chunk has the same timestamp.
+    pub created: u64,
+    /// The model used for completion.
+    pub model: String,
+    /// The object type, which is always "text_completion".
+    pub object: String,
+}
+
+#[test]
+fn test_serialize_completion_chunk() {
+    {
+        let chunk = CompletionChunk {
+            id: "cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a".to_string(),
+            choices: vec![CompletionChunkChoice {
+                finish_reason: None,
+                index: 0,
+                logprobs: None,
+                text: " there".to_string(),
+            }],
+            created: 1722433423,
+            model: "default".to_string(),
+            object: "text_completion".to_string(),
+        };
+
+        let actual = serde_json::to_string(&chunk).unwrap();
+        let expected = r#"{"id":"cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a","choices":[{"finish_reason":null,"index":0,"logprobs":null,"text":" there"}],"created":1722433423,"model":"default","object":"text_completion"}"#;
+        assert_eq!(actual, expected);
+    }
+
+    {
+        let chunk = CompletionChunk {
+            id: "cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a".to_string(),
+            choices: vec![CompletionChunkChoice {
+                finish_reason: Some(FinishReason::length),
+                index: 0,
+                logprobs: None,
+                text: "".to_string(),
+            }],
+            created: 1722433423,
+            model: "default".to_string(),
+            object: "text_completion".to_string(),
+        };
+
+        let actual = serde_json::to_string(&chunk).unwrap();
+        let expected = r#"{"id":"cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a","choices":[{"finish_reason":"length","index":0,"logprobs":null,"text":""}],"created":1722433423,"model":"default","object":"text_completion"}"#;
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn test_deserialize_completion_chunk() {
+    let json = r#"{"id":"cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a","choices":[{"finish_reason":"stop","index":0,"logprobs":null,"text":"."}],"created":1722433423,"model":"default","object":"text_completion"}"#;
+    let chunk: CompletionChunk = serde_json::from_str(json).unwrap();
+    assert_eq!(chunk.id, "cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a");
+    assert_eq!(chunk.choices.len(), 1);
+    assert_eq!(chunk.choices[0].finish_reason, Some(FinishReason::stop));
+    assert_eq!(chunk.choices[0].index, 0);
+    assert!(chunk.choices[0].logprobs.is_none());
+    assert_eq!(chunk.choices[0].text, ".");
+    assert_eq!(chunk.created, 1722433423);
+    assert_eq!(chunk.model, "default");
+    assert_eq!(chunk.object, "text_completion");
+}
+
+/// Represents a choice in a streamed chunk of a completion response.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompletionChunkChoice {
+    /// The reason the model stopped generating tokens. This will be `stop` if the model hit a natural stop point or a provided stop sequence, or `length` if the maximum number of tokens specified in the request was reached. It is only set in the last chunk.
+    pub finish_reason: Option<FinishReason>,
+    /// The index of the choice in the list of choices.
+    pub index: u32,
+    /// The log probabilities of the tokens in the chunk.
+    pub logprobs: Option<LogprobResult>,
+    /// The text generated since the previous chunk.
+    pub text: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LogprobResult {
     pub tokens: Vec<String>,
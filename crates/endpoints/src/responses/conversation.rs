@@ -146,15 +146,7 @@ pub enum ConversationItem {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum ConversationMessageContent {
-    /// A text input to the model.
-    InputText {
-        /// The text input to the model.
-        text: String,
-        /// The type of the input item. Always `input_text`.
-        #[serde(rename = "type")]
-        ty: String,
-    },
-    /// A text output from the model.
+    /// A text output from the model. It is tried before `InputText`, which would also match it and drop the annotations.
     OutputText {
         /// The annotations of the text output.
         annotations: Vec<Annotation>,
@@ -165,6 +157,14 @@ pub enum ConversationMessageContent {
         ty: String,
         // TODO: Add `logprobs` field
     },
+    /// A text input to the model.
+    InputText {
+        /// The text input to the model.
+        text: String,
+        /// The type of the input item. Always `input_text`.
+        #[serde(rename = "type")]
+        ty: String,
+    },
     /// A text content.
     TextContent {
         text: String,
@@ -246,6 +246,33 @@ pub struct McpTool {
     pub description: String,
 }
 
+/// Represents a request body to update a conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestOfUpdateConversation {
+    /// Set of 16 key-value pairs that can be attached to an object. This can be useful for storing additional information about the object in a structured format, and querying for objects via API or the dashboard.
+    ///
+    /// Keys are strings with a maximum length of 64 characters. Values are strings with a maximum length of 512 characters.
+    pub metadata: HashMap<String, String>,
+}
+
+/// Represents a request body to add items to a conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestOfCreateConversationItems {
+    /// The items to add to the conversation. You may add up to 20 items at a time.
+    pub items: Vec<InputItem>,
+}
+
+/// Represents the status of a conversation deletion operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteConversationStatus {
+    /// The unique ID of the conversation.
+    pub id: String,
+    /// The object type, which is always `conversation.deleted`.
+    pub object: String,
+    /// The status of the deletion operation.
+    pub deleted: bool,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -749,4 +776,88 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_conversation_output_text_item_round_trip() {
+        let item = ConversationItem::Message {
+            content: ConversationMessageContent::OutputText {
+                annotations: vec![],
+                text: "Hi! How can I help you?".to_string(),
+                ty: "output_text".to_string(),
+            },
+            id: "msg_def".to_string(),
+            role: "assistant".to_string(),
+            status: Some("completed".to_string()),
+            ty: "message".to_string(),
+        };
+
+        let json = serde_json::to_string(&item).unwrap();
+        assert_eq!(
+            json,
+            r#"{"content":{"annotations":[],"text":"Hi! How can I help you?","type":"output_text"},"id":"msg_def","role":"assistant","status":"completed","type":"message"}"#
+        );
+
+        // the annotations are kept after the round trip
+        let item: ConversationItem = serde_json::from_str(&json).unwrap();
+        match item {
+            ConversationItem::Message { content, .. } => {
+                assert!(matches!(
+                    content,
+                    ConversationMessageContent::OutputText { .. }
+                ));
+            }
+            _ => panic!("Expected a message item"),
+        }
+
+        // the input text does not match the output text
+        let json = r#"{"content":{"text":"Hello!","type":"input_text"},"id":"msg_abc","role":"user","type":"message"}"#;
+        let item: ConversationItem = serde_json::from_str(json).unwrap();
+        match item {
+            ConversationItem::Message { content, .. } => {
+                assert!(matches!(
+                    content,
+                    ConversationMessageContent::InputText { .. }
+                ));
+            }
+            _ => panic!("Expected a message item"),
+        }
+    }
+
+    #[test]
+    fn test_request_of_create_conversation_items_deserialization() {
+        let json = r#"{
+  "items": [
+    {
+      "type": "message",
+      "role": "user",
+      "content": "Hello!"
+    }
+  ]
+}"#;
+
+        let request: RequestOfCreateConversationItems = serde_json::from_str(json).unwrap();
+        assert_eq!(request.items.len(), 1);
+        match &request.items[0] {
+            InputItem::InputMessage { content, role, .. } => {
+                assert_eq!(role, "user");
+                assert!(matches!(content, InputMessageContent::Text(text) if text == "Hello!"));
+            }
+            _ => panic!("Expected an input message"),
+        }
+    }
+
+    #[test]
+    fn test_delete_conversation_status_serialization() {
+        let status = DeleteConversationStatus {
+            id: "conv_123".to_string(),
+            object: "conversation.deleted".to_string(),
+            deleted: true,
+        };
+
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":"conv_123","object":"conversation.deleted","deleted":true}"#
+        );
+    }
 }
@@ -0,0 +1,300 @@
+//! Define the events of a response stream.
+//!
+//! A response generated in the stream mode is sent as a sequence of server-sent events. The name of each event is its `type`, and the data is the JSON of the event.
+
+use super::{
+    items::{LogProb, ResponseOutputItem, ResponseOutputItemOutputMessageContent},
+    response_object::ResponseObject,
+};
+use serde::{Deserialize, Serialize};
+
+/// Represents an event emitted when a response is streamed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponseStreamEvent {
+    /// The response is created.
+    #[serde(rename = "response.created")]
+    Created {
+        /// The response that was created.
+        response: ResponseObject,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// The response is in progress.
+    #[serde(rename = "response.in_progress")]
+    InProgress {
+        /// The response that is in progress.
+        response: ResponseObject,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// The response is completed.
+    #[serde(rename = "response.completed")]
+    Completed {
+        /// The completed response.
+        response: ResponseObject,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// The response failed.
+    #[serde(rename = "response.failed")]
+    Failed {
+        /// The response that failed.
+        response: ResponseObject,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// The response finished as incomplete.
+    #[serde(rename = "response.incomplete")]
+    Incomplete {
+        /// The response that was incomplete.
+        response: ResponseObject,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// A new output item is added.
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded {
+        /// The index of the output item.
+        output_index: u32,
+        /// The output item that was added.
+        item: ResponseOutputItem,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// An output item is done.
+    #[serde(rename = "response.output_item.done")]
+    OutputItemDone {
+        /// The index of the output item.
+        output_index: u32,
+        /// The output item that was done.
+        item: ResponseOutputItem,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// A new content part of an output message is added.
+    #[serde(rename = "response.content_part.added")]
+    ContentPartAdded {
+        /// The ID of the output message.
+        item_id: String,
+        /// The index of the output message.
+        output_index: u32,
+        /// The index of the content part.
+        content_index: u32,
+        /// The content part that was added.
+        part: ResponseOutputItemOutputMessageContent,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// A content part of an output message is done.
+    #[serde(rename = "response.content_part.done")]
+    ContentPartDone {
+        /// The ID of the output message.
+        item_id: String,
+        /// The index of the output message.
+        output_index: u32,
+        /// The index of the content part.
+        content_index: u32,
+        /// The content part that was done.
+        part: ResponseOutputItemOutputMessageContent,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// A piece of the text of a content part is generated.
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta {
+        /// The ID of the output message.
+        item_id: String,
+        /// The index of the output message.
+        output_index: u32,
+        /// The index of the content part.
+        content_index: u32,
+        /// The text that was added.
+        delta: String,
+        /// The log probabilities of the tokens in the delta.
+        #[serde(default)]
+        logprobs: Vec<LogProb>,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// The text of a content part is done.
+    #[serde(rename = "response.output_text.done")]
+    OutputTextDone {
+        /// The ID of the output message.
+        item_id: String,
+        /// The index of the output message.
+        output_index: u32,
+        /// The index of the content part.
+        content_index: u32,
+        /// The whole text of the content part.
+        text: String,
+        /// The log probabilities of the tokens in the text.
+        #[serde(default)]
+        logprobs: Vec<LogProb>,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// A piece of the arguments of a function call is generated.
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta {
+        /// The ID of the function call item.
+        item_id: String,
+        /// The index of the function call item.
+        output_index: u32,
+        /// The arguments that were added.
+        delta: String,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// The arguments of a function call are done.
+    #[serde(rename = "response.function_call_arguments.done")]
+    FunctionCallArgumentsDone {
+        /// The ID of the function call item.
+        item_id: String,
+        /// The index of the function call item.
+        output_index: u32,
+        /// The whole arguments of the function call.
+        arguments: String,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+    /// An error occurred.
+    #[serde(rename = "error")]
+    Error {
+        /// The error code.
+        code: Option<String>,
+        /// The error message.
+        message: String,
+        /// The parameter of the request causing the error.
+        param: Option<String>,
+        /// The sequence number of the event.
+        sequence_number: u64,
+    },
+}
+impl ResponseStreamEvent {
+    /// Returns the type of the event, which is also the name of the server-sent event.
+    pub fn ty(&self) -> &'static str {
+        match self {
+            ResponseStreamEvent::Created { .. } => "response.created",
+            ResponseStreamEvent::InProgress { .. } => "response.in_progress",
+            ResponseStreamEvent::Completed { .. } => "response.completed",
+            ResponseStreamEvent::Failed { .. } => "response.failed",
+            ResponseStreamEvent::Incomplete { .. } => "response.incomplete",
+            ResponseStreamEvent::OutputItemAdded { .. } => "response.output_item.added",
+            ResponseStreamEvent::OutputItemDone { .. } => "response.output_item.done",
+            ResponseStreamEvent::ContentPartAdded { .. } => "response.content_part.added",
+            ResponseStreamEvent::ContentPartDone { .. } => "response.content_part.done",
+            ResponseStreamEvent::OutputTextDelta { .. } => "response.output_text.delta",
+            ResponseStreamEvent::OutputTextDone { .. } => "response.output_text.done",
+            ResponseStreamEvent::FunctionCallArgumentsDelta { .. } => {
+                "response.function_call_arguments.delta"
+            }
+            ResponseStreamEvent::FunctionCallArgumentsDone { .. } => {
+                "response.function_call_arguments.done"
+            }
+            ResponseStreamEvent::Error { .. } => "error",
+        }
+    }
+
+    /// Returns the sequence number of the event.
+    pub fn sequence_number(&self) -> u64 {
+        match self {
+            ResponseStreamEvent::Created {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::InProgress {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::Completed {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::Failed {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::Incomplete {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::OutputItemAdded {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::OutputItemDone {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::ContentPartAdded {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::ContentPartDone {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::OutputTextDelta {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::OutputTextDone {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::FunctionCallArgumentsDelta {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::FunctionCallArgumentsDone {
+                sequence_number, ..
+            }
+            | ResponseStreamEvent::Error {
+                sequence_number, ..
+            } => *sequence_number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_output_text_delta() {
+        let event = ResponseStreamEvent::OutputTextDelta {
+            item_id: "msg_123".to_string(),
+            output_index: 0,
+            content_index: 0,
+            delta: "Hello".to_string(),
+            logprobs: vec![],
+            sequence_number: 4,
+        };
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"response.output_text.delta","item_id":"msg_123","output_index":0,"content_index":0,"delta":"Hello","logprobs":[],"sequence_number":4}"#
+        );
+        assert_eq!(event.ty(), "response.output_text.delta");
+        assert_eq!(event.sequence_number(), 4);
+    }
+
+    #[test]
+    fn test_deserialize_function_call_events() {
+        let json = r#"{"type":"response.output_item.added","output_index":1,"item":{"arguments":"","call_id":"call_123","id":"fc_123","name":"get_weather","type":"function_call","status":"in_progress"},"sequence_number":7}"#;
+        let event: ResponseStreamEvent = serde_json::from_str(json).unwrap();
+        match event {
+            ResponseStreamEvent::OutputItemAdded {
+                output_index,
+                item: ResponseOutputItem::FunctionCall { call_id, name, .. },
+                sequence_number,
+            } => {
+                assert_eq!(output_index, 1);
+                assert_eq!(call_id, "call_123");
+                assert_eq!(name, "get_weather");
+                assert_eq!(sequence_number, 7);
+            }
+            _ => panic!("Expected an output_item.added event with a function call"),
+        }
+
+        let json = r#"{"type":"response.function_call_arguments.done","item_id":"fc_123","output_index":1,"arguments":"{\"location\":\"Paris\"}","sequence_number":9}"#;
+        let event: ResponseStreamEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(event.ty(), "response.function_call_arguments.done");
+        match event {
+            ResponseStreamEvent::FunctionCallArgumentsDone { arguments, .. } => {
+                assert_eq!(arguments, r#"{"location":"Paris"}"#);
+            }
+            _ => panic!("Expected a function_call_arguments.done event"),
+        }
+    }
+}
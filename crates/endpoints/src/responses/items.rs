@@ -16,6 +16,44 @@ pub struct ResponseItemList {
     pub object: String,
 }
 
+/// Represents the query parameters of listing the items of a response or a conversation.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ListItemsQuery {
+    /// An item ID to list items after, used in pagination.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    /// A limit on the number of objects to be returned. Limit can range between 1 and 100, and the default is 20.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// The order to return the items in. Defaults to `desc`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<ListOrder>,
+}
+
+/// The order to return the listed items in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListOrder {
+    /// Return the items in ascending order.
+    Asc,
+    /// Return the items in descending order.
+    #[default]
+    Desc,
+}
+impl std::str::FromStr for ListOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(ListOrder::Asc),
+            "desc" => Ok(ListOrder::Desc),
+            _ => Err(format!(
+                "Invalid order: {s}. The order must be `asc` or `desc`."
+            )),
+        }
+    }
+}
+
 #[test]
 fn test_list_input_items() {
     let items = ResponseItemList {
@@ -1,3 +1,4 @@
 pub mod conversation;
+pub mod events;
 pub mod items;
 pub mod response_object;
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Represents a request body to generate a model response.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RequestOfModelResponse {
     /// Whether to run the model response in the background.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -363,6 +363,17 @@ pub struct ResponseObject {
     pub usage: Usage,
 }
 
+/// Represents the status of a response deletion operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteResponseStatus {
+    /// The unique ID of the response.
+    pub id: String,
+    /// The object type, which is always `response`.
+    pub object: String,
+    /// The status of the deletion operation.
+    pub deleted: bool,
+}
+
 /// Represents a conversation, either by ID or as an object with an ID.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -0,0 +1,259 @@
+//! Define the cancellation of inference requests.
+//!
+//! A [`CancellationToken`] is shared between the caller and a running request. Once the token is cancelled, the request stops at the next token, cleans up the context of the model and releases its slot, so that the requests waiting for the model can run.
+
+use crate::{
+    error::{BackendError, LlamaCoreError},
+    metadata::ggml::GgmlMetadata,
+    scheduler::SlotPermit,
+    utils::{
+        ends_with_stop_sequence, get_logprobs_by_graph_single, get_output_buffer_single,
+        with_chat_slot,
+    },
+    Graph, OUTPUT_TENSOR,
+};
+use endpoints::chat::LogProb;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::Waker,
+};
+use wasmedge_wasi_nn::{BackendError as WasiNnBackendError, Error as WasiNnError};
+
+/// A token for cancelling inference requests. Clones of the token share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    // the tasks woken up on cancellation, e.g. the requests waiting for a slot
+    wakers: Arc<Mutex<Vec<Waker>>>,
+}
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the requests holding the token or one of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+
+        let wakers = match self.wakers.lock() {
+            Ok(mut wakers) => std::mem::take(&mut *wakers),
+            Err(e) => std::mem::take(&mut *e.into_inner()),
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Returns `true` if the token is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wakes up the task of the waker once the token is cancelled.
+    pub(crate) fn wake_on_cancel(&self, waker: &Waker) {
+        let mut wakers = match self.wakers.lock() {
+            Ok(wakers) => wakers,
+            Err(e) => e.into_inner(),
+        };
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    /// Returns a guard that cancels the token when it is dropped, for example, when a server drops the handler of a request because the client disconnected.
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop {
+            token: Some(self.clone()),
+        }
+    }
+}
+
+/// A guard that cancels a [`CancellationToken`] when it is dropped.
+#[derive(Debug)]
+pub struct CancelOnDrop {
+    token: Option<CancellationToken>,
+}
+impl CancelOnDrop {
+    /// Consumes the guard without cancelling the token.
+    pub fn disarm(mut self) {
+        self.token = None;
+    }
+}
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.token.take() {
+            token.cancel();
+        }
+    }
+}
+
+/// The output of the inference computed one token at a time.
+#[derive(Debug)]
+pub(crate) struct Generation {
+    /// The bytes of the generated text.
+    pub(crate) output: Vec<u8>,
+    /// The end offsets of the generated tokens in `output`.
+    pub(crate) token_ends: Vec<usize>,
+    /// The log probabilities of the generated tokens, if requested.
+    pub(crate) logprobs: Option<Vec<LogProb>>,
+    /// How the inference ended. It is the same as the result of [`Graph::compute`].
+    pub(crate) outcome: Result<(), WasiNnError>,
+}
+
+/// Runs the inference on the chat model of the slot one token at a time, and passes the output to `process` before the context of the model is cleaned up.
+///
+/// The generation stops as soon as the output hits one of the stop sequences. The stop sequence stays in the output, so that `process` can truncate the output at it.
+///
+/// The request is checked for cancellation before each token, and other tasks, such as detecting closed connections, can run between tokens. If the request is cancelled, or the returned future is dropped, the context of the model is cleaned up and the metadata of the model is reset.
+///
+/// # Arguments
+///
+/// * `permit` - The slot of the chat model.
+///
+/// * `logprobs` - The number of most likely tokens to return at each position, if log probabilities are requested.
+///
+/// * `stop` - The stop sequences of the request.
+///
+/// * `cancel` - The cancellation token of the request.
+///
+/// * `process` - The function to build the result from the output.
+pub(crate) async fn generate<T>(
+    permit: &SlotPermit,
+    mut logprobs: Option<u8>,
+    stop: Option<&Vec<String>>,
+    cancel: &CancellationToken,
+    process: impl FnOnce(&mut Graph<GgmlMetadata>, Generation) -> Result<T, LlamaCoreError>,
+) -> Result<T, LlamaCoreError> {
+    let model_name = permit.model_name();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Generate tokens by the model named {model_name}");
+
+    let mut context = SingleContext {
+        model_name,
+        slot: permit.slot(),
+        active: true,
+    };
+
+    let mut generation = Generation {
+        output: vec![],
+        token_ends: vec![],
+        logprobs: logprobs.map(|_| vec![]),
+        outcome: Ok(()),
+    };
+
+    loop {
+        if cancel.is_cancelled() {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The request to the model named {model_name} is cancelled");
+
+            // the context is cleaned up when `context` is dropped
+            return Err(LlamaCoreError::Cancelled);
+        }
+
+        let done = with_chat_slot(model_name, permit.slot(), |graph| {
+            match graph.compute_single() {
+                Ok(_) => {
+                    let token = get_output_buffer_single(graph, OUTPUT_TENSOR)?;
+                    let token_len = token.len();
+                    generation.output.extend(token);
+                    generation.token_ends.push(generation.output.len());
+
+                    if let Some(top_logprobs) = logprobs {
+                        match get_logprobs_by_graph_single(graph, top_logprobs) {
+                            Some(token_logprobs) => {
+                                if let Some(content) = generation.logprobs.as_mut() {
+                                    content.extend(token_logprobs);
+                                }
+                            }
+                            // the backend does not return log probabilities
+                            None => {
+                                logprobs = None;
+                                generation.logprobs = None;
+                            }
+                        }
+                    }
+
+                    // no more tokens are needed once a stop sequence is hit
+                    Ok(stop.is_some_and(|stop| {
+                        ends_with_stop_sequence(&generation.output, token_len, stop)
+                    }))
+                }
+                Err(WasiNnError::BackendError(WasiNnBackendError::EndOfSequence)) => Ok(true),
+                Err(e) => {
+                    // the KV cache of the slot may no longer hold the prompt, e.g. once the context is full
+                    *graph.cached_prompt() = None;
+                    generation.outcome = Err(e);
+
+                    Ok(true)
+                }
+            }
+        })?;
+
+        if done {
+            break;
+        }
+
+        // let other tasks run between tokens
+        tokio::task::yield_now().await;
+    }
+
+    with_chat_slot(model_name, permit.slot(), |graph| {
+        let res = process(graph, generation);
+
+        // clean up the context
+        context.active = false;
+        graph.finish_single().map_err(|e| {
+            let err_msg = format!("Failed to clean up the context. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+        })?;
+
+        res
+    })
+}
+
+/// The context of a chat model used by an unfinished generation.
+struct SingleContext<'a> {
+    model_name: &'a str,
+    slot: usize,
+    active: bool,
+}
+impl Drop for SingleContext<'_> {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Clean up the context of the unfinished generation by the model named {}", self.model_name);
+
+        // the request may not reach its own reset of the metadata, so reset it here
+        let res = with_chat_slot(self.model_name, self.slot, |graph| {
+            graph.finish_single().map_err(|e| {
+                let err_msg = format!("Failed to clean up the context. Reason: {e}");
+
+                LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+            })?;
+
+            graph.update_metadata()
+        });
+
+        if let Err(e) = res {
+            let err_msg = format!("Failed to clean up the unfinished generation. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            #[cfg(not(feature = "logging"))]
+            eprintln!("[ERROR][llama_core] {}", &err_msg);
+        }
+    }
+}
@@ -0,0 +1,3188 @@
+//! Define the generation engine shared by the chat completions and the responses APIs.
+//!
+//! The engine generates the tokens of the prompt fed to a chat model, and turns the output into [`GenerationEvent`]s, which do not depend on the API serving the request. The chunks of a chat completion stream and the events of a response stream are serialized from the same events, so a fix or a feature of the generation reaches both APIs.
+
+use super::{
+    reasoning::{split_reasoning, ReasoningDelta, ReasoningParser},
+    truncation::{fit_prompt, FittedPrompt},
+};
+use crate::{
+    cancellation::{generate, CancellationToken, Generation},
+    error::{BackendError, LlamaCoreError},
+    metadata::ggml::GgmlMetadata,
+    prompt_cache::feed_prompt,
+    scheduler::SlotPermit,
+    utils::{
+        get_logprobs_by_graph_single, get_output_buffer_single, get_token_info_by_graph,
+        parse_logit_bias, set_tensor_data_u8, truncate_at_stop_sequence, with_chat_slot,
+        StopSequences,
+    },
+    Graph, CACHED_UTF8_ENCODINGS, CHAT_GRAPHS, OUTPUT_TENSOR,
+};
+use chat_prompts::{
+    chat::{custom_template, ChatTemplate, JinjaPrompt},
+    BuildChatPrompt, ChatPrompt, PromptTemplateType,
+};
+use endpoints::{
+    chat::{ChatCompletionRequestMessage, Function, LogProb, Tool, ToolCall, TruncationStrategy},
+    common::{CompletionTokensDetails, FinishReason, PromptTokensDetails, Usage},
+};
+use once_cell::sync::OnceCell;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// The prompt builders of the chat templates, so that each chat template is parsed once.
+static JINJA_PROMPTS: OnceCell<Mutex<HashMap<ChatTemplate, JinjaPrompt>>> = OnceCell::new();
+
+/// An event of the generation of a chat model.
+#[derive(Debug, Clone)]
+pub(crate) enum GenerationEvent {
+    /// A piece of the text generated for a choice.
+    TextDelta {
+        /// The index of the choice.
+        index: u32,
+        /// The generated text. It may be empty if the text of the token is held back.
+        text: String,
+        /// The log probabilities of the token, if requested.
+        logprobs: Option<Vec<LogProb>>,
+    },
+    /// A piece of the reasoning of a model that thinks before answering.
+    ReasoningDelta {
+        /// The index of the choice.
+        index: u32,
+        /// The reasoning text.
+        text: String,
+    },
+    /// The tools called by a choice.
+    ToolCalls {
+        /// The index of the choice.
+        index: u32,
+        /// The tool calls parsed from the output.
+        tool_calls: Vec<ToolCall>,
+    },
+    /// The end of a choice.
+    Finish {
+        /// The index of the choice.
+        index: u32,
+        /// The reason the choice stopped.
+        reason: FinishReason,
+    },
+    /// The token usage of all the choices. It is the last event of a generation.
+    Usage(Usage),
+}
+
+/// Generates the choices of a prompt one token at a time, and returns them as [`GenerationEvent`]s.
+///
+/// The stream holds a slot of the model until all the events are returned, or until it is cancelled or dropped. Then the context of the model is cleaned up, the metadata of the model is reset and the slot is released.
+pub(crate) struct GenerationStream {
+    choices: StreamChoices,
+    // the events generated but not returned yet
+    pending: VecDeque<GenerationEvent>,
+    // whether all the choices are generated
+    finished: bool,
+    // a slot of the model, released after the context is cleaned up
+    permit: Option<SlotPermit>,
+    model_name: String,
+    slot: usize,
+    // the token to cancel the stream
+    cancel: CancellationToken,
+}
+impl GenerationStream {
+    /// Creates a stream generating the choices of the prompt fed to the model of the slot.
+    pub(crate) fn new(
+        choices: StreamChoices,
+        permit: SlotPermit,
+        cancel: CancellationToken,
+    ) -> Self {
+        Self {
+            choices,
+            pending: VecDeque::new(),
+            finished: false,
+            model_name: permit.model_name().to_owned(),
+            slot: permit.slot(),
+            permit: Some(permit),
+            cancel,
+        }
+    }
+
+    /// Creates a stream returning the events generated beforehand, e.g. by [`tool_events`].
+    pub(crate) fn from_events(
+        events: Vec<GenerationEvent>,
+        permit: SlotPermit,
+        cancel: CancellationToken,
+    ) -> Self {
+        Self {
+            choices: StreamChoices::default(),
+            pending: VecDeque::from(events),
+            finished: true,
+            model_name: permit.model_name().to_owned(),
+            slot: permit.slot(),
+            permit: Some(permit),
+            cancel,
+        }
+    }
+
+    /// The name of the model generating the choices.
+    pub(crate) fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    /// Generates all the choices, and returns them with the token usage once the generation is over.
+    ///
+    /// Other tasks, such as detecting closed connections, can run between tokens.
+    pub(crate) async fn collect(mut self) -> Result<Generated, LlamaCoreError> {
+        let mut choices: Vec<GeneratedChoice> = vec![];
+        let mut usage = Usage::default();
+
+        while let Some(event) = self.next_event() {
+            match event? {
+                GenerationEvent::TextDelta {
+                    index,
+                    text,
+                    logprobs,
+                } => {
+                    let choice = GeneratedChoice::get_or_insert(&mut choices, index);
+                    if !text.is_empty() {
+                        choice
+                            .content
+                            .get_or_insert_with(String::new)
+                            .push_str(&text);
+                    }
+                    if let Some(logprobs) = logprobs {
+                        choice
+                            .logprobs
+                            .get_or_insert_with(Vec::new)
+                            .extend(logprobs);
+                    }
+                }
+                GenerationEvent::ReasoningDelta { index, text } => {
+                    GeneratedChoice::get_or_insert(&mut choices, index)
+                        .reasoning
+                        .get_or_insert_with(String::new)
+                        .push_str(&text);
+                }
+                GenerationEvent::ToolCalls { index, tool_calls } => {
+                    GeneratedChoice::get_or_insert(&mut choices, index)
+                        .tool_calls
+                        .extend(tool_calls);
+                }
+                GenerationEvent::Finish { index, reason } => {
+                    GeneratedChoice::get_or_insert(&mut choices, index).finish_reason = reason;
+                }
+                GenerationEvent::Usage(total) => usage = total,
+            }
+
+            // let other tasks run between tokens
+            tokio::task::yield_now().await;
+        }
+
+        // the text streamed token by token is post-processed as a whole, like the output of a tool generation
+        if let Some(prompt_template) = self.choices.prompt_template {
+            for choice in choices.iter_mut() {
+                let content = choice.content.take().unwrap_or_default();
+                let content = post_process(&content, &prompt_template).map_err(|e| {
+                    let err_msg = format!("Failed to post-process the output. {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    LlamaCoreError::Operation(err_msg)
+                })?;
+
+                choice.content = Some(content);
+            }
+        }
+
+        Ok(Generated { choices, usage })
+    }
+
+    /// Returns `true` if the events generated beforehand call tools.
+    pub(crate) fn includes_tool_calls(&self) -> bool {
+        self.pending
+            .iter()
+            .any(|event| matches!(event, GenerationEvent::ToolCalls { .. }))
+    }
+
+    /// Returns the next event, or `None` once the generation is over.
+    pub(crate) fn next_event(&mut self) -> Option<Result<GenerationEvent, LlamaCoreError>> {
+        loop {
+            // the stream is over once it is cleaned up
+            self.permit.as_ref()?;
+
+            if self.cancel.is_cancelled() {
+                #[cfg(feature = "logging")]
+                warn!(target: "stdout", "The generation by the model named {} is cancelled", &self.model_name);
+
+                // release the model now instead of when the stream is dropped
+                self.clean_up();
+
+                return Some(Err(LlamaCoreError::Cancelled));
+            }
+
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.finished {
+                self.clean_up();
+
+                return None;
+            }
+
+            let model_name = self.model_name.clone();
+            if let Err(e) = with_chat_slot(&model_name, self.slot, |graph| {
+                self.generate_by_graph(graph)
+            }) {
+                self.clean_up();
+
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Generates the next token of the current choice by the given graph, and queues the events of it.
+    fn generate_by_graph(&mut self, graph: &mut Graph<GgmlMetadata>) -> Result<(), LlamaCoreError> {
+        // the current choice is over once a stop sequence is hit
+        if self.choices.stop.stopped() {
+            return self.finish_choice(graph, FinishReason::stop);
+        }
+
+        match graph.compute_single() {
+            Ok(_) => {
+                let output_buffer = get_output_buffer_single(graph, OUTPUT_TENSOR)?;
+                let token = decode_token(output_buffer)?;
+
+                // hold back the text which may be part of a stop sequence
+                let (text, _) = self.choices.stop.push(&token);
+
+                // retrieve the log probabilities of the token if requested
+                let logprobs = self
+                    .choices
+                    .logprobs
+                    .and_then(|top_logprobs| get_logprobs_by_graph_single(graph, top_logprobs));
+                if logprobs.is_none() {
+                    // the backend does not return log probabilities
+                    self.choices.logprobs = None;
+                }
+
+                self.push_token(text, logprobs);
+
+                Ok(())
+            }
+            Err(wasmedge_wasi_nn::Error::BackendError(
+                wasmedge_wasi_nn::BackendError::EndOfSequence,
+            )) => {
+                #[cfg(feature = "logging")]
+                debug!(target: "stdout", "End of sequence");
+
+                // the text held back for the stop sequences
+                if let Some(text) = self.choices.stop.flush() {
+                    self.push_token(text, None);
+                }
+
+                self.finish_choice(graph, FinishReason::stop)
+            }
+            Err(wasmedge_wasi_nn::Error::BackendError(
+                wasmedge_wasi_nn::BackendError::ContextFull,
+            )) => {
+                #[cfg(feature = "logging")]
+                debug!(target: "stdout", "Context full");
+
+                // the text held back for the stop sequences
+                if let Some(text) = self.choices.stop.flush() {
+                    self.push_token(text, None);
+                }
+
+                // only the current choice is cut off, the next one starts from the prompt again
+                self.finish_choice(graph, FinishReason::length)
+            }
+            Err(wasmedge_wasi_nn::Error::BackendError(
+                wasmedge_wasi_nn::BackendError::PromptTooLong,
+            )) => {
+                #[cfg(feature = "logging")]
+                debug!(target: "stdout", "Prompt too long");
+
+                // the text held back for the stop sequences
+                if let Some(text) = self.choices.stop.flush() {
+                    self.push_token(text, None);
+                }
+
+                self.finish_choice(graph, FinishReason::length)
+            }
+            Err(e) => {
+                let err_msg = format!("Failed to compute the chat completion. Reason: {e}");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
+                    err_msg,
+                )))
+            }
+        }
+    }
+
+    /// Queues the reasoning and the answer in the text of the generated token.
+    fn push_token(&mut self, text: String, logprobs: Option<Vec<LogProb>>) {
+        let delta = match self.choices.reasoning.as_mut() {
+            Some(parser) => parser.push(&text),
+            None => ReasoningDelta {
+                reasoning: String::new(),
+                answer: text,
+            },
+        };
+
+        self.push_delta(delta, logprobs);
+    }
+
+    fn push_delta(&mut self, delta: ReasoningDelta, logprobs: Option<Vec<LogProb>>) {
+        if !delta.reasoning.is_empty() {
+            self.pending.push_back(GenerationEvent::ReasoningDelta {
+                index: self.choices.index,
+                text: delta.reasoning,
+            });
+        }
+
+        if !delta.answer.is_empty() || logprobs.is_some() {
+            self.pending.push_back(GenerationEvent::TextDelta {
+                index: self.choices.index,
+                text: delta.answer,
+                logprobs,
+            });
+        }
+    }
+
+    /// Queues the text held back by the reasoning parser of the current choice.
+    fn flush_reasoning(&mut self) {
+        if let Some(delta) = self.choices.reasoning.as_mut().map(|parser| parser.flush()) {
+            self.push_delta(delta, None);
+        }
+    }
+
+    /// Finishes the current choice, and starts the next one if any.
+    fn finish_choice(
+        &mut self,
+        graph: &mut Graph<GgmlMetadata>,
+        reason: FinishReason,
+    ) -> Result<(), LlamaCoreError> {
+        if !self.choices.has_next() {
+            #[cfg(feature = "logging")]
+            debug!(target: "stdout", "All choices generated");
+
+            return self.finish(graph, reason);
+        }
+
+        self.flush_reasoning();
+        self.pending.push_back(GenerationEvent::Finish {
+            index: self.choices.index,
+            reason,
+        });
+
+        self.choices.next(graph)
+    }
+
+    /// Finishes the current choice and the generation, and queues the token usage of all the choices.
+    fn finish(
+        &mut self,
+        graph: &Graph<GgmlMetadata>,
+        reason: FinishReason,
+    ) -> Result<(), LlamaCoreError> {
+        // retrieve the number of prompt and completion tokens of all choices
+        let usage = self.choices.usage(graph)?;
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "token_info: {} prompt tokens, {} completion tokens", usage.prompt_tokens, usage.completion_tokens);
+
+        self.flush_reasoning();
+        self.pending.push_back(GenerationEvent::Finish {
+            index: self.choices.index,
+            reason,
+        });
+        self.pending.push_back(GenerationEvent::Usage(usage));
+        self.finished = true;
+
+        Ok(())
+    }
+
+    /// Cleans up the context, resets the model metadata and releases the slot.
+    fn clean_up(&mut self) {
+        // Clean up is only needed if the stream still holds a slot of the model
+        if self.permit.is_none() {
+            return;
+        }
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Cleaning up context of the model named {}", &self.model_name);
+
+        // clean up the context
+        let res = with_chat_slot(&self.model_name, self.slot, |graph| {
+            graph.finish_single().map_err(|e| {
+                let err_msg = format!("Failed to clean up the context. Reason: {e}");
+
+                LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+            })
+        });
+        if let Err(e) = res {
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &e);
+        }
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Model context cleanup done!");
+
+        // reset the model metadata
+        if let Err(e) = reset_model_metadata(&self.model_name, self.slot) {
+            let err_msg = format!("Fail to reset model metadata. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+        }
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Model metadata reset done!");
+
+        // release the slot, so that the next request in the queue can run
+        drop(self.permit.take());
+    }
+}
+impl Drop for GenerationStream {
+    fn drop(&mut self) {
+        self.clean_up();
+    }
+}
+
+/// The choices and the token usage of a whole generation, collected from its events.
+#[derive(Debug)]
+pub(crate) struct Generated {
+    /// The choices ordered by their index.
+    pub(crate) choices: Vec<GeneratedChoice>,
+    /// The token usage of all the choices.
+    pub(crate) usage: Usage,
+}
+
+/// A choice of a whole generation.
+#[derive(Debug)]
+pub(crate) struct GeneratedChoice {
+    /// The index of the choice.
+    pub(crate) index: u32,
+    /// The generated text, or `None` if the choice only calls tools.
+    pub(crate) content: Option<String>,
+    /// The reasoning of a model that thinks before answering.
+    pub(crate) reasoning: Option<String>,
+    /// The log probabilities of the generated tokens, if requested.
+    pub(crate) logprobs: Option<Vec<LogProb>>,
+    /// The tools called by the choice.
+    pub(crate) tool_calls: Vec<ToolCall>,
+    /// The reason the choice stopped.
+    pub(crate) finish_reason: FinishReason,
+}
+impl GeneratedChoice {
+    /// Returns the choice with the given index, which is added if the events of the choice come first.
+    fn get_or_insert(choices: &mut Vec<GeneratedChoice>, index: u32) -> &mut GeneratedChoice {
+        while choices.len() <= index as usize {
+            choices.push(GeneratedChoice {
+                index: choices.len() as u32,
+                content: None,
+                reasoning: None,
+                logprobs: None,
+                tool_calls: vec![],
+                finish_reason: FinishReason::stop,
+            });
+        }
+
+        &mut choices[index as usize]
+    }
+}
+
+/// Decodes the bytes of a token to a string. The bytes of a character split across several tokens are cached until the character is complete.
+fn decode_token(bytes: Vec<u8>) -> Result<String, LlamaCoreError> {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(token) => return Ok(token),
+        Err(e) => e.into_bytes(),
+    };
+
+    let mutex = CACHED_UTF8_ENCODINGS.get_or_init(|| Mutex::new(Vec::new()));
+    let mut cached_encodings = mutex.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `UTF8_ENCODINGS`. Reason: {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    // cache the bytes for future decoding
+    cached_encodings.extend_from_slice(&bytes[..]);
+
+    match String::from_utf8(cached_encodings.to_vec()) {
+        Ok(token) => {
+            // clear CACHED_UTF8_ENCODINGS
+            cached_encodings.clear();
+
+            Ok(token)
+        }
+        Err(e) => {
+            // TODO This is a temp check. In case, infinite cached encodings happen.
+            if cached_encodings.len() > 4 {
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "Fail to convert a vector of bytes to string. The length of the utf8 bytes exceeds 4. {e}");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "The cached buffer: {:?}", &cached_encodings[..]);
+
+                // clear CACHED_UTF8_ENCODINGS
+                cached_encodings.clear();
+            } else {
+                #[cfg(feature = "logging")]
+                warn!(target: "stdout", "Fail to convert a vector of bytes to string. {e}");
+            }
+
+            Ok(String::new())
+        }
+    }
+}
+
+/// Generates the choices of the prompt fed to the model of the slot with the available tools, and returns a stream of their events.
+///
+/// The tool calls can only be parsed from the whole output, so all the choices are generated before the stream is returned.
+pub(crate) async fn tool_generation(
+    permit: SlotPermit,
+    cancel: CancellationToken,
+    prompt: &str,
+    n_choice: u64,
+    stop: Option<&Vec<String>>,
+    logprobs: Option<u8>,
+    prompt_usage: Usage,
+) -> Result<GenerationStream, LlamaCoreError> {
+    let n_choice = n_choice.max(1) as u32;
+
+    let mut events = vec![];
+    let mut usage = prompt_usage;
+    for index in 0..n_choice {
+        if index > 0 {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Start generating choice {} of {}", index + 1, n_choice);
+
+            // feed the prompt again to draw an independent sample
+            let cached_tokens = with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+                feed_prompt(graph, prompt)
+            })?;
+            add_cached_tokens(&mut usage, cached_tokens);
+        }
+
+        let (choice_events, choice_usage) =
+            generate(&permit, logprobs, stop, &cancel, |graph, generation| {
+                tool_events(graph, index, stop, generation)
+            })
+            .await?;
+
+        events.extend(choice_events);
+        add_usage(&mut usage, choice_usage);
+    }
+    events.push(GenerationEvent::Usage(usage));
+
+    Ok(GenerationStream::from_events(events, permit, cancel))
+}
+
+/// Adds the token usage of a choice to the token usage of the finished choices.
+fn add_usage(total: &mut Usage, usage: Usage) {
+    total.prompt_tokens += usage.prompt_tokens;
+    total.completion_tokens += usage.completion_tokens;
+    total.total_tokens += usage.total_tokens;
+
+    if let Some(details) = usage.completion_tokens_details {
+        total
+            .completion_tokens_details
+            .get_or_insert_with(CompletionTokensDetails::default)
+            .reasoning_tokens += details.reasoning_tokens;
+    }
+}
+
+/// Adds the number of prompt tokens reused by a choice to the token usage, if the prompt cache of the model is enabled.
+fn add_cached_tokens(total: &mut Usage, cached_tokens: Option<u64>) {
+    if let (Some(details), Some(cached_tokens)) =
+        (total.prompt_tokens_details.as_mut(), cached_tokens)
+    {
+        details.cached_tokens += cached_tokens;
+    }
+}
+
+/// Builds the events of a choice from the whole output generated with the available tools, and returns them with the token usage of the choice.
+fn tool_events(
+    graph: &mut Graph<GgmlMetadata>,
+    index: u32,
+    stop: Option<&Vec<String>>,
+    generation: Generation,
+) -> Result<(Vec<GenerationEvent>, Usage), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Handle chat request with available tools by the model named {}.", graph.name());
+
+    let reason = match generation.outcome {
+        Ok(_) => FinishReason::stop,
+        Err(wasmedge_wasi_nn::Error::BackendError(wasmedge_wasi_nn::BackendError::ContextFull)) => {
+            FinishReason::length
+        }
+        Err(wasmedge_wasi_nn::Error::BackendError(
+            wasmedge_wasi_nn::BackendError::PromptTooLong,
+        )) => {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The prompt is too long. Please reduce the length of your input and try again.");
+
+            FinishReason::length
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to compute the chat completion. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Backend(BackendError::Compute(err_msg)));
+        }
+    };
+
+    // Retrieve the output.
+    let output = std::str::from_utf8(&generation.output[..]).map_err(|e| {
+        let err_msg =
+            format!("Failed to decode the buffer of the inference result to a utf-8 string. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "raw generation:\n{output}");
+
+    // split the reasoning of thinking models from the answer
+    let split = split_reasoning(
+        output,
+        &generation.token_ends,
+        graph.metadata.prompt_template,
+    );
+
+    // post-process
+    let message = post_process(&split.answer, &graph.metadata.prompt_template).map_err(|e| {
+        let err_msg = format!("Failed to post-process the output. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "post-processed generation:\n{}", &message);
+
+    // retrieve the number of prompt and completion tokens
+    let token_info = get_token_info_by_graph(graph)?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
+
+    let usage = Usage {
+        prompt_tokens: token_info.prompt_tokens,
+        completion_tokens: token_info.completion_tokens,
+        total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
+        prompt_tokens_details: None,
+        completion_tokens_details: split
+            .reasoning_tokens
+            .map(|reasoning_tokens| CompletionTokensDetails { reasoning_tokens }),
+        truncated_messages: None,
+    };
+
+    let mut events = vec![];
+    if let Some(text) = split.reasoning {
+        events.push(GenerationEvent::ReasoningDelta { index, text });
+    }
+    match reason {
+        FinishReason::stop => {
+            check_tool_use(graph.metadata.prompt_template)?;
+
+            let parsed_result = parse_tool_calls(&message, graph.metadata.prompt_template)?;
+
+            let content = if parsed_result.tool_calls.is_empty() {
+                let mut content = parsed_result.raw;
+                truncate_at_stop_sequence(&mut content, stop);
+
+                Some(content)
+            } else {
+                parsed_result.content
+            };
+
+            if content.is_some() || generation.logprobs.is_some() {
+                events.push(GenerationEvent::TextDelta {
+                    index,
+                    text: content.unwrap_or_default(),
+                    logprobs: generation.logprobs,
+                });
+            }
+
+            let reason = match parsed_result.tool_calls.is_empty() {
+                true => FinishReason::stop,
+                false => {
+                    events.push(GenerationEvent::ToolCalls {
+                        index,
+                        tool_calls: parsed_result.tool_calls,
+                    });
+
+                    FinishReason::tool_calls
+                }
+            };
+
+            events.push(GenerationEvent::Finish { index, reason });
+        }
+        _ => {
+            events.push(GenerationEvent::TextDelta {
+                index,
+                text: message,
+                logprobs: generation.logprobs,
+            });
+            events.push(GenerationEvent::Finish { index, reason });
+        }
+    }
+
+    Ok((events, usage))
+}
+
+/// Creates the prompt builder of the model. The `jinja` prompt template renders the chat template of the model.
+pub(crate) fn chat_prompt(metadata: &GgmlMetadata) -> Result<ChatPrompt, LlamaCoreError> {
+    match (metadata.prompt_template, &metadata.chat_template) {
+        (PromptTemplateType::Jinja, Some(template)) => {
+            jinja_prompt(template).map(ChatPrompt::JinjaPrompt)
+        }
+        (prompt_template, _) => Ok(ChatPrompt::from(prompt_template)),
+    }
+}
+
+/// Returns the prompt builder of the chat template, parsing the template on first use.
+fn jinja_prompt(template: &ChatTemplate) -> Result<JinjaPrompt, LlamaCoreError> {
+    let prompts = JINJA_PROMPTS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut prompts = match prompts.lock() {
+        Ok(prompts) => prompts,
+        Err(e) => e.into_inner(),
+    };
+
+    if let Some(prompt) = prompts.get(template) {
+        return Ok(prompt.clone());
+    }
+
+    let prompt = JinjaPrompt::new(template.clone()).map_err(|e| {
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{e}");
+
+        LlamaCoreError::Prompt(e)
+    })?;
+    prompts.insert(template.clone(), prompt.clone());
+
+    Ok(prompt)
+}
+
+/// Returns the stop sequences declared by the custom prompt template, or `None` for the other templates.
+pub(crate) fn template_stop(prompt_template: PromptTemplateType) -> Option<Vec<String>> {
+    match prompt_template {
+        PromptTemplateType::Custom(name) => custom_template(name)
+            .map(|template| template.stop)
+            .filter(|stop| !stop.is_empty()),
+        _ => None,
+    }
+}
+
+/// Checks if the tool use is supported by the prompt template.
+pub(crate) fn check_tool_use(prompt_template: PromptTemplateType) -> Result<(), LlamaCoreError> {
+    if prompt_template != PromptTemplateType::MistralTool
+        && prompt_template != PromptTemplateType::ChatMLTool
+        && prompt_template != PromptTemplateType::GroqLlama3Tool
+        && prompt_template != PromptTemplateType::Llama3Tool
+        && prompt_template != PromptTemplateType::InternLM2Tool
+        && prompt_template != PromptTemplateType::NemotronTool
+        && prompt_template != PromptTemplateType::FunctionaryV32
+        && prompt_template != PromptTemplateType::FunctionaryV31
+        && prompt_template != PromptTemplateType::MistralSmallTool
+        && prompt_template != PromptTemplateType::Llama4Chat
+        && prompt_template != PromptTemplateType::Qwen3NoThink
+        && prompt_template != PromptTemplateType::Smol3NoThink
+        && prompt_template != PromptTemplateType::Gemma3
+        && prompt_template != PromptTemplateType::GptOss
+        && prompt_template != PromptTemplateType::Qwen3Agent
+        && prompt_template != PromptTemplateType::SeedOssNoThink
+        && prompt_template != PromptTemplateType::SeedOssThink
+        && prompt_template != PromptTemplateType::Jinja
+    {
+        let err_msg = format!("Unsupported prompt template: {prompt_template}. The tool use is only supported for 'mistral-tool', 'chatml-tool', 'groq-llama3-tool', 'llama-3-tool', 'internlm-2-tool', 'nemotron-tool', 'functionary-31', 'functionary-32', 'mistral-small-tool', 'llama-4-chat', 'qwen3-no-think', 'smol-3-no-think', 'gemma-3', 'gpt-oss', 'qwen3-agent', 'seed-oss-no-think', 'seed-oss-think', and 'jinja' prompt templates.");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::InvalidRequest(err_msg));
+    }
+
+    Ok(())
+}
+
+/// Tracks the choices generated one after another in a stream.
+#[derive(Debug, Default)]
+pub(crate) struct StreamChoices {
+    /// The prompt fed to the model for every choice.
+    prompt: String,
+    /// The number of choices to generate.
+    n_choice: u32,
+    /// The index of the choice being generated.
+    index: u32,
+    /// The token usage of the finished choices.
+    usage: Usage,
+    /// The stop sequences of the current choice.
+    stop: StopSequences,
+    /// The number of most likely tokens to return at each position, if logprobs are requested.
+    logprobs: Option<u8>,
+    /// The parser splitting the reasoning from the answer of the current choice, if the model thinks.
+    reasoning: Option<ReasoningParser>,
+    /// The prompt template of the model, which post-processes the collected text of the choices. It is `None` for the events generated beforehand, which are post-processed already.
+    prompt_template: Option<PromptTemplateType>,
+}
+impl StreamChoices {
+    /// Creates the choices of a stream whose prompt has been fed to the model, with the returned `prompt_usage`.
+    pub(crate) fn new(
+        prompt: impl Into<String>,
+        n_choice: u64,
+        stop: Option<&Vec<String>>,
+        logprobs: Option<u8>,
+        prompt_usage: Usage,
+        prompt_template: PromptTemplateType,
+    ) -> Self {
+        Self {
+            prompt: prompt.into(),
+            n_choice: n_choice.max(1) as u32,
+            index: 0,
+            usage: prompt_usage,
+            stop: StopSequences::new(stop),
+            logprobs,
+            reasoning: ReasoningParser::new(prompt_template),
+            prompt_template: Some(prompt_template),
+        }
+    }
+
+    fn has_next(&self) -> bool {
+        self.index + 1 < self.n_choice
+    }
+
+    /// Finishes the current choice and feeds the prompt to the model again for the next one.
+    fn next(&mut self, graph: &mut Graph<GgmlMetadata>) -> Result<(), LlamaCoreError> {
+        self.usage = self.usage(graph)?;
+
+        // clean up the context of the current choice
+        graph.finish_single().map_err(|e| {
+            let err_msg = format!("Failed to clean up the context. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+        })?;
+
+        let cached_tokens = feed_prompt(graph, &self.prompt)?;
+        add_cached_tokens(&mut self.usage, cached_tokens);
+
+        self.index += 1;
+        self.stop.reset();
+        if let Some(parser) = self.reasoning.as_mut() {
+            parser.reset();
+        }
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Start generating choice {} of {}", self.index + 1, self.n_choice);
+
+        Ok(())
+    }
+
+    /// Returns the token usage of the finished choices plus the current one.
+    fn usage(&self, graph: &Graph<GgmlMetadata>) -> Result<Usage, LlamaCoreError> {
+        let token_info = get_token_info_by_graph(graph)?;
+
+        let prompt_tokens = self.usage.prompt_tokens + token_info.prompt_tokens;
+        let completion_tokens = self.usage.completion_tokens + token_info.completion_tokens;
+
+        // the reasoning tokens of the finished choices plus the current one
+        let completion_tokens_details = self.reasoning.as_ref().map(|parser| {
+            let reasoning_tokens = self
+                .usage
+                .completion_tokens_details
+                .map(|details| details.reasoning_tokens)
+                .unwrap_or_default();
+
+            CompletionTokensDetails {
+                reasoning_tokens: reasoning_tokens + parser.reasoning_tokens(),
+            }
+        });
+
+        Ok(Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            completion_tokens_details,
+            ..self.usage
+        })
+    }
+}
+
+/// Feeds the prompt to the model of the slot, and returns the token usage of the prompt known before the generation, i.e. the reused tokens and the dropped messages.
+pub(crate) fn set_prompt(
+    permit: &SlotPermit,
+    prompt: &str,
+    truncated_messages: Option<u64>,
+) -> Result<Usage, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set prompt to the chat model named {}", permit.model_name());
+
+    let cached_tokens = with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+        feed_prompt(graph, prompt)
+    })?;
+
+    Ok(Usage {
+        prompt_tokens_details: cached_tokens
+            .map(|cached_tokens| PromptTokensDetails { cached_tokens }),
+        truncated_messages,
+        ..Default::default()
+    })
+}
+
+/// The options of a request which override the metadata of the model while the request is served.
+#[derive(Debug, Default)]
+pub(crate) struct RequestOptions<'a> {
+    pub(crate) temperature: Option<f64>,
+    pub(crate) top_p: Option<f64>,
+    pub(crate) frequency_penalty: Option<f64>,
+    pub(crate) presence_penalty: Option<f64>,
+    pub(crate) logit_bias: Option<&'a HashMap<String, f64>>,
+    pub(crate) logprobs: Option<bool>,
+    pub(crate) top_logprobs: Option<u8>,
+    /// The JSON schema constraining the output.
+    pub(crate) json_schema: Option<String>,
+}
+
+/// Updates the metadata of the model with the options of the request, and returns the updated metadata.
+pub(crate) fn check_model_metadata(
+    permit: &SlotPermit,
+    options: &RequestOptions,
+) -> Result<GgmlMetadata, LlamaCoreError> {
+    let mut should_update = false;
+    let mut metadata = get_model_metadata(Some(&permit.model_name().to_owned()))?;
+
+    // check if necessary to update temperature
+    if let Some(temp) = options.temperature {
+        if metadata.temperature != temp {
+            // update temperature
+            metadata.temperature = temp;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update top_p
+    if let Some(top_p) = options.top_p {
+        if metadata.top_p != top_p {
+            // update top_p
+            metadata.top_p = top_p;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update frequency_penalty
+    if let Some(frequency_penalty) = options.frequency_penalty {
+        if metadata.frequency_penalty != frequency_penalty {
+            // update frequency_penalty
+            metadata.frequency_penalty = frequency_penalty;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update presence_penalty
+    if let Some(presence_penalty) = options.presence_penalty {
+        if metadata.presence_penalty != presence_penalty {
+            // update presence_penalty
+            metadata.presence_penalty = presence_penalty;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update logit_bias
+    if let Some(logit_bias) = options.logit_bias {
+        let logit_bias = parse_logit_bias(logit_bias)?;
+
+        if metadata.logit_bias != logit_bias {
+            // update logit_bias
+            metadata.logit_bias = logit_bias;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update logprobs and top_logprobs
+    let logprobs = options.logprobs.unwrap_or_default();
+    let top_logprobs = match options.top_logprobs {
+        Some(top_logprobs) if !logprobs => {
+            let err_msg = format!(
+                "Invalid `top_logprobs`: {top_logprobs}. `logprobs` must be set to true if `top_logprobs` is used."
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::InvalidRequest(err_msg));
+        }
+        Some(top_logprobs) if top_logprobs > 20 => {
+            let err_msg =
+                format!("Invalid `top_logprobs`: {top_logprobs}. It must be between 0 and 20.");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::InvalidRequest(err_msg));
+        }
+        Some(top_logprobs) => top_logprobs,
+        None => 0,
+    };
+    if metadata.logprobs != logprobs || metadata.top_logprobs != top_logprobs {
+        // update logprobs and top_logprobs
+        metadata.logprobs = logprobs;
+        metadata.top_logprobs = top_logprobs;
+
+        if !should_update {
+            should_update = true;
+        }
+    }
+
+    // check if necessary to update json_schema
+    if let Some(json_schema) = options.json_schema.as_ref() {
+        if metadata.json_schema.as_ref() != Some(json_schema) {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Constrain the output with the JSON schema: {}", json_schema);
+
+            // update json_schema
+            metadata.json_schema = Some(json_schema.clone());
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if the `embedding` option is disabled
+    if metadata.embeddings {
+        metadata.embeddings = false;
+
+        if !should_update {
+            should_update = true;
+        }
+    }
+
+    if should_update {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Update the model metadata.");
+
+        // update the target graph with the new metadata
+        update_model_metadata(permit, &metadata)?;
+    }
+
+    Ok(metadata)
+}
+
+/// Updates the number of tokens to predict with the maximum number of tokens of the request and the number of tokens available for the completion.
+pub(crate) fn update_n_predict(
+    permit: &SlotPermit,
+    max_tokens: Option<i32>,
+    metadata: &mut GgmlMetadata,
+    available_completion_tokens: u64,
+) -> Result<(), LlamaCoreError> {
+    let mut should_update = false;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "n_predict: {}", metadata.n_predict);
+
+    // From high to low priority
+    // 1. max_tokens of the request
+    // 2. available_completion_tokens
+    // 3. n_predict
+
+    if let Some(max_tokens) = max_tokens {
+        if metadata.n_predict != max_tokens {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Update n_predict with the max tokens of the request from {} to {}", metadata.n_predict, max_tokens);
+
+            metadata.n_predict = max_tokens;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // TODO: remove this condition after [Issue #3958 on WasmEdge](https://github.com/WasmEdge/WasmEdge/issues/3958) is fixed
+    if metadata.n_predict == -2 {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Update n_predict with available_completion_tokens from {} to {}", metadata.n_predict, available_completion_tokens);
+
+        // update n_predict
+        metadata.n_predict = available_completion_tokens as i32;
+
+        if !should_update {
+            should_update = true;
+        }
+    }
+
+    if metadata.n_predict == -1
+        || (metadata.n_predict > 0 && metadata.n_predict < available_completion_tokens as i32)
+        || (metadata.n_predict < 0 && metadata.n_predict != -2)
+    // TODO: remove this condition after [Issue #3958 on WasmEdge](https://github.com/WasmEdge/WasmEdge/issues/3958) is fixed
+    {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Update n_predict with available_completion_tokens from {} to {}", metadata.n_predict, available_completion_tokens);
+
+        // update n_predict
+        metadata.n_predict = available_completion_tokens as i32;
+
+        if !should_update {
+            should_update = true;
+        }
+    }
+
+    if should_update {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Update the model metadata.");
+
+        // update the target graph with the new metadata
+        update_model_metadata(permit, metadata)?;
+    }
+
+    Ok(())
+}
+
+/// Build the chat prompt from the chat messages.
+///
+/// # Arguments
+///
+/// * `permit`: The slot of the chat model.
+///
+/// * `cancel`: The cancellation token of the request.
+///
+/// * `metadata`: The metadata of the request.
+///
+/// * `strategy`: The strategy to shorten the chat history if the prompt does not fit in the context window.
+///
+/// * `messages`: The chat messages, which are shortened with the chat history.
+///
+/// * `tools`: The tools available to the model.
+///
+/// # Returns
+///
+/// The prompt, the number of available tokens for completions, and the number of messages dropped from the chat history.
+pub(crate) async fn build_prompt(
+    permit: &SlotPermit,
+    cancel: &CancellationToken,
+    metadata: &GgmlMetadata,
+    strategy: TruncationStrategy,
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    tools: Option<&[Tool]>,
+) -> Result<FittedPrompt, LlamaCoreError> {
+    let chat_prompt = chat_prompt(metadata)?;
+
+    #[cfg(feature = "logging")]
+    {
+        let mut role_chain = String::new();
+        for (idx, message) in messages.iter().enumerate() {
+            if idx == messages.len() - 1 {
+                role_chain.push_str(&format!("{}", message.role()));
+            } else {
+                role_chain.push_str(&format!("{} -> ", message.role()));
+            }
+        }
+        info!(target: "stdout", "Role chain: {role_chain}");
+    }
+
+    fit_prompt(permit, cancel, metadata, strategy, messages, |messages| {
+        chat_prompt.build_with_tools(messages, tools).map_err(|e| {
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "Fail to build chat prompts. Reason: {e}");
+
+            LlamaCoreError::Prompt(e)
+        })
+    })
+    .await
+}
+
+/// Get a copy of the metadata of the model.
+pub(crate) fn get_model_metadata(
+    model_name: Option<&String>,
+) -> Result<GgmlMetadata, LlamaCoreError> {
+    let chat_graphs = match CHAT_GRAPHS.get() {
+        Some(chat_graphs) => chat_graphs,
+        None => {
+            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(LlamaCoreError::Operation(err_msg.into()));
+        }
+    };
+
+    let chat_graphs = chat_graphs.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    match model_name {
+        Some(model_name) => match chat_graphs.contains_key(model_name) {
+            true => {
+                let graph = chat_graphs.get(model_name).unwrap();
+                Ok(graph.metadata.clone())
+            }
+            false => match chat_graphs.iter().next() {
+                Some((_, graph)) => Ok(graph.metadata.clone()),
+                None => {
+                    let err_msg = "There is no model available in the chat graphs.";
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg.into()))
+                }
+            },
+        },
+        None => match chat_graphs.iter().next() {
+            Some((_, graph)) => Ok(graph.metadata.clone()),
+            None => {
+                let err_msg = "There is no model available in the chat graphs.";
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{err_msg}");
+
+                Err(LlamaCoreError::Operation(err_msg.into()))
+            }
+        },
+    }
+}
+
+/// Pushes the metadata to the execution context of the slot of the permit. The metadata of the model, which the other slots and the next requests start from, is unchanged.
+pub(crate) fn update_model_metadata(
+    permit: &SlotPermit,
+    metadata: &GgmlMetadata,
+) -> Result<(), LlamaCoreError> {
+    let config = match serde_json::to_string(metadata) {
+        Ok(config) => config,
+        Err(e) => {
+            let err_msg = format!("Fail to serialize metadata to a JSON string. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg));
+        }
+    };
+
+    with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+        // update metadata
+        set_tensor_data_u8(graph, 1, config.as_bytes())
+    })
+}
+
+/// Restores the metadata of the model in the execution context of the given slot.
+pub(crate) fn reset_model_metadata(model_name: &str, slot: usize) -> Result<(), LlamaCoreError> {
+    with_chat_slot(model_name, slot, |graph| graph.update_metadata())
+}
+
+/// Build post-processing for output based on template type
+pub(crate) fn post_process(
+    output: impl AsRef<str>,
+    template_ty: &PromptTemplateType,
+) -> Result<String, String> {
+    let output = if *template_ty == PromptTemplateType::Baichuan2 {
+        if output.as_ref().contains("用户:") {
+            output.as_ref().trim_end_matches("用户:").trim().to_owned()
+        } else {
+            output.as_ref().trim().to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::OpenChat {
+        if output.as_ref().contains("<|end_of_turn|>") {
+            output
+                .as_ref()
+                .trim_end_matches("<|end_of_turn|>")
+                .trim()
+                .to_owned()
+        } else {
+            output.as_ref().trim().to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::GemmaInstruct
+        || *template_ty == PromptTemplateType::Gemma3
+    {
+        let s = output.as_ref().trim();
+        if s.ends_with("<end_of_turn>") {
+            s.trim_end_matches("<end_of_turn>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::ChatML
+        || *template_ty == PromptTemplateType::ChatMLTool
+        || *template_ty == PromptTemplateType::InternLM2Tool
+        || *template_ty == PromptTemplateType::MiniCPMV
+    {
+        let mut s = output.as_ref().trim();
+        if s.ends_with("<|endoftext|>") {
+            s = s.trim_end_matches("<|endoftext|>").trim();
+        }
+
+        if s.starts_with(":") {
+            s = s.trim_start_matches(":").trim();
+        }
+
+        // handle Qwen3 empty think tags
+        let x = {
+            let pat = r#"<think>
+
+</think>
+"#;
+            if s.contains(pat) {
+                let x = s.replace(pat, "");
+                if x.starts_with("()") {
+                    x.trim_start_matches("()").to_owned()
+                } else {
+                    x.to_owned()
+                }
+            } else {
+                s.to_owned()
+            }
+        };
+        s = x.trim();
+
+        if s.contains("<|im_start|>") && s.contains("<|im_end|>") {
+            let idx_start = s.find("<|im_start|>").unwrap();
+            let idx_end = s.find("<|im_end|>").unwrap();
+
+            match idx_start <= idx_end {
+                true => s.split("<|im_start|>").collect::<Vec<_>>()[0]
+                    .trim()
+                    .to_owned(),
+                false => s.split("<|im_end|>").collect::<Vec<_>>()[0]
+                    .trim()
+                    .to_owned(),
+            }
+        } else if s.contains("<|im_start|>") {
+            s.split("<|im_start|>").collect::<Vec<_>>()[0]
+                .trim()
+                .to_owned()
+        } else if s.contains("<|im_end|>") {
+            let output = s.trim_end_matches("<|im_end|>").trim();
+            if output.starts_with(": ") {
+                output.trim_start_matches(": ").to_owned()
+            } else {
+                output.to_owned()
+            }
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Zephyr
+        || *template_ty == PromptTemplateType::MistralLite
+        || *template_ty == PromptTemplateType::MistralTool
+        || *template_ty == PromptTemplateType::MistralInstruct
+        || *template_ty == PromptTemplateType::MistralSmallChat
+        || *template_ty == PromptTemplateType::MistralSmallTool
+        || *template_ty == PromptTemplateType::BreezeInstruct
+    {
+        if output.as_ref().contains("</s><") {
+            output.as_ref().trim_end_matches("</s><").trim().to_owned()
+        } else if output.as_ref().contains("</s>") {
+            output
+                .as_ref()
+                .strip_suffix("</s>")
+                .unwrap()
+                .trim()
+                .to_owned()
+        } else {
+            output.as_ref().trim().to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::DeepseekChat {
+        if output.as_ref().contains("<|end_of_sentence|>") {
+            output
+                .as_ref()
+                .trim_end_matches("<|end_of_sentence|>")
+                .trim()
+                .replace("<|end_of_sentence|>", " ")
+                .trim()
+                .to_owned()
+        } else {
+            output.as_ref().trim().to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::HumanAssistant {
+        if output.as_ref().contains("Human:") {
+            output.as_ref().trim_end_matches("Human:").trim().to_owned()
+        } else {
+            output.as_ref().trim().to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::SolarInstruct {
+        let s = output.as_ref().trim();
+
+        if s.starts_with("### Answer") {
+            let s = s.trim_start_matches("###").trim();
+
+            if s.starts_with("Answer:\n") {
+                s.replace("Answer:\n", "Answer: ")
+            } else {
+                s.to_owned()
+            }
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Llama2Chat
+        || *template_ty == PromptTemplateType::NemotronTool
+        || *template_ty == PromptTemplateType::NemotronChat
+    {
+        let s = output.as_ref().trim();
+        if s.ends_with("</s>") {
+            s.trim_end_matches("</s>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Llama3Chat
+        || *template_ty == PromptTemplateType::GroqLlama3Tool
+        || *template_ty == PromptTemplateType::Llama3Tool
+        || *template_ty == PromptTemplateType::FunctionaryV32
+    {
+        let s = output.as_ref().trim();
+        if s.ends_with("<|eot_id|>") {
+            s.trim_end_matches("<|eot_id|>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Phi3Chat {
+        let s = output.as_ref().trim();
+        if s.ends_with("<|end|>") {
+            s.trim_end_matches("<|end|>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Phi4Chat {
+        let mut s = output.as_ref().trim();
+
+        if s.starts_with("think>") {
+            s = s.trim_start_matches("think>").trim();
+        }
+
+        if s.ends_with("<|im_end|>") {
+            s.trim_end_matches("<|im_end|>").trim().to_owned()
+        } else if s.ends_with("<|end|>") {
+            s.trim_end_matches("<|end|>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::FunctionaryV31 {
+        let mut s = output.as_ref().trim();
+        if s.ends_with("<|eot_id|>") {
+            s = s.trim_end_matches("<|eot_id|>").trim();
+        }
+        if s.ends_with("<|eom_id|>") {
+            s = s.trim_end_matches("<|eom_id|>").trim();
+        }
+        s.to_owned()
+    } else if *template_ty == PromptTemplateType::MoxinChat
+        || *template_ty == PromptTemplateType::MoxinInstruct
+    {
+        let s = output.as_ref().trim();
+        if s.ends_with("</s>") {
+            s.trim_end_matches("</s>").trim().to_owned()
+        } else if s.ends_with("[INST]") {
+            s.trim_end_matches("[INST]").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Falcon3 {
+        let s = output.as_ref().trim();
+        if s.ends_with("<|endoftext|>") {
+            s.trim_end_matches("<|endoftext|>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Megrez {
+        let s = output.as_ref().trim();
+        if s.ends_with("<|turn_end|>") {
+            s.trim_end_matches("<|turn_end|>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Qwen2vl
+        || *template_ty == PromptTemplateType::Qwen3NoThink
+        || *template_ty == PromptTemplateType::ChatMLThink
+    {
+        let mut s = output.as_ref().trim();
+
+        if s.starts_with(":") {
+            s = s.trim_start_matches(":").trim();
+        }
+
+        if s.starts_with("</think>") {
+            s = s.trim_start_matches("</think>").trim();
+        }
+
+        if s.ends_with("<|im_end|>") {
+            s.trim_end_matches("<|im_end|>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::VicunaLlava {
+        let s = output.as_ref().trim();
+        if s.ends_with("</s>") {
+            s.trim_end_matches("</s>").trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::ExaoneDeepChat
+        || *template_ty == PromptTemplateType::ExaoneChat
+    {
+        let mut s = output.as_ref().trim();
+
+        if s.ends_with("[|endofturn|]") {
+            s = s.trim_end_matches("[|endofturn|]").trim();
+        }
+
+        s.to_owned()
+    } else if *template_ty == PromptTemplateType::Llama4Chat {
+        let mut s = output.as_ref().trim();
+
+        if s.ends_with("<|eot|>") {
+            s = s.trim_end_matches("<|eot|>").trim();
+        }
+
+        s.to_owned()
+    } else if *template_ty == PromptTemplateType::Smolvl {
+        let mut s = output.as_ref().trim();
+
+        if s.starts_with(":") {
+            s = s.trim_start_matches(":").trim();
+        }
+
+        if s.ends_with("<end_of_utterance>") {
+            s = s.trim_end_matches("<end_of_utterance>").trim();
+        }
+
+        if s.contains("<end_of_utterance>:") {
+            let parts = s.split("<end_of_utterance>:").collect::<Vec<_>>();
+            parts.last().unwrap().trim().to_owned()
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Smol3NoThink {
+        let mut s = output.as_ref().trim();
+
+        if s.ends_with("<|im_end|>") {
+            s = s.trim_end_matches("<|im_end|>").trim();
+        }
+
+        let re = regex::Regex::new(r"(?s)^<think>.*?</think>\s*").unwrap();
+        re.replace(s, "").to_string()
+    } else if *template_ty == PromptTemplateType::GptOss {
+        let s = output.as_ref().trim();
+
+        let re =
+            regex::Regex::new(r"(?s).*<\|channel\|>final<\|message\|>(.*?)<\|return\|>$").unwrap();
+
+        if let Some(caps) = re.captures(s) {
+            let extracted = &caps[1];
+            extracted.to_owned()
+        } else {
+            // the answer split from the reasoning
+            s.trim_end_matches("<|return|>").trim().to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::Qwen3Agent {
+        let mut s = output.as_ref().trim();
+
+        if s.starts_with(":") {
+            s = s.trim_start_matches(":").trim();
+        }
+
+        if s.starts_with("</think>") {
+            s = s.trim_start_matches("</think>").trim();
+        }
+
+        if s.ends_with("<|im_end|>") {
+            s = s.trim_end_matches("<|im_end|>").trim();
+        }
+
+        if s.contains("<final_answer>") && !s.contains("</final_answer>") {
+            format!("{s}</final_answer>")
+        } else {
+            s.to_owned()
+        }
+    } else if *template_ty == PromptTemplateType::SeedOssNoThink {
+        let s = output.as_ref().trim();
+
+        let re = regex::Regex::new(r"(?s)</seed:think>\s*(.*?)\s*<seed:eos>").unwrap();
+
+        if let Some(caps) = re.captures(s) {
+            let extracted = &caps[1];
+            extracted.to_owned()
+        } else {
+            // the answer split from the reasoning
+            s.trim_end_matches("<seed:eos>").trim().to_owned()
+        }
+    } else {
+        output.as_ref().trim().to_owned()
+    };
+
+    Ok(output)
+}
+
+/// Parses the tool calls from the post-processed output of a model.
+pub(crate) fn parse_tool_calls(
+    input: &str,
+    prompt_template: PromptTemplateType,
+) -> Result<ParseResult, LlamaCoreError> {
+    match prompt_template {
+        PromptTemplateType::MistralTool => match regex::Regex::new(r"\[\{.*?\}\]") {
+            Ok(re) => {
+                let mut values: Vec<serde_json::Value> = vec![];
+                for cap in re.captures_iter(input) {
+                    let matched = &cap[0];
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "captured: {matched}");
+
+                    match serde_json::from_str::<Vec<serde_json::Value>>(matched) {
+                        Ok(group) => values.extend(group),
+                        Err(e) => {
+                            let err_msg =
+                                format!("Failed to deserialize generated tool calls. Reason: {e}");
+
+                            #[cfg(feature = "logging")]
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            return Err(LlamaCoreError::Operation(err_msg));
+                        }
+                    }
+                }
+
+                let mut tool_calls: Vec<ToolCall> = vec![];
+                for value in values.iter() {
+                    let name = match value.get("name") {
+                        Some(name) => name.to_string().replace("\"", ""),
+                        None => {
+                            let err_msg = format!(
+                                "Failed to get the name of the function. Tool call: {value:?}"
+                            );
+
+                            #[cfg(feature = "logging")]
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            return Err(LlamaCoreError::Operation(err_msg));
+                        }
+                    };
+
+                    let arguments = match value.get("arguments") {
+                        Some(arguments) => arguments.to_string(),
+                        None => {
+                            let err_msg = format!(
+                                "Failed to get the arguments of the function. Tool call: {value:?}"
+                            );
+
+                            #[cfg(feature = "logging")]
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            return Err(LlamaCoreError::Operation(err_msg));
+                        }
+                    };
+
+                    let function = Function { name, arguments };
+
+                    let tool_call = ToolCall {
+                        id: "call_abc123".to_string(),
+                        ty: "function".to_string(),
+                        function,
+                    };
+
+                    tool_calls.push(tool_call);
+                }
+
+                let parsed = ParseResult {
+                    raw: input.to_owned(),
+                    content: None,
+                    tool_calls,
+                };
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "parsed result: {parsed:?}");
+
+                Ok(parsed)
+            }
+            Err(e) => {
+                let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                Err(LlamaCoreError::Operation(err_msg))
+            }
+        },
+        PromptTemplateType::ChatMLTool => {
+            match regex::Regex::new(r"<tool_call>(.*?)</tool_call>") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    for cap in re.captures_iter(input) {
+                        let matched = cap[1].replace("\\n", ""); // Remove "\\n" from the captured group
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {}", &matched);
+
+                        match serde_json::from_str::<serde_json::Value>(&matched) {
+                            Ok(value) => values.push(value),
+                            Err(e) => {
+                                let err_msg = format!(
+                                    "Failed to deserialize generated tool calls. Reason: {e}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        }
+                    }
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        let name = match value.get("name") {
+                            Some(name) => name.to_string().replace("\"", ""),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the name of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let arguments = match value.get("arguments") {
+                            Some(arguments) => arguments.to_string(),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the arguments of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let function = Function { name, arguments };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls,
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::GroqLlama3Tool => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            match regex::Regex::new(r"(?s)<tool_call>((.|\r|\n)*?)</tool_call>") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    for cap in re.captures_iter(input) {
+                        let matched = cap[1].trim();
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {matched}");
+
+                        match serde_json::from_str::<serde_json::Value>(matched) {
+                            Ok(value) => values.push(value),
+                            Err(e) => {
+                                let err_msg = format!(
+                                    "Failed to deserialize generated tool calls. Reason: {e}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        }
+                    }
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        let name = match value.get("name") {
+                            Some(name) => name.to_string().replace("\"", ""),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the name of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let arguments = match value.get("arguments") {
+                            Some(arguments) => {
+                                if arguments.is_string() {
+                                    arguments.as_str().unwrap().to_string()
+                                } else if arguments.is_object() {
+                                    let map = arguments.as_object().unwrap();
+
+                                    #[cfg(feature = "logging")]
+                                    info!(target: "stdout", "func arguments: {map:?}");
+
+                                    serde_json::to_string(map).unwrap()
+                                } else {
+                                    serde_json::to_string(arguments).unwrap()
+                                }
+                            }
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the arguments of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let function = Function { name, arguments };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = if tool_calls.is_empty() {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: Some(input.to_owned()),
+                            tool_calls: vec![],
+                        }
+                    } else {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: None,
+                            tool_calls,
+                        }
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::Llama3Tool => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            let re = match regex::Regex::new(r"^\{(.|\r|\n)*\}$") {
+                Ok(re) => re,
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return Err(LlamaCoreError::Operation(err_msg));
+                }
+            };
+
+            if re.is_match(input) {
+                match serde_json::from_str::<serde_json::Value>(input) {
+                    Ok(value) => {
+                        let values: Vec<serde_json::Value> = vec![value];
+
+                        let mut tool_calls: Vec<ToolCall> = vec![];
+                        for value in values.iter() {
+                            let name = match value.get("name") {
+                                Some(name) => name.to_string().replace("\"", ""),
+                                None => {
+                                    let err_msg = format!(
+                                        "Failed to get the name of the function. Tool call: {value:?}"
+                                    );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            };
+
+                            let arguments = match value.get("parameters") {
+                                Some(arguments) => arguments.to_string(),
+                                None => {
+                                    let err_msg = format!(
+                                        "Failed to get the arguments of the function. Tool call: {value:?}"
+                                    );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            };
+
+                            let function = Function { name, arguments };
+
+                            let tool_call = ToolCall {
+                                id: "call_abc123".to_string(),
+                                ty: "function".to_string(),
+                                function,
+                            };
+
+                            tool_calls.push(tool_call);
+                        }
+
+                        let parsed = ParseResult {
+                            raw: input.to_owned(),
+                            content: None,
+                            tool_calls,
+                        };
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "parsed result: {parsed:?}");
+
+                        Ok(parsed)
+                    }
+                    Err(e) => {
+                        let err_msg =
+                            format!("Failed to deserialize generated tool calls. Reason: {e}");
+
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        Err(LlamaCoreError::Operation(err_msg))
+                    }
+                }
+            } else {
+                let parsed = ParseResult {
+                    raw: input.to_owned(),
+                    content: None,
+                    tool_calls: vec![],
+                };
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "parsed result: {parsed:?}");
+
+                Ok(parsed)
+            }
+        }
+        PromptTemplateType::InternLM2Tool => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            let blocks: Vec<&str> = input.trim().split("<|action_start|><|plugin|>").collect();
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "blocks: {blocks:?}");
+
+            let mut tool_calls: Vec<ToolCall> = vec![];
+            let mut content = String::new();
+            for block in blocks {
+                let block = block.trim();
+                if !block.is_empty() {
+                    if block.ends_with("<|action_end|>") {
+                        let value = block.trim().trim_end_matches("<|action_end|>");
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "tool call: {value}");
+
+                        match serde_json::from_str::<serde_json::Value>(value) {
+                            Ok(value) => {
+                                let name = match value.get("name") {
+                                    Some(name) => name.to_string().replace("\"", ""),
+                                    None => {
+                                        let err_msg = format!(
+                                            "Failed to get the name of the function. Tool call: {value:?}"
+                                        );
+
+                                        #[cfg(feature = "logging")]
+                                        error!(target: "stdout", "{}", &err_msg);
+
+                                        return Err(LlamaCoreError::Operation(err_msg));
+                                    }
+                                };
+
+                                let arguments = match value.get("parameters") {
+                                    Some(arguments) => arguments.to_string(),
+                                    None => {
+                                        let err_msg = format!(
+                                            "Failed to get the arguments of the function. Tool call: {value:?}"
+                                        );
+
+                                        #[cfg(feature = "logging")]
+                                        error!(target: "stdout", "{}", &err_msg);
+
+                                        return Err(LlamaCoreError::Operation(err_msg));
+                                    }
+                                };
+
+                                let function = Function { name, arguments };
+
+                                let tool_call = ToolCall {
+                                    id: "call_abc123".to_string(),
+                                    ty: "function".to_string(),
+                                    function,
+                                };
+
+                                tool_calls.push(tool_call);
+                            }
+                            Err(e) => {
+                                let err_msg = format!(
+                                    "Failed to deserialize generated tool calls. Reason: {e}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        }
+                    } else {
+                        content.push_str(block);
+                        content.push('\n');
+                    }
+                }
+            }
+
+            let parsed = match content.is_empty() {
+                true => ParseResult {
+                    raw: input.to_owned(),
+                    content: None,
+                    tool_calls,
+                },
+                false => ParseResult {
+                    raw: input.to_owned(),
+                    content: Some(content.trim().to_owned()),
+                    tool_calls,
+                },
+            };
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "parsed result: {parsed:?}");
+
+            Ok(parsed)
+        }
+        PromptTemplateType::NemotronTool => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            match regex::Regex::new(r"(?s)<toolcall>\s*(.*?)\s*</toolcall>") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    for cap in re.captures_iter(input) {
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {}", &cap[0]);
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "extracted: {}", &cap[1]);
+
+                        let matched = cap[1].trim();
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {matched}");
+
+                        match serde_json::from_str::<serde_json::Value>(matched) {
+                            Ok(value) => values.push(value),
+                            Err(e) => {
+                                let err_msg = format!(
+                                    "Failed to deserialize generated tool calls. Reason: {e}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        }
+                    }
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        let name = match value.get("name") {
+                            Some(name) => name.to_string().replace("\"", ""),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the name of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let arguments = match value.get("arguments") {
+                            Some(arguments) => arguments.to_string(),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the arguments of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let function = Function { name, arguments };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls,
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::FunctionaryV32 => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            match regex::Regex::new(r">>>\s*(\w+)\s*\{(.*)\}<\|eot_id\|>") {
+                Ok(re) => {
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for cap in re.captures_iter(input) {
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "func_name: {}", &cap[1]);
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "arguments: {}", &cap[2]);
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function: Function {
+                                name: cap[1].to_string(),
+                                arguments: cap[2].to_string(),
+                            },
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls,
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let warn_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    warn!(target: "stdout", "{}", &warn_msg);
+
+                    Ok(ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls: vec![],
+                    })
+                }
+            }
+        }
+        PromptTemplateType::FunctionaryV31 => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            match regex::Regex::new(r"<function=(\w+)>\s*(\{.*?\})</function>") {
+                Ok(re) => {
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for cap in re.captures_iter(input) {
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "func_name: {}", &cap[1]);
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "arguments: {}", &cap[2]);
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function: Function {
+                                name: cap[1].to_string(),
+                                arguments: cap[2].to_string(),
+                            },
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls,
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let warn_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    warn!(target: "stdout", "{}", &warn_msg);
+
+                    Ok(ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls: vec![],
+                    })
+                }
+            }
+        }
+        PromptTemplateType::MistralSmallTool => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input}");
+
+            match regex::Regex::new(r"\[TOOL_CALLS\]\s*(\[(.*?)\])") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    if let Some(cap) = re.captures(input) {
+                        let matched = cap[1].trim();
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {matched}");
+
+                        match serde_json::from_str::<Vec<serde_json::Value>>(matched) {
+                            Ok(vals) => values = vals,
+                            Err(e) => {
+                                let err_msg = format!(
+                                    "Failed to deserialize generated tool calls. Reason: {e}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        }
+                    };
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        if let Some(object_map) = value.as_object() {
+                            if object_map.contains_key("function") {
+                                let mut function = Function {
+                                    name: String::new(),
+                                    arguments: String::new(),
+                                };
+
+                                let value = object_map.get("function").unwrap();
+                                let func_map = value.as_object().unwrap();
+                                if func_map.contains_key("name") {
+                                    let func_name = func_map.get("name").unwrap().as_str().unwrap();
+                                    #[cfg(feature = "logging")]
+                                    debug!(target: "stdout", "Function name: {func_name:?}");
+
+                                    function.name = func_name.to_string();
+                                }
+                                if func_map.contains_key("arguments") {
+                                    let args = func_map.get("arguments").unwrap();
+                                    let arguments = args.to_string();
+                                    #[cfg(feature = "logging")]
+                                    debug!(target: "stdout", "Arguments: {arguments:?}");
+
+                                    function.arguments = arguments;
+                                }
+
+                                let tool_call = ToolCall {
+                                    id: "call_abc123".to_string(),
+                                    ty: "function".to_string(),
+                                    function,
+                                };
+
+                                tool_calls.push(tool_call);
+                            } else if object_map.contains_key("name") {
+                                let mut function = Function {
+                                    name: String::new(),
+                                    arguments: String::new(),
+                                };
+
+                                let name = object_map.get("name").unwrap().as_str().unwrap();
+                                #[cfg(feature = "logging")]
+                                debug!(target: "stdout", "name: {name:?}");
+                                function.name = name.to_string();
+
+                                if object_map.contains_key("arguments") {
+                                    let args = object_map.get("arguments").unwrap();
+                                    let arguments = args.to_string();
+                                    #[cfg(feature = "logging")]
+                                    debug!(target: "stdout", "Arguments: {arguments:?}");
+
+                                    function.arguments = arguments;
+                                }
+
+                                let tool_call = ToolCall {
+                                    id: "call_abc123".to_string(),
+                                    ty: "function".to_string(),
+                                    function,
+                                };
+
+                                tool_calls.push(tool_call);
+                            }
+                        }
+                    }
+
+                    let parsed = ParseResult {
+                        raw: input.to_owned(),
+                        content: None,
+                        tool_calls,
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::Llama4Chat => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input:?}");
+
+            let mut tool_calls: Vec<ToolCall> = vec![];
+            if let Ok(value) = serde_json::from_str::<serde_json::Value>(input) {
+                match value.as_object() {
+                    Some(object_map) => {
+                        #[cfg(feature = "logging")]
+                        debug!(target: "stdout", "object_map: {object_map:?}");
+
+                        // parse function name
+                        if object_map.contains_key("name") {
+                            let name = object_map.get("name").unwrap().as_str().unwrap();
+
+                            #[cfg(feature = "logging")]
+                            debug!(target: "stdout", "name: {name:?}");
+
+                            let mut function = Function {
+                                name: name.to_string(),
+                                arguments: String::new(),
+                            };
+
+                            // parse function arguments
+                            if object_map.contains_key("parameters") {
+                                let args = object_map.get("parameters").unwrap();
+                                let arguments = args.to_string();
+
+                                #[cfg(feature = "logging")]
+                                debug!(target: "stdout", "arguments: {:?}", &arguments);
+
+                                function.arguments = arguments;
+                            }
+
+                            tool_calls.push(ToolCall {
+                                id: "call_abc123".to_string(),
+                                ty: "function".to_string(),
+                                function,
+                            });
+                        } else {
+                            let err_msg = format!(
+                                "Failed to get the name of the function. raw input: {input:?}"
+                            );
+
+                            #[cfg(feature = "logging")]
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            return Err(LlamaCoreError::Operation(err_msg));
+                        }
+                    }
+                    None => {
+                        let err_msg = format!("Failed to parse the JSON string. JSON: {input}");
+
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        return Err(LlamaCoreError::Operation(err_msg));
+                    }
+                }
+            }
+
+            let parsed = ParseResult {
+                raw: input.to_owned(),
+                content: None,
+                tool_calls,
+            };
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "parsed result: {parsed:?}");
+
+            Ok(parsed)
+        }
+        // most chat templates with tools ask the model to call them in `<tool_call>` tags
+        PromptTemplateType::Qwen3NoThink
+        | PromptTemplateType::Smol3NoThink
+        | PromptTemplateType::Jinja => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input:?}");
+
+            match regex::Regex::new(r"(?s)<tool_call>((.|\r|\n)*?)</tool_call>") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    for cap in re.captures_iter(input) {
+                        let mut matched = cap[1].trim();
+
+                        if matched.starts_with("\\n") {
+                            matched = matched.trim_start_matches("\\n");
+                        }
+
+                        if matched.ends_with("\\n") {
+                            matched = matched.trim_end_matches("\\n");
+                        }
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {matched:#?}");
+
+                        if !matched.is_empty() {
+                            match serde_json::from_str::<serde_json::Value>(matched) {
+                                Ok(value) => values.push(value),
+                                Err(e) => {
+                                    let err_msg = format!(
+                                    "Failed to deserialize generated tool calls: {matched:#?}. Reason: {e}"
+                                );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            }
+                        }
+                    }
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        let name = match value.get("name") {
+                            Some(name) => name.to_string().replace("\"", ""),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the name of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let arguments = match value.get("arguments") {
+                            Some(arguments) => {
+                                if arguments.is_string() {
+                                    arguments.as_str().unwrap().to_string()
+                                } else if arguments.is_object() {
+                                    let map = arguments.as_object().unwrap();
+
+                                    #[cfg(feature = "logging")]
+                                    info!(target: "stdout", "func arguments: {map:?}");
+
+                                    serde_json::to_string(map).unwrap()
+                                } else {
+                                    serde_json::to_string(arguments).unwrap()
+                                }
+                            }
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the arguments of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let function = Function { name, arguments };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = if tool_calls.is_empty() {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: Some(input.to_owned()),
+                            tool_calls: vec![],
+                        }
+                    } else {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: None,
+                            tool_calls,
+                        }
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::Gemma3 => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input:?}");
+
+            match regex::Regex::new(r"(?s)```json\s*(.*?)\s*```") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    for cap in re.captures_iter(input) {
+                        let mut matched = cap[1].trim();
+
+                        if matched.starts_with("\\n") {
+                            matched = matched.trim_start_matches("\\n");
+                        }
+
+                        if matched.ends_with("\\n") {
+                            matched = matched.trim_end_matches("\\n");
+                        }
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {matched:#?}");
+
+                        if !matched.is_empty() {
+                            match serde_json::from_str::<serde_json::Value>(matched) {
+                                Ok(value) => values.push(value),
+                                Err(e) => {
+                                    let err_msg = format!(
+                                    "Failed to deserialize generated tool calls: {matched:#?}. Reason: {e}"
+                                );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            }
+                        }
+                    }
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        let name = match value.get("name") {
+                            Some(name) => name.to_string().replace("\"", ""),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the name of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let arguments = match value.get("arguments") {
+                            Some(arguments) => {
+                                if arguments.is_string() {
+                                    arguments.as_str().unwrap().to_string()
+                                } else if arguments.is_object() {
+                                    let map = arguments.as_object().unwrap();
+
+                                    #[cfg(feature = "logging")]
+                                    info!(target: "stdout", "func arguments: {map:?}");
+
+                                    serde_json::to_string(map).unwrap()
+                                } else {
+                                    serde_json::to_string(arguments).unwrap()
+                                }
+                            }
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the arguments of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let function = Function { name, arguments };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = if tool_calls.is_empty() {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: Some(input.to_owned()),
+                            tool_calls: vec![],
+                        }
+                    } else {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: None,
+                            tool_calls,
+                        }
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::GptOss => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "raw input: {input:?}");
+
+            // Match strings ending with: <|channel|>commentary to=functions.xxxxx <|constrain|>json<|message|>yyyyy<|call|>
+            match regex::Regex::new(
+                r"<\|channel\|>commentary to=functions\.([^<\s]+)\s*<\|constrain\|>json<\|message\|>([^<]*)<\|call\|>$",
+            ) {
+                Ok(re) => {
+                    if let Some(cap) = re.captures(input) {
+                        let function_name = cap[1].trim();
+                        let arguments = cap[2].trim();
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "extracted function_name: {function_name}, arguments: {arguments}");
+
+                        let function = Function {
+                            name: function_name.to_string(),
+                            arguments: arguments.to_string(),
+                        };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        let parsed = ParseResult {
+                            raw: input.to_owned(),
+                            content: None,
+                            tool_calls: vec![tool_call],
+                        };
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "parsed result: {parsed:?}");
+
+                        Ok(parsed)
+                    } else {
+                        match regex::Regex::new(r"(?s)```json\s*(.*?)\s*```") {
+                            Ok(re) => {
+                                let mut values: Vec<serde_json::Value> = vec![];
+                                for cap in re.captures_iter(input) {
+                                    let mut matched = cap[1].trim();
+
+                                    if matched.starts_with("\\n") {
+                                        matched = matched.trim_start_matches("\\n");
+                                    }
+
+                                    if matched.ends_with("\\n") {
+                                        matched = matched.trim_end_matches("\\n");
+                                    }
+
+                                    #[cfg(feature = "logging")]
+                                    info!(target: "stdout", "captured: {matched:#?}");
+
+                                    if !matched.is_empty() {
+                                        match serde_json::from_str::<serde_json::Value>(matched) {
+                                            Ok(value) => values.push(value),
+                                            Err(e) => {
+                                                let err_msg = format!(
+                                                "Failed to deserialize generated tool calls: {matched:#?}. Reason: {e}"
+                                            );
+
+                                                #[cfg(feature = "logging")]
+                                                error!(target: "stdout", "{}", &err_msg);
+
+                                                return Err(LlamaCoreError::Operation(err_msg));
+                                            }
+                                        }
+                                    }
+                                }
+
+                                let mut tool_calls: Vec<ToolCall> = vec![];
+                                for value in values.iter() {
+                                    let name = match value.get("name") {
+                                        Some(name) => name.to_string().replace("\"", ""),
+                                        None => {
+                                            let err_msg = format!(
+                                                "Failed to get the name of the function. Tool call: {value:?}"
+                                            );
+
+                                            #[cfg(feature = "logging")]
+                                            error!(target: "stdout", "{}", &err_msg);
+
+                                            return Err(LlamaCoreError::Operation(err_msg));
+                                        }
+                                    };
+
+                                    let arguments = match value.get("arguments") {
+                                        Some(arguments) => {
+                                            if arguments.is_string() {
+                                                arguments.as_str().unwrap().to_string()
+                                            } else if arguments.is_object() {
+                                                let map = arguments.as_object().unwrap();
+
+                                                #[cfg(feature = "logging")]
+                                                info!(target: "stdout", "func arguments: {map:?}");
+
+                                                serde_json::to_string(map).unwrap()
+                                            } else {
+                                                serde_json::to_string(arguments).unwrap()
+                                            }
+                                        }
+                                        None => {
+                                            let err_msg = format!(
+                                                "Failed to get the arguments of the function. Tool call: {value:?}"
+                                            );
+
+                                            #[cfg(feature = "logging")]
+                                            error!(target: "stdout", "{}", &err_msg);
+
+                                            return Err(LlamaCoreError::Operation(err_msg));
+                                        }
+                                    };
+
+                                    let function = Function { name, arguments };
+
+                                    let tool_call = ToolCall {
+                                        id: "call_abc123".to_string(),
+                                        ty: "function".to_string(),
+                                        function,
+                                    };
+
+                                    tool_calls.push(tool_call);
+                                }
+
+                                let parsed = if tool_calls.is_empty() {
+                                    ParseResult {
+                                        raw: input.to_owned(),
+                                        content: Some(input.to_owned()),
+                                        tool_calls: vec![],
+                                    }
+                                } else {
+                                    ParseResult {
+                                        raw: input.to_owned(),
+                                        content: Some(input.to_owned()),
+                                        tool_calls,
+                                    }
+                                };
+
+                                #[cfg(feature = "logging")]
+                                info!(target: "stdout", "parsed result: {parsed:?}");
+
+                                Ok(parsed)
+                            }
+                            Err(e) => {
+                                let err_msg =
+                                    format!("Failed to create a regex pattern. Reason: {e}");
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                Err(LlamaCoreError::Operation(err_msg))
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        PromptTemplateType::Qwen3Agent => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Raw input to tool call parser: {input:?}");
+
+            // detect <action> tags
+            match regex::Regex::new(r"<action>(.*?)</action>")
+                .unwrap()
+                .captures(input)
+            {
+                Some(captures) => {
+                    let action = captures.get(1).unwrap().as_str();
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "Action: {action}");
+
+                    match serde_json::from_str::<serde_json::Value>(action) {
+                        Ok(value) => {
+                            let name = match value.get("name") {
+                                Some(name) => name.to_string().replace("\"", ""),
+                                None => {
+                                    let err_msg = format!(
+                                        "Failed to get the name of the function. Tool call: {value:?}"
+                                    );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            };
+
+                            let arguments = match value.get("arguments") {
+                                Some(arguments) => {
+                                    if arguments.is_string() {
+                                        arguments.as_str().unwrap().to_string()
+                                    } else if arguments.is_object() {
+                                        let map = arguments.as_object().unwrap();
+
+                                        #[cfg(feature = "logging")]
+                                        info!(target: "stdout", "func arguments: {map:?}");
+
+                                        serde_json::to_string(map).unwrap()
+                                    } else {
+                                        serde_json::to_string(arguments).unwrap()
+                                    }
+                                }
+                                None => {
+                                    let err_msg = format!(
+                                        "Failed to get the arguments of the function. Tool call: {value:?}"
+                                    );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            };
+
+                            let function = Function { name, arguments };
+
+                            let tool_call = ToolCall {
+                                id: "call_abc123".to_string(),
+                                ty: "function".to_string(),
+                                function,
+                            };
+
+                            Ok(ParseResult {
+                                raw: input.to_owned(),
+                                content: Some(input.to_owned()),
+                                tool_calls: vec![tool_call],
+                            })
+                        }
+                        Err(e) => {
+                            let err_msg = format!(
+                            "Failed to deserialize generated tool calls: {action:#?}. Reason: {e}"
+                        );
+
+                            #[cfg(feature = "logging")]
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            Err(LlamaCoreError::Operation(err_msg))
+                        }
+                    }
+                }
+                None => match input.contains("<final_answer>") {
+                    true => Ok(ParseResult {
+                        raw: input.to_owned(),
+                        content: Some(input.to_owned()),
+                        tool_calls: vec![],
+                    }),
+                    false => {
+                        let content = format!("<final_answer>{}</final_answer>", input.trim());
+
+                        Ok(ParseResult {
+                            raw: input.to_owned(),
+                            content: Some(content),
+                            tool_calls: vec![],
+                        })
+                    }
+                },
+            }
+        }
+        PromptTemplateType::SeedOssNoThink | PromptTemplateType::SeedOssThink => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Raw input to tool call parser: {input:?}");
+
+            match regex::Regex::new(r"```json\n([\s\S]*?)\n") {
+                Ok(re) => {
+                    let mut values: Vec<serde_json::Value> = vec![];
+                    for cap in re.captures_iter(input) {
+                        let mut matched = cap[1].trim();
+
+                        if matched.starts_with("\\n") {
+                            matched = matched.trim_start_matches("\\n");
+                        }
+
+                        if matched.ends_with("\\n") {
+                            matched = matched.trim_end_matches("\\n");
+                        }
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "captured: {matched:#?}");
+
+                        if !matched.is_empty() {
+                            match serde_json::from_str::<serde_json::Value>(matched) {
+                                Ok(value) => values.push(value),
+                                Err(e) => {
+                                    let err_msg = format!(
+                                    "Failed to deserialize generated tool calls: {matched:#?}. Reason: {e}"
+                                );
+
+                                    #[cfg(feature = "logging")]
+                                    error!(target: "stdout", "{}", &err_msg);
+
+                                    return Err(LlamaCoreError::Operation(err_msg));
+                                }
+                            }
+                        }
+                    }
+
+                    let mut tool_calls: Vec<ToolCall> = vec![];
+                    for value in values.iter() {
+                        let name = match value.get("name") {
+                            Some(name) => name.to_string().replace("\"", ""),
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the name of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let arguments = match value.get("arguments") {
+                            Some(arguments) => {
+                                if arguments.is_string() {
+                                    arguments.as_str().unwrap().to_string()
+                                } else if arguments.is_object() {
+                                    let map = arguments.as_object().unwrap();
+
+                                    #[cfg(feature = "logging")]
+                                    info!(target: "stdout", "func arguments: {map:?}");
+
+                                    serde_json::to_string(map).unwrap()
+                                } else {
+                                    serde_json::to_string(arguments).unwrap()
+                                }
+                            }
+                            None => {
+                                let err_msg = format!(
+                                    "Failed to get the arguments of the function. Tool call: {value:?}"
+                                );
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                        };
+
+                        let function = Function { name, arguments };
+
+                        let tool_call = ToolCall {
+                            id: "call_abc123".to_string(),
+                            ty: "function".to_string(),
+                            function,
+                        };
+
+                        tool_calls.push(tool_call);
+                    }
+
+                    let parsed = if tool_calls.is_empty() {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: Some(input.to_owned()),
+                            tool_calls: vec![],
+                        }
+                    } else {
+                        ParseResult {
+                            raw: input.to_owned(),
+                            content: None,
+                            tool_calls,
+                        }
+                    };
+
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "parsed result: {parsed:?}");
+
+                    Ok(parsed)
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to create a regex pattern. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Operation(err_msg))
+                }
+            }
+        }
+        _ => {
+            let err_msg = format!(
+                "The tool use is only supported for prompt templates: {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, and {}.",
+                PromptTemplateType::MistralTool,
+                PromptTemplateType::ChatMLTool,
+                PromptTemplateType::GroqLlama3Tool,
+                PromptTemplateType::Llama3Tool,
+                PromptTemplateType::InternLM2Tool,
+                PromptTemplateType::NemotronTool,
+                PromptTemplateType::FunctionaryV32,
+                PromptTemplateType::MistralSmallTool,
+                PromptTemplateType::Llama4Chat,
+                PromptTemplateType::Qwen3NoThink,
+                PromptTemplateType::Smol3NoThink,
+                PromptTemplateType::Gemma3,
+                PromptTemplateType::GptOss,
+                PromptTemplateType::Qwen3Agent,
+                PromptTemplateType::SeedOssNoThink,
+                PromptTemplateType::SeedOssThink
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::InvalidRequest(err_msg))
+        }
+    }
+}
+
+/// The tool calls parsed from the output of a model.
+#[derive(Debug)]
+pub(crate) struct ParseResult {
+    /// The output.
+    pub(crate) raw: String,
+    /// The text of the output around the tool calls.
+    pub(crate) content: Option<String>,
+    /// The tool calls.
+    pub(crate) tool_calls: Vec<ToolCall>,
+}
@@ -1,2 +1,5 @@
 pub mod chat_completions;
+mod engine;
+mod reasoning;
 pub mod responses;
+mod truncation;
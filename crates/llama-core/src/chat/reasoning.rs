@@ -0,0 +1,462 @@
+//! Define the extraction of the reasoning from the output of thinking models.
+//!
+//! Thinking models write their reasoning before the answer, between markers which depend on the prompt template, such as `<think>…</think>`, `<seed:think>…</seed:think>` or the `analysis` channel of the harmony format. The [`ReasoningParser`] splits the output into the reasoning and the answer one token at a time, so that the same extraction is used by the stream and non-stream modes.
+
+use chat_prompts::PromptTemplateType;
+
+/// The markers around the reasoning in the output of a prompt template.
+#[derive(Debug, Clone, Copy)]
+struct ReasoningMarkers {
+    /// The marker opening the reasoning.
+    start: &'static str,
+    /// The marker closing the reasoning.
+    end: &'static str,
+    /// The marker after which the answer starts. The text between `end` and this marker is dropped.
+    answer_start: Option<&'static str>,
+    /// Whether the reasoning is opened by the prompt, so that the output starts with the reasoning.
+    opened_by_prompt: bool,
+}
+
+/// Returns the reasoning markers of the prompt template, or `None` if the models using it do not think.
+fn reasoning_markers(template: PromptTemplateType) -> Option<ReasoningMarkers> {
+    let think = ReasoningMarkers {
+        start: "<think>",
+        end: "</think>",
+        answer_start: None,
+        opened_by_prompt: false,
+    };
+
+    match template {
+        PromptTemplateType::ChatML
+        | PromptTemplateType::ChatMLTool
+        | PromptTemplateType::Qwen3NoThink
+        | PromptTemplateType::Qwen3Agent
+        | PromptTemplateType::Smol3NoThink
+        | PromptTemplateType::SeedReasoning
+        | PromptTemplateType::Jinja => Some(think),
+        // the prompt ends with `<|im_start|>think`
+        PromptTemplateType::ChatMLThink => Some(ReasoningMarkers {
+            opened_by_prompt: true,
+            ..think
+        }),
+        // the prompt ends with `<thought>`
+        PromptTemplateType::ExaoneDeepChat => Some(ReasoningMarkers {
+            start: "<thought>",
+            end: "</thought>",
+            answer_start: None,
+            opened_by_prompt: true,
+        }),
+        PromptTemplateType::SeedOssThink | PromptTemplateType::SeedOssNoThink => {
+            Some(ReasoningMarkers {
+                start: "<seed:think>",
+                end: "</seed:think>",
+                answer_start: None,
+                opened_by_prompt: false,
+            })
+        }
+        PromptTemplateType::GptOss => Some(ReasoningMarkers {
+            start: "<|channel|>analysis<|message|>",
+            end: "<|end|>",
+            answer_start: Some("<|channel|>final<|message|>"),
+            opened_by_prompt: false,
+        }),
+        _ => None,
+    }
+}
+
+/// The reasoning and the answer split from a piece of the output.
+#[derive(Debug, Default)]
+pub(crate) struct ReasoningDelta {
+    /// The text of the reasoning.
+    pub(crate) reasoning: String,
+    /// The text of the answer.
+    pub(crate) answer: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParserState {
+    /// It is not known yet whether the output starts with the reasoning.
+    Detecting,
+    /// In the reasoning.
+    Reasoning,
+    /// After the reasoning, and before the marker starting the answer.
+    Header,
+    /// In the answer.
+    Answer,
+}
+
+/// Splits the output of a model into the reasoning and the answer, one token at a time.
+///
+/// The text which may be the beginning of a marker is held back until the next token tells whether it is a marker.
+#[derive(Debug)]
+pub(crate) struct ReasoningParser {
+    markers: ReasoningMarkers,
+    state: ParserState,
+    // the text pushed but not split yet
+    buffer: String,
+    // whether the whitespace at the beginning of the reasoning or of the answer after it is dropped
+    trim_start: bool,
+    // the number of tokens of the reasoning, including the markers
+    reasoning_tokens: u64,
+}
+impl ReasoningParser {
+    /// Creates a parser for the output of the prompt template, or returns `None` if the models using it do not think.
+    pub(crate) fn new(template: PromptTemplateType) -> Option<Self> {
+        let markers = reasoning_markers(template)?;
+
+        Some(Self {
+            markers,
+            state: Self::initial_state(&markers),
+            buffer: String::new(),
+            trim_start: markers.opened_by_prompt,
+            reasoning_tokens: 0,
+        })
+    }
+
+    fn initial_state(markers: &ReasoningMarkers) -> ParserState {
+        match markers.opened_by_prompt {
+            true => ParserState::Reasoning,
+            false => ParserState::Detecting,
+        }
+    }
+
+    /// The number of tokens of the reasoning pushed so far.
+    pub(crate) fn reasoning_tokens(&self) -> u64 {
+        self.reasoning_tokens
+    }
+
+    /// Resets the parser for the output of the next choice.
+    pub(crate) fn reset(&mut self) {
+        self.state = Self::initial_state(&self.markers);
+        self.buffer.clear();
+        self.trim_start = self.markers.opened_by_prompt;
+        self.reasoning_tokens = 0;
+    }
+
+    /// Pushes the text of the next token, and returns the reasoning and the answer which can be told apart so far.
+    pub(crate) fn push(&mut self, token: &str) -> ReasoningDelta {
+        let was_reasoning = self.in_reasoning();
+
+        self.buffer.push_str(token);
+        let mut delta = ReasoningDelta::default();
+        self.split(&mut delta);
+
+        if was_reasoning || self.in_reasoning() {
+            self.reasoning_tokens += 1;
+        }
+
+        delta
+    }
+
+    /// Returns the text held back at the end of the output.
+    pub(crate) fn flush(&mut self) -> ReasoningDelta {
+        let text = std::mem::take(&mut self.buffer);
+
+        match self.state {
+            ParserState::Reasoning => ReasoningDelta {
+                reasoning: text,
+                answer: String::new(),
+            },
+            // the answer is not marked, e.g. the model calls a tool instead of answering
+            ParserState::Detecting | ParserState::Header | ParserState::Answer => ReasoningDelta {
+                reasoning: String::new(),
+                answer: text,
+            },
+        }
+    }
+
+    /// Drops the whitespace at the beginning of the buffer if required, and returns `false` if nothing is left.
+    fn take_start(&mut self) -> bool {
+        if self.trim_start {
+            let text = self.buffer.trim_start();
+            if text.is_empty() {
+                self.buffer.clear();
+
+                return false;
+            }
+
+            self.buffer = text.to_owned();
+            self.trim_start = false;
+        }
+
+        true
+    }
+
+    fn in_reasoning(&self) -> bool {
+        matches!(self.state, ParserState::Reasoning | ParserState::Header)
+    }
+
+    fn split(&mut self, delta: &mut ReasoningDelta) {
+        loop {
+            match self.state {
+                ParserState::Detecting => {
+                    let text = self.buffer.trim_start();
+                    if let Some(rest) = text.strip_prefix(self.markers.start) {
+                        self.buffer = rest.to_owned();
+                        self.state = ParserState::Reasoning;
+                        self.trim_start = true;
+                    } else if text.is_empty() || self.markers.start.starts_with(text) {
+                        return;
+                    } else {
+                        self.state = ParserState::Answer;
+                    }
+                }
+                ParserState::Reasoning => {
+                    // the model may repeat the marker opened by the prompt
+                    if self.markers.opened_by_prompt {
+                        if let Some(rest) =
+                            self.buffer.trim_start().strip_prefix(self.markers.start)
+                        {
+                            self.buffer = rest.to_owned();
+                        }
+                    }
+
+                    if !self.take_start() {
+                        return;
+                    }
+
+                    match self.buffer.find(self.markers.end) {
+                        Some(index) => {
+                            delta.reasoning.push_str(&self.buffer[..index]);
+                            self.buffer = self.buffer[index + self.markers.end.len()..].to_owned();
+                            self.state = match self.markers.answer_start {
+                                Some(_) => ParserState::Header,
+                                None => ParserState::Answer,
+                            };
+                            self.trim_start = true;
+                        }
+                        None => {
+                            let len =
+                                self.buffer.len() - held_back_len(&self.buffer, self.markers.end);
+                            delta.reasoning.push_str(&self.buffer[..len]);
+                            self.buffer.drain(..len);
+
+                            return;
+                        }
+                    }
+                }
+                ParserState::Header => {
+                    let answer_start = self.markers.answer_start.unwrap_or_default();
+                    match self.buffer.find(answer_start) {
+                        Some(index) => {
+                            self.buffer.drain(..index + answer_start.len());
+                            self.state = ParserState::Answer;
+                        }
+                        None => return,
+                    }
+                }
+                ParserState::Answer => {
+                    if !self.take_start() {
+                        return;
+                    }
+
+                    delta.answer.push_str(&self.buffer);
+                    self.buffer.clear();
+
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the length of the longest suffix of the text which is the beginning of the marker.
+fn held_back_len(text: &str, marker: &str) -> usize {
+    (1..marker.len().min(text.len() + 1))
+        .rev()
+        .find(|&len| {
+            text.is_char_boundary(text.len() - len) && marker.starts_with(&text[text.len() - len..])
+        })
+        .unwrap_or(0)
+}
+
+/// The reasoning and the answer split from the whole output of a model.
+#[derive(Debug)]
+pub(crate) struct ReasoningSplit {
+    /// The reasoning, if any.
+    pub(crate) reasoning: Option<String>,
+    /// The answer, which is the whole output if the model does not think.
+    pub(crate) answer: String,
+    /// The number of tokens of the reasoning, or `None` if the prompt template does not separate the reasoning.
+    pub(crate) reasoning_tokens: Option<u64>,
+}
+
+/// Splits the whole output of a model into the reasoning and the answer.
+///
+/// # Arguments
+///
+/// * `output` - The output of the model.
+///
+/// * `token_ends` - The end offsets of the tokens in the output, which are used to count the tokens of the reasoning.
+///
+/// * `template` - The prompt template of the model.
+pub(crate) fn split_reasoning(
+    output: &str,
+    token_ends: &[usize],
+    template: PromptTemplateType,
+) -> ReasoningSplit {
+    let mut parser = match ReasoningParser::new(template) {
+        Some(parser) => parser,
+        None => {
+            return ReasoningSplit {
+                reasoning: None,
+                answer: output.to_owned(),
+                reasoning_tokens: None,
+            }
+        }
+    };
+
+    let mut reasoning = String::new();
+    let mut answer = String::new();
+    let mut extend = |delta: ReasoningDelta| {
+        reasoning.push_str(&delta.reasoning);
+        answer.push_str(&delta.answer);
+    };
+
+    // push the output token by token, as in the stream mode
+    let mut start = 0;
+    for &end in token_ends {
+        // the bytes of a character split across several tokens are pushed with the last one
+        match end >= start && end <= output.len() && output.is_char_boundary(end) {
+            true => {
+                extend(parser.push(&output[start..end]));
+                start = end;
+            }
+            false => extend(parser.push("")),
+        }
+    }
+    if start < output.len() {
+        extend(parser.push(&output[start..]));
+    }
+    extend(parser.flush());
+
+    let reasoning = reasoning.trim();
+
+    ReasoningSplit {
+        reasoning: (!reasoning.is_empty()).then(|| reasoning.to_owned()),
+        answer,
+        reasoning_tokens: Some(parser.reasoning_tokens()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits the output made of the tokens.
+    fn split(tokens: &[&str], template: PromptTemplateType) -> ReasoningSplit {
+        let output = tokens.concat();
+        let token_ends = tokens
+            .iter()
+            .scan(0, |end, token| {
+                *end += token.len();
+                Some(*end)
+            })
+            .collect::<Vec<_>>();
+
+        split_reasoning(&output, &token_ends, template)
+    }
+
+    #[test]
+    fn test_split_think_markers_across_tokens() {
+        let split = split(
+            &["<th", "ink>", "\nI think", "</th", "ink>", "\n\nHello"],
+            PromptTemplateType::ChatML,
+        );
+
+        assert_eq!(split.reasoning.as_deref(), Some("I think"));
+        assert_eq!(split.answer, "Hello");
+        assert_eq!(split.reasoning_tokens, Some(4));
+    }
+
+    #[test]
+    fn test_split_output_without_reasoning() {
+        let split = split(&["Hello", " world"], PromptTemplateType::ChatML);
+
+        assert_eq!(split.reasoning, None);
+        assert_eq!(split.answer, "Hello world");
+        assert_eq!(split.reasoning_tokens, Some(0));
+    }
+
+    #[test]
+    fn test_split_template_without_reasoning() {
+        let split = split(
+            &["<think>", "a", "</think>", "b"],
+            PromptTemplateType::Llama3Chat,
+        );
+
+        assert_eq!(split.reasoning, None);
+        assert_eq!(split.answer, "<think>a</think>b");
+        assert_eq!(split.reasoning_tokens, None);
+    }
+
+    #[test]
+    fn test_split_reasoning_opened_by_prompt() {
+        let split = split(
+            &["Let me see.", "</think>", "Answer"],
+            PromptTemplateType::ChatMLThink,
+        );
+
+        assert_eq!(split.reasoning.as_deref(), Some("Let me see."));
+        assert_eq!(split.answer, "Answer");
+        assert_eq!(split.reasoning_tokens, Some(2));
+    }
+
+    #[test]
+    fn test_split_harmony_channels() {
+        let split = split(
+            &[
+                "<|channel|>analysis<|message|>",
+                "Think.",
+                "<|end|>",
+                "<|start|>assistant",
+                "<|channel|>final<|message|>",
+                "Done.",
+            ],
+            PromptTemplateType::GptOss,
+        );
+
+        assert_eq!(split.reasoning.as_deref(), Some("Think."));
+        assert_eq!(split.answer, "Done.");
+    }
+
+    #[test]
+    fn test_split_unterminated_reasoning() {
+        let split = split(
+            &["<think>", "Still thinking </th"],
+            PromptTemplateType::ChatML,
+        );
+
+        assert_eq!(split.reasoning.as_deref(), Some("Still thinking </th"));
+        assert_eq!(split.answer, "");
+    }
+
+    #[test]
+    fn test_split_character_across_tokens() {
+        let output = "<think>é</think>ok";
+        let split = split_reasoning(output, &[7, 8, 9, 17, 19], PromptTemplateType::ChatML);
+
+        assert_eq!(split.reasoning.as_deref(), Some("é"));
+        assert_eq!(split.answer, "ok");
+    }
+
+    #[test]
+    fn test_parser_reset() {
+        let mut parser = ReasoningParser::new(PromptTemplateType::ChatML).unwrap();
+        assert_eq!(parser.push("<think>a").reasoning, "a");
+        assert_eq!(parser.reasoning_tokens(), 1);
+
+        parser.reset();
+        let delta = parser.push("b");
+        assert_eq!((delta.reasoning.as_str(), delta.answer.as_str()), ("", "b"));
+        assert_eq!(parser.reasoning_tokens(), 0);
+    }
+
+    #[test]
+    fn test_held_back_len() {
+        assert_eq!(held_back_len("abc</th", "</think>"), 4);
+        assert_eq!(held_back_len("abc<", "</think>"), 1);
+        assert_eq!(held_back_len("abc", "</think>"), 0);
+        assert_eq!(held_back_len("é", "</think>"), 0);
+    }
+}
@@ -0,0 +1,477 @@
+//! Define the strategies to shorten the chat history if the prompt does not fit in the context window of the model.
+//!
+//! The chat history is split into turns. A turn starts with a user message and contains the assistant and tool messages that follow it, so that a tool call is never separated from its result. The system message at the beginning of the history and the latest turn are never dropped.
+
+use super::engine::{chat_prompt, post_process};
+use crate::{
+    cancellation::{generate, CancellationToken},
+    error::LlamaCoreError,
+    metadata::ggml::GgmlMetadata,
+    prompt_cache::feed_prompt,
+    scheduler::SlotPermit,
+    utils::{get_token_info_by_graph, set_tensor_data_u8, with_chat_graph, with_chat_slot},
+};
+use chat_prompts::BuildChatPrompt;
+use endpoints::chat::{
+    ChatCompletionRequestMessage, ChatCompletionRole, ChatCompletionSystemMessage,
+    ChatCompletionUserMessage, ChatCompletionUserMessageContent, ContentPart, TruncationStrategy,
+};
+
+/// The maximum number of tokens of the summary generated by [`TruncationStrategy::Summarize`].
+const SUMMARY_MAX_TOKENS: u64 = 256;
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the following conversation in a few sentences. Keep the facts, names, numbers, decisions and open questions that later messages may refer to. Reply with the summary only.";
+
+const SUMMARY_HEADER: &str = "Summary of the earlier conversation:";
+
+/// The prompt built from the chat history that fits in the context window of the model.
+#[derive(Debug)]
+pub(crate) struct FittedPrompt {
+    /// The prompt.
+    pub(crate) prompt: String,
+    /// The number of tokens available for the completion.
+    pub(crate) available_completion_tokens: u64,
+    /// The number of messages dropped from the chat history.
+    pub(crate) truncated_messages: usize,
+}
+
+/// Builds the prompt from the chat messages and shortens the chat history with the given strategy until the prompt takes at most 80% of the context window.
+///
+/// If the prompt still does not fit after dropping all the turns but the latest one, the prompt is used as long as it fits in the context window; otherwise, [`LlamaCoreError::PromptTooLong`] is returned.
+///
+/// # Arguments
+///
+/// * `permit` - The slot of the chat model.
+///
+/// * `cancel` - The cancellation token of the request, used while generating the summary.
+///
+/// * `metadata` - The metadata of the request. It is restored after generating the summary.
+///
+/// * `strategy` - The truncation strategy.
+///
+/// * `messages` - The chat messages. The dropped messages are removed.
+///
+/// * `build` - The function to build the prompt from the chat messages.
+pub(crate) async fn fit_prompt(
+    permit: &SlotPermit,
+    cancel: &CancellationToken,
+    metadata: &GgmlMetadata,
+    strategy: TruncationStrategy,
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    build: impl Fn(&mut Vec<ChatCompletionRequestMessage>) -> Result<String, LlamaCoreError>,
+) -> Result<FittedPrompt, LlamaCoreError> {
+    if messages.is_empty() {
+        let err_msg = "The messages in the chat request are empty.";
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{err_msg}");
+
+        return Err(LlamaCoreError::InvalidRequest(err_msg.to_owned()));
+    }
+
+    let ctx_size = metadata.ctx_size;
+
+    // compute max prompt tokens, which is 80% of the context size
+    let max_prompt_tokens = ctx_size * 4 / 5;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Fit the prompt in {max_prompt_tokens} tokens with the truncation strategy: {strategy}");
+
+    // the dropped messages. Only the oldest turns are dropped before summarizing, so they are in the order of the chat history.
+    let mut dropped = vec![];
+
+    if let TruncationStrategy::LastTurns { last_turns } = strategy {
+        while turn_starts(messages).len() > last_turns.max(1) {
+            drop_turn(messages, 0, &mut dropped);
+        }
+    }
+
+    let prompt = loop {
+        let prompt = build(messages)?;
+        let prompt_tokens = count_prompt_tokens(permit, &prompt)?;
+
+        // leave room for the summary once a turn is dropped
+        let budget = match strategy {
+            TruncationStrategy::Summarize if !dropped.is_empty() => {
+                max_prompt_tokens.saturating_sub(SUMMARY_MAX_TOKENS)
+            }
+            _ => max_prompt_tokens,
+        };
+
+        if prompt_tokens <= budget {
+            break prompt;
+        }
+
+        let turns = turn_starts(messages).len();
+        let turn = match strategy {
+            TruncationStrategy::Disabled => None,
+            _ if turns < 2 => None,
+            TruncationStrategy::MiddleOut if turns > 2 => Some((turns - 1) / 2),
+            _ => Some(0),
+        };
+
+        match turn {
+            Some(turn) => drop_turn(messages, turn, &mut dropped),
+            None if prompt_tokens > ctx_size => {
+                let err_msg = match strategy {
+                    TruncationStrategy::Disabled => format!(
+                        "The number of prompt tokens ({prompt_tokens}) is greater than the context size ({ctx_size}), and the truncation of the chat history is disabled. Please increase the context size, or shorten the chat history."
+                    ),
+                    _ => format!(
+                        "The number of prompt tokens ({prompt_tokens}) is greater than the context size ({ctx_size}). Please increase the context size, or simplify the input message."
+                    ),
+                };
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::PromptTooLong(err_msg));
+            }
+            None => {
+                // the prompt cannot be shortened, but still fits in the context window
+                return Ok(FittedPrompt {
+                    prompt,
+                    available_completion_tokens: ctx_size - prompt_tokens,
+                    truncated_messages: dropped.len(),
+                });
+            }
+        }
+    };
+
+    if strategy == TruncationStrategy::Summarize && !dropped.is_empty() {
+        if let Some(summary) = summarize(permit, cancel, metadata, &dropped).await? {
+            let mut summarized = messages.clone();
+            insert_summary(&mut summarized, &summary);
+
+            let summarized_prompt = build(&mut summarized)?;
+            let summarized_prompt_tokens = count_prompt_tokens(permit, &summarized_prompt)?;
+
+            if summarized_prompt_tokens <= max_prompt_tokens {
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "Replace {} message(s) with a summary. The prompt has {summarized_prompt_tokens} tokens", dropped.len());
+
+                *messages = summarized;
+
+                return Ok(FittedPrompt {
+                    prompt: summarized_prompt,
+                    available_completion_tokens: ctx_size - max_prompt_tokens,
+                    truncated_messages: dropped.len(),
+                });
+            }
+
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The summary does not fit in the context window. Drop the messages without the summary.");
+        }
+    }
+
+    #[cfg(feature = "logging")]
+    if !dropped.is_empty() {
+        info!(target: "stdout", "Dropped {} message(s) from the chat history", dropped.len());
+    }
+
+    Ok(FittedPrompt {
+        prompt,
+        available_completion_tokens: ctx_size - max_prompt_tokens,
+        truncated_messages: dropped.len(),
+    })
+}
+
+/// Returns the indexes of the first messages of the turns in the chat history. The system message at the beginning is not part of any turn; the messages before the first user message make up a turn.
+fn turn_starts(messages: &[ChatCompletionRequestMessage]) -> Vec<usize> {
+    let first = match messages.first() {
+        Some(message) if message.role() == ChatCompletionRole::System => 1,
+        _ => 0,
+    };
+
+    messages
+        .iter()
+        .enumerate()
+        .skip(first)
+        .filter(|(idx, message)| *idx == first || message.role() == ChatCompletionRole::User)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Removes the messages of the turn with the given index from the chat history, and appends them to `dropped`.
+fn drop_turn(
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    turn: usize,
+    dropped: &mut Vec<ChatCompletionRequestMessage>,
+) {
+    let starts = turn_starts(messages);
+    let start = starts[turn];
+    let end = starts.get(turn + 1).copied().unwrap_or(messages.len());
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Remove turn {} of {} ({} message(s)) from the chat history", turn + 1, starts.len(), end - start);
+
+    dropped.extend(messages.drain(start..end));
+}
+
+/// Returns the number of tokens of the prompt.
+fn count_prompt_tokens(permit: &SlotPermit, prompt: &str) -> Result<u64, LlamaCoreError> {
+    with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+        set_tensor_data_u8(graph, 0, prompt.as_bytes())?;
+        get_token_info_by_graph(graph).map(|token_info| token_info.prompt_tokens)
+    })
+}
+
+/// Generates a summary of the dropped messages with the model of the slot. Returns `None` if no text of the messages fits in the context window.
+async fn summarize(
+    permit: &SlotPermit,
+    cancel: &CancellationToken,
+    metadata: &GgmlMetadata,
+    dropped: &[ChatCompletionRequestMessage],
+) -> Result<Option<String>, LlamaCoreError> {
+    let model_name = permit.model_name();
+    let chat_prompt = chat_prompt(metadata)?;
+    let max_prompt_tokens = (metadata.ctx_size * 4 / 5).saturating_sub(SUMMARY_MAX_TOKENS);
+
+    // drop the oldest messages from the transcript if it is too long
+    let mut transcript: Vec<String> = dropped.iter().filter_map(transcript_line).collect();
+    let prompt = loop {
+        if transcript.is_empty() {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The messages to summarize do not fit in the context window");
+
+            return Ok(None);
+        }
+
+        let mut summary_messages = vec![
+            ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(
+                SUMMARY_INSTRUCTION,
+                None,
+            )),
+            ChatCompletionRequestMessage::User(ChatCompletionUserMessage::new(
+                ChatCompletionUserMessageContent::Text(transcript.join("\n")),
+                None,
+            )),
+        ];
+        let prompt = chat_prompt
+            .build(&mut summary_messages)
+            .map_err(LlamaCoreError::Prompt)?;
+
+        if count_prompt_tokens(permit, &prompt)? <= max_prompt_tokens {
+            break prompt;
+        }
+
+        transcript.remove(0);
+    };
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Summarize {} message(s) of the chat history", dropped.len());
+
+    // generate the summary with the settings of the model instead of the ones of the request, e.g. the json schema
+    let mut summary_metadata = with_chat_graph(model_name, |graph| Ok(graph.metadata.clone()))?;
+    summary_metadata.n_predict = SUMMARY_MAX_TOKENS as i32;
+    set_metadata(permit, &summary_metadata)?;
+    // the summary prompt replaces the last prompt of the slot in the KV cache
+    with_chat_slot(model_name, permit.slot(), |graph| {
+        feed_prompt(graph, &prompt)
+    })?;
+
+    let output = generate(permit, None, None, cancel, |graph, generation| {
+        let output = String::from_utf8_lossy(&generation.output).to_string();
+
+        post_process(output, &graph.metadata.prompt_template).map_err(|e| {
+            let err_msg = format!("Failed to post-process the summary. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+    })
+    .await;
+
+    // restore the metadata of the request
+    set_metadata(permit, metadata)?;
+
+    let summary = output?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "summary:\n{summary}");
+
+    Ok((!summary.is_empty()).then_some(summary))
+}
+
+/// Returns the text of the message as a line of the transcript to summarize.
+fn transcript_line(message: &ChatCompletionRequestMessage) -> Option<String> {
+    let text = match message {
+        ChatCompletionRequestMessage::System(message) => message.content().to_owned(),
+        ChatCompletionRequestMessage::Developer(message) => message.content().to_owned(),
+        ChatCompletionRequestMessage::User(message) => match message.content() {
+            ChatCompletionUserMessageContent::Text(text) => text.clone(),
+            ChatCompletionUserMessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text_part) => Some(text_part.text()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        },
+        ChatCompletionRequestMessage::Assistant(message) => {
+            let mut text = message.content().cloned().unwrap_or_default();
+            for tool_call in message.tool_calls().into_iter().flatten() {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(&format!(
+                    "[call {}({})]",
+                    tool_call.function.name, tool_call.function.arguments
+                ));
+            }
+            text
+        }
+        ChatCompletionRequestMessage::Tool(message) => message.content().to_owned(),
+    };
+
+    (!text.trim().is_empty()).then(|| format!("{}: {}", message.role(), text.trim()))
+}
+
+/// Appends the summary to the system message at the beginning of the chat history, or inserts a system message with the summary if there is none.
+fn insert_summary(messages: &mut Vec<ChatCompletionRequestMessage>, summary: &str) {
+    match messages.first_mut() {
+        Some(ChatCompletionRequestMessage::System(system_message)) => {
+            *system_message = ChatCompletionSystemMessage::new(
+                format!(
+                    "{}\n\n{SUMMARY_HEADER}\n{summary}",
+                    system_message.content()
+                ),
+                system_message.name().cloned(),
+            );
+        }
+        _ => messages.insert(
+            0,
+            ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(
+                format!("{SUMMARY_HEADER}\n{summary}"),
+                None,
+            )),
+        ),
+    }
+}
+
+/// Sets the metadata of the slot of the chat model.
+fn set_metadata(permit: &SlotPermit, metadata: &GgmlMetadata) -> Result<(), LlamaCoreError> {
+    let config = serde_json::to_string(metadata).map_err(|e| {
+        let err_msg = format!("Fail to serialize metadata to a JSON string. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+        set_tensor_data_u8(graph, 1, config.as_bytes())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use endpoints::chat::{Function, ToolCall};
+
+    fn system(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_system_message(content, None)
+    }
+
+    fn user(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(content.to_string()),
+            None,
+        )
+    }
+
+    fn assistant(content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_assistant_message(Some(content.to_string()), None, None)
+    }
+
+    #[test]
+    fn test_turn_starts() {
+        let messages = vec![
+            system("Be brief."),
+            user("Hi"),
+            assistant("Hello"),
+            user("How are you?"),
+            assistant("Fine"),
+            ChatCompletionRequestMessage::new_tool_message("42", "call_1"),
+            user("Thanks"),
+        ];
+        assert_eq!(turn_starts(&messages), vec![1, 3, 6]);
+
+        // the messages before the first user message make up a turn
+        let messages = vec![assistant("Welcome"), user("Hi"), assistant("Hello")];
+        assert_eq!(turn_starts(&messages), vec![0, 1]);
+
+        assert!(turn_starts(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_drop_turn() {
+        let mut messages = vec![
+            system("Be brief."),
+            user("Hi"),
+            assistant("Hello"),
+            user("How are you?"),
+            assistant("Fine"),
+        ];
+        let mut dropped = vec![];
+
+        drop_turn(&mut messages, 0, &mut dropped);
+
+        assert_eq!(
+            messages,
+            vec![system("Be brief."), user("How are you?"), assistant("Fine")]
+        );
+        assert_eq!(dropped, vec![user("Hi"), assistant("Hello")]);
+    }
+
+    #[test]
+    fn test_transcript_line() {
+        assert_eq!(transcript_line(&user(" Hi ")).as_deref(), Some("user: Hi"));
+        assert_eq!(transcript_line(&assistant("  ")), None);
+
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            ty: "function".to_string(),
+            function: Function {
+                name: "get_weather".to_string(),
+                arguments: r#"{"city":"Paris"}"#.to_string(),
+            },
+        };
+        let message = ChatCompletionRequestMessage::new_assistant_message(
+            Some("Let me check.".to_string()),
+            None,
+            Some(vec![tool_call]),
+        );
+        assert_eq!(
+            transcript_line(&message).as_deref(),
+            Some("assistant: Let me check.\n[call get_weather({\"city\":\"Paris\"})]")
+        );
+    }
+
+    #[test]
+    fn test_insert_summary() {
+        let mut messages = vec![system("Be brief."), user("Hi")];
+        insert_summary(&mut messages, "The user greeted.");
+        assert_eq!(
+            messages,
+            vec![
+                system(&format!("Be brief.\n\n{SUMMARY_HEADER}\nThe user greeted.")),
+                user("Hi")
+            ]
+        );
+
+        let mut messages = vec![user("Hi")];
+        insert_summary(&mut messages, "The user greeted.");
+        assert_eq!(
+            messages,
+            vec![
+                system(&format!("{SUMMARY_HEADER}\nThe user greeted.")),
+                user("Hi")
+            ]
+        );
+    }
+}
@@ -1,23 +1,112 @@
 //! Define APIs for completions.
 
 use crate::{
+    cancellation::{generate, CancellationToken, Generation},
     error::{BackendError, LlamaCoreError},
     metadata::ggml::GgmlMetadata,
+    prompt_cache::feed_prompt,
     running_mode,
-    utils::{get_output_buffer, get_token_info_by_graph},
-    Graph, RunningMode, CHAT_GRAPHS, OUTPUT_TENSOR,
+    scheduler::{acquire_chat_slot, SlotPermit},
+    utils::{
+        get_output_buffer_single, get_token_info_by_graph, parse_logit_bias, set_tensor_data_u8,
+        truncate_at_stop_sequence, with_chat_slot, ResetMetadataOnDrop, StopSequences,
+    },
+    Graph, RunningMode, OUTPUT_TENSOR,
 };
 use endpoints::{
-    common::{FinishReason, Usage},
-    completions::{CompletionChoice, CompletionObject, CompletionPrompt, CompletionRequest},
+    common::{FinishReason, PromptTokensDetails, Usage},
+    completions::{
+        CompletionChoice, CompletionChunk, CompletionChunkChoice, CompletionObject,
+        CompletionPrompt, CompletionRequest,
+    },
+};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
 };
-use std::time::SystemTime;
 
 /// Given a prompt, the model will return one or more predicted completions along with the probabilities of alternative tokens at each position.
+///
+/// The `stream` field of the request is ignored. Use [`completions_stream`] for the stream mode.
 pub async fn completions(request: &CompletionRequest) -> Result<CompletionObject, LlamaCoreError> {
+    completions_with_cancellation(request, CancellationToken::new()).await
+}
+
+/// Given a prompt, the model will return one or more predicted completions. The request can be cancelled by the given token.
+///
+/// Once the token is cancelled, the request stops at the next token with [`LlamaCoreError::Cancelled`] and releases the model.
+pub async fn completions_with_cancellation(
+    request: &CompletionRequest,
+    cancel: CancellationToken,
+) -> Result<CompletionObject, LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Generate completions");
 
+    let (prompt, logit_bias) = check_request(request)?;
+
+    // wait for a slot of the model; the slot is released when the request is done
+    let permit = acquire_chat_slot(request.model.as_ref(), &cancel).await?;
+
+    compute(&prompt, request, &permit, logit_bias, &cancel).await
+}
+
+/// Given a prompt, the model will return a stream of completion chunks in the format of server-sent events. The stream ends with `data: [DONE]`.
+///
+/// Once the token is cancelled, the stream yields [`LlamaCoreError::Cancelled`] and releases the model.
+pub async fn completions_stream(
+    request: &CompletionRequest,
+    cancel: CancellationToken,
+) -> Result<impl futures::TryStream<Ok = String, Error = LlamaCoreError>, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Generate completions in the stream mode");
+
+    let (prompt, logit_bias) = check_request(request)?;
+
+    // wait for a slot of the model; the slot is held by the stream until it is dropped
+    let permit = acquire_chat_slot(request.model.as_ref(), &cancel).await?;
+
+    // the options of the request are dropped from the model if the request fails before the stream is created
+    let reset_metadata = ResetMetadataOnDrop::new(&permit);
+
+    with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+        prepare_graph(graph, &prompt, logit_bias, request.max_tokens)
+    })?;
+
+    let created = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| {
+            let err_msg = format!("Failed to get the current time. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+    // the stream resets the metadata once the generation is over
+    reset_metadata.disarm();
+
+    Ok(CompletionStream {
+        id: uuid::Uuid::new_v4().to_string(),
+        created: created.as_secs(),
+        model: permit.model_name().to_owned(),
+        echo: request.echo.unwrap_or_default().then_some(prompt),
+        max_tokens: request.max_tokens,
+        stop: StopSequences::new(request.stop.as_ref()),
+        utf8_cache: vec![],
+        state: CompletionStreamState::Generating,
+        slot: permit.slot(),
+        permit: Some(permit),
+        cancel,
+    })
+}
+
+/// Checks the running mode and returns the prompt and the parsed `logit_bias` of the request.
+fn check_request(
+    request: &CompletionRequest,
+) -> Result<(String, Option<HashMap<u32, f64>>), LlamaCoreError> {
     let running_mode = running_mode()?;
     if !running_mode.contains(RunningMode::CHAT) {
         let err_msg = "The completion is only supported in the chat mode.";
@@ -33,76 +122,52 @@ pub async fn completions(request: &CompletionRequest) -> Result<CompletionObject
         CompletionPrompt::MultiText(prompts) => prompts.join(" "),
     };
 
-    compute(prompt.trim(), request.model.as_ref())
+    // parse the `logit_bias` option
+    let logit_bias = match &request.logit_bias {
+        Some(logit_bias) => Some(parse_logit_bias(logit_bias)?),
+        None => None,
+    };
+
+    Ok((prompt.trim().to_owned(), logit_bias))
 }
 
-fn compute(
-    prompt: impl AsRef<str>,
-    model_name: Option<&String>,
+async fn compute(
+    prompt: &str,
+    request: &CompletionRequest,
+    permit: &SlotPermit,
+    logit_bias: Option<HashMap<u32, f64>>,
+    cancel: &CancellationToken,
 ) -> std::result::Result<CompletionObject, LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Compute completions");
 
-    let chat_graphs = match CHAT_GRAPHS.get() {
-        Some(chat_graphs) => chat_graphs,
-        None => {
-            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{err_msg}");
-
-            return Err(LlamaCoreError::Operation(err_msg.into()));
-        }
-    };
-
-    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {e}");
-
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
+    // reset the model metadata once the request is done, whether it succeeds or not
+    let _reset_metadata = (logit_bias.is_some() || request.max_tokens.is_some())
+        .then(|| ResetMetadataOnDrop::new(permit));
 
-        LlamaCoreError::Operation(err_msg)
+    let cached_tokens = with_chat_slot(permit.model_name(), permit.slot(), |graph| {
+        prepare_graph(graph, prompt, logit_bias, request.max_tokens)
     })?;
 
-    match model_name {
-        Some(model_name) => match chat_graphs.contains_key(model_name) {
-            true => {
-                let graph = chat_graphs.get_mut(model_name).unwrap();
-                compute_by_graph(graph, prompt)
-            }
-            false => match chat_graphs.iter_mut().next() {
-                Some((_, graph)) => compute_by_graph(graph, prompt),
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    Err(LlamaCoreError::Operation(err_msg.into()))
-                }
-            },
-        },
-        None => match chat_graphs.iter_mut().next() {
-            Some((_, graph)) => compute_by_graph(graph, prompt),
-            None => {
-                let err_msg = "There is no model available in the chat graphs.";
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                Err(LlamaCoreError::Operation(err_msg.into()))
-            }
-        },
-    }
+    generate(
+        permit,
+        None,
+        request.stop.as_ref(),
+        cancel,
+        |graph, generation| infer_by_graph(graph, generation, prompt, cached_tokens, request),
+    )
+    .await
 }
 
-/// Runs inference on the model with the given name and returns the output.
-fn compute_by_graph(
+/// Updates the metadata of the model for the request and feeds the prompt to the model. Returns the number of prompt tokens reused from the KV cache of the slot, if the prompt cache of the model is enabled.
+fn prepare_graph(
     graph: &mut Graph<GgmlMetadata>,
     prompt: impl AsRef<str>,
-) -> std::result::Result<CompletionObject, LlamaCoreError> {
+    logit_bias: Option<HashMap<u32, f64>>,
+    max_tokens: Option<u32>,
+) -> std::result::Result<Option<u64>, LlamaCoreError> {
     #[cfg(feature = "logging")]
-    info!(target: "stdout", "Compute completions by graph");
+    info!(target: "stdout", "Prepare the model named {} for completions", graph.name());
 
     // check if the `embedding` model is disabled or not
     if graph.metadata.embeddings {
@@ -114,34 +179,68 @@ fn compute_by_graph(
         graph.update_metadata()?;
     }
 
-    // set input
-    let tensor_data = prompt.as_ref().as_bytes().to_vec();
-    graph
-        .set_input(0, wasmedge_wasi_nn::TensorType::U8, &[1], &tensor_data)
-        .map_err(|e| {
-            let err_msg = format!("Failed to set the input tensor. {e}");
+    // the options only apply to this request, so keep the stored metadata unchanged
+    if logit_bias.is_some() || max_tokens.is_some() {
+        let mut metadata = graph.metadata.clone();
+
+        if let Some(logit_bias) = logit_bias {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Apply the logit bias of {} tokens", logit_bias.len());
+
+            metadata.logit_bias = logit_bias;
+        }
+
+        if let Some(max_tokens) = max_tokens {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Update n_predict with max_tokens from {} to {}", metadata.n_predict, max_tokens);
+
+            metadata.n_predict = max_tokens as i32;
+        }
+
+        let config = serde_json::to_string(&metadata).map_err(|e| {
+            let err_msg = format!("Fail to serialize metadata to a JSON string. {e}");
 
             #[cfg(feature = "logging")]
             error!(target: "stdout", "{}", &err_msg);
 
-            LlamaCoreError::Backend(BackendError::SetInput(err_msg))
+            LlamaCoreError::Operation(err_msg)
         })?;
 
-    // execute the inference
-    graph.compute().map_err(|e| {
-        let err_msg = format!("Failed to execute the inference. {e}");
+        set_tensor_data_u8(graph, 1, config.as_bytes())?;
+    }
 
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
+    // set input
+    feed_prompt(graph, prompt.as_ref())
+}
 
-        LlamaCoreError::Backend(BackendError::Compute(err_msg))
-    })?;
+fn infer_by_graph(
+    graph: &mut Graph<GgmlMetadata>,
+    generation: Generation,
+    prompt: &str,
+    cached_tokens: Option<u64>,
+    request: &CompletionRequest,
+) -> std::result::Result<CompletionObject, LlamaCoreError> {
+    // check the result of the inference
+    let context_full = match generation.outcome {
+        Ok(_) => false,
+        Err(wasmedge_wasi_nn::Error::BackendError(wasmedge_wasi_nn::BackendError::ContextFull)) => {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The context is full. The completion is truncated.");
+
+            true
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to execute the inference. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
 
-    // Retrieve the output
-    let buffer = get_output_buffer(graph, OUTPUT_TENSOR)?;
+            return Err(LlamaCoreError::Backend(BackendError::Compute(err_msg)));
+        }
+    };
 
     // convert inference result to string
-    let model_answer = String::from_utf8(buffer).map_err(|e| {
+    let mut model_answer = String::from_utf8(generation.output).map_err(|e| {
         let err_msg =
             format!("Failed to decode the buffer of the inference result to a utf-8 string. {e}");
 
@@ -150,7 +249,9 @@ fn compute_by_graph(
 
         LlamaCoreError::Operation(err_msg)
     })?;
-    let answer = model_answer.trim();
+
+    // the text after the stop sequence is dropped
+    let stopped = truncate_at_stop_sequence(&mut model_answer, request.stop.as_ref());
 
     // retrieve the number of prompt and completion tokens
     let token_info = get_token_info_by_graph(graph)?;
@@ -158,6 +259,19 @@ fn compute_by_graph(
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Prompt tokens: {}, Completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
 
+    let finish_reason = finish_reason(
+        stopped,
+        context_full,
+        token_info.completion_tokens,
+        request.max_tokens,
+    );
+
+    // the completion continues the prompt, so only its leading whitespace is kept when echoed
+    let text = match request.echo {
+        Some(true) => format!("{}{}", prompt, model_answer.trim_end()),
+        _ => model_answer.trim().to_owned(),
+    };
+
     let created = SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .map_err(|e| {
@@ -179,14 +293,262 @@ fn compute_by_graph(
         model: graph.name().to_string(),
         choices: vec![CompletionChoice {
             index: 0,
-            text: String::from(answer),
-            finish_reason: FinishReason::stop,
+            text,
+            finish_reason,
             logprobs: None,
         }],
         usage: Usage {
             prompt_tokens: token_info.prompt_tokens,
             completion_tokens: token_info.completion_tokens,
             total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
+            prompt_tokens_details: cached_tokens
+                .map(|cached_tokens| PromptTokensDetails { cached_tokens }),
+            completion_tokens_details: None,
+            truncated_messages: None,
         },
     })
 }
+
+/// Returns `length` if the completion is cut off by the context size or `max_tokens`; otherwise, returns `stop`.
+fn finish_reason(
+    stopped: bool,
+    context_full: bool,
+    completion_tokens: u64,
+    max_tokens: Option<u32>,
+) -> FinishReason {
+    if stopped {
+        return FinishReason::stop;
+    }
+
+    match context_full || max_tokens.is_some_and(|max| completion_tokens >= max as u64) {
+        true => FinishReason::length,
+        false => FinishReason::stop,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompletionStreamState {
+    Generating,
+    Finished(FinishReason),
+    Done,
+    EndOfSequence,
+}
+
+struct CompletionStream {
+    id: String,
+    created: u64,
+    model: String,
+    // the prompt to send before the completion if `echo` is set
+    echo: Option<String>,
+    max_tokens: Option<u32>,
+    stop: StopSequences,
+    // the bytes of an incomplete utf-8 character
+    utf8_cache: Vec<u8>,
+    state: CompletionStreamState,
+    slot: usize,
+    // a slot of the model, released after the context is cleaned up
+    permit: Option<SlotPermit>,
+    // the token to cancel the stream
+    cancel: CancellationToken,
+}
+impl CompletionStream {
+    /// Serializes a chunk with the given text and finish reason into a server-sent event.
+    fn chunk(
+        &self,
+        text: impl Into<String>,
+        finish_reason: Option<FinishReason>,
+    ) -> Result<String, LlamaCoreError> {
+        let chunk = CompletionChunk {
+            id: self.id.clone(),
+            choices: vec![CompletionChunkChoice {
+                finish_reason,
+                index: 0,
+                logprobs: None,
+                text: text.into(),
+            }],
+            created: self.created,
+            model: self.model.clone(),
+            object: String::from("text_completion"),
+        };
+
+        let chunk_str = serde_json::to_string(&chunk).map_err(|e| {
+            let err_msg = format!("Failed to serialize completion chunk. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+        Ok(format!("data: {chunk_str}\n\n"))
+    }
+
+    /// Generates the next token. Returns the text that is safe to send, which may be empty if the token is held back.
+    fn next_token(&mut self) -> Result<String, LlamaCoreError> {
+        let model_name = self.model.clone();
+        let res = with_chat_slot(&model_name, self.slot, |graph| {
+            match graph.compute_single() {
+                Ok(_) => get_output_buffer_single(graph, OUTPUT_TENSOR).map(Ok),
+                Err(wasmedge_wasi_nn::Error::BackendError(
+                    wasmedge_wasi_nn::BackendError::EndOfSequence,
+                )) => {
+                    let token_info = get_token_info_by_graph(graph)?;
+
+                    Ok(Err(finish_reason(
+                        false,
+                        false,
+                        token_info.completion_tokens,
+                        self.max_tokens,
+                    )))
+                }
+                Err(wasmedge_wasi_nn::Error::BackendError(
+                    wasmedge_wasi_nn::BackendError::ContextFull,
+                ))
+                | Err(wasmedge_wasi_nn::Error::BackendError(
+                    wasmedge_wasi_nn::BackendError::PromptTooLong,
+                )) => Ok(Err(FinishReason::length)),
+                Err(e) => {
+                    let err_msg = format!("Failed to compute the completion chunk. Reason: {e}");
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
+                        err_msg,
+                    )))
+                }
+            }
+        })?;
+
+        match res {
+            Ok(token) => {
+                // a utf-8 character may be split across several tokens
+                self.utf8_cache.extend(token);
+                let text = match String::from_utf8(std::mem::take(&mut self.utf8_cache)) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        self.utf8_cache = e.into_bytes();
+                        return Ok(String::new());
+                    }
+                };
+
+                let (text, stopped) = self.stop.push(&text);
+                if stopped {
+                    self.state = CompletionStreamState::Finished(FinishReason::stop);
+                }
+
+                Ok(text)
+            }
+            Err(finish_reason) => {
+                self.state = CompletionStreamState::Finished(finish_reason);
+
+                Ok(String::new())
+            }
+        }
+    }
+
+    /// Cleans up the context, resets the model metadata and releases the slot.
+    fn clean_up(&mut self) {
+        // Clean up is only needed if the stream still holds a slot of the model
+        if self.permit.is_none() {
+            return;
+        }
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Cleaning up context for CompletionStream {}", &self.id);
+
+        let res = with_chat_slot(&self.model, self.slot, |graph| {
+            graph.finish_single().map_err(|e| {
+                let err_msg = format!("Failed to clean up the context. Reason: {e}");
+
+                LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+            })?;
+
+            // reset the model metadata
+            graph.update_metadata()
+        });
+
+        if let Err(e) = res {
+            let err_msg = format!("Failed to clean up the completion stream. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            #[cfg(not(feature = "logging"))]
+            println!("[ERROR][llama_core] {}", &err_msg);
+        }
+
+        // release the slot, so that the next request in the queue can run
+        drop(self.permit.take());
+    }
+}
+impl Drop for CompletionStream {
+    fn drop(&mut self) {
+        self.clean_up();
+    }
+}
+impl futures::Stream for CompletionStream {
+    type Item = Result<String, LlamaCoreError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        // the stream is over once it is cleaned up
+        if this.permit.is_none() {
+            return Poll::Ready(None);
+        }
+
+        if this.cancel.is_cancelled() {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "CompletionStream {} is cancelled", &this.id);
+
+            // release the model now instead of when the stream is dropped
+            this.clean_up();
+
+            return Poll::Ready(Some(Err(LlamaCoreError::Cancelled)));
+        }
+
+        // send the prompt first if `echo` is set
+        if let Some(prompt) = this.echo.take() {
+            return Poll::Ready(Some(this.chunk(prompt, None)));
+        }
+
+        match this.state {
+            CompletionStreamState::Generating => match this.next_token() {
+                Ok(text) if !text.is_empty() => Poll::Ready(Some(this.chunk(text, None))),
+                Ok(_) => {
+                    // nothing to send for this token, so poll again
+                    cx.waker().wake_by_ref();
+
+                    Poll::Pending
+                }
+                Err(e) => {
+                    this.clean_up();
+
+                    Poll::Ready(Some(Err(e)))
+                }
+            },
+            CompletionStreamState::Finished(finish_reason) => {
+                this.state = CompletionStreamState::Done;
+
+                // send the text held back for the stop sequences with the finish reason
+                let text = this.stop.flush().unwrap_or_default();
+
+                Poll::Ready(Some(this.chunk(text, Some(finish_reason))))
+            }
+            CompletionStreamState::Done => {
+                this.state = CompletionStreamState::EndOfSequence;
+
+                Poll::Ready(Some(Ok("data: [DONE]\n\n".to_string())))
+            }
+            CompletionStreamState::EndOfSequence => {
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "End of the completion stream {}", &this.id);
+
+                this.clean_up();
+
+                Poll::Ready(None)
+            }
+        }
+    }
+}
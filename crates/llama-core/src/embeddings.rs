@@ -4,7 +4,7 @@ use crate::{
     error::{BackendError, LlamaCoreError},
     metadata::ggml::GgmlMetadata,
     running_mode,
-    utils::{get_output_buffer, get_token_info_by_graph, set_tensor_data_u8},
+    utils::{get_output_buffer, get_token_info_by_graph, resolve_model_name, set_tensor_data_u8},
     Graph, RunningMode, CHAT_GRAPHS, EMBEDDING_GRAPHS, OUTPUT_TENSOR,
 };
 use endpoints::{
@@ -40,7 +40,7 @@ pub async fn embeddings(
         return Err(LlamaCoreError::Operation(err_msg.into()));
     }
 
-    let model_name = &embedding_request.model;
+    let mut model_name = embedding_request.model.clone();
 
     let embedding_reponse = {
         // For general embedding scenario, the embedding model is the same as the chat model.
@@ -69,22 +69,11 @@ pub async fn embeddings(
             LlamaCoreError::Operation(err_msg)
         })?;
 
-        let graph = match model_name {
-            Some(model_name) if embedding_graphs.contains_key(model_name) => {
-                embedding_graphs.get_mut(model_name).unwrap()
-            }
-            _ => match embedding_graphs.iter_mut().next() {
-                Some((_, graph)) => graph,
-                None => {
-                    let err_msg = "Not found available model in the embedding graphs.";
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
+        let name = resolve_model_name(&embedding_graphs, model_name.as_ref(), "embedding")?;
+        let graph = embedding_graphs.get_mut(&name).unwrap();
 
-                    return Err(LlamaCoreError::Operation(err_msg.into()));
-                }
-            },
-        };
+        // reset the metadata of the model that served the request
+        model_name = Some(name);
 
         // check if the `embedding` option of metadata is enabled
         if !graph.metadata.embeddings {
@@ -264,7 +253,7 @@ pub fn dimension(name: Option<&str>) -> Result<u64, LlamaCoreError> {
                 #[cfg(feature = "logging")]
                 error!(target: "stdout", "{}", &err_msg);
 
-                Err(LlamaCoreError::Operation(err_msg))
+                Err(LlamaCoreError::ModelNotFound(err_msg))
             }
         },
         None => {
@@ -1,5 +1,6 @@
 //! Error types for the Llama Core library.
 
+use chat_prompts::error::PromptError;
 use thiserror::Error;
 
 /// Error types for the Llama Core library.
@@ -17,6 +18,30 @@ pub enum LlamaCoreError {
     /// Errors in file not found.
     #[error("File not found.")]
     FileNotFound,
+    /// Errors in cancelled requests.
+    #[error("The request was cancelled.")]
+    Cancelled,
+    /// Errors in building the prompt from the messages of the request.
+    #[error("Fail to build chat prompts. Reason: {0}")]
+    Prompt(#[from] PromptError),
+    /// Errors in the parameters of the request.
+    #[error("{0}")]
+    InvalidRequest(String),
+    /// Errors in requesting a model that does not exist.
+    #[error("{0}")]
+    ModelNotFound(String),
+    /// Errors in the prompt exceeding the context size of the model.
+    #[error("{0}")]
+    PromptTooLong(String),
+    /// Errors in the queue of the model being full.
+    #[error("{0}")]
+    QueueFull(String),
+    /// Errors in loading a model with a name in use, or unloading a model in use.
+    #[error("{0}")]
+    ModelConflict(String),
+    /// Errors in requesting a stored object, such as a response or a conversation, that does not exist.
+    #[error("{0}")]
+    NotFound(String),
 }
 
 /// Error types for wasi-nn errors.
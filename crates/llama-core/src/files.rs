@@ -21,6 +21,18 @@ use walkdir::{DirEntry, WalkDir};
 ///
 /// A `DeleteFileStatus` instance.
 pub fn remove_file(id: impl AsRef<str>) -> Result<DeleteFileStatus, LlamaCoreError> {
+    // the hidden directories, e.g. the one of the response store, are not files
+    if id.as_ref().starts_with('.') {
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "Failed to delete the target file with id {}. It is not a file.", id.as_ref());
+
+        return Ok(DeleteFileStatus {
+            id: id.as_ref().into(),
+            object: "file".to_string(),
+            deleted: false,
+        });
+    }
+
     let root = format!("{}/{}", ARCHIVES_DIR, id.as_ref());
     let status = match fs::remove_dir_all(root) {
         Ok(_) => {
@@ -65,8 +77,10 @@ pub fn list_files() -> Result<ListFilesResponse, LlamaCoreError> {
     info!(target: "stdout", "Listing all archive files");
 
     let mut file_objects: Vec<FileObject> = Vec::new();
+    // the hidden directories, e.g. the one of the response store, do not hold uploaded files
     for entry in WalkDir::new(ARCHIVES_DIR)
         .into_iter()
+        .filter_entry(|e| !is_hidden(e))
         .filter_map(|e| e.ok())
     {
         if !is_hidden(&entry) && entry.path().is_file() {
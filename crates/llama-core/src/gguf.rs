@@ -0,0 +1,577 @@
+//! Define the reader of the metadata of GGUF model files.
+//!
+//! A GGUF file starts with a header followed by the metadata of the model as key-value pairs, such as `general.architecture` or `tokenizer.chat_template`. Only the header and the metadata are read; the tensors are left to the backend.
+//!
+//! The metadata is also used to detect the prompt template of the model with [`GgufMetadata::detect_prompt_template`].
+
+use crate::error::LlamaCoreError;
+use chat_prompts::{chat::ChatTemplate, PromptTemplateType};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+// the maximum depth of nested arrays, which bounds the recursion on corrupted files
+const MAX_ARRAY_DEPTH: usize = 8;
+
+// the markers of the chat templates written for the prompt templates, checked in order; a chat template matches an entry if it has all the markers of the entry
+const CHAT_TEMPLATE_MARKERS: &[(&[&str], PromptTemplateType)] = &[
+    (&["<|channel|>", "<|message|>"], PromptTemplateType::GptOss),
+    (
+        &["<|header_start|>", "<|eot|>"],
+        PromptTemplateType::Llama4Chat,
+    ),
+    (
+        &["<|start_header_id|>", "<|eot_id|>"],
+        PromptTemplateType::Llama3Chat,
+    ),
+    (
+        &["[SYSTEM_PROMPT]", "[INST]"],
+        PromptTemplateType::MistralSmallChat,
+    ),
+    (&["<<SYS>>", "[INST]"], PromptTemplateType::Llama2Chat),
+    (&["[INST]"], PromptTemplateType::MistralInstruct),
+    (&["<|im_sep|>"], PromptTemplateType::Phi4Chat),
+    (
+        &["<start_of_image>", "<start_of_turn>"],
+        PromptTemplateType::Gemma3,
+    ),
+    (&["<start_of_turn>"], PromptTemplateType::GemmaInstruct),
+    (
+        &["<｜User｜>", "<｜Assistant｜>"],
+        PromptTemplateType::DeepseekChat3,
+    ),
+    (
+        &["<|vision_start|>", "<|im_start|>"],
+        PromptTemplateType::Qwen2vl,
+    ),
+    // the chat templates of the reasoning models, e.g. Qwen3, also declare tool calls
+    (
+        &["<think>", "<|im_start|>"],
+        PromptTemplateType::ChatMLThink,
+    ),
+    (
+        &["<tool_call>", "<|im_start|>"],
+        PromptTemplateType::ChatMLTool,
+    ),
+    (&["<|im_start|>"], PromptTemplateType::ChatML),
+    (
+        &["[|system|]", "[|endofturn|]"],
+        PromptTemplateType::ExaoneChat,
+    ),
+    (
+        &["<|role_start|>", "<|turn_end|>"],
+        PromptTemplateType::Megrez,
+    ),
+    (&["<seed:bos>"], PromptTemplateType::SeedOssThink),
+    (&["GPT4 Correct User"], PromptTemplateType::OpenChat),
+    (&["[gMASK]"], PromptTemplateType::Glm4Chat),
+    (
+        &["<|user|>", "<|end|>", "<|assistant|>"],
+        PromptTemplateType::Phi3Chat,
+    ),
+];
+
+/// The value of a metadata key of a GGUF file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GgufValue {
+    /// An unsigned integer of any width.
+    Uint(u64),
+    /// A signed integer of any width.
+    Int(i64),
+    /// A floating-point number of any width.
+    Float(f64),
+    /// A boolean.
+    Bool(bool),
+    /// A UTF-8 string.
+    String(String),
+    /// An array of values of the same type.
+    Array(Vec<GgufValue>),
+}
+impl GgufValue {
+    /// Returns the string if the value is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            GgufValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer if the value is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            GgufValue::Uint(value) => Some(*value),
+            GgufValue::Int(value) => u64::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the boolean if the value is a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            GgufValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the items if the value is an array.
+    pub fn as_array(&self) -> Option<&[GgufValue]> {
+        match self {
+            GgufValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// The metadata of a GGUF model file.
+#[derive(Debug, Clone, Default)]
+pub struct GgufMetadata {
+    values: HashMap<String, GgufValue>,
+}
+impl GgufMetadata {
+    /// Reads the metadata of a GGUF file. The versions 2 and 3 of the format are supported.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, LlamaCoreError> {
+        let path = path.as_ref();
+
+        let read_error = |reason: String| {
+            let err_msg = format!(
+                "Failed to read the metadata of the GGUF file {}. {}",
+                path.display(),
+                reason
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        };
+
+        let file = File::open(path).map_err(|e| read_error(e.to_string()))?;
+        let mut reader = GgufReader {
+            reader: BufReader::new(file),
+        };
+
+        Self::read_from(&mut reader).map_err(read_error)
+    }
+
+    fn read_from<R: Read>(reader: &mut GgufReader<R>) -> Result<Self, String> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != GGUF_MAGIC {
+            return Err("The file is not in the GGUF format.".to_string());
+        }
+
+        let version = reader.u32()?;
+        if !(2..=3).contains(&version) {
+            return Err(format!("The version {version} of GGUF is not supported."));
+        }
+
+        // the number of tensors
+        reader.u64()?;
+        let kv_count = reader.u64()?;
+
+        let mut values = HashMap::new();
+        for _ in 0..kv_count {
+            let key = reader.string()?;
+            let ty = reader.u32()?;
+            let value = reader.value(ty, 0)?;
+            values.insert(key, value);
+        }
+
+        Ok(Self { values })
+    }
+
+    /// Returns the value of the metadata key.
+    pub fn get(&self, key: &str) -> Option<&GgufValue> {
+        self.values.get(key)
+    }
+
+    /// Returns the value of the metadata key if it is a string.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(GgufValue::as_str)
+    }
+
+    /// Returns the architecture of the model, e.g. `llama` or `qwen2`.
+    pub fn architecture(&self) -> Option<&str> {
+        self.get_str("general.architecture")
+    }
+
+    /// Returns the text of the token with the given id.
+    pub fn token(&self, id: u64) -> Option<&str> {
+        self.get("tokenizer.ggml.tokens")?
+            .as_array()?
+            .get(usize::try_from(id).ok()?)?
+            .as_str()
+    }
+
+    /// Returns `true` if the vocabulary of the model has the token.
+    pub fn has_token(&self, text: &str) -> bool {
+        self.get("tokenizer.ggml.tokens")
+            .and_then(GgufValue::as_array)
+            .is_some_and(|tokens| tokens.iter().any(|token| token.as_str() == Some(text)))
+    }
+
+    /// Returns the text of the special token with the given name, e.g. `bos` or `eos`.
+    pub fn special_token(&self, name: &str) -> Option<&str> {
+        let id = self
+            .get(&format!("tokenizer.ggml.{name}_token_id"))?
+            .as_u64()?;
+
+        self.token(id)
+    }
+
+    /// Returns the chat template of the model, or `None` if the file has no `tokenizer.chat_template`.
+    ///
+    /// The backend adds the BOS token when tokenizing the prompt unless `tokenizer.ggml.add_bos_token` is `false`, so `bos_token` is empty in the other cases to avoid adding it twice.
+    pub fn chat_template(&self) -> Option<ChatTemplate> {
+        let source = self.get_str("tokenizer.chat_template")?;
+
+        let mut template = ChatTemplate::new(source);
+        if let Some(eos_token) = self.special_token("eos") {
+            template = template.with_eos_token(eos_token);
+        }
+        let add_bos_token = self
+            .get("tokenizer.ggml.add_bos_token")
+            .and_then(GgufValue::as_bool);
+        if add_bos_token == Some(false) {
+            if let Some(bos_token) = self.special_token("bos") {
+                template = template.with_bos_token(bos_token);
+            }
+        }
+
+        Some(template)
+    }
+
+    /// Detects the prompt template of the model.
+    ///
+    /// The markers of the `tokenizer.chat_template` are checked first. A chat template without known markers is rendered by the `jinja` prompt template. If the model has no chat template, the prompt template is guessed from the architecture and the special tokens of the model. Returns `None` if nothing is known about the model.
+    pub fn detect_prompt_template(&self) -> Option<PromptTemplateType> {
+        if let Some(source) = self.get_str("tokenizer.chat_template") {
+            let prompt_template = CHAT_TEMPLATE_MARKERS
+                .iter()
+                .find(|(markers, _)| markers.iter().all(|marker| source.contains(marker)))
+                .map(|(_, prompt_template)| *prompt_template)
+                .unwrap_or(PromptTemplateType::Jinja);
+
+            return Some(prompt_template);
+        }
+
+        let prompt_template = match self.architecture()? {
+            "llama" if self.has_token("<|eot_id|>") => PromptTemplateType::Llama3Chat,
+            "llama" if self.has_token("[INST]") => PromptTemplateType::MistralInstruct,
+            "llama4" => PromptTemplateType::Llama4Chat,
+            "gemma" | "gemma2" => PromptTemplateType::GemmaInstruct,
+            "gemma3" => PromptTemplateType::Gemma3,
+            "phi3" if self.has_token("<|im_sep|>") => PromptTemplateType::Phi4Chat,
+            "phi3" => PromptTemplateType::Phi3Chat,
+            "qwen2vl" => PromptTemplateType::Qwen2vl,
+            "qwen2" | "qwen2moe" | "qwen3" | "qwen3moe" => PromptTemplateType::ChatML,
+            "exaone" => PromptTemplateType::ExaoneChat,
+            "gpt-oss" => PromptTemplateType::GptOss,
+            "seed_oss" => PromptTemplateType::SeedOssThink,
+            _ => return None,
+        };
+
+        Some(prompt_template)
+    }
+}
+
+struct GgufReader<R> {
+    reader: R,
+}
+impl<R: Read> GgufReader<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        self.reader.read_exact(buf).map_err(|e| e.to_string())
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut buf = [0u8; N];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u64()?;
+        let mut buf = vec![];
+        (&mut self.reader)
+            .take(len)
+            .read_to_end(&mut buf)
+            .map_err(|e| e.to_string())?;
+        if buf.len() as u64 != len {
+            return Err("Unexpected end of the file.".to_string());
+        }
+
+        // the vocabularies of some models have tokens which are not valid UTF-8
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    /// Reads a value of the type. The depth is the number of arrays containing the value.
+    fn value(&mut self, ty: u32, depth: usize) -> Result<GgufValue, String> {
+        let value = match ty {
+            0 => GgufValue::Uint(u8::from_le_bytes(self.bytes()?) as u64),
+            1 => GgufValue::Int(i8::from_le_bytes(self.bytes()?) as i64),
+            2 => GgufValue::Uint(u16::from_le_bytes(self.bytes()?) as u64),
+            3 => GgufValue::Int(i16::from_le_bytes(self.bytes()?) as i64),
+            4 => GgufValue::Uint(self.u32()? as u64),
+            5 => GgufValue::Int(i32::from_le_bytes(self.bytes()?) as i64),
+            6 => GgufValue::Float(f32::from_le_bytes(self.bytes()?) as f64),
+            7 => GgufValue::Bool(self.bytes::<1>()?[0] != 0),
+            8 => GgufValue::String(self.string()?),
+            9 if depth == MAX_ARRAY_DEPTH => {
+                return Err(format!(
+                    "Arrays are nested deeper than {MAX_ARRAY_DEPTH} levels."
+                ))
+            }
+            9 => {
+                let item_ty = self.u32()?;
+                let len = self.u64()?;
+                let mut items = Vec::with_capacity(len.min(1 << 20) as usize);
+                for _ in 0..len {
+                    items.push(self.value(item_ty, depth + 1)?);
+                }
+                GgufValue::Array(items)
+            }
+            10 => GgufValue::Uint(self.u64()?),
+            11 => GgufValue::Int(i64::from_le_bytes(self.bytes()?)),
+            12 => GgufValue::Float(f64::from_le_bytes(self.bytes()?)),
+            ty => return Err(format!("Unknown type {ty} of a metadata value.")),
+        };
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a GGUF file of version 3 without tensors.
+    fn gguf(values: &[(&str, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = GGUF_MAGIC.to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        bytes.extend((values.len() as u64).to_le_bytes());
+        for (key, ty, value) in values {
+            bytes.extend(string(key));
+            bytes.extend(ty.to_le_bytes());
+            bytes.extend(value);
+        }
+        bytes
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut bytes = (text.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(text.as_bytes());
+        bytes
+    }
+
+    fn strings(texts: &[&str]) -> Vec<u8> {
+        let mut bytes = 8u32.to_le_bytes().to_vec();
+        bytes.extend((texts.len() as u64).to_le_bytes());
+        for text in texts {
+            bytes.extend(string(text));
+        }
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> Result<GgufMetadata, String> {
+        GgufMetadata::read_from(&mut GgufReader { reader: bytes })
+    }
+
+    #[test]
+    fn test_read_values() {
+        let metadata = read(&gguf(&[
+            ("general.architecture", 8, string("llama")),
+            ("llama.context_length", 4, 8192u32.to_le_bytes().to_vec()),
+            ("llama.rope.freq_base", 6, 10000f32.to_le_bytes().to_vec()),
+            ("tokenizer.ggml.add_bos_token", 7, vec![1]),
+            ("tokenizer.ggml.tokens", 9, strings(&["<s>", "</s>"])),
+            (
+                "tokenizer.ggml.eos_token_id",
+                5,
+                1i32.to_le_bytes().to_vec(),
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(metadata.architecture(), Some("llama"));
+        assert_eq!(
+            metadata
+                .get("llama.context_length")
+                .and_then(GgufValue::as_u64),
+            Some(8192)
+        );
+        assert_eq!(
+            metadata.get("llama.rope.freq_base"),
+            Some(&GgufValue::Float(10000.0))
+        );
+        assert_eq!(
+            metadata
+                .get("tokenizer.ggml.add_bos_token")
+                .and_then(GgufValue::as_bool),
+            Some(true)
+        );
+        assert_eq!(metadata.token(0), Some("<s>"));
+        assert_eq!(metadata.special_token("eos"), Some("</s>"));
+        assert!(metadata.has_token("</s>"));
+        assert!(!metadata.has_token("<|eot_id|>"));
+    }
+
+    #[test]
+    fn test_read_invalid_files() {
+        assert!(read(b"GGML").is_err());
+
+        let mut bytes = gguf(&[]);
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        assert!(read(&bytes).is_err());
+
+        // the string is longer than the rest of the file
+        let mut bytes = gguf(&[("general.architecture", 8, string("llama"))]);
+        bytes.truncate(bytes.len() - 2);
+        assert!(read(&bytes).is_err());
+
+        assert!(read(&gguf(&[("general.architecture", 13, vec![])])).is_err());
+    }
+
+    #[test]
+    fn test_read_nested_arrays() {
+        // an array of the depth, whose innermost array holds a string
+        let nested = |depth: usize| {
+            let mut bytes = strings(&["<s>"]);
+            for _ in 1..depth {
+                let mut array = 9u32.to_le_bytes().to_vec();
+                array.extend(1u64.to_le_bytes());
+                array.extend(bytes);
+                bytes = array;
+            }
+            bytes
+        };
+
+        let metadata = read(&gguf(&[("nested", 9, nested(MAX_ARRAY_DEPTH))])).unwrap();
+        let mut value = metadata.get("nested").unwrap();
+        for _ in 0..MAX_ARRAY_DEPTH {
+            value = &value.as_array().unwrap()[0];
+        }
+        assert_eq!(value.as_str(), Some("<s>"));
+
+        assert!(read(&gguf(&[("nested", 9, nested(MAX_ARRAY_DEPTH + 1))])).is_err());
+
+        // a corrupted file declaring arrays nested without end
+        let mut bytes = gguf(&[]);
+        bytes[16..24].copy_from_slice(&1u64.to_le_bytes());
+        bytes.extend(string("nested"));
+        bytes.extend(9u32.to_le_bytes());
+        for _ in 0..100_000 {
+            bytes.extend(9u32.to_le_bytes());
+            bytes.extend(1u64.to_le_bytes());
+        }
+        assert!(read(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_detect_prompt_template_by_chat_template() {
+        let detect = |source: &str| {
+            read(&gguf(&[("tokenizer.chat_template", 8, string(source))]))
+                .unwrap()
+                .detect_prompt_template()
+        };
+
+        assert_eq!(
+            detect("{{ '<|start_header_id|>' + message['role'] + '<|end_header_id|>' }}{{ '<|eot_id|>' }}"),
+            Some(PromptTemplateType::Llama3Chat)
+        );
+        assert_eq!(
+            detect("{{ '[INST] ' + message['content'] + ' [/INST]' }}"),
+            Some(PromptTemplateType::MistralInstruct)
+        );
+        assert_eq!(
+            detect("{{ '<|im_start|>' + message['role'] }}"),
+            Some(PromptTemplateType::ChatML)
+        );
+        assert_eq!(
+            detect("{{ '<|vision_start|>' }}{{ '<|im_start|>' }}"),
+            Some(PromptTemplateType::Qwen2vl)
+        );
+        assert_eq!(
+            detect(
+                "{{ '<|im_start|>' + message['role'] }}{{ '<think>\\n' }}{{ '<tool_call>\\n' }}"
+            ),
+            Some(PromptTemplateType::ChatMLThink)
+        );
+        assert_eq!(
+            detect("{{ '<|im_start|>' + message['role'] }}{{ '<tool_call>\\n' }}"),
+            Some(PromptTemplateType::ChatMLTool)
+        );
+        assert_eq!(
+            detect("{{ message['content'] }}"),
+            Some(PromptTemplateType::Jinja)
+        );
+    }
+
+    #[test]
+    fn test_detect_prompt_template_by_architecture() {
+        let detect = |architecture: &str, tokens: &[&str]| {
+            read(&gguf(&[
+                ("general.architecture", 8, string(architecture)),
+                ("tokenizer.ggml.tokens", 9, strings(tokens)),
+            ]))
+            .unwrap()
+            .detect_prompt_template()
+        };
+
+        assert_eq!(
+            detect("llama", &["<|eot_id|>"]),
+            Some(PromptTemplateType::Llama3Chat)
+        );
+        assert_eq!(
+            detect("llama", &["[INST]"]),
+            Some(PromptTemplateType::MistralInstruct)
+        );
+        assert_eq!(detect("qwen3", &[]), Some(PromptTemplateType::ChatML));
+        assert_eq!(detect("llama", &[]), None);
+        assert_eq!(GgufMetadata::default().detect_prompt_template(), None);
+    }
+
+    #[test]
+    fn test_chat_template_tokens() {
+        let metadata = |add_bos_token: u8| {
+            read(&gguf(&[
+                ("tokenizer.chat_template", 8, string("{{ bos_token }}")),
+                ("tokenizer.ggml.tokens", 9, strings(&["<s>", "</s>"])),
+                (
+                    "tokenizer.ggml.bos_token_id",
+                    4,
+                    0u32.to_le_bytes().to_vec(),
+                ),
+                (
+                    "tokenizer.ggml.eos_token_id",
+                    4,
+                    1u32.to_le_bytes().to_vec(),
+                ),
+                ("tokenizer.ggml.add_bos_token", 7, vec![add_bos_token]),
+            ]))
+            .unwrap()
+        };
+
+        // the backend adds the BOS token itself
+        let template = metadata(1).chat_template().unwrap();
+        assert_eq!(template.source, "{{ bos_token }}");
+        assert_eq!(template.bos_token, "");
+        assert_eq!(template.eos_token, "</s>");
+
+        let template = metadata(0).chat_template().unwrap();
+        assert_eq!(template.bos_token, "<s>");
+    }
+}
@@ -1,6 +1,8 @@
 //! Define Graph and GraphBuilder APIs for creating a new computation graph.
 
-use crate::{error::LlamaCoreError, utils::set_tensor_data_u8, BaseMetadata};
+use crate::{
+    error::LlamaCoreError, prompt_cache::CachedPrompt, utils::set_tensor_data_u8, BaseMetadata,
+};
 use wasmedge_wasi_nn::{
     Error as WasiNnError, Graph as WasiNnGraph, GraphExecutionContext, TensorType,
 };
@@ -95,7 +97,10 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
             created,
             metadata: self.metadata.clone().unwrap_or_default(),
             graph,
-            context,
+            contexts: vec![context],
+            cached_prompts: vec![None],
+            slot: 0,
+            logprobs_supported: None,
         })
     }
 
@@ -132,7 +137,10 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
             created,
             metadata: self.metadata.clone().unwrap_or_default(),
             graph,
-            context,
+            contexts: vec![context],
+            cached_prompts: vec![None],
+            slot: 0,
+            logprobs_supported: None,
         })
     }
 
@@ -168,7 +176,10 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
                     created,
                     metadata: metadata.clone(),
                     graph,
-                    context,
+                    contexts: vec![context],
+                    cached_prompts: vec![None],
+                    slot: 0,
+                    logprobs_supported: None,
                 })
             }
             None => {
@@ -191,7 +202,14 @@ pub struct Graph<M: BaseMetadata + serde::Serialize + Clone + Default> {
     pub created: std::time::SystemTime,
     pub metadata: M,
     graph: WasiNnGraph,
-    context: GraphExecutionContext,
+    // one execution context per slot of the scheduler
+    contexts: Vec<GraphExecutionContext>,
+    // the last prompt evaluated by each context, if the prompt cache is enabled
+    cached_prompts: Vec<Option<CachedPrompt>>,
+    // the index of the context used by the methods below
+    slot: usize,
+    /// Whether the backend returns the log probabilities of the generated tokens. It is unknown until they are first requested.
+    pub(crate) logprobs_supported: Option<bool>,
 }
 impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
     /// Create a new computation graph from the given metadata.
@@ -237,7 +255,10 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
             created,
             metadata: metadata.clone(),
             graph,
-            context,
+            contexts: vec![context],
+            cached_prompts: vec![None],
+            slot: 0,
+            logprobs_supported: None,
         })
     }
 
@@ -251,6 +272,34 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         self.metadata.model_alias()
     }
 
+    /// Selects the execution context of the given slot for the following operations. The context is created when the slot is first used, with the metadata the graph was built with.
+    pub(crate) fn use_slot(&mut self, slot: usize) -> Result<(), LlamaCoreError> {
+        while self.contexts.len() <= slot {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Initialize the execution context of the slot {} of the model named {}", self.contexts.len(), self.name());
+
+            let context = self.graph.init_execution_context().map_err(|e| {
+                let err_msg =
+                    format!("Failed to initialize the execution context of the slot {slot}. {e}");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::Operation(err_msg)
+            })?;
+            self.contexts.push(context);
+            self.cached_prompts.push(None);
+        }
+        self.slot = slot;
+
+        Ok(())
+    }
+
+    /// Returns the last prompt evaluated by the execution context of the current slot.
+    pub(crate) fn cached_prompt(&mut self) -> &mut Option<CachedPrompt> {
+        &mut self.cached_prompts[self.slot]
+    }
+
     /// Update metadata
     pub fn update_metadata(&mut self) -> Result<(), LlamaCoreError> {
         #[cfg(feature = "logging")]
@@ -285,19 +334,19 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         dimensions: &[usize],
         data: impl AsRef<[T]>,
     ) -> Result<(), WasiNnError> {
-        self.context.set_input(index, tensor_type, dimensions, data)
+        self.contexts[self.slot].set_input(index, tensor_type, dimensions, data)
     }
 
     /// Compute the inference on the given inputs.
     pub fn compute(&mut self) -> Result<(), WasiNnError> {
-        self.context.compute()
+        self.contexts[self.slot].compute()
     }
 
     /// Compute the inference on the given inputs.
     ///
     /// Note that this method is used for the stream mode. It generates one token at a time.
     pub fn compute_single(&mut self) -> Result<(), WasiNnError> {
-        self.context.compute_single()
+        self.contexts[self.slot].compute_single()
     }
 
     /// Copy output tensor to out_buffer, return the output’s **size in bytes**.
@@ -306,7 +355,7 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         index: usize,
         out_buffer: &mut [T],
     ) -> Result<usize, WasiNnError> {
-        self.context.get_output(index, out_buffer)
+        self.contexts[self.slot].get_output(index, out_buffer)
     }
 
     /// Copy output tensor to out_buffer, return the output’s **size in bytes**.
@@ -317,14 +366,14 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         index: usize,
         out_buffer: &mut [T],
     ) -> Result<usize, WasiNnError> {
-        self.context.get_output_single(index, out_buffer)
+        self.contexts[self.slot].get_output_single(index, out_buffer)
     }
 
     /// Clear the computation context.
     ///
     /// Note that this method is used for the stream mode. It clears the context after the stream mode is finished.
     pub fn finish_single(&mut self) -> Result<(), WasiNnError> {
-        self.context.fini_single()
+        self.contexts[self.slot].fini_single()
     }
 }
 impl<M: BaseMetadata + serde::Serialize + Clone + Default> Drop for Graph<M> {
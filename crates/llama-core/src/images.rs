@@ -3,8 +3,8 @@
 use crate::{error::LlamaCoreError, ARCHIVES_DIR, SD_IMAGE_TO_IMAGE, SD_TEXT_TO_IMAGE};
 use base64::{engine::general_purpose, Engine as _};
 use endpoints::images::{
-    ImageCreateRequest, ImageEditRequest, ImageObject, ImageVariationRequest, ListImagesResponse,
-    ResponseFormat, SamplingMethod,
+    ImageCreateRequest, ImageEditRequest, ImageEditRequestBuilder, ImageObject,
+    ImageVariationRequest, ListImagesResponse, ResponseFormat, SamplingMethod,
 };
 use std::{
     fs::{self, File},
@@ -509,10 +509,51 @@ pub async fn image_edit(req: &mut ImageEditRequest) -> Result<ListImagesResponse
 }
 
 /// Create a variation of a given image.
+///
+/// The variation is created by the image-to-image context with an empty prompt, so the same model serves both the edits and the variations.
 pub async fn image_variation(
-    _req: &mut ImageVariationRequest,
+    req: &mut ImageVariationRequest,
 ) -> Result<ListImagesResponse, LlamaCoreError> {
-    unimplemented!("image_variation")
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Processing the image variation request.");
+
+    let mut builder = ImageEditRequestBuilder::new(&req.model, req.image.clone(), "");
+    if let Some(n) = req.n {
+        builder = builder.with_number_of_images(n);
+    }
+    if let Some(response_format) = req.response_format {
+        builder = builder.with_response_format(response_format);
+    }
+    if let Some(user) = &req.user {
+        builder = builder.with_user(user);
+    }
+    if let Some(size) = &req.size {
+        let (width, height) = parse_image_size(size)?;
+        builder = builder.with_image_size(height, width);
+    }
+
+    image_edit(&mut builder.build()).await
+}
+
+/// Parse the size of an image in the form of `{width}x{height}`, e.g. `512x512`.
+fn parse_image_size(size: &str) -> Result<(usize, usize), LlamaCoreError> {
+    let parsed = size.split_once('x').and_then(|(width, height)| {
+        Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+    });
+
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => {
+            let err_msg = format!(
+                "Invalid image size: {size}. The size should be in the form of `{{width}}x{{height}}`, e.g. `512x512`."
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::InvalidRequest(err_msg))
+        }
+    }
 }
 
 // convert an image file to a base64 string
@@ -7,15 +7,21 @@
 extern crate log;
 
 pub mod audio;
+pub mod cancellation;
 pub mod chat;
 pub mod completions;
 pub mod embeddings;
 pub mod error;
 pub mod files;
+pub mod gguf;
 pub mod graph;
 pub mod images;
 pub mod metadata;
 pub mod models;
+pub mod pool;
+mod prompt_cache;
+pub mod scheduler;
+pub mod store;
 pub mod tts;
 pub mod utils;
 
@@ -62,6 +68,8 @@ pub(crate) static PIPER_GRAPH: OnceCell<Mutex<Graph<PiperMetadata>>> = OnceCell:
 
 pub(crate) const MAX_BUFFER_SIZE: usize = 2usize.pow(14) * 15 + 128;
 pub(crate) const OUTPUT_TENSOR: usize = 0;
+// The output index of the log probabilities of the latest token in the stream mode. The wasi-nn spec does not define it: the ggml plugin writes the generated text to the output 0 and the token counts to the output 1, and the plugin builds supporting the `logprobs` metadata write the log probabilities as JSON to the next output. Other builds fail to return it, so the support is probed on the first request for log probabilities.
+pub(crate) const LOGPROBS_TENSOR: usize = 2;
 const PLUGIN_VERSION: usize = 1;
 
 /// The directory for storing the archives in wasm virtual file system.
@@ -83,7 +91,8 @@ pub fn init_ggml_chat_context(metadata_for_chats: &[GgmlMetadata]) -> Result<(),
 
     let mut chat_graphs = HashMap::new();
     for metadata in metadata_for_chats {
-        let graph = Graph::new(metadata.clone())?;
+        let mut graph = Graph::new(metadata.clone())?;
+        models::resolve_prompt_template(&mut graph.metadata)?;
 
         chat_graphs.insert(graph.name().to_string(), graph);
     }
@@ -234,6 +243,9 @@ pub fn get_plugin_info() -> Result<PluginInfo, LlamaCoreError> {
     let running_mode = running_mode()?;
 
     if running_mode.contains(RunningMode::CHAT) || running_mode.contains(RunningMode::RAG) {
+        // the plugin info is read from a loaded model
+        pool::ensure_chat_model(None)?;
+
         let chat_graphs = match CHAT_GRAPHS.get() {
             Some(chat_graphs) => chat_graphs,
             None => {
@@ -656,6 +668,25 @@ pub fn init_sd_context_with_full_model(
         info!(target: "stdout", "The stable diffusion image-to-image context has been initialized");
     }
 
+    // set running mode
+    let running_mode = RunningMode::IMAGE;
+    match RUNNING_MODE.get() {
+        Some(mode) => {
+            let mut mode = mode.write().unwrap();
+            *mode |= running_mode;
+        }
+        None => {
+            RUNNING_MODE.set(RwLock::new(running_mode)).map_err(|_| {
+                let err_msg = "Failed to initialize the stable diffusion context. Reason: The `RUNNING_MODE` has already been initialized";
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{err_msg}");
+
+                LlamaCoreError::InitContext(err_msg.into())
+            })?;
+        }
+    }
+
     Ok(())
 }
 
@@ -920,19 +951,64 @@ pub fn init_sd_context_with_standalone_model(
         info!(target: "stdout", "The stable diffusion image-to-image context has been initialized");
     }
 
+    // set running mode
+    let running_mode = RunningMode::IMAGE;
+    match RUNNING_MODE.get() {
+        Some(mode) => {
+            let mut mode = mode.write().unwrap();
+            *mode |= running_mode;
+        }
+        None => {
+            RUNNING_MODE.set(RwLock::new(running_mode)).map_err(|_| {
+                let err_msg = "Failed to initialize the stable diffusion context. Reason: The `RUNNING_MODE` has already been initialized";
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{err_msg}");
+
+                LlamaCoreError::InitContext(err_msg.into())
+            })?;
+        }
+    }
+
     Ok(())
 }
 
 /// The task type of the stable diffusion context
-#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum StableDiffusionTask {
     /// `text_to_image` context
+    #[serde(rename = "text2image")]
     TextToImage,
     /// `image_to_image` context
+    #[serde(rename = "image2image")]
     ImageToImage,
     /// Both `text_to_image` and `image_to_image` contexts
+    #[serde(rename = "full")]
     Full,
 }
+impl std::fmt::Display for StableDiffusionTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StableDiffusionTask::TextToImage => write!(f, "text2image"),
+            StableDiffusionTask::ImageToImage => write!(f, "image2image"),
+            StableDiffusionTask::Full => write!(f, "full"),
+        }
+    }
+}
+impl std::str::FromStr for StableDiffusionTask {
+    type Err = LlamaCoreError;
+
+    fn from_str(task: &str) -> Result<Self, Self::Err> {
+        match task.to_lowercase().as_str() {
+            "text2image" => Ok(StableDiffusionTask::TextToImage),
+            "image2image" => Ok(StableDiffusionTask::ImageToImage),
+            "full" => Ok(StableDiffusionTask::Full),
+            _ => Err(LlamaCoreError::Operation(format!(
+                "Unsupported stable diffusion task: {task}. Supported tasks are `text2image`, `image2image` and `full`."
+            ))),
+        }
+    }
+}
 
 /// Initialize the whisper context
 #[cfg(feature = "whisper")]
@@ -978,6 +1054,25 @@ pub fn init_whisper_context(whisper_metadata: &WhisperMetadata) -> Result<(), Ll
     #[cfg(feature = "logging")]
     info!(target: "stdout", "The audio context has been initialized");
 
+    // set running mode
+    let running_mode = RunningMode::AUDIO;
+    match RUNNING_MODE.get() {
+        Some(mode) => {
+            let mut mode = mode.write().unwrap();
+            *mode |= running_mode;
+        }
+        None => {
+            RUNNING_MODE.set(RwLock::new(running_mode)).map_err(|_| {
+                let err_msg = "Failed to initialize the audio context. Reason: The `RUNNING_MODE` has already been initialized";
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{err_msg}");
+
+                LlamaCoreError::InitContext(err_msg.into())
+            })?;
+        }
+    }
+
     Ok(())
 }
 
@@ -1,9 +1,13 @@
 //! Define metadata for the ggml model.
 
 use super::BaseMetadata;
-use chat_prompts::PromptTemplateType;
+use chat_prompts::{chat::ChatTemplate, PromptTemplateType};
+use endpoints::chat::TruncationStrategy;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 /// Builder for creating a ggml metadata
 #[derive(Debug)]
@@ -27,6 +31,16 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn with_chat_template(mut self, template: Option<ChatTemplate>) -> Self {
+        self.metadata.chat_template = template;
+        self
+    }
+
+    pub fn with_model_file(mut self, path: Option<PathBuf>) -> Self {
+        self.metadata.model_file = path;
+        self
+    }
+
     pub fn enable_plugin_log(mut self, enable: bool) -> Self {
         self.metadata.log_enable = enable;
         self
@@ -47,6 +61,11 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn enable_prompt_cache(mut self, enable: bool) -> Self {
+        self.metadata.cache_prompt = enable;
+        self
+    }
+
     pub fn with_n_predict(mut self, n: i32) -> Self {
         self.metadata.n_predict = n;
         self
@@ -152,6 +171,11 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn with_truncation_strategy(mut self, strategy: TruncationStrategy) -> Self {
+        self.metadata.truncation = strategy;
+        self
+    }
+
     pub fn build(self) -> GgmlMetadata {
         self.metadata
     }
@@ -172,6 +196,18 @@ pub struct GgmlMetadata {
     // this field not defined for the beckend plugin
     #[serde(skip_serializing)]
     pub prompt_template: PromptTemplateType,
+    // this field not defined for the beckend plugin
+    /// The chat template of the model, which is rendered by the `jinja` prompt template. Defaults to None.
+    #[serde(skip_serializing, default)]
+    pub chat_template: Option<ChatTemplate>,
+    // this field not defined for the beckend plugin
+    /// The path to the GGUF file of the model, whose metadata is read to detect the prompt template. Defaults to None.
+    #[serde(skip_serializing, default)]
+    pub model_file: Option<PathBuf>,
+    // this field not defined for the beckend plugin
+    /// The prompt template detected from the model file when the model is loaded. Defaults to None.
+    #[serde(skip_serializing, default)]
+    pub detected_prompt_template: Option<PromptTemplateType>,
 
     // * Plugin parameters (used by this plugin):
     #[serde(rename = "enable-log")]
@@ -194,6 +230,14 @@ pub struct GgmlMetadata {
     /// Path to the image file for llava
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
+    /// Whether to keep the KV cache of the last prompt evaluated by the execution context of each slot, so that the next prompt starting with it only evaluates the new tokens. Defaults to false.
+    // only serialized if enabled, so that the plugin gets the same metadata as before unless the prompt cache is requested
+    #[serde(
+        rename = "cache-prompt",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub cache_prompt: bool,
 
     // * Model parameters (need to reload the model if updated):
     #[serde(rename = "n-gpu-layers")]
@@ -246,6 +290,16 @@ pub struct GgmlMetadata {
     /// Repeat alpha frequency penalty. Defaults to 0.0.
     #[serde(rename = "frequency-penalty")]
     pub frequency_penalty: f64,
+    /// Modify the likelihood of the specified tokens appearing in the completion. Maps token ids to bias values between -100 and 100; `-100` bans the token. Defaults to empty.
+    // always serialized, so that an empty map clears the biases of the previous request
+    #[serde(rename = "logit-bias")]
+    pub logit_bias: HashMap<u32, f64>,
+    /// Whether to return the log probabilities of the output tokens. Defaults to false.
+    #[serde(rename = "logprobs")]
+    pub logprobs: bool,
+    /// Number of most likely tokens to return at each token position, between 0 and 20. Only used if `logprobs` is enabled. Defaults to 0.
+    #[serde(rename = "top-logprobs")]
+    pub top_logprobs: u8,
 
     // * grammar parameters
     /// BNF-like grammar to constrain generations (see samples in grammars/ dir). Defaults to empty string.
@@ -256,6 +310,11 @@ pub struct GgmlMetadata {
 
     /// Whether to include usage in the stream response. Defaults to false.
     pub include_usage: bool,
+
+    // this field not defined for the beckend plugin
+    /// How the chat history is shortened if the prompt does not fit in the context window, unless set by the request. Defaults to `auto`.
+    #[serde(skip_serializing, default)]
+    pub truncation: TruncationStrategy,
 }
 impl Default for GgmlMetadata {
     fn default() -> Self {
@@ -265,12 +324,16 @@ impl Default for GgmlMetadata {
             log_prompts: false,
             debug_log: false,
             prompt_template: PromptTemplateType::Llama2Chat,
+            chat_template: None,
+            model_file: None,
+            detected_prompt_template: None,
             log_enable: false,
             embeddings: false,
             n_predict: -1,
             reverse_prompt: None,
             mmproj: None,
             image: None,
+            cache_prompt: false,
             n_gpu_layers: 100,
             main_gpu: None,
             tensor_split: None,
@@ -285,9 +348,13 @@ impl Default for GgmlMetadata {
             repeat_penalty: 1.0,
             presence_penalty: 0.0,
             frequency_penalty: 0.0,
+            logit_bias: HashMap::new(),
+            logprobs: false,
+            top_logprobs: 0,
             grammar: String::new(),
             json_schema: None,
             include_usage: false,
+            truncation: TruncationStrategy::default(),
         }
     }
 }
@@ -1,7 +1,24 @@
-//! Define APIs for querying models.
+//! Define APIs for querying, loading and unloading models.
+//!
+//! The chat, embedding and TTS models can be added, replaced and removed at runtime, and the running mode is updated as the models come and go. A chat model cannot be replaced or removed while requests are holding or waiting for its slots.
+//!
+//! When a chat model is added, its prompt template is detected from the metadata of its model file, if the file is given. The `auto` prompt template is replaced with the detected one.
 
-use crate::{error::LlamaCoreError, CHAT_GRAPHS, EMBEDDING_GRAPHS};
+use crate::{
+    error::LlamaCoreError,
+    gguf::GgufMetadata,
+    metadata::ggml::{GgmlMetadata, GgmlTtsMetadata},
+    pool, scheduler,
+    utils::RunningMode,
+    BaseMetadata, Graph, CHAT_GRAPHS, EMBEDDING_GRAPHS, RUNNING_MODE, TTS_GRAPHS,
+};
+use chat_prompts::PromptTemplateType;
 use endpoints::models::{ListModelsResponse, Model};
+use once_cell::sync::OnceCell;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+};
 
 /// Lists models available
 pub async fn models() -> Result<ListModelsResponse, LlamaCoreError> {
@@ -37,6 +54,18 @@ pub async fn models() -> Result<ListModelsResponse, LlamaCoreError> {
         }
     }
 
+    // the models of the pool that are not loaded
+    for name in pool::registered_chat_models()? {
+        if !models.iter().any(|model| model.id == name) {
+            models.push(Model {
+                id: name,
+                created: 0,
+                object: String::from("model"),
+                owned_by: String::from("Not specified"),
+            });
+        }
+    }
+
     {
         if let Some(embedding_graphs) = EMBEDDING_GRAPHS.get() {
             let embedding_graphs = embedding_graphs.lock().map_err(|e| {
@@ -68,3 +97,371 @@ pub async fn models() -> Result<ListModelsResponse, LlamaCoreError> {
         data: models,
     })
 }
+
+/// Adds a chat model. The name of the model must not be in use.
+///
+/// # Arguments
+///
+/// * `graph` - The graph of the chat model, e.g. created by [`Graph::new`] from a preloaded model, or by [`GraphBuilder::build_from_files`](crate::GraphBuilder::build_from_files).
+pub fn add_chat_graph(mut graph: Graph<GgmlMetadata>) -> Result<(), LlamaCoreError> {
+    resolve_prompt_template(&mut graph.metadata)?;
+
+    add_graph(&CHAT_GRAPHS, "chat", graph)?;
+
+    update_running_mode(RunningMode::CHAT, true)
+}
+
+/// Replaces the chat model with the same name as the given graph, and returns the metadata of the replaced model.
+pub fn replace_chat_graph(mut graph: Graph<GgmlMetadata>) -> Result<GgmlMetadata, LlamaCoreError> {
+    check_chat_model_idle(graph.name())?;
+
+    resolve_prompt_template(&mut graph.metadata)?;
+
+    replace_graph(&CHAT_GRAPHS, "chat", graph)
+}
+
+/// Removes the chat model with the given name, and returns its metadata.
+pub fn remove_chat_graph(model_name: impl AsRef<str>) -> Result<GgmlMetadata, LlamaCoreError> {
+    let model_name = model_name.as_ref();
+
+    let (metadata, is_empty) = unload_chat_graph(model_name)?;
+
+    scheduler::remove_model(model_name)?;
+
+    // the requests for the models of the pool are still served in the chat mode
+    if is_empty && pool::registered_chat_models()?.is_empty() {
+        update_running_mode(RunningMode::CHAT, false)?;
+    }
+
+    Ok(metadata)
+}
+
+/// Removes the chat model with the given name without changing the running mode and the settings of its queue, and returns its metadata and whether no chat model is left.
+pub(crate) fn unload_chat_graph(model_name: &str) -> Result<(GgmlMetadata, bool), LlamaCoreError> {
+    check_chat_model_idle(model_name)?;
+
+    remove_graph(&CHAT_GRAPHS, "chat", model_name)
+}
+
+/// Sets the chat mode in the running mode.
+pub(crate) fn enable_chat_mode() -> Result<(), LlamaCoreError> {
+    update_running_mode(RunningMode::CHAT, true)
+}
+
+/// Returns the prompt templates of the loaded chat models by their names, with the prompt templates detected from their model files.
+pub fn chat_prompt_templates(
+) -> Result<HashMap<String, (PromptTemplateType, Option<PromptTemplateType>)>, LlamaCoreError> {
+    // the chat graphs are not initialized here, so that the core context can still be initialized
+    if CHAT_GRAPHS.get().is_none() {
+        return Ok(HashMap::new());
+    }
+
+    let chat_graphs = lock_graphs(&CHAT_GRAPHS, "chat")?;
+
+    Ok(chat_graphs
+        .iter()
+        .map(|(name, graph)| {
+            (
+                name.clone(),
+                (
+                    graph.metadata.prompt_template,
+                    graph.metadata.detected_prompt_template,
+                ),
+            )
+        })
+        .collect())
+}
+
+/// Detects the prompt template of the chat model from its model file.
+///
+/// The `auto` prompt template is replaced with the detected one, and an error is returned if it cannot be detected. A warning is logged if the prompt template set by the user looks inconsistent with the model.
+pub(crate) fn resolve_prompt_template(metadata: &mut GgmlMetadata) -> Result<(), LlamaCoreError> {
+    let is_auto = metadata.prompt_template == PromptTemplateType::Auto;
+
+    let gguf = match (&metadata.model_file, is_auto) {
+        (Some(model_file), true) => GgufMetadata::read(model_file)?,
+        // the prompt template set by the user is used even if the model file cannot be read
+        (Some(model_file), false) => match GgufMetadata::read(model_file) {
+            Ok(gguf) => gguf,
+            Err(_) => return Ok(()),
+        },
+        (None, true) => {
+            // the file preloaded by the backend cannot be read by the application
+            let err_msg = format!(
+                "The `auto` prompt template of the chat model `{}` requires the `model_file` field, set to the path of the GGUF file of the model.",
+                metadata.model_name
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg));
+        }
+        (None, false) => return Ok(()),
+    };
+
+    let detected = gguf.detect_prompt_template();
+    metadata.detected_prompt_template = detected;
+
+    match (metadata.prompt_template, detected) {
+        (PromptTemplateType::Auto, Some(detected)) => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The prompt template of the chat model {} is detected: {}", metadata.model_name, detected);
+
+            if detected == PromptTemplateType::Jinja && metadata.chat_template.is_none() {
+                metadata.chat_template = gguf.chat_template();
+            }
+
+            metadata.prompt_template = detected;
+        }
+        (PromptTemplateType::Auto, None) => {
+            let err_msg = format!(
+                "Failed to detect the prompt template of the chat model `{}`. Please set the prompt template explicitly.",
+                metadata.model_name
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg));
+        }
+        (prompt_template, Some(detected))
+            if !is_consistent_prompt_template(prompt_template, detected) =>
+        {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The prompt template `{}` of the chat model {} looks inconsistent with the model, whose chat template matches `{}`. Use `auto` to detect the prompt template.", prompt_template, metadata.model_name, detected);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if the prompt template set by the user builds prompts in the same format as the detected one. The variants of a prompt template, e.g. the ones for tool use, build prompts in the same format.
+fn is_consistent_prompt_template(
+    prompt_template: PromptTemplateType,
+    detected: PromptTemplateType,
+) -> bool {
+    fn format_of(prompt_template: PromptTemplateType) -> PromptTemplateType {
+        match prompt_template {
+            PromptTemplateType::Llama3Tool
+            | PromptTemplateType::GroqLlama3Tool
+            | PromptTemplateType::FunctionaryV31
+            | PromptTemplateType::FunctionaryV32 => PromptTemplateType::Llama3Chat,
+            PromptTemplateType::ChatMLTool
+            | PromptTemplateType::ChatMLThink
+            | PromptTemplateType::InternLM2Tool
+            | PromptTemplateType::Qwen3NoThink
+            | PromptTemplateType::Qwen3Agent
+            | PromptTemplateType::NemotronChat
+            | PromptTemplateType::NemotronTool
+            | PromptTemplateType::Smol3NoThink => PromptTemplateType::ChatML,
+            PromptTemplateType::MistralTool => PromptTemplateType::MistralInstruct,
+            PromptTemplateType::MistralSmallTool => PromptTemplateType::MistralSmallChat,
+            PromptTemplateType::Phi3Instruct => PromptTemplateType::Phi3Chat,
+            PromptTemplateType::DeepseekChat25 => PromptTemplateType::DeepseekChat3,
+            PromptTemplateType::SeedOssNoThink => PromptTemplateType::SeedOssThink,
+            PromptTemplateType::ExaoneDeepChat => PromptTemplateType::ExaoneChat,
+            prompt_template => prompt_template,
+        }
+    }
+
+    // the chat templates without known markers, and the templates declared by the users are not compared
+    matches!(
+        prompt_template,
+        PromptTemplateType::Jinja | PromptTemplateType::Custom(_)
+    ) || detected == PromptTemplateType::Jinja
+        || format_of(prompt_template) == format_of(detected)
+}
+
+/// Adds an embedding model. The name of the model must not be in use.
+pub fn add_embedding_graph(graph: Graph<GgmlMetadata>) -> Result<(), LlamaCoreError> {
+    add_graph(&EMBEDDING_GRAPHS, "embedding", graph)?;
+
+    update_running_mode(RunningMode::EMBEDDINGS, true)
+}
+
+/// Replaces the embedding model with the same name as the given graph, and returns the metadata of the replaced model.
+pub fn replace_embedding_graph(graph: Graph<GgmlMetadata>) -> Result<GgmlMetadata, LlamaCoreError> {
+    replace_graph(&EMBEDDING_GRAPHS, "embedding", graph)
+}
+
+/// Removes the embedding model with the given name, and returns its metadata.
+pub fn remove_embedding_graph(model_name: impl AsRef<str>) -> Result<GgmlMetadata, LlamaCoreError> {
+    let (metadata, is_empty) = remove_graph(&EMBEDDING_GRAPHS, "embedding", model_name.as_ref())?;
+
+    if is_empty {
+        update_running_mode(RunningMode::EMBEDDINGS, false)?;
+    }
+
+    Ok(metadata)
+}
+
+/// Adds a TTS model. The name of the model must not be in use.
+pub fn add_tts_graph(graph: Graph<GgmlTtsMetadata>) -> Result<(), LlamaCoreError> {
+    add_graph(&TTS_GRAPHS, "tts", graph)?;
+
+    update_running_mode(RunningMode::TTS, true)
+}
+
+/// Replaces the TTS model with the same name as the given graph, and returns the metadata of the replaced model.
+pub fn replace_tts_graph(graph: Graph<GgmlTtsMetadata>) -> Result<GgmlTtsMetadata, LlamaCoreError> {
+    replace_graph(&TTS_GRAPHS, "tts", graph)
+}
+
+/// Removes the TTS model with the given name, and returns its metadata.
+pub fn remove_tts_graph(model_name: impl AsRef<str>) -> Result<GgmlTtsMetadata, LlamaCoreError> {
+    let (metadata, is_empty) = remove_graph(&TTS_GRAPHS, "tts", model_name.as_ref())?;
+
+    if is_empty {
+        update_running_mode(RunningMode::TTS, false)?;
+    }
+
+    Ok(metadata)
+}
+
+type Graphs<M> = OnceCell<Mutex<HashMap<String, Graph<M>>>>;
+
+fn add_graph<M: BaseMetadata + serde::Serialize + Clone + Default>(
+    graphs: &Graphs<M>,
+    kind: &str,
+    graph: Graph<M>,
+) -> Result<(), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Add the {} model named {}", kind, graph.name());
+
+    let mut graphs = lock_graphs(graphs, kind)?;
+
+    if graphs.contains_key(graph.name()) {
+        let err_msg = format!(
+            "The model `{}` already exists in the {} graphs.",
+            graph.name(),
+            kind
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::ModelConflict(err_msg));
+    }
+
+    graphs.insert(graph.name().to_string(), graph);
+
+    Ok(())
+}
+
+fn replace_graph<M: BaseMetadata + serde::Serialize + Clone + Default>(
+    graphs: &Graphs<M>,
+    kind: &str,
+    graph: Graph<M>,
+) -> Result<M, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Replace the {} model named {}", kind, graph.name());
+
+    let mut graphs = lock_graphs(graphs, kind)?;
+
+    match graphs.get_mut(graph.name()) {
+        Some(old_graph) => {
+            // keep the creation time, so that the default model does not change
+            let mut graph = graph;
+            graph.created = old_graph.created;
+
+            let old_graph = std::mem::replace(old_graph, graph);
+
+            Ok(old_graph.metadata.clone())
+        }
+        None => {
+            let err_msg = format!(
+                "The model `{}` does not exist in the {} graphs.",
+                graph.name(),
+                kind
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::ModelNotFound(err_msg))
+        }
+    }
+}
+
+/// Removes the graph with the given name, and returns its metadata and whether no graph is left.
+fn remove_graph<M: BaseMetadata + serde::Serialize + Clone + Default>(
+    graphs: &Graphs<M>,
+    kind: &str,
+    model_name: &str,
+) -> Result<(M, bool), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Remove the {kind} model named {model_name}");
+
+    let mut graphs = lock_graphs(graphs, kind)?;
+
+    match graphs.remove(model_name) {
+        Some(graph) => Ok((graph.metadata.clone(), graphs.is_empty())),
+        None => {
+            let err_msg = format!("The model `{model_name}` does not exist in the {kind} graphs.");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::ModelNotFound(err_msg))
+        }
+    }
+}
+
+fn lock_graphs<'a, M: BaseMetadata + serde::Serialize + Clone + Default>(
+    graphs: &'a Graphs<M>,
+    kind: &str,
+) -> Result<std::sync::MutexGuard<'a, HashMap<String, Graph<M>>>, LlamaCoreError> {
+    graphs
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of the {kind} graphs. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+/// Returns an error if requests are holding or waiting for the slots of the chat model.
+fn check_chat_model_idle(model_name: &str) -> Result<(), LlamaCoreError> {
+    let status = scheduler::queue_status(model_name)?;
+
+    if status.active > 0 || status.queued > 0 {
+        let err_msg = format!(
+            "The model `{}` is in use by {} request(s), and {} request(s) are waiting for it. Please retry later.",
+            model_name, status.active, status.queued
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::ModelConflict(err_msg));
+    }
+
+    Ok(())
+}
+
+/// Sets or clears the given mode in the running mode.
+fn update_running_mode(mode: RunningMode, enabled: bool) -> Result<(), LlamaCoreError> {
+    let running_mode = RUNNING_MODE.get_or_init(|| RwLock::new(RunningMode::UNSET));
+
+    let mut running_mode = running_mode.write().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `RUNNING_MODE`. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    running_mode.set(mode, enabled);
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "running mode: {}", *running_mode);
+
+    Ok(())
+}
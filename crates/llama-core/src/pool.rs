@@ -0,0 +1,678 @@
+//! Define the pool of chat models that are loaded on demand.
+//!
+//! A chat model registered by [`register_chat_model`] is not loaded until the first request for it. Before loading a model, the pool unloads the least recently used models of the pool until the number of resident models and their estimated memory fit the limits of [`PoolConfig`]. The models that have been idle for longer than the idle timeout are unloaded by [`evict_idle_models`], which is also called before each load. A model is never unloaded while requests are holding or waiting for its slots. The chat models loaded by other means, e.g. [`init_ggml_chat_context`](crate::init_ggml_chat_context), are not managed by the pool.
+//!
+//! The loads and evictions are reported to the handler set by [`set_event_handler`].
+
+use crate::{
+    error::LlamaCoreError, metadata::ggml::GgmlMetadata, models, scheduler, Graph, CHAT_GRAPHS,
+};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+static POOL: OnceCell<Mutex<ModelPool>> = OnceCell::new();
+
+/// The limits of the model pool. No limit is applied to an option set to `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The maximum number of models of the pool that are loaded at the same time.
+    pub max_resident: Option<usize>,
+    /// The maximum estimated memory in bytes of the models of the pool that are loaded at the same time.
+    pub memory_budget: Option<u64>,
+    /// The time after which an idle model is unloaded.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// The reason why a model is unloaded from the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// The model has been idle for longer than the idle timeout.
+    IdleTimeout,
+    /// The model is unloaded to make room for another model.
+    Capacity,
+}
+
+/// An event of the model pool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PoolEvent {
+    /// A model is loaded.
+    Loaded {
+        /// The name of the model.
+        model_name: String,
+        /// The time spent on loading the model.
+        elapsed: Duration,
+    },
+    /// A model is unloaded.
+    Evicted {
+        /// The name of the model.
+        model_name: String,
+        /// The reason of the eviction.
+        reason: EvictionReason,
+        /// The time since the model was last used.
+        idle: Duration,
+    },
+}
+
+/// The status of a model of the pool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PooledModelStatus {
+    /// The name of the model.
+    pub name: String,
+    /// Whether the model is loaded.
+    pub resident: bool,
+    /// The estimated memory of the model in bytes.
+    pub memory: u64,
+    /// The Unix timestamp (in seconds) of the last use of the model, if it has been used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<u64>,
+    /// The number of times the model has been loaded.
+    pub loads: u64,
+    /// The number of times the model has been unloaded by the pool.
+    pub evictions: u64,
+}
+
+/// The status of the model pool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolStatus {
+    /// The maximum number of resident models.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_resident: Option<usize>,
+    /// The memory budget in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_budget: Option<u64>,
+    /// The idle timeout in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    /// The estimated memory in bytes of the resident models.
+    pub resident_memory: u64,
+    /// The models of the pool, in the order of registration.
+    pub models: Vec<PooledModelStatus>,
+}
+
+type EventHandler = Arc<dyn Fn(&PoolEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct ModelPool {
+    config: PoolConfig,
+    // key: model_name
+    models: HashMap<String, PooledModel>,
+    // model names in the order of registration
+    order: Vec<String>,
+    handler: Option<EventHandler>,
+}
+
+#[derive(Debug)]
+struct PooledModel {
+    metadata: GgmlMetadata,
+    memory: u64,
+    last_used: Option<SystemTime>,
+    loads: u64,
+    evictions: u64,
+}
+impl PooledModel {
+    fn idle_time(&self) -> Duration {
+        self.last_used
+            .and_then(|last_used| SystemTime::now().duration_since(last_used).ok())
+            .unwrap_or_default()
+    }
+}
+
+fn lock_pool() -> Result<MutexGuard<'static, ModelPool>, LlamaCoreError> {
+    POOL.get_or_init(|| Mutex::new(ModelPool::default()))
+        .lock()
+        .map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of `POOL`. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+/// Sets the limits of the model pool. The new limits apply to the next load.
+pub fn set_pool_config(config: PoolConfig) -> Result<(), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set the config of the model pool: {config:?}");
+
+    let mut pool = lock_pool()?;
+    pool.config = config;
+
+    Ok(())
+}
+
+/// Registers a chat model in the pool. The model is loaded from the model preloaded with the alias in the metadata when the first request for it arrives.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the chat model.
+///
+/// * `memory` - The estimated memory of the model in bytes, e.g. the size of the model file. It is checked against the memory budget of the pool.
+pub fn register_chat_model(metadata: GgmlMetadata, memory: u64) -> Result<(), LlamaCoreError> {
+    let model_name = metadata.model_name.clone();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Register the chat model named {model_name} in the model pool");
+
+    let mut pool = lock_pool()?;
+
+    if let Some(memory_budget) = pool.config.memory_budget {
+        if memory > memory_budget {
+            let err_msg = format!(
+                "The estimated memory of the model `{model_name}` ({memory} bytes) exceeds the memory budget of the model pool ({memory_budget} bytes)."
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg));
+        }
+    }
+
+    if pool.models.contains_key(&model_name) {
+        let err_msg = format!("The model `{model_name}` is already registered in the model pool.");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::ModelConflict(err_msg));
+    }
+
+    pool.order.push(model_name.clone());
+    pool.models.insert(
+        model_name,
+        PooledModel {
+            metadata,
+            memory,
+            last_used: None,
+            loads: 0,
+            evictions: 0,
+        },
+    );
+
+    // the requests for the registered models are served in the chat mode
+    models::enable_chat_mode()
+}
+
+/// Sets the handler of the load and eviction events of the pool. The handler must not call the functions of this module.
+pub fn set_event_handler(
+    handler: impl Fn(&PoolEvent) + Send + Sync + 'static,
+) -> Result<(), LlamaCoreError> {
+    let mut pool = lock_pool()?;
+    pool.handler = Some(Arc::new(handler));
+
+    Ok(())
+}
+
+/// Unloads the models of the pool that have been idle for longer than the idle timeout, and returns their names.
+pub fn evict_idle_models() -> Result<Vec<String>, LlamaCoreError> {
+    let mut pool = lock_pool()?;
+
+    let mut events = vec![];
+    let res = pool.evict_idle_models(&mut events);
+    emit(pool, events);
+
+    res
+}
+
+/// Returns the status of the model pool.
+pub fn pool_status() -> Result<PoolStatus, LlamaCoreError> {
+    let pool = lock_pool()?;
+    let resident = resident_chat_models()?;
+
+    let models: Vec<PooledModelStatus> = pool
+        .order
+        .iter()
+        .filter_map(|name| pool.models.get(name).map(|model| (name, model)))
+        .map(|(name, model)| PooledModelStatus {
+            name: name.clone(),
+            resident: resident.contains(name),
+            memory: model.memory,
+            last_used: model.last_used.and_then(|last_used| {
+                last_used
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_secs())
+            }),
+            loads: model.loads,
+            evictions: model.evictions,
+        })
+        .collect();
+
+    Ok(PoolStatus {
+        max_resident: pool.config.max_resident,
+        memory_budget: pool.config.memory_budget,
+        idle_timeout: pool.config.idle_timeout.map(|timeout| timeout.as_secs()),
+        resident_memory: models
+            .iter()
+            .filter(|model| model.resident)
+            .map(|model| model.memory)
+            .sum(),
+        models,
+    })
+}
+
+/// Returns the names of the registered models, in the order of registration.
+pub(crate) fn registered_chat_models() -> Result<Vec<String>, LlamaCoreError> {
+    let pool = lock_pool()?;
+
+    Ok(pool.order.clone())
+}
+
+/// Loads the chat model requested by the given model name if it is registered in the pool and not loaded. If the model name is not given and no chat model is loaded, the model registered first is loaded.
+///
+/// The caller is expected to acquire a slot of the model right after this function returns, so that the model cannot be evicted before the request is served.
+pub(crate) fn ensure_chat_model(model_name: Option<&String>) -> Result<(), LlamaCoreError> {
+    let mut pool = lock_pool()?;
+    if pool.models.is_empty() {
+        return Ok(());
+    }
+
+    let mut events = vec![];
+    let res = pool.ensure_chat_model(model_name, &mut events);
+    emit(pool, events);
+
+    res
+}
+
+/// Updates the time of the last use of the chat model if it is registered in the pool.
+pub(crate) fn touch(model_name: &str) {
+    if let Ok(mut pool) = lock_pool() {
+        if let Some(model) = pool.models.get_mut(model_name) {
+            model.last_used = Some(SystemTime::now());
+        }
+    }
+}
+
+/// Sends the events to the handler after releasing the lock of the pool.
+fn emit(pool: MutexGuard<'_, ModelPool>, events: Vec<PoolEvent>) {
+    let handler = pool.handler.clone();
+    drop(pool);
+
+    if let Some(handler) = handler {
+        for event in events.iter() {
+            handler(event);
+        }
+    }
+}
+
+impl ModelPool {
+    fn ensure_chat_model(
+        &mut self,
+        model_name: Option<&String>,
+        events: &mut Vec<PoolEvent>,
+    ) -> Result<(), LlamaCoreError> {
+        self.evict_idle_models(events)?;
+
+        let resident = resident_chat_models()?;
+        let target = match model_name {
+            Some(model_name) if self.models.contains_key(model_name) => model_name.clone(),
+            // the models not in the pool are resolved by the scheduler
+            Some(_) => return Ok(()),
+            None if resident.is_empty() => match self.order.first() {
+                Some(model_name) => model_name.clone(),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+
+        if resident.contains(&target) {
+            return Ok(());
+        }
+
+        self.make_room(&target, resident, events)?;
+
+        self.load(&target, events)
+    }
+
+    /// Unloads the least recently used models until the target model fits the limits of the pool.
+    fn make_room(
+        &mut self,
+        target: &str,
+        mut resident: HashSet<String>,
+        events: &mut Vec<PoolEvent>,
+    ) -> Result<(), LlamaCoreError> {
+        let memory = self
+            .models
+            .get(target)
+            .map(|model| model.memory)
+            .unwrap_or(0);
+
+        loop {
+            let resident_models: Vec<&String> = resident
+                .iter()
+                .filter(|name| self.models.contains_key(*name))
+                .collect();
+            let resident_memory: u64 = resident_models
+                .iter()
+                .filter_map(|name| self.models.get(*name))
+                .map(|model| model.memory)
+                .sum();
+
+            let fits_count = self
+                .config
+                .max_resident
+                .is_none_or(|max_resident| resident_models.len() < max_resident);
+            let fits_memory = self
+                .config
+                .memory_budget
+                .is_none_or(|memory_budget| resident_memory + memory <= memory_budget);
+            if fits_count && fits_memory {
+                return Ok(());
+            }
+
+            // the least recently used model that is not serving requests
+            let mut candidate = None;
+            for name in resident_models {
+                if !is_idle(name)? {
+                    continue;
+                }
+
+                let last_used = self.models.get(name).and_then(|model| model.last_used);
+                match candidate {
+                    Some((_, candidate_last_used)) if candidate_last_used <= last_used => {}
+                    _ => candidate = Some((name.clone(), last_used)),
+                }
+            }
+
+            match candidate {
+                Some((name, _)) => {
+                    self.evict(&name, EvictionReason::Capacity, events)?;
+                    resident.remove(&name);
+                }
+                None => {
+                    let err_msg = format!(
+                        "Fail to load the model `{target}`. The model pool is full, and all the resident models are serving requests. Please retry later."
+                    );
+
+                    #[cfg(feature = "logging")]
+                    warn!(target: "stdout", "{}", &err_msg);
+
+                    return Err(LlamaCoreError::QueueFull(err_msg));
+                }
+            }
+        }
+    }
+
+    fn load(
+        &mut self,
+        model_name: &str,
+        events: &mut Vec<PoolEvent>,
+    ) -> Result<(), LlamaCoreError> {
+        let model = match self.models.get_mut(model_name) {
+            Some(model) => model,
+            None => {
+                let err_msg =
+                    format!("The model `{model_name}` is not registered in the model pool.");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::ModelNotFound(err_msg));
+            }
+        };
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Load the model named {model_name} in the model pool");
+
+        let start = Instant::now();
+        let graph = Graph::new(model.metadata.clone())?;
+        models::add_chat_graph(graph)?;
+        let elapsed = start.elapsed();
+
+        model.loads += 1;
+        model.last_used = Some(SystemTime::now());
+
+        events.push(PoolEvent::Loaded {
+            model_name: model_name.to_string(),
+            elapsed,
+        });
+
+        Ok(())
+    }
+
+    fn evict_idle_models(
+        &mut self,
+        events: &mut Vec<PoolEvent>,
+    ) -> Result<Vec<String>, LlamaCoreError> {
+        let idle_timeout = match self.config.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return Ok(vec![]),
+        };
+
+        let resident = resident_chat_models()?;
+
+        let mut evicted = vec![];
+        for name in self.order.clone() {
+            let expired = self
+                .models
+                .get(&name)
+                .is_some_and(|model| model.idle_time() >= idle_timeout);
+            if resident.contains(&name) && expired && is_idle(&name)? {
+                self.evict(&name, EvictionReason::IdleTimeout, events)?;
+                evicted.push(name);
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    fn evict(
+        &mut self,
+        model_name: &str,
+        reason: EvictionReason,
+        events: &mut Vec<PoolEvent>,
+    ) -> Result<(), LlamaCoreError> {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Evict the model named {model_name} from the model pool. Reason: {reason:?}");
+
+        // the graph is unloaded when it is dropped
+        models::unload_chat_graph(model_name)?;
+
+        let idle = match self.models.get_mut(model_name) {
+            Some(model) => {
+                model.evictions += 1;
+                model.idle_time()
+            }
+            None => Duration::default(),
+        };
+
+        events.push(PoolEvent::Evicted {
+            model_name: model_name.to_string(),
+            reason,
+            idle,
+        });
+
+        Ok(())
+    }
+}
+
+/// Returns `true` if no request is holding or waiting for the slots of the chat model.
+fn is_idle(model_name: &str) -> Result<bool, LlamaCoreError> {
+    let status = scheduler::queue_status(model_name)?;
+
+    Ok(status.active == 0 && status.queued == 0)
+}
+
+/// Returns the names of the loaded chat models.
+fn resident_chat_models() -> Result<HashSet<String>, LlamaCoreError> {
+    match CHAT_GRAPHS.get() {
+        Some(chat_graphs) => {
+            let chat_graphs = chat_graphs.lock().map_err(|e| {
+                let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {e}");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::Operation(err_msg)
+            })?;
+
+            Ok(chat_graphs.keys().cloned().collect())
+        }
+        None => Ok(HashSet::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(config: PoolConfig, models: &[(&str, u64, Option<SystemTime>)]) -> ModelPool {
+        let mut pool = ModelPool {
+            config,
+            ..Default::default()
+        };
+        for (name, memory, last_used) in models {
+            pool.order.push(name.to_string());
+            pool.models.insert(
+                name.to_string(),
+                PooledModel {
+                    metadata: GgmlMetadata {
+                        model_name: name.to_string(),
+                        ..Default::default()
+                    },
+                    memory: *memory,
+                    last_used: *last_used,
+                    loads: 0,
+                    evictions: 0,
+                },
+            );
+        }
+        pool
+    }
+
+    fn resident(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_idle_time() {
+        let model = pool(PoolConfig::default(), &[("pool-idle", 1, None)]);
+        assert_eq!(model.models["pool-idle"].idle_time(), Duration::ZERO);
+
+        let last_used = SystemTime::now() - Duration::from_secs(60);
+        let model = pool(PoolConfig::default(), &[("pool-idle", 1, Some(last_used))]);
+        assert!(model.models["pool-idle"].idle_time() >= Duration::from_secs(60));
+
+        // a clock moving backwards does not make the model idle
+        let last_used = SystemTime::now() + Duration::from_secs(60);
+        let model = pool(PoolConfig::default(), &[("pool-idle", 1, Some(last_used))]);
+        assert_eq!(model.models["pool-idle"].idle_time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_make_room_within_limits() {
+        let config = PoolConfig {
+            max_resident: Some(2),
+            memory_budget: Some(10),
+            idle_timeout: None,
+        };
+        let mut pool = pool(config, &[("pool-fit-a", 4, None), ("pool-fit-b", 6, None)]);
+
+        let mut events = vec![];
+        pool.make_room("pool-fit-b", resident(&["pool-fit-a"]), &mut events)
+            .unwrap();
+        assert!(events.is_empty());
+
+        // the models loaded outside the pool are not counted
+        pool.config.max_resident = Some(1);
+        pool.make_room("pool-fit-a", resident(&["outside-the-pool"]), &mut events)
+            .unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_make_room_without_idle_models() {
+        let _permit = scheduler::try_acquire_slot("pool-busy-a").unwrap();
+        assert!(!is_idle("pool-busy-a").unwrap());
+
+        // the pool is full by count
+        let config = PoolConfig {
+            max_resident: Some(1),
+            ..Default::default()
+        };
+        let mut full = pool(
+            config,
+            &[("pool-busy-a", 1, None), ("pool-busy-b", 1, None)],
+        );
+        let mut events = vec![];
+        let res = full.make_room("pool-busy-b", resident(&["pool-busy-a"]), &mut events);
+        assert!(matches!(res, Err(LlamaCoreError::QueueFull(_))));
+        assert!(events.is_empty());
+        assert_eq!(full.models["pool-busy-a"].evictions, 0);
+
+        // the pool is full by memory
+        let config = PoolConfig {
+            memory_budget: Some(10),
+            ..Default::default()
+        };
+        let mut full = pool(
+            config,
+            &[("pool-busy-a", 6, None), ("pool-busy-b", 6, None)],
+        );
+        let res = full.make_room("pool-busy-b", resident(&["pool-busy-a"]), &mut events);
+        assert!(matches!(res, Err(LlamaCoreError::QueueFull(_))));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_evict_idle_models_without_resident_models() {
+        let last_used = SystemTime::now() - Duration::from_secs(60);
+        let mut pool = pool(
+            PoolConfig::default(),
+            &[("pool-expired", 1, Some(last_used))],
+        );
+
+        // no idle timeout
+        let mut events = vec![];
+        assert!(pool.evict_idle_models(&mut events).unwrap().is_empty());
+
+        // the expired model is not loaded
+        pool.config.idle_timeout = Some(Duration::from_secs(1));
+        assert!(pool.evict_idle_models(&mut events).unwrap().is_empty());
+        assert!(events.is_empty());
+        assert_eq!(pool.models["pool-expired"].evictions, 0);
+    }
+
+    #[test]
+    fn test_ensure_chat_model_outside_the_pool() {
+        let mut pool = pool(PoolConfig::default(), &[("pool-registered", 1, None)]);
+
+        // the models not registered in the pool are left to the scheduler
+        let mut events = vec![];
+        pool.ensure_chat_model(Some(&"pool-unregistered".to_string()), &mut events)
+            .unwrap();
+        assert!(events.is_empty());
+        assert_eq!(pool.models["pool-registered"].loads, 0);
+    }
+
+    #[test]
+    fn test_load_unregistered_model() {
+        let mut pool = pool(PoolConfig::default(), &[]);
+
+        let mut events = vec![];
+        let res = pool.load("pool-unregistered", &mut events);
+        assert!(matches!(res, Err(LlamaCoreError::ModelNotFound(_))));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_pool_event_serialization() {
+        let event = PoolEvent::Evicted {
+            model_name: "pool-event".to_string(),
+            reason: EvictionReason::IdleTimeout,
+            idle: Duration::from_secs(3),
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "evicted");
+        assert_eq!(value["model_name"], "pool-event");
+        assert_eq!(value["reason"], "idle_timeout");
+    }
+}
@@ -0,0 +1,90 @@
+//! Define the tracking of the prompts evaluated by the chat models.
+//!
+//! If the prompt cache of a chat model is enabled by [`GgmlMetadataBuilder::enable_prompt_cache`](crate::metadata::ggml::GgmlMetadataBuilder::enable_prompt_cache), the backend keeps the KV cache of the last prompt evaluated by the execution context of each slot, and a new prompt starting with it only evaluates the tokens after it. The prompts of a conversation usually start with the prompt of the previous turn, so only the new messages are evaluated. The graph records the last prompt fed to each of its slots, so that the number of reused tokens can be reported in the usage of the response.
+
+use crate::{
+    error::LlamaCoreError,
+    metadata::ggml::GgmlMetadata,
+    utils::{get_token_info_by_graph, set_tensor_data_u8},
+    Graph,
+};
+
+/// The last prompt evaluated by the execution context of a slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CachedPrompt {
+    prompt: String,
+    prompt_tokens: u64,
+}
+
+/// Feeds the prompt to the current slot of the graph, and returns the number of prompt tokens reused from the KV cache of the slot. `None` is returned if the prompt cache of the model is disabled.
+///
+/// All the prompts evaluated on a slot must be fed by this function; otherwise, the reported number of reused tokens is wrong.
+pub(crate) fn feed_prompt(
+    graph: &mut Graph<GgmlMetadata>,
+    prompt: &str,
+) -> Result<Option<u64>, LlamaCoreError> {
+    set_tensor_data_u8(graph, 0, prompt.as_bytes())?;
+
+    if !graph.metadata.cache_prompt {
+        return Ok(None);
+    }
+
+    let prompt_tokens = get_token_info_by_graph(graph)?.prompt_tokens;
+    let cached_tokens = cached_tokens(graph.cached_prompt().as_ref(), prompt, prompt_tokens);
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Reuse {} of {} prompt tokens of the model named {}", cached_tokens, prompt_tokens, graph.name());
+
+    *graph.cached_prompt() = Some(CachedPrompt {
+        prompt: prompt.to_owned(),
+        prompt_tokens,
+    });
+
+    Ok(Some(cached_tokens))
+}
+
+/// Returns the number of tokens of the new prompt that are in the KV cache of the previous prompt.
+///
+/// Only a prompt starting with the whole previous prompt reuses its tokens. The prompts built by the chat templates end with the special tokens opening the turn of the assistant, so the tokens of the previous prompt are a prefix of the tokens of the new one. The last token of the new prompt is always evaluated to predict the next token.
+fn cached_tokens(previous: Option<&CachedPrompt>, prompt: &str, prompt_tokens: u64) -> u64 {
+    match previous {
+        Some(previous) if prompt.starts_with(&previous.prompt) => {
+            previous.prompt_tokens.min(prompt_tokens.saturating_sub(1))
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached(prompt: &str, prompt_tokens: u64) -> CachedPrompt {
+        CachedPrompt {
+            prompt: prompt.to_owned(),
+            prompt_tokens,
+        }
+    }
+
+    #[test]
+    fn test_cached_tokens() {
+        let previous = cached(
+            "<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n",
+            10,
+        );
+
+        // the next turn of the conversation reuses the whole previous prompt
+        let prompt = "<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\nHello!<|im_end|>\n<|im_start|>user\nHow are you?<|im_end|>\n<|im_start|>assistant\n";
+        assert_eq!(cached_tokens(Some(&previous), prompt, 24), 10);
+
+        // the same prompt, e.g. for the next choice, evaluates its last token again
+        assert_eq!(cached_tokens(Some(&previous), &previous.prompt, 10), 9);
+
+        // a different conversation on the same slot reuses nothing
+        let prompt = "<|im_start|>user\nHey<|im_end|>\n<|im_start|>assistant\n";
+        assert_eq!(cached_tokens(Some(&previous), prompt, 10), 0);
+
+        // the first prompt of the slot
+        assert_eq!(cached_tokens(None, prompt, 10), 0);
+    }
+}
@@ -0,0 +1,566 @@
+//! Define the per-model scheduler for inference requests.
+//!
+//! Each chat model has a number of slots, i.e. the number of sequences it can generate at the same time, which is set by [`set_parallel_slots`]. Every slot has its own execution context in the backend, so the requests holding different slots do not share the metadata, the prompt or the KV cache. A request must hold a slot of the model from the moment it updates the model metadata and feeds the prompt until its output is complete. Requests that cannot get a slot wait in a first-in-first-out queue, so that a slow client only delays the requests queued behind it. If the length of the queue is limited by [`set_max_queued_requests`], the requests arriving at a full queue are rejected with [`LlamaCoreError::QueueFull`].
+
+use crate::{
+    cancellation::CancellationToken, error::LlamaCoreError, pool, utils::resolve_model_name,
+    CHAT_GRAPHS,
+};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+// key: model_name, value: the slots and the waiting queue of the model
+static SCHEDULERS: OnceCell<Mutex<HashMap<String, ModelQueue>>> = OnceCell::new();
+
+/// The number of slots of a model unless set by [`set_parallel_slots`].
+pub const DEFAULT_PARALLEL_SLOTS: usize = 1;
+
+/// Represents the status of the queue of a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct QueueStatus {
+    /// The number of sequences the model can generate at the same time.
+    pub slots: usize,
+    /// The number of requests holding a slot.
+    pub active: usize,
+    /// The number of requests waiting for a slot.
+    pub queued: usize,
+    /// The maximum number of requests waiting for a slot. `None` means unlimited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queued: Option<usize>,
+}
+
+#[derive(Debug)]
+struct ModelQueue {
+    slots: usize,
+    // the indexes of the slots held by requests
+    busy_slots: BTreeSet<usize>,
+    max_queued: Option<usize>,
+    next_ticket: u64,
+    waiting: VecDeque<(u64, Option<Waker>)>,
+}
+impl Default for ModelQueue {
+    fn default() -> Self {
+        Self {
+            slots: DEFAULT_PARALLEL_SLOTS,
+            busy_slots: BTreeSet::new(),
+            max_queued: None,
+            next_ticket: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+}
+impl ModelQueue {
+    fn has_free_slot(&self) -> bool {
+        self.busy_slots.len() < self.slots
+    }
+
+    /// Takes the free slot with the lowest index. The caller must check [`Self::has_free_slot`] first.
+    fn take_slot(&mut self) -> usize {
+        let slot = (0..)
+            .find(|slot| !self.busy_slots.contains(slot))
+            .unwrap_or_default();
+        self.busy_slots.insert(slot);
+        slot
+    }
+
+    /// Wakes up the request at the head of the queue if a slot is free.
+    fn wake_next(&mut self) {
+        if self.has_free_slot() {
+            if let Some((_, waker)) = self.waiting.front_mut() {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> QueueStatus {
+        QueueStatus {
+            slots: self.slots,
+            active: self.busy_slots.len(),
+            queued: self.waiting.len(),
+            max_queued: self.max_queued,
+        }
+    }
+}
+
+fn lock_schedulers() -> Result<MutexGuard<'static, HashMap<String, ModelQueue>>, LlamaCoreError> {
+    SCHEDULERS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of `SCHEDULERS`. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+/// Sets the number of sequences the chat model can generate at the same time. Each slot gets its own execution context in the backend when it is first used, so the memory of the context, e.g. the KV cache, is allocated once per slot.
+///
+/// # Arguments
+///
+/// * `model_name` - The name of the chat model.
+///
+/// * `slots` - The number of slots. If `slots` is less than 1, then sets to `1`.
+pub fn set_parallel_slots(
+    model_name: impl Into<String>,
+    slots: usize,
+) -> Result<(), LlamaCoreError> {
+    let model_name = model_name.into();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set the number of parallel slots of the model named {model_name} to {slots}");
+
+    let mut schedulers = lock_schedulers()?;
+    let queue = schedulers.entry(model_name).or_default();
+    queue.slots = slots.max(1);
+
+    // more requests may run now
+    queue.wake_next();
+
+    Ok(())
+}
+
+/// Sets the maximum number of requests waiting for a slot of the model. The requests arriving at a full queue fail with [`LlamaCoreError::QueueFull`].
+///
+/// # Arguments
+///
+/// * `model_name` - The name of the chat model.
+///
+/// * `max_queued` - The maximum length of the queue. `None` means unlimited, which is the default.
+pub fn set_max_queued_requests(
+    model_name: impl Into<String>,
+    max_queued: Option<usize>,
+) -> Result<(), LlamaCoreError> {
+    let model_name = model_name.into();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set the maximum number of queued requests of the model named {model_name} to {max_queued:?}");
+
+    let mut schedulers = lock_schedulers()?;
+    schedulers.entry(model_name).or_default().max_queued = max_queued;
+
+    Ok(())
+}
+
+/// Removes the queue of the given chat model, e.g. after the model is unloaded.
+pub(crate) fn remove_model(model_name: &str) -> Result<(), LlamaCoreError> {
+    let mut schedulers = lock_schedulers()?;
+    schedulers.remove(model_name);
+
+    Ok(())
+}
+
+/// Returns the status of the queue of the given chat model.
+pub fn queue_status(model_name: impl AsRef<str>) -> Result<QueueStatus, LlamaCoreError> {
+    let schedulers = lock_schedulers()?;
+
+    Ok(schedulers
+        .get(model_name.as_ref())
+        .map(ModelQueue::status)
+        .unwrap_or(QueueStatus {
+            slots: DEFAULT_PARALLEL_SLOTS,
+            active: 0,
+            queued: 0,
+            max_queued: None,
+        }))
+}
+
+/// Returns the number of requests waiting for a slot of the given chat model.
+pub fn queue_depth(model_name: impl AsRef<str>) -> Result<usize, LlamaCoreError> {
+    queue_status(model_name).map(|status| status.queued)
+}
+
+/// Returns the queue status of all the chat models that have received requests.
+pub fn queue_statuses() -> Result<HashMap<String, QueueStatus>, LlamaCoreError> {
+    let schedulers = lock_schedulers()?;
+
+    Ok(schedulers
+        .iter()
+        .map(|(name, queue)| (name.clone(), queue.status()))
+        .collect())
+}
+
+/// Waits for a slot of the chat model with the given name. The model is looked up by [`resolve_model_name`].
+///
+/// Once the token is cancelled, the request leaves the queue and fails with [`LlamaCoreError::Cancelled`].
+pub(crate) async fn acquire_chat_slot(
+    model_name: Option<&String>,
+    cancel: &CancellationToken,
+) -> Result<SlotPermit, LlamaCoreError> {
+    // load the model if it is in the model pool. The first poll below takes a slot or a place in the queue of the model before other tasks can run, so the model is not evicted in between.
+    pool::ensure_chat_model(model_name)?;
+
+    let model_name = chat_graph_name(model_name)?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Wait for a slot of the model named {model_name}");
+
+    let permit = AcquireSlot {
+        model_name,
+        ticket: None,
+        cancel: cancel.clone(),
+    }
+    .await?;
+
+    pool::touch(&permit.model_name);
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Acquired the slot {} of the model named {}", permit.slot, &permit.model_name);
+
+    Ok(permit)
+}
+
+/// Returns the name of the chat graph that serves the requests for the given model name.
+fn chat_graph_name(model_name: Option<&String>) -> Result<String, LlamaCoreError> {
+    let chat_graphs = match CHAT_GRAPHS.get() {
+        Some(chat_graphs) => chat_graphs,
+        None => {
+            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg.into()));
+        }
+    };
+
+    let chat_graphs = chat_graphs.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    resolve_model_name(&chat_graphs, model_name, "chat")
+}
+
+/// Takes a slot of the chat model with the given name if one is free, without waiting in the queue.
+#[cfg(test)]
+pub(crate) fn try_acquire_slot(model_name: &str) -> Option<SlotPermit> {
+    let mut future = AcquireSlot {
+        model_name: model_name.to_string(),
+        ticket: None,
+        cancel: CancellationToken::new(),
+    };
+
+    match Pin::new(&mut future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(Ok(permit)) => Some(permit),
+        _ => None,
+    }
+}
+
+/// A slot of a chat model. The slot is released when the permit is dropped.
+#[derive(Debug)]
+pub(crate) struct SlotPermit {
+    model_name: String,
+    slot: usize,
+}
+impl SlotPermit {
+    /// Returns the name of the chat model of the slot.
+    pub(crate) fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    /// Returns the index of the slot, which is also the index of its execution context in the chat graph.
+    pub(crate) fn slot(&self) -> usize {
+        self.slot
+    }
+}
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        if let Ok(mut schedulers) = lock_schedulers() {
+            if let Some(queue) = schedulers.get_mut(&self.model_name) {
+                queue.busy_slots.remove(&self.slot);
+                queue.wake_next();
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "Released the slot {} of the model named {}. {} request(s) waiting", self.slot, &self.model_name, queue.waiting.len());
+            }
+        }
+
+        // the idle time of the model starts when the request is done
+        pool::touch(&self.model_name);
+    }
+}
+
+/// The future of waiting for a slot. A request takes a ticket when it has to wait and gets a slot once its ticket is at the head of the queue.
+struct AcquireSlot {
+    model_name: String,
+    ticket: Option<u64>,
+    cancel: CancellationToken,
+}
+impl Future for AcquireSlot {
+    type Output = Result<SlotPermit, LlamaCoreError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        // the ticket is removed from the queue when the future is dropped
+        if this.cancel.is_cancelled() {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The request waiting for a slot of the model named {} is cancelled", &this.model_name);
+
+            return Poll::Ready(Err(LlamaCoreError::Cancelled));
+        }
+
+        let mut schedulers = match lock_schedulers() {
+            Ok(schedulers) => schedulers,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let queue = schedulers.entry(this.model_name.clone()).or_default();
+
+        let granted = match this.ticket {
+            // the first request arriving at an idle model does not queue
+            None => queue.has_free_slot() && queue.waiting.is_empty(),
+            Some(ticket) => {
+                queue.has_free_slot()
+                    && matches!(queue.waiting.front(), Some((front, _)) if *front == ticket)
+            }
+        };
+
+        if granted {
+            if this.ticket.take().is_some() {
+                queue.waiting.pop_front();
+            }
+            let slot = queue.take_slot();
+
+            // the next request may also get a slot if the model has more
+            queue.wake_next();
+
+            return Poll::Ready(Ok(SlotPermit {
+                model_name: this.model_name.clone(),
+                slot,
+            }));
+        }
+
+        match this.ticket {
+            Some(ticket) => {
+                if let Some((_, waker)) = queue.waiting.iter_mut().find(|(t, _)| *t == ticket) {
+                    *waker = Some(cx.waker().clone());
+                }
+            }
+            None => {
+                if let Some(max_queued) = queue.max_queued {
+                    if queue.waiting.len() >= max_queued {
+                        let err_msg = format!(
+                            "The model named {} is busy. {} request(s) are waiting for it. Please retry later.",
+                            &this.model_name,
+                            queue.waiting.len()
+                        );
+
+                        #[cfg(feature = "logging")]
+                        warn!(target: "stdout", "{}", &err_msg);
+
+                        return Poll::Ready(Err(LlamaCoreError::QueueFull(err_msg)));
+                    }
+                }
+
+                let ticket = queue.next_ticket;
+                queue.next_ticket += 1;
+                queue.waiting.push_back((ticket, Some(cx.waker().clone())));
+                this.ticket = Some(ticket);
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "No free slot of the model named {}. Queued at position {}", &this.model_name, queue.waiting.len());
+            }
+        }
+
+        // poll again if the token was cancelled before the waker was registered
+        this.cancel.wake_on_cancel(cx.waker());
+        if this.cancel.is_cancelled() {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+}
+impl Drop for AcquireSlot {
+    fn drop(&mut self) {
+        // the request gave up waiting, e.g. the client disconnected
+        if let Some(ticket) = self.ticket {
+            if let Ok(mut schedulers) = lock_schedulers() {
+                if let Some(queue) = schedulers.get_mut(&self.model_name) {
+                    queue.waiting.retain(|(t, _)| *t != ticket);
+                    queue.wake_next();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::Wake,
+    };
+
+    /// Records whether the task was woken up.
+    #[derive(Default)]
+    struct Woken(AtomicBool);
+    impl Wake for Woken {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn acquire(model_name: &str, cancel: &CancellationToken) -> AcquireSlot {
+        AcquireSlot {
+            model_name: model_name.to_string(),
+            ticket: None,
+            cancel: cancel.clone(),
+        }
+    }
+
+    fn poll(
+        future: &mut AcquireSlot,
+        woken: &Arc<Woken>,
+    ) -> Poll<Result<SlotPermit, LlamaCoreError>> {
+        let waker = Waker::from(woken.clone());
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    fn status(active: usize, queued: usize) -> QueueStatus {
+        QueueStatus {
+            slots: DEFAULT_PARALLEL_SLOTS,
+            active,
+            queued,
+            max_queued: None,
+        }
+    }
+
+    #[test]
+    fn test_requests_get_the_slot_in_order() {
+        let model_name = "test-fifo";
+        let cancel = CancellationToken::new();
+        let woken = Arc::new(Woken::default());
+
+        let permit = match poll(&mut acquire(model_name, &cancel), &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the idle model has a free slot"),
+        };
+
+        let mut second = acquire(model_name, &cancel);
+        let mut third = acquire(model_name, &cancel);
+        assert!(poll(&mut second, &woken).is_pending());
+        assert!(poll(&mut third, &woken).is_pending());
+        assert_eq!(queue_status(model_name).unwrap(), status(1, 2));
+
+        // the head of the queue is woken up once the slot is released
+        drop(permit);
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert!(poll(&mut third, &woken).is_pending());
+        let permit = match poll(&mut second, &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the head of the queue gets the slot"),
+        };
+        assert_eq!(queue_status(model_name).unwrap(), status(1, 1));
+
+        drop(permit);
+        assert!(matches!(poll(&mut third, &woken), Poll::Ready(Ok(_))));
+        assert_eq!(queue_status(model_name).unwrap(), status(0, 0));
+    }
+
+    #[test]
+    fn test_parallel_slots() {
+        let model_name = "test-parallel";
+        let cancel = CancellationToken::new();
+        let woken = Arc::new(Woken::default());
+        set_parallel_slots(model_name, 2).unwrap();
+
+        let first = match poll(&mut acquire(model_name, &cancel), &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the idle model has a free slot"),
+        };
+        let second = match poll(&mut acquire(model_name, &cancel), &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the model has a second slot"),
+        };
+        assert_eq!((first.slot(), second.slot()), (0, 1));
+
+        let mut third = acquire(model_name, &cancel);
+        assert!(poll(&mut third, &woken).is_pending());
+        assert_eq!(queue_status(model_name).unwrap().active, 2);
+        assert_eq!(queue_depth(model_name).unwrap(), 1);
+
+        // the released slot is reused, so its execution context is not shared with the running request
+        drop(first);
+        let third = match poll(&mut third, &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the head of the queue gets the released slot"),
+        };
+        assert_eq!(third.slot(), 0);
+    }
+
+    #[test]
+    fn test_cancelled_request_leaves_the_queue() {
+        let model_name = "test-cancel";
+        let woken = Arc::new(Woken::default());
+
+        let permit = match poll(&mut acquire(model_name, &CancellationToken::new()), &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the idle model has a free slot"),
+        };
+
+        let cancel = CancellationToken::new();
+        let mut waiting = acquire(model_name, &cancel);
+        assert!(poll(&mut waiting, &woken).is_pending());
+        assert_eq!(queue_status(model_name).unwrap(), status(1, 1));
+
+        // the cancellation wakes up the request, which gives up its place
+        cancel.cancel();
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert!(matches!(
+            poll(&mut waiting, &woken),
+            Poll::Ready(Err(LlamaCoreError::Cancelled))
+        ));
+        drop(waiting);
+        assert_eq!(queue_status(model_name).unwrap(), status(1, 0));
+
+        // a cancelled request does not queue at all
+        drop(permit);
+        assert!(matches!(
+            poll(&mut acquire(model_name, &cancel), &woken),
+            Poll::Ready(Err(LlamaCoreError::Cancelled))
+        ));
+        assert_eq!(queue_status(model_name).unwrap(), status(0, 0));
+    }
+
+    #[test]
+    fn test_full_queue_rejects_requests() {
+        let model_name = "test-full";
+        let cancel = CancellationToken::new();
+        let woken = Arc::new(Woken::default());
+        set_max_queued_requests(model_name, Some(1)).unwrap();
+
+        let _permit = match poll(&mut acquire(model_name, &cancel), &woken) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("the idle model has a free slot"),
+        };
+        let mut waiting = acquire(model_name, &cancel);
+        assert!(poll(&mut waiting, &woken).is_pending());
+
+        assert!(matches!(
+            poll(&mut acquire(model_name, &cancel), &woken),
+            Poll::Ready(Err(LlamaCoreError::QueueFull(_)))
+        ));
+        assert_eq!(queue_depth(model_name).unwrap(), 1);
+    }
+}
@@ -0,0 +1,1282 @@
+//! Define APIs for storing the responses and conversations of the Responses API.
+//!
+//! The non-stream responses are saved unless the `store` field of the request is `false`, so that they can be retrieved, deleted, and continued by the `previous_response_id` field of the next request. The input and output items of a response created in a conversation are appended to the conversation. By default, the objects are saved as JSON files in the hidden `.store` directory under [`ARCHIVES_DIR`]; use [`set_response_store`] to save them somewhere else.
+
+use crate::{
+    chat::responses::input_message_to_chat_message, error::LlamaCoreError, utils::gen_item_id,
+    ARCHIVES_DIR,
+};
+use endpoints::{
+    chat::{ChatCompletionRequestMessage, Function, ToolCall},
+    responses::{
+        conversation::{
+            Conversation, ConversationItem, ConversationItemList, ConversationMessageContent,
+            DeleteConversationStatus, RequestOfCreateConversation,
+            RequestOfCreateConversationItems, RequestOfUpdateConversation,
+        },
+        items::{
+            ListItemsQuery, ListOrder, ResponseItem, ResponseItemInputMessageContent,
+            ResponseItemList, ResponseItemOutputMessageContent, ResponseOutputItem,
+            ResponseOutputItemOutputMessageContent,
+        },
+        response_object::{
+            Conversation as ResponseConversation, DeleteResponseStatus, Input, InputItem,
+            InputMessageContent, RequestOfModelResponse, ResponseObject,
+        },
+    },
+};
+use once_cell::sync::OnceCell;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+static RESPONSE_STORE: OnceCell<Mutex<Arc<dyn ResponseStore>>> = OnceCell::new();
+// serializes the updates of the conversations, which read and write back the whole conversation
+static CONVERSATION_LOCK: Mutex<()> = Mutex::new(());
+
+const DEFAULT_LIST_LIMIT: usize = 20;
+const MAX_LIST_LIMIT: usize = 100;
+
+/// Storage of the responses and conversations of the Responses API.
+pub trait ResponseStore: Send + Sync {
+    /// Saves the response, replacing the saved one with the same ID.
+    fn save_response(&self, response: &StoredResponse) -> Result<(), LlamaCoreError>;
+    /// Loads the response with the given ID. Returns `None` if it does not exist.
+    fn load_response(&self, id: &str) -> Result<Option<StoredResponse>, LlamaCoreError>;
+    /// Deletes the response with the given ID. Returns `false` if it does not exist.
+    fn delete_response(&self, id: &str) -> Result<bool, LlamaCoreError>;
+    /// Saves the conversation, replacing the saved one with the same ID.
+    fn save_conversation(&self, conversation: &StoredConversation) -> Result<(), LlamaCoreError>;
+    /// Loads the conversation with the given ID. Returns `None` if it does not exist.
+    fn load_conversation(&self, id: &str) -> Result<Option<StoredConversation>, LlamaCoreError>;
+    /// Deletes the conversation with the given ID. Returns `false` if it does not exist.
+    fn delete_conversation(&self, id: &str) -> Result<bool, LlamaCoreError>;
+}
+
+/// A response saved in the store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredResponse {
+    /// The response object.
+    pub response: ResponseObject,
+    /// The input items of the request of the response.
+    pub input_items: Vec<ResponseItem>,
+    /// The chat history ending with the output of the response, which the input of the next response continues. The instructions are not included, since they are not carried over to the next response.
+    pub messages: Vec<ChatCompletionRequestMessage>,
+}
+
+/// A conversation saved in the store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredConversation {
+    /// The conversation object.
+    pub conversation: Conversation,
+    /// The items of the conversation in the order they were added.
+    pub items: Vec<ConversationItem>,
+}
+
+/// The default store, which saves each response and conversation as a JSON file.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+impl FileStore {
+    /// Creates a store saving the responses and conversations in the `responses` and `conversations` subdirectories of the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the path of the file of the object, or `None` if the ID is not a valid file name.
+    fn path(&self, kind: &str, id: &str) -> Option<PathBuf> {
+        // the IDs are generated by the server, and the others may point outside of the directory
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+        valid.then(|| self.dir.join(kind).join(format!("{id}.json")))
+    }
+
+    fn save<T: Serialize>(&self, kind: &str, id: &str, object: &T) -> Result<(), LlamaCoreError> {
+        let path = match self.path(kind, id) {
+            Some(path) => path,
+            None => {
+                let err_msg = format!("Failed to save the object with id {id}. Invalid id.");
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::Operation(err_msg));
+            }
+        };
+
+        let bytes = serde_json::to_vec(object).map_err(|e| {
+            let err_msg = format!("Failed to serialize the object with id {id}. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+        let dir = self.dir.join(kind);
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&path, bytes))
+            .map_err(|e| {
+                let err_msg = format!("Failed to save the file {}. {e}", path.display());
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::Operation(err_msg)
+            })
+    }
+
+    fn load<T: DeserializeOwned>(&self, kind: &str, id: &str) -> Result<Option<T>, LlamaCoreError> {
+        let path = match self.path(kind, id) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                let err_msg = format!("Failed to read the file {}. {e}", path.display());
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::Operation(err_msg));
+            }
+        };
+
+        serde_json::from_slice(&bytes).map(Some).map_err(|e| {
+            let err_msg = format!("Failed to deserialize the file {}. {e}", path.display());
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+    }
+
+    fn delete(&self, kind: &str, id: &str) -> Result<bool, LlamaCoreError> {
+        let path = match self.path(kind, id) {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+
+        match fs::remove_file(&path) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => {
+                let err_msg = format!("Failed to delete the file {}. {e}", path.display());
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                Err(LlamaCoreError::Operation(err_msg))
+            }
+        }
+    }
+}
+impl Default for FileStore {
+    /// Creates a store saving the objects in the hidden `.store` directory under [`ARCHIVES_DIR`], which is not listed as files.
+    fn default() -> Self {
+        Self::new(Path::new(ARCHIVES_DIR).join(".store"))
+    }
+}
+impl ResponseStore for FileStore {
+    fn save_response(&self, response: &StoredResponse) -> Result<(), LlamaCoreError> {
+        self.save("responses", &response.response.id, response)
+    }
+
+    fn load_response(&self, id: &str) -> Result<Option<StoredResponse>, LlamaCoreError> {
+        self.load("responses", id)
+    }
+
+    fn delete_response(&self, id: &str) -> Result<bool, LlamaCoreError> {
+        self.delete("responses", id)
+    }
+
+    fn save_conversation(&self, conversation: &StoredConversation) -> Result<(), LlamaCoreError> {
+        self.save("conversations", &conversation.conversation.id, conversation)
+    }
+
+    fn load_conversation(&self, id: &str) -> Result<Option<StoredConversation>, LlamaCoreError> {
+        self.load("conversations", id)
+    }
+
+    fn delete_conversation(&self, id: &str) -> Result<bool, LlamaCoreError> {
+        self.delete("conversations", id)
+    }
+}
+
+/// Replaces the store of the responses and conversations. The objects saved in the previous store are not moved.
+pub fn set_response_store(store: impl ResponseStore + 'static) -> Result<(), LlamaCoreError> {
+    *lock_response_store()? = Arc::new(store);
+
+    Ok(())
+}
+
+/// Retrieves the response with the given ID.
+pub fn retrieve_response(id: &str) -> Result<ResponseObject, LlamaCoreError> {
+    load_response(id).map(|stored| stored.response)
+}
+
+/// Deletes the response with the given ID.
+pub fn delete_response(id: &str) -> Result<DeleteResponseStatus, LlamaCoreError> {
+    if !response_store()?.delete_response(id)? {
+        return Err(not_found("Response", id));
+    }
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Deleted the response with id {id}");
+
+    Ok(DeleteResponseStatus {
+        id: id.to_owned(),
+        object: "response".to_string(),
+        deleted: true,
+    })
+}
+
+/// Lists the input items of the response with the given ID.
+pub fn list_input_items(
+    id: &str,
+    query: &ListItemsQuery,
+) -> Result<ResponseItemList, LlamaCoreError> {
+    let stored = load_response(id)?;
+    let (data, has_more) = paginate(stored.input_items, query, response_item_id)?;
+
+    Ok(ResponseItemList {
+        first_id: data
+            .first()
+            .map(response_item_id)
+            .unwrap_or_default()
+            .to_owned(),
+        last_id: data
+            .last()
+            .map(response_item_id)
+            .unwrap_or_default()
+            .to_owned(),
+        data,
+        has_more,
+        object: "list".to_string(),
+    })
+}
+
+/// Creates a conversation with the initial items of the request.
+pub fn create_conversation(
+    request: RequestOfCreateConversation,
+) -> Result<Conversation, LlamaCoreError> {
+    let items = match &request.items {
+        Some(items) => to_conversation_items(&to_response_items(items)),
+        None => Vec::new(),
+    };
+
+    let conversation = Conversation {
+        created_at: now()?,
+        id: gen_item_id("conv"),
+        metadata: request.metadata,
+        object: "conversation".to_string(),
+    };
+
+    response_store()?.save_conversation(&StoredConversation {
+        conversation: conversation.clone(),
+        items,
+    })?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Created the conversation with id {}", &conversation.id);
+
+    Ok(conversation)
+}
+
+/// Retrieves the conversation with the given ID.
+pub fn retrieve_conversation(id: &str) -> Result<Conversation, LlamaCoreError> {
+    load_conversation(id).map(|stored| stored.conversation)
+}
+
+/// Replaces the metadata of the conversation with the given ID.
+pub fn update_conversation(
+    id: &str,
+    request: RequestOfUpdateConversation,
+) -> Result<Conversation, LlamaCoreError> {
+    let _lock = lock_conversations()?;
+
+    let mut stored = load_conversation(id)?;
+    stored.conversation.metadata = Some(request.metadata);
+    response_store()?.save_conversation(&stored)?;
+
+    Ok(stored.conversation)
+}
+
+/// Deletes the conversation with the given ID. The responses created in the conversation are not deleted.
+pub fn delete_conversation(id: &str) -> Result<DeleteConversationStatus, LlamaCoreError> {
+    let _lock = lock_conversations()?;
+
+    if !response_store()?.delete_conversation(id)? {
+        return Err(not_found("Conversation", id));
+    }
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Deleted the conversation with id {id}");
+
+    Ok(DeleteConversationStatus {
+        id: id.to_owned(),
+        object: "conversation.deleted".to_string(),
+        deleted: true,
+    })
+}
+
+/// Lists the items of the conversation with the given ID.
+pub fn list_conversation_items(
+    id: &str,
+    query: &ListItemsQuery,
+) -> Result<ConversationItemList, LlamaCoreError> {
+    let stored = load_conversation(id)?;
+    let (data, has_more) = paginate(stored.items, query, conversation_item_id)?;
+
+    Ok(to_conversation_item_list(data, has_more))
+}
+
+/// Appends the items of the request to the conversation with the given ID, and returns the added items.
+pub fn create_conversation_items(
+    id: &str,
+    request: RequestOfCreateConversationItems,
+) -> Result<ConversationItemList, LlamaCoreError> {
+    let _lock = lock_conversations()?;
+
+    let mut stored = load_conversation(id)?;
+    let items = to_conversation_items(&to_response_items(&request.items));
+    stored.items.extend(items.iter().cloned());
+    response_store()?.save_conversation(&stored)?;
+
+    Ok(to_conversation_item_list(items, false))
+}
+
+/// Retrieves the item with the given ID of the conversation.
+pub fn retrieve_conversation_item(
+    id: &str,
+    item_id: &str,
+) -> Result<ConversationItem, LlamaCoreError> {
+    load_conversation(id)?
+        .items
+        .into_iter()
+        .find(|item| conversation_item_id(item) == item_id)
+        .ok_or_else(|| not_found("Item", item_id))
+}
+
+/// Deletes the item with the given ID from the conversation, and returns the conversation.
+pub fn delete_conversation_item(id: &str, item_id: &str) -> Result<Conversation, LlamaCoreError> {
+    let _lock = lock_conversations()?;
+
+    let mut stored = load_conversation(id)?;
+    let len = stored.items.len();
+    stored
+        .items
+        .retain(|item| conversation_item_id(item) != item_id);
+    if stored.items.len() == len {
+        return Err(not_found("Item", item_id));
+    }
+    response_store()?.save_conversation(&stored)?;
+
+    Ok(stored.conversation)
+}
+
+/// Returns the chat history continued by the input of the request, which is the history of the previous response or the messages of the conversation.
+pub(crate) fn load_history(
+    chat_request: &RequestOfModelResponse,
+) -> Result<Vec<ChatCompletionRequestMessage>, LlamaCoreError> {
+    match (
+        &chat_request.previous_response_id,
+        &chat_request.conversation,
+    ) {
+        (Some(_), Some(_)) => {
+            let err_msg =
+                "The `previous_response_id` and `conversation` fields cannot be used together.";
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{err_msg}");
+
+            Err(LlamaCoreError::InvalidRequest(err_msg.to_owned()))
+        }
+        (Some(id), None) => {
+            let stored = load_response(id)?;
+
+            // the history of a background response is saved when it is completed
+            if stored.response.status != "completed" {
+                let err_msg = format!(
+                    "The previous response with id '{id}' is {}. Only completed responses can be continued.",
+                    stored.response.status
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::InvalidRequest(err_msg));
+            }
+
+            Ok(stored.messages)
+        }
+        (None, Some(conversation)) => {
+            let stored = load_conversation(conversation_id(conversation))?;
+
+            let mut messages = Vec::new();
+            for item in stored.items.iter() {
+                if let ConversationItem::Message { content, role, .. } = item {
+                    let text = match content {
+                        ConversationMessageContent::InputText { text, .. }
+                        | ConversationMessageContent::OutputText { text, .. }
+                        | ConversationMessageContent::TextContent { text, .. } => text,
+                        _ => {
+                            #[cfg(feature = "logging")]
+                            warn!(target: "stdout", "Skipping the unsupported content of the conversation item {}", conversation_item_id(item));
+
+                            continue;
+                        }
+                    };
+
+                    let content = InputMessageContent::Text(text.clone());
+                    messages.push(input_message_to_chat_message(&content, role)?);
+                }
+            }
+
+            Ok(messages)
+        }
+        (None, None) => Ok(Vec::new()),
+    }
+}
+
+/// Saves the response of the request unless `store` is `false`, and appends the input and output items to the conversation of the request.
+///
+/// `input_messages` are the chat messages converted from the input of the request.
+pub(crate) fn save_response(
+    chat_request: &RequestOfModelResponse,
+    input_messages: Vec<ChatCompletionRequestMessage>,
+    response: &ResponseObject,
+) -> Result<(), LlamaCoreError> {
+    // the input items of a background response are saved when it is queued
+    let input_items = match response_store()?.load_response(&response.id)? {
+        Some(stored) => stored.input_items,
+        None => request_input_items(chat_request),
+    };
+
+    // the history is loaded before the conversation is updated
+    let mut messages = match chat_request.store {
+        Some(false) => Vec::new(),
+        _ => load_history(chat_request)?,
+    };
+
+    if let Some(conversation) = &chat_request.conversation {
+        let _lock = lock_conversations()?;
+
+        let mut stored = load_conversation(conversation_id(conversation))?;
+        stored.items.extend(to_conversation_items(&input_items));
+        stored
+            .items
+            .extend(output_to_conversation_items(&response.output));
+        response_store()?.save_conversation(&stored)?;
+    }
+
+    if chat_request.store == Some(false) {
+        return Ok(());
+    }
+
+    messages.extend(input_messages);
+    messages.extend(output_to_chat_messages(&response.output));
+
+    response_store()?.save_response(&StoredResponse {
+        response: response.clone(),
+        input_items,
+        messages,
+    })?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Saved the response with id {}", &response.id);
+
+    Ok(())
+}
+
+/// Saves the response of a background request before it is generated, so that it can be polled.
+pub(crate) fn save_queued_response(
+    chat_request: &RequestOfModelResponse,
+    response: &ResponseObject,
+) -> Result<(), LlamaCoreError> {
+    response_store()?.save_response(&StoredResponse {
+        response: response.clone(),
+        input_items: request_input_items(chat_request),
+        messages: Vec::new(),
+    })
+}
+
+/// Updates the saved response with the given ID, and returns the updated response.
+pub(crate) fn update_response(
+    id: &str,
+    update: impl FnOnce(&mut ResponseObject),
+) -> Result<ResponseObject, LlamaCoreError> {
+    let mut stored = load_response(id)?;
+    update(&mut stored.response);
+    response_store()?.save_response(&stored)?;
+
+    Ok(stored.response)
+}
+
+/// Returns the ID of the conversation referenced by a request or a response.
+pub(crate) fn conversation_id(conversation: &ResponseConversation) -> &str {
+    match conversation {
+        ResponseConversation::Id(id) | ResponseConversation::ConversationObject { id } => id,
+    }
+}
+
+fn lock_response_store() -> Result<MutexGuard<'static, Arc<dyn ResponseStore>>, LlamaCoreError> {
+    RESPONSE_STORE
+        .get_or_init(|| Mutex::new(Arc::new(FileStore::default())))
+        .lock()
+        .map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of `RESPONSE_STORE`. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+fn response_store() -> Result<Arc<dyn ResponseStore>, LlamaCoreError> {
+    lock_response_store().map(|store| store.clone())
+}
+
+fn lock_conversations() -> Result<MutexGuard<'static, ()>, LlamaCoreError> {
+    CONVERSATION_LOCK.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `CONVERSATION_LOCK`. {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })
+}
+
+fn load_response(id: &str) -> Result<StoredResponse, LlamaCoreError> {
+    response_store()?
+        .load_response(id)?
+        .ok_or_else(|| not_found("Response", id))
+}
+
+fn load_conversation(id: &str) -> Result<StoredConversation, LlamaCoreError> {
+    response_store()?
+        .load_conversation(id)?
+        .ok_or_else(|| not_found("Conversation", id))
+}
+
+fn not_found(kind: &str, id: &str) -> LlamaCoreError {
+    let err_msg = format!("{kind} with id '{id}' not found.");
+
+    #[cfg(feature = "logging")]
+    error!(target: "stdout", "{}", &err_msg);
+
+    LlamaCoreError::NotFound(err_msg)
+}
+
+fn now() -> Result<u64, LlamaCoreError> {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|created| created.as_secs())
+        .map_err(|e| {
+            let err_msg = format!("Failed to get the current time. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+/// Orders the items as requested, and returns the page of the items after the `after` item, and whether there are more items.
+fn paginate<T>(
+    mut items: Vec<T>,
+    query: &ListItemsQuery,
+    id_of: impl Fn(&T) -> &str,
+) -> Result<(Vec<T>, bool), LlamaCoreError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if !(1..=MAX_LIST_LIMIT).contains(&limit) {
+        let err_msg =
+            format!("Invalid limit: {limit}. The limit must be between 1 and {MAX_LIST_LIMIT}.");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::InvalidRequest(err_msg));
+    }
+
+    if query.order.unwrap_or_default() == ListOrder::Desc {
+        items.reverse();
+    }
+
+    if let Some(after) = &query.after {
+        match items.iter().position(|item| id_of(item) == after) {
+            Some(index) => {
+                items.drain(..=index);
+            }
+            None => return Err(not_found("Item", after)),
+        }
+    }
+
+    let has_more = items.len() > limit;
+    items.truncate(limit);
+
+    Ok((items, has_more))
+}
+
+fn to_conversation_item_list(data: Vec<ConversationItem>, has_more: bool) -> ConversationItemList {
+    ConversationItemList {
+        first_id: data
+            .first()
+            .map(conversation_item_id)
+            .unwrap_or_default()
+            .to_owned(),
+        last_id: data
+            .last()
+            .map(conversation_item_id)
+            .unwrap_or_default()
+            .to_owned(),
+        data,
+        has_more,
+        object: "list".to_string(),
+    }
+}
+
+fn response_item_id(item: &ResponseItem) -> &str {
+    match item {
+        ResponseItem::InputMessage { id, .. }
+        | ResponseItem::OutputMessage { id, .. }
+        | ResponseItem::FunctionCall { id, .. }
+        | ResponseItem::FunctionCallOutput { id, .. }
+        | ResponseItem::ImageGeneration { id, .. }
+        | ResponseItem::McpListTools { id, .. }
+        | ResponseItem::McpToolCall { id, .. } => id,
+    }
+}
+
+fn conversation_item_id(item: &ConversationItem) -> &str {
+    match item {
+        ConversationItem::Message { id, .. }
+        | ConversationItem::FunctionCall { id, .. }
+        | ConversationItem::FunctionCallOutput { id, .. }
+        | ConversationItem::ImageGeneration { id, .. }
+        | ConversationItem::McpListTools { id, .. }
+        | ConversationItem::McpToolCall { id, .. } => id,
+    }
+}
+
+/// Returns the input items of the request.
+fn request_input_items(chat_request: &RequestOfModelResponse) -> Vec<ResponseItem> {
+    match &chat_request.input {
+        Some(Input::Text(text)) => vec![ResponseItem::InputMessage {
+            content: vec![ResponseItemInputMessageContent::Text {
+                text: text.clone(),
+                ty: "input_text".to_string(),
+            }],
+            id: gen_item_id("msg"),
+            role: "user".to_string(),
+            status: Some("completed".to_string()),
+            ty: "message".to_string(),
+        }],
+        Some(Input::InputItemList(items)) => to_response_items(items),
+        None => Vec::new(),
+    }
+}
+
+/// Converts the input items to the items of a response. The messages with the `assistant` role become output messages, and the item references are skipped.
+fn to_response_items(items: &[InputItem]) -> Vec<ResponseItem> {
+    let mut response_items = Vec::new();
+    for item in items {
+        match item {
+            InputItem::InputMessage { content, role, .. } => {
+                let content = match content {
+                    InputMessageContent::Text(text) => {
+                        vec![ResponseItemInputMessageContent::Text {
+                            text: text.clone(),
+                            ty: "input_text".to_string(),
+                        }]
+                    }
+                    InputMessageContent::InputItemContentList(content) => content.clone(),
+                };
+
+                let response_item = match role.as_str() {
+                    "assistant" => ResponseItem::OutputMessage {
+                        content: content
+                            .into_iter()
+                            .filter_map(|part| match part {
+                                ResponseItemInputMessageContent::Text { text, .. } => {
+                                    Some(ResponseItemOutputMessageContent::Text {
+                                        text,
+                                        annotations: vec![],
+                                    })
+                                }
+                                _ => None,
+                            })
+                            .collect(),
+                        id: gen_item_id("msg"),
+                        role: role.clone(),
+                        status: "completed".to_string(),
+                        ty: "message".to_string(),
+                    },
+                    _ => ResponseItem::InputMessage {
+                        content,
+                        id: gen_item_id("msg"),
+                        role: role.clone(),
+                        status: Some("completed".to_string()),
+                        ty: "message".to_string(),
+                    },
+                };
+                response_items.push(response_item);
+            }
+            InputItem::Item(item) => response_items.push(item.clone()),
+            InputItem::ItemReference(_) => {
+                #[cfg(feature = "logging")]
+                warn!(target: "stdout", "Skipping the unsupported item reference");
+            }
+        }
+    }
+
+    response_items
+}
+
+/// Converts the items of a response to the items of a conversation. A conversation message holds a single content, so a message with several contents is split.
+fn to_conversation_items(items: &[ResponseItem]) -> Vec<ConversationItem> {
+    let mut conversation_items = Vec::new();
+    for item in items {
+        match item {
+            ResponseItem::InputMessage {
+                content,
+                id,
+                role,
+                status,
+                ..
+            } => {
+                for (index, part) in content.iter().enumerate() {
+                    let text = match part {
+                        ResponseItemInputMessageContent::Text { text, .. } => text,
+                        _ => {
+                            #[cfg(feature = "logging")]
+                            warn!(target: "stdout", "Skipping the unsupported content of the item {id}");
+
+                            continue;
+                        }
+                    };
+
+                    conversation_items.push(ConversationItem::Message {
+                        content: ConversationMessageContent::InputText {
+                            text: text.clone(),
+                            ty: "input_text".to_string(),
+                        },
+                        id: part_id(id, index),
+                        role: role.clone(),
+                        status: status.clone(),
+                        ty: "message".to_string(),
+                    });
+                }
+            }
+            ResponseItem::OutputMessage {
+                content,
+                id,
+                role,
+                status,
+                ..
+            } => {
+                for (index, part) in content.iter().enumerate() {
+                    let content = match part {
+                        ResponseItemOutputMessageContent::Text { text, annotations } => {
+                            ConversationMessageContent::OutputText {
+                                annotations: annotations.clone(),
+                                text: text.clone(),
+                                ty: "output_text".to_string(),
+                            }
+                        }
+                        ResponseItemOutputMessageContent::Refusal { refusal } => {
+                            ConversationMessageContent::Refusal {
+                                refusal: refusal.clone(),
+                                ty: "refusal".to_string(),
+                            }
+                        }
+                    };
+                    conversation_items.push(ConversationItem::Message {
+                        content,
+                        id: part_id(id, index),
+                        role: role.clone(),
+                        status: Some(status.clone()),
+                        ty: "message".to_string(),
+                    });
+                }
+            }
+            ResponseItem::FunctionCall {
+                arguments,
+                call_id,
+                id,
+                name,
+                ty,
+                status,
+            } => conversation_items.push(ConversationItem::FunctionCall {
+                arguments: arguments.clone(),
+                call_id: call_id.clone(),
+                id: id.clone(),
+                name: name.clone(),
+                ty: ty.clone(),
+                status: Some(status.clone()),
+            }),
+            ResponseItem::FunctionCallOutput {
+                call_id,
+                id,
+                output,
+                ty,
+                status,
+            } => conversation_items.push(ConversationItem::FunctionCallOutput {
+                call_id: call_id.clone(),
+                id: id.clone(),
+                output: output.clone(),
+                ty: ty.clone(),
+                status: Some(status.clone()),
+            }),
+            _ => {
+                #[cfg(feature = "logging")]
+                warn!(target: "stdout", "Skipping the unsupported item {}", response_item_id(item));
+            }
+        }
+    }
+
+    conversation_items
+}
+
+/// Converts the output of a response to the items of a conversation.
+fn output_to_conversation_items(output: &[ResponseOutputItem]) -> Vec<ConversationItem> {
+    let mut conversation_items = Vec::new();
+    for item in output {
+        match item {
+            ResponseOutputItem::OutputMessage {
+                content,
+                id,
+                role,
+                status,
+                ..
+            } => {
+                for (index, part) in content.iter().enumerate() {
+                    let content = match part {
+                        ResponseOutputItemOutputMessageContent::OutputText {
+                            annotations,
+                            text,
+                            ..
+                        } => ConversationMessageContent::OutputText {
+                            annotations: annotations.clone(),
+                            text: text.clone(),
+                            ty: "output_text".to_string(),
+                        },
+                        ResponseOutputItemOutputMessageContent::Refusal { refusal, .. } => {
+                            ConversationMessageContent::Refusal {
+                                refusal: refusal.clone(),
+                                ty: "refusal".to_string(),
+                            }
+                        }
+                    };
+                    conversation_items.push(ConversationItem::Message {
+                        content,
+                        id: part_id(id, index),
+                        role: role.clone(),
+                        status: Some(status.clone()),
+                        ty: "message".to_string(),
+                    });
+                }
+            }
+            ResponseOutputItem::FunctionCall {
+                arguments,
+                call_id,
+                id,
+                name,
+                ty,
+                status,
+            } => conversation_items.push(ConversationItem::FunctionCall {
+                arguments: arguments.clone(),
+                call_id: call_id.clone(),
+                id: id.clone(),
+                name: name.clone(),
+                ty: ty.clone(),
+                status: Some(status.clone()),
+            }),
+            _ => {
+                #[cfg(feature = "logging")]
+                warn!(target: "stdout", "Skipping the unsupported output item");
+            }
+        }
+    }
+
+    conversation_items
+}
+
+/// Converts the output of a response to the chat messages replayed by the next response. The text and the function calls of the output form one assistant message, which is followed by the outputs of the MCP tool calls run by the server.
+fn output_to_chat_messages(output: &[ResponseOutputItem]) -> Vec<ChatCompletionRequestMessage> {
+    let mut texts = Vec::new();
+    let mut tool_calls = Vec::new();
+    let mut tool_messages = Vec::new();
+    for item in output {
+        match item {
+            ResponseOutputItem::OutputMessage { content, .. } => {
+                for part in content.iter() {
+                    match part {
+                        ResponseOutputItemOutputMessageContent::OutputText { text, .. } => {
+                            texts.push(text.clone())
+                        }
+                        ResponseOutputItemOutputMessageContent::Refusal { refusal, .. } => {
+                            texts.push(refusal.clone())
+                        }
+                    }
+                }
+            }
+            ResponseOutputItem::FunctionCall {
+                arguments,
+                call_id,
+                name,
+                ..
+            } => tool_calls.push(tool_call(call_id, name, arguments)),
+            ResponseOutputItem::McpToolCall {
+                arguments,
+                id,
+                name,
+                error,
+                output,
+                ..
+            } => {
+                tool_calls.push(tool_call(id, name, arguments));
+
+                let content = match error.is_empty() {
+                    true => output.clone(),
+                    false => error.clone(),
+                };
+                tool_messages.push(ChatCompletionRequestMessage::new_tool_message(
+                    content,
+                    id.clone(),
+                ));
+            }
+            // the generated images and the listed tools are not a part of the chat
+            ResponseOutputItem::ImageGeneration { .. }
+            | ResponseOutputItem::McpListTools { .. } => {}
+        }
+    }
+
+    let mut messages = Vec::new();
+    if !texts.is_empty() || !tool_calls.is_empty() {
+        messages.push(ChatCompletionRequestMessage::new_assistant_message(
+            (!texts.is_empty()).then(|| texts.join("\n")),
+            None,
+            (!tool_calls.is_empty()).then_some(tool_calls),
+        ));
+    }
+    messages.extend(tool_messages);
+
+    messages
+}
+
+/// Builds the tool call of a chat message from a function call item.
+pub(crate) fn tool_call(call_id: &str, name: &str, arguments: &str) -> ToolCall {
+    ToolCall {
+        id: call_id.to_owned(),
+        ty: "function".to_string(),
+        function: Function {
+            name: name.to_owned(),
+            arguments: arguments.to_owned(),
+        },
+    }
+}
+
+/// Returns the ID of the conversation item made of the content at the index of a message. The first content keeps the ID of the message.
+fn part_id(id: &str, index: usize) -> String {
+    match index {
+        0 => id.to_owned(),
+        _ => gen_item_id("msg"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use endpoints::{
+        chat::ChatCompletionUserMessageContent,
+        responses::response_object::{InputTokensDetails, OutputTokensDetails, ToolChoice, Usage},
+    };
+    use std::collections::HashMap;
+
+    /// Keeps the objects in memory instead of the files.
+    #[derive(Default)]
+    struct MemoryStore {
+        responses: Mutex<HashMap<String, StoredResponse>>,
+        conversations: Mutex<HashMap<String, StoredConversation>>,
+    }
+    impl ResponseStore for MemoryStore {
+        fn save_response(&self, response: &StoredResponse) -> Result<(), LlamaCoreError> {
+            let mut responses = self.responses.lock().unwrap();
+            responses.insert(response.response.id.clone(), response.clone());
+            Ok(())
+        }
+
+        fn load_response(&self, id: &str) -> Result<Option<StoredResponse>, LlamaCoreError> {
+            Ok(self.responses.lock().unwrap().get(id).cloned())
+        }
+
+        fn delete_response(&self, id: &str) -> Result<bool, LlamaCoreError> {
+            Ok(self.responses.lock().unwrap().remove(id).is_some())
+        }
+
+        fn save_conversation(
+            &self,
+            conversation: &StoredConversation,
+        ) -> Result<(), LlamaCoreError> {
+            let mut conversations = self.conversations.lock().unwrap();
+            conversations.insert(conversation.conversation.id.clone(), conversation.clone());
+            Ok(())
+        }
+
+        fn load_conversation(
+            &self,
+            id: &str,
+        ) -> Result<Option<StoredConversation>, LlamaCoreError> {
+            Ok(self.conversations.lock().unwrap().get(id).cloned())
+        }
+
+        fn delete_conversation(&self, id: &str) -> Result<bool, LlamaCoreError> {
+            Ok(self.conversations.lock().unwrap().remove(id).is_some())
+        }
+    }
+
+    fn request(json: serde_json::Value) -> RequestOfModelResponse {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn response(id: &str, output: Vec<ResponseOutputItem>) -> ResponseObject {
+        ResponseObject {
+            background: false,
+            conversation: None,
+            created_at: 0,
+            error: None,
+            id: id.to_string(),
+            incomplete_details: None,
+            instructions: None,
+            max_output_tokens: None,
+            max_tool_calls: None,
+            metadata: HashMap::new(),
+            model: "model".to_string(),
+            object: "response".to_string(),
+            output,
+            parallel_tool_calls: false,
+            previous_response_id: None,
+            safety_identifier: None,
+            status: "completed".to_string(),
+            temperature: 1.0,
+            tool_choice: ToolChoice::Auto,
+            tools: None,
+            top_p: 1.0,
+            truncation: None,
+            usage: Usage {
+                input_tokens: 0,
+                input_tokens_details: InputTokensDetails { cached_tokens: 0 },
+                output_tokens: 0,
+                output_tokens_details: OutputTokensDetails {
+                    reasoning_tokens: 0,
+                },
+                total_tokens: 0,
+            },
+        }
+    }
+
+    fn output_message(text: &str) -> ResponseOutputItem {
+        ResponseOutputItem::OutputMessage {
+            content: vec![ResponseOutputItemOutputMessageContent::OutputText {
+                annotations: vec![],
+                text: text.to_string(),
+                ty: "output_text".to_string(),
+                logprobs: None,
+            }],
+            id: gen_item_id("msg"),
+            role: "assistant".to_string(),
+            status: "completed".to_string(),
+            ty: "message".to_string(),
+        }
+    }
+
+    fn user(text: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(text.to_string()),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_previous_response_chain_keeps_tool_calls() {
+        set_response_store(MemoryStore::default()).unwrap();
+
+        // the first response calls a function
+        let first_request = request(serde_json::json!({
+            "model": "model",
+            "input": "What is the weather in Paris?",
+        }));
+        let function_call = ResponseOutputItem::FunctionCall {
+            arguments: r#"{"city":"Paris"}"#.to_string(),
+            call_id: "call_1".to_string(),
+            id: gen_item_id("fc"),
+            name: "get_weather".to_string(),
+            ty: "function_call".to_string(),
+            status: "completed".to_string(),
+        };
+        let first_response = response(
+            "resp_1",
+            vec![output_message("Let me check."), function_call],
+        );
+        save_response(
+            &first_request,
+            vec![user("What is the weather in Paris?")],
+            &first_response,
+        )
+        .unwrap();
+
+        // the second response answers with the output of the function
+        let second_request = request(serde_json::json!({
+            "model": "model",
+            "previous_response_id": "resp_1",
+            "input": [{
+                "call_id": "call_1",
+                "id": "fc_output_1",
+                "output": "22°C",
+                "type": "function_call_output",
+                "status": "completed",
+            }],
+        }));
+        let tool_call = tool_call("call_1", "get_weather", r#"{"city":"Paris"}"#);
+        let expected = vec![
+            user("What is the weather in Paris?"),
+            ChatCompletionRequestMessage::new_assistant_message(
+                Some("Let me check.".to_string()),
+                None,
+                Some(vec![tool_call]),
+            ),
+        ];
+        assert_eq!(load_history(&second_request).unwrap(), expected);
+
+        let tool_message = ChatCompletionRequestMessage::new_tool_message("22°C", "call_1");
+        let second_response = response("resp_2", vec![output_message("It is 22°C.")]);
+        save_response(
+            &second_request,
+            vec![tool_message.clone()],
+            &second_response,
+        )
+        .unwrap();
+
+        let third_request = request(serde_json::json!({
+            "model": "model",
+            "previous_response_id": "resp_2",
+            "input": "Thanks!",
+        }));
+        let mut expected = expected;
+        expected.push(tool_message);
+        expected.push(ChatCompletionRequestMessage::new_assistant_message(
+            Some("It is 22°C.".to_string()),
+            None,
+            None,
+        ));
+        assert_eq!(load_history(&third_request).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_output_to_chat_messages_replays_mcp_tool_calls() {
+        let output = vec![
+            ResponseOutputItem::McpToolCall {
+                arguments: "{}".to_string(),
+                id: "mcp_1".to_string(),
+                name: "now".to_string(),
+                server_label: "clock".to_string(),
+                ty: "mcp_call".to_string(),
+                error: String::new(),
+                output: "12:00".to_string(),
+            },
+            output_message("It is noon."),
+        ];
+
+        assert_eq!(
+            output_to_chat_messages(&output),
+            vec![
+                ChatCompletionRequestMessage::new_assistant_message(
+                    Some("It is noon.".to_string()),
+                    None,
+                    Some(vec![tool_call("mcp_1", "now", "{}")]),
+                ),
+                ChatCompletionRequestMessage::new_tool_message("12:00", "mcp_1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paginate() {
+        let items = vec!["a", "b", "c", "d"];
+        let query = |after: Option<&str>, limit, order| ListItemsQuery {
+            after: after.map(str::to_string),
+            limit,
+            order,
+        };
+
+        let (page, has_more) = paginate(
+            items.clone(),
+            &query(None, Some(2), Some(ListOrder::Asc)),
+            |item| item,
+        )
+        .unwrap();
+        assert_eq!((page, has_more), (vec!["a", "b"], true));
+
+        let (page, has_more) = paginate(
+            items.clone(),
+            &query(Some("b"), None, Some(ListOrder::Asc)),
+            |item| item,
+        )
+        .unwrap();
+        assert_eq!((page, has_more), (vec!["c", "d"], false));
+
+        // the newest items come first by default
+        let (page, has_more) =
+            paginate(items.clone(), &query(Some("c"), Some(1), None), |item| item).unwrap();
+        assert_eq!((page, has_more), (vec!["b"], true));
+
+        assert!(paginate(items.clone(), &query(Some("e"), None, None), |item| item).is_err());
+        assert!(paginate(items, &query(None, Some(0), None), |item| item).is_err());
+    }
+
+    #[test]
+    fn test_to_conversation_items_splits_messages() {
+        let items = to_response_items(&[serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "content": "Hello",
+            "type": "message",
+        }))
+        .unwrap()]);
+        assert!(matches!(&items[..], [ResponseItem::OutputMessage { .. }]));
+
+        let conversation_items = to_conversation_items(&[ResponseItem::InputMessage {
+            content: vec![
+                ResponseItemInputMessageContent::Text {
+                    text: "first".to_string(),
+                    ty: "input_text".to_string(),
+                },
+                ResponseItemInputMessageContent::Text {
+                    text: "second".to_string(),
+                    ty: "input_text".to_string(),
+                },
+            ],
+            id: "msg_1".to_string(),
+            role: "user".to_string(),
+            status: None,
+            ty: "message".to_string(),
+        }]);
+        assert_eq!(conversation_items.len(), 2);
+        assert_eq!(conversation_item_id(&conversation_items[0]), "msg_1");
+        assert_ne!(conversation_item_id(&conversation_items[1]), "msg_1");
+    }
+}
@@ -2,11 +2,15 @@
 
 use crate::{
     error::{BackendError, LlamaCoreError},
-    BaseMetadata, Graph, CHAT_GRAPHS, EMBEDDING_GRAPHS, MAX_BUFFER_SIZE,
+    scheduler::SlotPermit,
+    BaseMetadata, GgmlMetadata, Graph, CHAT_GRAPHS, EMBEDDING_GRAPHS, LOGPROBS_TENSOR,
+    MAX_BUFFER_SIZE,
 };
 use bitflags::bitflags;
-use chat_prompts::PromptTemplateType;
+use chat_prompts::{chat::ChatTemplate, PromptTemplateType};
+use endpoints::chat::LogProb;
 use serde_json::Value;
+use std::{collections::HashMap, path::Path};
 
 pub(crate) fn gen_chat_id() -> String {
     format!("chatcmpl-{}", uuid::Uuid::new_v4())
@@ -23,6 +27,11 @@ pub(crate) fn gen_response_id() -> String {
     format!("resp_{}{}", part1, &part2[..16]) // resp_ + 48字符 = 53字符总长度
 }
 
+/// Generates the ID of an object of the Responses API, such as `msg_<uuid>` or `conv_<uuid>`.
+pub(crate) fn gen_item_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, uuid::Uuid::new_v4().simple())
+}
+
 /// Return the names of the chat models.
 pub fn chat_model_names() -> Result<Vec<String>, LlamaCoreError> {
     #[cfg(feature = "logging")]
@@ -91,6 +100,83 @@ pub fn embedding_model_names() -> Result<Vec<String>, LlamaCoreError> {
     Ok(model_names)
 }
 
+/// Loads the chat template rendered by the `jinja` prompt template.
+///
+/// # Arguments
+///
+/// * `path` - The path to a GGUF model file with `tokenizer.chat_template` in its metadata, a `tokenizer_config.json` file of Hugging Face, or a file with the source of the template.
+pub fn load_chat_template(path: impl AsRef<Path>) -> Result<ChatTemplate, LlamaCoreError> {
+    let path = path.as_ref();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Load the chat template from {}", path.display());
+
+    let load_error = |reason: String| {
+        let err_msg = format!(
+            "Failed to load the chat template from {}. {}",
+            path.display(),
+            reason
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    };
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gguf") => crate::gguf::GgufMetadata::read(path)?
+            .chat_template()
+            .ok_or_else(|| load_error("The model has no `tokenizer.chat_template`.".to_string())),
+        Some("json") => {
+            let json = std::fs::read_to_string(path).map_err(|e| load_error(e.to_string()))?;
+            let config: Value =
+                serde_json::from_str(&json).map_err(|e| load_error(e.to_string()))?;
+
+            // the template is either a string or a list of named templates
+            let source = match config.get("chat_template") {
+                Some(Value::String(source)) => Some(source.as_str()),
+                Some(Value::Array(templates)) => {
+                    let template = |name: &str| {
+                        templates
+                            .iter()
+                            .find(|template| template.get("name") == Some(&Value::from(name)))
+                    };
+                    template("default")
+                        .or_else(|| templates.first())
+                        .and_then(|template| template.get("template"))
+                        .and_then(Value::as_str)
+                }
+                _ => None,
+            };
+            let source = source.ok_or_else(|| {
+                load_error("The tokenizer config has no `chat_template`.".to_string())
+            })?;
+
+            // the special tokens are either strings or objects with the `content` field
+            let token = |name: &str| match config.get(name) {
+                Some(Value::String(token)) => token.as_str(),
+                Some(token) => token
+                    .get("content")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                None => "",
+            };
+
+            // the backend adds the BOS token unless the tokenizer does not
+            let mut template = ChatTemplate::new(source).with_eos_token(token("eos_token"));
+            if config.get("add_bos_token") == Some(&Value::Bool(false)) {
+                template = template.with_bos_token(token("bos_token"));
+            }
+
+            Ok(template)
+        }
+        _ => std::fs::read_to_string(path)
+            .map(ChatTemplate::new)
+            .map_err(|e| load_error(e.to_string())),
+    }
+}
+
 /// Get the chat prompt template type from the given model name.
 pub fn chat_prompt_template(name: Option<&str>) -> Result<PromptTemplateType, LlamaCoreError> {
     #[cfg(feature = "logging")]
@@ -305,10 +391,79 @@ where
     })
 }
 
-/// Get the token information from the graph by the model name.
-pub(crate) fn get_token_info_by_graph_name(
-    name: Option<&String>,
-) -> Result<TokenInfo, LlamaCoreError> {
+/// Returns the name of the graph that serves the requests for the given model name.
+///
+/// If the model name is not given, the model loaded first is used. If the model name is not found, the requests are served by the only model if there is one, so that the clients sending the names of OpenAI models still work; otherwise, [`LlamaCoreError::ModelNotFound`] is returned.
+///
+/// # Arguments
+///
+/// * `graphs` - The graphs to look up.
+///
+/// * `model_name` - The model name of the request.
+///
+/// * `kind` - The kind of the graphs, e.g. `chat` or `embedding`, used in the error messages.
+pub(crate) fn resolve_model_name<M: BaseMetadata + serde::Serialize + Clone + Default>(
+    graphs: &HashMap<String, Graph<M>>,
+    model_name: Option<&String>,
+    kind: &str,
+) -> Result<String, LlamaCoreError> {
+    if let Some(model_name) = model_name {
+        if graphs.contains_key(model_name) {
+            return Ok(model_name.clone());
+        }
+
+        if graphs.len() > 1 {
+            let mut names: Vec<&str> = graphs.keys().map(|name| name.as_str()).collect();
+            names.sort_unstable();
+
+            let err_msg = format!(
+                "The model `{}` does not exist in the {} graphs. Available models: {}.",
+                model_name,
+                kind,
+                names.join(", ")
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::ModelNotFound(err_msg));
+        }
+    }
+
+    // the model loaded first is the default one
+    match graphs
+        .iter()
+        .min_by(|(a_name, a), (b_name, b)| a.created.cmp(&b.created).then(a_name.cmp(b_name)))
+    {
+        Some((name, _)) => Ok(name.clone()),
+        None => {
+            let err_msg = format!("There is no model available in the {kind} graphs.");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::ModelNotFound(err_msg))
+        }
+    }
+}
+
+/// Runs `f` on the execution context of the given slot of the chat graph with the given name. The requests holding a slot of the model must only run the model through this function, so that they do not feed each other's contexts.
+pub(crate) fn with_chat_slot<T>(
+    model_name: &str,
+    slot: usize,
+    f: impl FnOnce(&mut Graph<GgmlMetadata>) -> Result<T, LlamaCoreError>,
+) -> Result<T, LlamaCoreError> {
+    with_chat_graph(model_name, |graph| {
+        graph.use_slot(slot)?;
+        f(graph)
+    })
+}
+
+/// Runs `f` on the chat graph with the given name.
+pub(crate) fn with_chat_graph<T>(
+    model_name: &str,
+    f: impl FnOnce(&mut Graph<GgmlMetadata>) -> Result<T, LlamaCoreError>,
+) -> Result<T, LlamaCoreError> {
     let chat_graphs = match CHAT_GRAPHS.get() {
         Some(chat_graphs) => chat_graphs,
         None => {
@@ -321,7 +476,7 @@ pub(crate) fn get_token_info_by_graph_name(
         }
     };
 
-    let chat_graphs = chat_graphs.lock().map_err(|e| {
+    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
         let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {e}");
 
         #[cfg(feature = "logging")]
@@ -330,35 +485,58 @@ pub(crate) fn get_token_info_by_graph_name(
         LlamaCoreError::Operation(err_msg)
     })?;
 
-    match name {
-        Some(model_name) => match chat_graphs.contains_key(model_name) {
-            true => {
-                let graph = chat_graphs.get(model_name).unwrap();
-                get_token_info_by_graph(graph)
-            }
-            false => match chat_graphs.iter().next() {
-                Some((_, graph)) => get_token_info_by_graph(graph),
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
+    match chat_graphs.get_mut(model_name) {
+        Some(graph) => f(graph),
+        None => {
+            let err_msg = format!("There is no model named {model_name} in the chat graphs.");
 
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
 
-                    Err(LlamaCoreError::Operation(err_msg.into()))
-                }
-            },
-        },
-        None => match chat_graphs.iter().next() {
-            Some((_, graph)) => get_token_info_by_graph(graph),
-            None => {
-                let err_msg = "There is no model available in the chat graphs.";
+            Err(LlamaCoreError::Operation(err_msg))
+        }
+    }
+}
 
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
+/// A guard that restores the metadata of a slot of a chat model when it is dropped, so that the options of a request do not apply to the next request on the slot, even if the request fails before its generation.
+#[derive(Debug)]
+pub(crate) struct ResetMetadataOnDrop {
+    model_name: Option<String>,
+    slot: usize,
+}
+impl ResetMetadataOnDrop {
+    /// Creates a guard restoring the metadata of the slot held by the permit.
+    pub(crate) fn new(permit: &SlotPermit) -> Self {
+        Self {
+            model_name: Some(permit.model_name().to_owned()),
+            slot: permit.slot(),
+        }
+    }
 
-                Err(LlamaCoreError::Operation(err_msg.into()))
-            }
-        },
+    /// Consumes the guard without restoring the metadata, once the metadata is restored by a stream taking over the request.
+    pub(crate) fn disarm(mut self) {
+        self.model_name = None;
+    }
+}
+impl Drop for ResetMetadataOnDrop {
+    fn drop(&mut self) {
+        let model_name = match self.model_name.take() {
+            Some(model_name) => model_name,
+            None => return,
+        };
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Reset the metadata of the slot {} of the model named {model_name}", self.slot);
+
+        if let Err(e) = with_chat_slot(&model_name, self.slot, |graph| graph.update_metadata()) {
+            let err_msg = format!("Fail to reset model metadata. Reason: {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            #[cfg(not(feature = "logging"))]
+            eprintln!("[ERROR][llama_core] {}", &err_msg);
+        }
     }
 }
 
@@ -368,6 +546,551 @@ pub(crate) struct TokenInfo {
     pub(crate) completion_tokens: u64,
 }
 
+/// Get the log probabilities of the latest generated token from the graph in the stream mode.
+///
+/// Returns `None` if the backend does not return log probabilities. The first failure to read them marks the backend of the model as not supporting them, so that the generation goes on without log probabilities instead of failing.
+pub(crate) fn get_logprobs_by_graph_single<M>(
+    graph: &mut Graph<M>,
+    top_logprobs: u8,
+) -> Option<Vec<LogProb>>
+where
+    M: BaseMetadata + serde::Serialize + Clone + Default,
+{
+    if graph.logprobs_supported == Some(false) {
+        return None;
+    }
+
+    match get_output_buffer_single(graph, LOGPROBS_TENSOR)
+        .and_then(|output_buffer| parse_logprobs(&output_buffer, top_logprobs))
+    {
+        Ok(logprobs) => {
+            graph.logprobs_supported = Some(true);
+
+            Some(logprobs)
+        }
+        Err(e) => {
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "The log probabilities are not returned by the backend of the model named {}. {e}", graph.name());
+
+            match graph.logprobs_supported {
+                // a backend that returned log probabilities before only fails for this token
+                Some(true) => Some(vec![]),
+                _ => {
+                    graph.logprobs_supported = Some(false);
+
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Parse the logprobs output tensor, which is a JSON array of `{"token", "logprob", "bytes", "top_logprobs"}` objects, one per generated token.
+fn parse_logprobs(output_buffer: &[u8], top_logprobs: u8) -> Result<Vec<LogProb>, LlamaCoreError> {
+    if output_buffer.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut logprobs: Vec<LogProb> = serde_json::from_slice(output_buffer).map_err(|e| {
+        let err_msg = format!("Fail to deserialize logprobs: {e}");
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    // the backend may return more candidates than requested
+    for logprob in logprobs.iter_mut() {
+        logprob.top_logprobs.truncate(top_logprobs as usize);
+    }
+
+    Ok(logprobs)
+}
+
+/// Parse the `logit_bias` option of a request, which maps token ids in string form to bias values between -100 and 100.
+pub(crate) fn parse_logit_bias<V>(
+    logit_bias: &HashMap<String, V>,
+) -> Result<HashMap<u32, f64>, LlamaCoreError>
+where
+    V: Into<f64> + Copy,
+{
+    let mut parsed = HashMap::with_capacity(logit_bias.len());
+
+    for (token, bias) in logit_bias {
+        let token_id = token.trim().parse::<u32>().map_err(|e| {
+            let err_msg = format!("Invalid token id in `logit_bias`: {token}. {e}");
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::InvalidRequest(err_msg)
+        })?;
+
+        let bias: f64 = (*bias).into();
+        if !(-100.0..=100.0).contains(&bias) {
+            let err_msg = format!(
+                "Invalid bias value in `logit_bias` for the token {token_id}: {bias}. The value should be between -100 and 100."
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::InvalidRequest(err_msg));
+        }
+
+        parsed.insert(token_id, bias);
+    }
+
+    Ok(parsed)
+}
+
+/// Returns the byte offset of the earliest occurrence of any of the given stop sequences in the text.
+pub(crate) fn find_stop_sequence(text: &str, stop: &[String]) -> Option<usize> {
+    stop.iter()
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| text.find(s.as_str()))
+        .min()
+}
+
+/// Returns `true` if one of the stop sequences ends in the last `new_len` bytes of the output, i.e. the latest token completes a stop sequence.
+pub(crate) fn ends_with_stop_sequence(output: &[u8], new_len: usize, stop: &[String]) -> bool {
+    stop.iter().filter(|s| !s.is_empty()).any(|s| {
+        let s = s.as_bytes();
+        let start = output.len().saturating_sub(new_len + s.len() - 1);
+
+        output[start..].windows(s.len()).any(|window| window == s)
+    })
+}
+
+/// Truncates the text at the earliest stop sequence. Returns `true` if a stop sequence was found.
+pub(crate) fn truncate_at_stop_sequence(text: &mut String, stop: Option<&Vec<String>>) -> bool {
+    match stop.and_then(|stop| find_stop_sequence(text, stop)) {
+        Some(pos) => {
+            text.truncate(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Matches the `stop` sequences of a request against the text generated in the stream mode.
+///
+/// A stop sequence may be split across several tokens, so the tail of the generated text that
+/// could be the beginning of a stop sequence is held back until it is either confirmed or ruled out.
+#[derive(Debug, Default)]
+pub(crate) struct StopSequences {
+    stop: Vec<String>,
+    pending: String,
+    stopped: bool,
+}
+impl StopSequences {
+    pub(crate) fn new(stop: Option<&Vec<String>>) -> Self {
+        Self {
+            stop: stop
+                .map(|stop| stop.iter().filter(|s| !s.is_empty()).cloned().collect())
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether a stop sequence has been hit or the held-back text has been flushed.
+    pub(crate) fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Feeds a piece of generated text. Returns the text that is safe to send to the client and
+    /// whether a stop sequence has been hit. The stop sequence itself is never returned.
+    pub(crate) fn push(&mut self, text: &str) -> (String, bool) {
+        if self.stop.is_empty() {
+            return (text.to_string(), false);
+        }
+
+        self.pending.push_str(text);
+
+        if let Some(pos) = find_stop_sequence(&self.pending, &self.stop) {
+            let output = self.pending[..pos].to_string();
+            self.pending.clear();
+            self.stopped = true;
+
+            return (output, true);
+        }
+
+        // hold back the longest suffix which is a prefix of any stop sequence
+        let hold_from = self
+            .pending
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .find(|&idx| {
+                let tail = &self.pending[idx..];
+                self.stop.iter().any(|s| s.starts_with(tail))
+            })
+            .unwrap_or(self.pending.len());
+
+        let output = self.pending[..hold_from].to_string();
+        self.pending.drain(..hold_from);
+
+        (output, false)
+    }
+
+    /// Starts matching a new generation with the same stop sequences.
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+        self.stopped = false;
+    }
+
+    /// Returns the held-back text once the generation ends without hitting a stop sequence.
+    pub(crate) fn flush(&mut self) -> Option<String> {
+        self.stopped = true;
+
+        match self.pending.is_empty() {
+            true => None,
+            false => Some(std::mem::take(&mut self.pending)),
+        }
+    }
+}
+
+/// The keywords of JSON Schema checked by [`validate_json_schema`].
+const JSON_SCHEMA_KEYWORDS: &[&str] = &[
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "minLength",
+    "maxLength",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "$ref",
+];
+
+/// The keywords of JSON Schema which only annotate or hold schemas, and do not constrain the value.
+const JSON_SCHEMA_ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "$defs",
+    "definitions",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+/// The maximum depth of the schemas nested in a JSON schema, including the schemas reached by `$ref`s. It stops recursive references, such as `{"$ref": "#"}`.
+const MAX_JSON_SCHEMA_DEPTH: usize = 64;
+
+/// Checks that the JSON schema only uses the keywords supported by [`validate_json_schema`], and that its `$ref`s can be resolved.
+pub(crate) fn check_json_schema(schema: &Value) -> Result<(), String> {
+    check_json_schema_value(schema, schema, "$", 0)
+}
+
+fn check_json_schema_value(
+    schema: &Value,
+    root: &Value,
+    path: &str,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > MAX_JSON_SCHEMA_DEPTH {
+        return Err(format!(
+            "{path}: the schema is nested deeper than {MAX_JSON_SCHEMA_DEPTH} levels"
+        ));
+    }
+
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        Value::Bool(_) => return Ok(()),
+        _ => return Err(format!("{path}: a schema must be an object or a boolean")),
+    };
+
+    for (keyword, value) in schema {
+        let keyword_path = format!("{path}.{keyword}");
+
+        match keyword.as_str() {
+            "properties" | "$defs" | "definitions" => {
+                let schemas = value
+                    .as_object()
+                    .ok_or_else(|| format!("{keyword_path}: expected an object of schemas"))?;
+                for (name, sub_schema) in schemas {
+                    check_json_schema_value(
+                        sub_schema,
+                        root,
+                        &format!("{keyword_path}.{name}"),
+                        depth + 1,
+                    )?;
+                }
+            }
+            "items" | "additionalProperties" => {
+                check_json_schema_value(value, root, &keyword_path, depth + 1)?
+            }
+            "allOf" | "anyOf" | "oneOf" => {
+                let schemas = value
+                    .as_array()
+                    .ok_or_else(|| format!("{keyword_path}: expected an array of schemas"))?;
+                for (idx, sub_schema) in schemas.iter().enumerate() {
+                    check_json_schema_value(
+                        sub_schema,
+                        root,
+                        &format!("{keyword_path}[{idx}]"),
+                        depth + 1,
+                    )?;
+                }
+            }
+            "$ref" => {
+                resolve_json_schema_ref(value, root, path)?;
+            }
+            keyword
+                if JSON_SCHEMA_KEYWORDS.contains(&keyword)
+                    || JSON_SCHEMA_ANNOTATIONS.contains(&keyword) => {}
+            keyword => return Err(format!("{path}: unsupported keyword `{keyword}`")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves a local `$ref` of a JSON schema, such as `#/$defs/...`.
+fn resolve_json_schema_ref<'a>(
+    reference: &Value,
+    root: &'a Value,
+    path: &str,
+) -> Result<&'a Value, String> {
+    let reference = reference
+        .as_str()
+        .ok_or_else(|| format!("{path}: `$ref` must be a string"))?;
+
+    reference
+        .strip_prefix('#')
+        .and_then(|pointer| root.pointer(pointer))
+        .ok_or_else(|| format!("{path}: unresolvable reference `{reference}`"))
+}
+
+/// Validate a JSON value against a JSON schema.
+///
+/// Only the commonly used subset of JSON Schema is supported: `type`, `enum`, `const`, `properties`, `required`, `additionalProperties`, `items`, the length and range keywords, `allOf`/`anyOf`/`oneOf`, and local `$ref`s such as `#/definitions/...` or `#/$defs/...`. Check the schema by [`check_json_schema`] first, which rejects the other keywords.
+pub(crate) fn validate_json_schema(instance: &Value, schema: &Value) -> Result<(), String> {
+    validate_json_value(instance, schema, schema, "$", 0)
+}
+
+fn validate_json_value(
+    instance: &Value,
+    schema: &Value,
+    root: &Value,
+    path: &str,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > MAX_JSON_SCHEMA_DEPTH {
+        return Err(format!(
+            "{path}: the schema is nested deeper than {MAX_JSON_SCHEMA_DEPTH} levels, or its references are recursive"
+        ));
+    }
+
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        Value::Bool(false) => return Err(format!("{path}: no value is allowed")),
+        _ => return Ok(()),
+    };
+
+    if let Some(reference) = schema.get("$ref") {
+        let target = resolve_json_schema_ref(reference, root, path)?;
+        validate_json_value(instance, target, root, path, depth + 1)?;
+    }
+
+    // type
+    if let Some(ty) = schema.get("type") {
+        let types: Vec<&str> = match ty {
+            Value::String(ty) => vec![ty.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|ty| json_type_matches(instance, ty)) {
+            return Err(format!(
+                "{path}: expected {}, but found {}",
+                types.join(" or "),
+                json_type_name(instance)
+            ));
+        }
+    }
+
+    // enum and const
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if !values.contains(instance) {
+            return Err(format!(
+                "{path}: {instance} is not one of {}",
+                Value::from(values.clone())
+            ));
+        }
+    }
+    if let Some(value) = schema.get("const") {
+        if value != instance {
+            return Err(format!("{path}: expected {value}, but found {instance}"));
+        }
+    }
+
+    // combinators
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for sub_schema in schemas {
+            validate_json_value(instance, sub_schema, root, path, depth + 1)?;
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas.iter().any(|sub_schema| {
+            validate_json_value(instance, sub_schema, root, path, depth + 1).is_ok()
+        }) {
+            return Err(format!("{path}: does not match any schema of `anyOf`"));
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let matched = schemas
+            .iter()
+            .filter(|sub_schema| {
+                validate_json_value(instance, sub_schema, root, path, depth + 1).is_ok()
+            })
+            .count();
+        if matched != 1 {
+            return Err(format!(
+                "{path}: expected to match exactly one schema of `oneOf`, but matched {matched}"
+            ));
+        }
+    }
+
+    match instance {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        return Err(format!("{path}: missing required property `{key}`"));
+                    }
+                }
+            }
+
+            for (key, value) in object {
+                let property_path = format!("{path}.{key}");
+                match properties.and_then(|properties| properties.get(key)) {
+                    Some(property_schema) => validate_json_value(
+                        value,
+                        property_schema,
+                        root,
+                        &property_path,
+                        depth + 1,
+                    )?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return Err(format!("{path}: unexpected property `{key}`"))
+                        }
+                        Some(additional) => {
+                            validate_json_value(value, additional, root, &property_path, depth + 1)?
+                        }
+                        None => (),
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min_items) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min_items {
+                    return Err(format!("{path}: expected at least {min_items} items"));
+                }
+            }
+            if let Some(max_items) = schema.get("maxItems").and_then(Value::as_u64) {
+                if (items.len() as u64) > max_items {
+                    return Err(format!("{path}: expected at most {max_items} items"));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (idx, item) in items.iter().enumerate() {
+                    validate_json_value(
+                        item,
+                        item_schema,
+                        root,
+                        &format!("{path}[{idx}]"),
+                        depth + 1,
+                    )?;
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min_length) = schema.get("minLength").and_then(Value::as_u64) {
+                if len < min_length {
+                    return Err(format!("{path}: expected at least {min_length} characters"));
+                }
+            }
+            if let Some(max_length) = schema.get("maxLength").and_then(Value::as_u64) {
+                if len > max_length {
+                    return Err(format!("{path}: expected at most {max_length} characters"));
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < minimum {
+                    return Err(format!("{path}: {n} is less than the minimum {minimum}"));
+                }
+            }
+            if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > maximum {
+                    return Err(format!("{path}: {n} is greater than the maximum {maximum}"));
+                }
+            }
+            if let Some(minimum) = schema.get("exclusiveMinimum").and_then(Value::as_f64) {
+                if n <= minimum {
+                    return Err(format!("{path}: {n} must be greater than {minimum}"));
+                }
+            }
+            if let Some(maximum) = schema.get("exclusiveMaximum").and_then(Value::as_f64) {
+                if n >= maximum {
+                    return Err(format!("{path}: {n} must be less than {maximum}"));
+                }
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn json_type_matches(instance: &Value, ty: &str) -> bool {
+    match ty {
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "boolean" => instance.is_boolean(),
+        "null" => instance.is_null(),
+        "number" => instance.is_number(),
+        "integer" => match instance {
+            Value::Number(n) => {
+                n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+            }
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+fn json_type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::String(_) => "string",
+        Value::Bool(_) => "boolean",
+        Value::Null => "null",
+        Value::Number(_) => "number",
+    }
+}
+
 pub(crate) trait TensorType {
     fn tensor_type() -> wasmedge_wasi_nn::TensorType;
     fn shape(shape: impl AsRef<[usize]>) -> Vec<usize> {
@@ -420,6 +1143,8 @@ bitflags! {
         const EMBEDDINGS = 0b00000010;
         const TTS = 0b00000100;
         const RAG = 0b00001000;
+        const IMAGE = 0b00010000;
+        const AUDIO = 0b00100000;
     }
 }
 impl std::fmt::Display for RunningMode {
@@ -435,9 +1160,358 @@ impl std::fmt::Display for RunningMode {
         if self.contains(RunningMode::TTS) {
             mode.push_str("tts, ");
         }
+        if self.contains(RunningMode::IMAGE) {
+            mode.push_str("image, ");
+        }
+        if self.contains(RunningMode::AUDIO) {
+            mode.push_str("audio, ");
+        }
 
         mode = mode.trim_end_matches(", ").to_string();
 
         write!(f, "{mode}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_json_schema_type() {
+        let schema = json!({ "type": "integer" });
+        assert!(validate_json_schema(&json!(1), &schema).is_ok());
+        assert!(validate_json_schema(&json!(1.0), &schema).is_ok());
+        assert!(validate_json_schema(&json!(1.5), &schema).is_err());
+        assert!(validate_json_schema(&json!("1"), &schema).is_err());
+
+        let schema = json!({ "type": ["string", "null"] });
+        assert!(validate_json_schema(&json!("a"), &schema).is_ok());
+        assert!(validate_json_schema(&json!(null), &schema).is_ok());
+        assert!(validate_json_schema(&json!(true), &schema).is_err());
+    }
+
+    #[test]
+    fn test_validate_json_schema_enum_and_const() {
+        let schema = json!({ "enum": ["red", "green"] });
+        assert!(validate_json_schema(&json!("red"), &schema).is_ok());
+        assert!(validate_json_schema(&json!("blue"), &schema).is_err());
+
+        let schema = json!({ "const": 42 });
+        assert!(validate_json_schema(&json!(42), &schema).is_ok());
+        assert!(validate_json_schema(&json!(41), &schema).is_err());
+    }
+
+    #[test]
+    fn test_validate_json_schema_object() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": "integer" }
+            },
+            "required": ["name"],
+            "additionalProperties": false
+        });
+        assert!(validate_json_schema(&json!({ "name": "Alice", "age": 30 }), &schema).is_ok());
+
+        let err = validate_json_schema(&json!({ "age": 30 }), &schema).unwrap_err();
+        assert_eq!(err, "$: missing required property `name`");
+
+        let err = validate_json_schema(&json!({ "name": 1 }), &schema).unwrap_err();
+        assert_eq!(err, "$.name: expected string, but found number");
+
+        let err = validate_json_schema(&json!({ "name": "Bob", "x": 1 }), &schema).unwrap_err();
+        assert_eq!(err, "$: unexpected property `x`");
+
+        let schema = json!({ "additionalProperties": { "type": "boolean" } });
+        assert!(validate_json_schema(&json!({ "a": true }), &schema).is_ok());
+        assert!(validate_json_schema(&json!({ "a": 1 }), &schema).is_err());
+    }
+
+    #[test]
+    fn test_validate_json_schema_array() {
+        let schema = json!({
+            "type": "array",
+            "items": { "type": "number" },
+            "minItems": 1,
+            "maxItems": 2
+        });
+        assert!(validate_json_schema(&json!([1, 2.5]), &schema).is_ok());
+        assert!(validate_json_schema(&json!([]), &schema).is_err());
+        assert!(validate_json_schema(&json!([1, 2, 3]), &schema).is_err());
+
+        let err = validate_json_schema(&json!([1, "2"]), &schema).unwrap_err();
+        assert_eq!(err, "$[1]: expected number, but found string");
+    }
+
+    #[test]
+    fn test_validate_json_schema_string_length() {
+        let schema = json!({ "minLength": 2, "maxLength": 3 });
+        assert!(validate_json_schema(&json!("日本"), &schema).is_ok());
+        assert!(validate_json_schema(&json!("a"), &schema).is_err());
+        assert!(validate_json_schema(&json!("abcd"), &schema).is_err());
+    }
+
+    #[test]
+    fn test_validate_json_schema_number_range() {
+        let schema = json!({ "minimum": 0, "maximum": 10 });
+        assert!(validate_json_schema(&json!(0), &schema).is_ok());
+        assert!(validate_json_schema(&json!(10), &schema).is_ok());
+        assert!(validate_json_schema(&json!(-1), &schema).is_err());
+        assert!(validate_json_schema(&json!(11), &schema).is_err());
+
+        let schema = json!({ "exclusiveMinimum": 0, "exclusiveMaximum": 10 });
+        assert!(validate_json_schema(&json!(5), &schema).is_ok());
+        assert!(validate_json_schema(&json!(0), &schema).is_err());
+        assert!(validate_json_schema(&json!(10), &schema).is_err());
+    }
+
+    #[test]
+    fn test_validate_json_schema_combinators() {
+        let schema = json!({ "allOf": [{ "type": "integer" }, { "minimum": 1 }] });
+        assert!(validate_json_schema(&json!(1), &schema).is_ok());
+        assert!(validate_json_schema(&json!(0), &schema).is_err());
+
+        let schema = json!({ "anyOf": [{ "type": "string" }, { "type": "null" }] });
+        assert!(validate_json_schema(&json!(null), &schema).is_ok());
+        assert!(validate_json_schema(&json!(1), &schema).is_err());
+
+        let schema = json!({ "oneOf": [{ "type": "integer" }, { "type": "number" }] });
+        assert!(validate_json_schema(&json!(1.5), &schema).is_ok());
+        assert!(validate_json_schema(&json!(1), &schema).is_err());
+    }
+
+    #[test]
+    fn test_validate_json_schema_ref() {
+        let schema = json!({
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": {
+                        "children": { "type": "array", "items": { "$ref": "#/$defs/node" } }
+                    }
+                }
+            },
+            "$ref": "#/$defs/node"
+        });
+        let tree = json!({ "children": [{ "children": [] }, { "children": [{}] }] });
+        assert!(validate_json_schema(&tree, &schema).is_ok());
+        assert!(validate_json_schema(&json!({ "children": [1] }), &schema).is_err());
+
+        let schema = json!({ "$ref": "#/definitions/missing" });
+        let err = validate_json_schema(&json!(1), &schema).unwrap_err();
+        assert_eq!(err, "$: unresolvable reference `#/definitions/missing`");
+    }
+
+    #[test]
+    fn test_validate_json_schema_recursive_ref() {
+        let schema = json!({ "$ref": "#" });
+        let err = validate_json_schema(&json!(1), &schema).unwrap_err();
+        assert!(err.contains("references are recursive"));
+
+        let schema = json!({
+            "$defs": { "a": { "$ref": "#/$defs/b" }, "b": { "$ref": "#/$defs/a" } },
+            "$ref": "#/$defs/a"
+        });
+        assert!(validate_json_schema(&json!(1), &schema).is_err());
+    }
+
+    #[test]
+    fn test_check_json_schema() {
+        let schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Person",
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "description": "The name" },
+                "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } }
+            },
+            "required": ["name"],
+            "$defs": { "tag": { "enum": ["a", "b"] } }
+        });
+        assert!(check_json_schema(&schema).is_ok());
+
+        let schema = json!({ "properties": { "name": { "type": "string", "pattern": "^a" } } });
+        let err = check_json_schema(&schema).unwrap_err();
+        assert_eq!(err, "$.properties.name: unsupported keyword `pattern`");
+
+        let schema = json!({ "anyOf": [{ "uniqueItems": true }] });
+        assert!(check_json_schema(&schema).is_err());
+
+        let schema = json!({ "items": { "$ref": "#/$defs/missing" } });
+        let err = check_json_schema(&schema).unwrap_err();
+        assert_eq!(err, "$.items: unresolvable reference `#/$defs/missing`");
+    }
+
+    fn stop(stop: &[&str]) -> StopSequences {
+        StopSequences::new(Some(&stop.iter().map(|s| s.to_string()).collect()))
+    }
+
+    #[test]
+    fn test_stop_sequence_split_across_tokens() {
+        let mut matcher = stop(&["</s>"]);
+        assert_eq!(matcher.push("Hello <"), ("Hello ".to_string(), false));
+        assert_eq!(matcher.push("/"), (String::new(), false));
+        assert_eq!(matcher.push("s> world"), (String::new(), true));
+        assert!(matcher.stopped());
+
+        // the held-back text is sent once the stop sequence is ruled out
+        let mut matcher = stop(&["</s>"]);
+        assert_eq!(matcher.push("a </"), ("a ".to_string(), false));
+        assert_eq!(matcher.push("b>"), ("</b>".to_string(), false));
+        assert!(!matcher.stopped());
+    }
+
+    #[test]
+    fn test_stop_sequence_overlapping_prefixes() {
+        // the stop sequence starts within the held-back text
+        let mut matcher = stop(&["aab"]);
+        assert_eq!(matcher.push("a"), (String::new(), false));
+        assert_eq!(matcher.push("a"), (String::new(), false));
+        assert_eq!(matcher.push("a"), ("a".to_string(), false));
+        assert_eq!(matcher.push("b"), (String::new(), true));
+
+        // the held-back text is a prefix of several stop sequences
+        let mut matcher = stop(&["END", "ENOUGH"]);
+        assert_eq!(matcher.push("EN"), (String::new(), false));
+        assert_eq!(matcher.push("O"), (String::new(), false));
+        assert_eq!(matcher.push("X"), ("ENOX".to_string(), false));
+        assert_eq!(matcher.push("ENOUGH!"), (String::new(), true));
+
+        // the earliest stop sequence wins
+        let mut matcher = stop(&["world", "lo"]);
+        assert_eq!(matcher.push("hello world"), ("hel".to_string(), true));
+    }
+
+    #[test]
+    fn test_stop_sequence_at_start() {
+        let mut matcher = stop(&["</s>"]);
+        assert_eq!(matcher.push("</s>"), (String::new(), true));
+        assert!(matcher.stopped());
+
+        let mut matcher = stop(&["\n\n"]);
+        assert_eq!(matcher.push("\n"), (String::new(), false));
+        assert_eq!(matcher.push("\nHello"), (String::new(), true));
+    }
+
+    #[test]
+    fn test_stop_sequence_flush() {
+        let mut matcher = stop(&["</s>"]);
+        assert_eq!(matcher.push("Hello </"), ("Hello ".to_string(), false));
+        assert_eq!(matcher.flush(), Some("</".to_string()));
+        assert!(matcher.stopped());
+        assert_eq!(matcher.flush(), None);
+
+        // a new generation starts without the held-back text
+        matcher.reset();
+        assert!(!matcher.stopped());
+        assert_eq!(matcher.push("<"), (String::new(), false));
+        matcher.reset();
+        assert_eq!(matcher.flush(), None);
+    }
+
+    #[test]
+    fn test_stop_sequence_multibyte() {
+        let mut matcher = stop(&["。\n"]);
+        assert_eq!(matcher.push("你好。"), ("你好".to_string(), false));
+        assert_eq!(matcher.push("\n"), (String::new(), true));
+    }
+
+    #[test]
+    fn test_without_stop_sequences() {
+        let mut matcher = StopSequences::new(None);
+        assert_eq!(matcher.push("</s>"), ("</s>".to_string(), false));
+        assert_eq!(matcher.flush(), None);
+
+        // empty stop sequences are ignored
+        let mut matcher = stop(&[""]);
+        assert_eq!(matcher.push("Hello"), ("Hello".to_string(), false));
+    }
+
+    #[test]
+    fn test_ends_with_stop_sequence() {
+        let stop = vec!["</s>".to_string()];
+        assert!(ends_with_stop_sequence(b"Hello </s>", 2, &stop));
+        assert!(ends_with_stop_sequence(b"Hello </s> more", 6, &stop));
+        assert!(ends_with_stop_sequence(b"</s>", 4, &stop));
+        assert!(!ends_with_stop_sequence(b"Hello </", 1, &stop));
+        assert!(!ends_with_stop_sequence(b"", 0, &stop));
+
+        // the stop sequence completed by an earlier token is not checked again
+        assert!(!ends_with_stop_sequence(b"</s> and more", 5, &stop));
+        assert!(!ends_with_stop_sequence(b"Hello", 5, &["".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_logprobs() {
+        let output = json!([
+            {
+                "token": "Hello",
+                "logprob": -0.1,
+                "bytes": [72, 101, 108, 108, 111],
+                "top_logprobs": [
+                    { "token": "Hello", "logprob": -0.1, "bytes": [72, 101, 108, 108, 111] },
+                    { "token": "Hi", "logprob": -2.5, "bytes": [72, 105] },
+                    { "token": "Hey", "logprob": -3.0, "bytes": null }
+                ]
+            },
+            {
+                "token": "!",
+                "logprob": -0.5,
+                "bytes": null,
+                "top_logprobs": []
+            }
+        ]);
+        let output = serde_json::to_vec(&output).unwrap();
+
+        let logprobs = parse_logprobs(&output, 20).unwrap();
+        assert_eq!(logprobs.len(), 2);
+        assert_eq!(logprobs[0].token, "Hello");
+        assert_eq!(logprobs[0].logprob, -0.1);
+        assert_eq!(logprobs[0].bytes, Some(b"Hello".to_vec()));
+        assert_eq!(logprobs[0].top_logprobs.len(), 3);
+        assert_eq!(logprobs[0].top_logprobs[2].bytes, None);
+        assert_eq!(logprobs[1].token, "!");
+        assert!(logprobs[1].top_logprobs.is_empty());
+
+        // the backend may return more candidates than requested
+        let logprobs = parse_logprobs(&output, 2).unwrap();
+        let top: Vec<&str> = logprobs[0]
+            .top_logprobs
+            .iter()
+            .map(|top| top.token.as_str())
+            .collect();
+        assert_eq!(top, ["Hello", "Hi"]);
+
+        let logprobs = parse_logprobs(&output, 0).unwrap();
+        assert!(logprobs
+            .iter()
+            .all(|logprob| logprob.top_logprobs.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_empty_logprobs() {
+        assert!(parse_logprobs(b"", 5).unwrap().is_empty());
+        assert!(parse_logprobs(b"[]", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_malformed_logprobs() {
+        // not JSON
+        assert!(parse_logprobs(b"Hello", 5).is_err());
+        // truncated
+        assert!(parse_logprobs(br#"[{"token": "Hello", "logprob": -0.1"#, 5).is_err());
+        // not an array of logprobs
+        assert!(parse_logprobs(br#"{"token": "Hello"}"#, 5).is_err());
+        // missing fields
+        assert!(parse_logprobs(br#"[{"token": "Hello"}]"#, 5).is_err());
+        // wrong types
+        assert!(parse_logprobs(
+            br#"[{"token": 1, "logprob": "high", "bytes": null, "top_logprobs": []}]"#,
+            5
+        )
+        .is_err());
+    }
+}
@@ -1,26 +1,40 @@
-use crate::{error, utils::gen_chat_id, SERVER_INFO};
+use crate::{error, utils::gen_chat_id, ApiServer, ModelConfig, ADMIN_API_KEY, SERVER_INFO};
+use chat_prompts::PromptTemplateType;
+#[cfg(feature = "whisper")]
+use endpoints::audio::{transcription::TranscriptionRequest, translation::TranslationRequest};
 use endpoints::{
     audio::speech::SpeechRequest,
-    chat::ChatCompletionRequest,
+    chat::{ChatCompletionRequest, TruncationStrategy},
     completions::CompletionRequest,
     embeddings::{ChunksRequest, ChunksResponse, EmbeddingRequest},
     files::{DeleteFileStatus, FileObject},
-    responses::response_object::RequestOfModelResponse,
+    images::{
+        ImageCreateRequest, ImageEditRequest, ImageVariationRequest, SamplingMethod, Scheduler,
+    },
+    responses::{items::ListItemsQuery, response_object::RequestOfModelResponse},
 };
 use futures_util::TryStreamExt;
 use hyper::{body::to_bytes, Body, Method, Request, Response};
 use llama_core::{
+    cancellation::CancellationToken,
     chat::{chat_completions, responses},
+    error::LlamaCoreError,
+    metadata::ggml::GgmlMetadataBuilder,
+    models, store,
     utils::RunningMode,
+    EngineType, Graph, GraphBuilder, ARCHIVES_DIR,
 };
 use multipart::server::{Multipart, ReadEntry, ReadEntryResult};
 use multipart_2021 as multipart;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     fs::{self, File},
     io::{Cursor, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    str::FromStr,
     time::SystemTime,
 };
+use subtle::ConstantTimeEq;
 
 /// List all models available.
 pub(crate) async fn models_handler() -> Response<Body> {
@@ -35,7 +49,7 @@ pub(crate) async fn models_handler() -> Response<Body> {
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            return error::internal_server_error(err_msg);
+            return error::llama_core_error(&e, err_msg);
         }
     };
 
@@ -199,7 +213,7 @@ pub(crate) async fn embeddings_handler(mut req: Request<Body>) -> Response<Body>
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            error::llama_core_error(&e, err_msg)
         }
     };
 
@@ -288,50 +302,108 @@ pub(crate) async fn completions_handler(mut req: Request<Body>) -> Response<Body
     // log user id
     info!(target: "stdout", "user: {}", &id);
 
-    let res = match llama_core::completions::completions(&completion_request).await {
-        Ok(completion_object) => {
-            // serialize completion object
-            let s = match serde_json::to_string(&completion_object) {
-                Ok(s) => s,
+    // cancel the request if the handler is dropped, e.g. the client disconnected
+    let cancel = CancellationToken::new();
+    let guard = cancel.cancel_on_drop();
+
+    if let Some(true) = completion_request.stream {
+        let res =
+            match llama_core::completions::completions_stream(&completion_request, cancel).await {
+                Ok(stream) => {
+                    // the stream releases the model by itself when it is dropped
+                    guard.disarm();
+
+                    let stream = stream.map_err(|e| e.to_string());
+
+                    let result = Response::builder()
+                        .header("Access-Control-Allow-Origin", "*")
+                        .header("Access-Control-Allow-Methods", "*")
+                        .header("Access-Control-Allow-Headers", "*")
+                        .header("Content-Type", "text/event-stream")
+                        .header("Cache-Control", "no-cache")
+                        .header("Connection", "keep-alive")
+                        .header("user", id)
+                        .body(Body::wrap_stream(stream));
+
+                    match result {
+                        Ok(response) => {
+                            // log
+                            info!(target: "stdout", "finish completions in stream mode");
+
+                            response
+                        }
+                        Err(e) => {
+                            let err_msg = format!("Failed completions in stream mode. Reason: {e}");
+
+                            // log
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            error::internal_server_error(err_msg)
+                        }
+                    }
+                }
                 Err(e) => {
-                    let err_msg = format!("Fail to serialize completion object. {e}");
+                    let err_msg = e.to_string();
 
                     // log
                     error!(target: "stdout", "{}", &err_msg);
 
-                    return error::internal_server_error(err_msg);
+                    error::llama_core_error(&e, err_msg)
                 }
             };
 
-            // return response
-            let result = Response::builder()
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Methods", "*")
-                .header("Access-Control-Allow-Headers", "*")
-                .header("Content-Type", "application/json")
-                .header("user", id)
-                .body(Body::from(s));
-            match result {
-                Ok(response) => response,
-                Err(e) => {
-                    let err_msg = e.to_string();
+        info!(target: "stdout", "Send the completions response.");
 
-                    // log
-                    error!(target: "stdout", "{}", &err_msg);
+        return res;
+    }
 
-                    error::internal_server_error(err_msg)
+    let res =
+        match llama_core::completions::completions_with_cancellation(&completion_request, cancel)
+            .await
+        {
+            Ok(completion_object) => {
+                // serialize completion object
+                let s = match serde_json::to_string(&completion_object) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        let err_msg = format!("Fail to serialize completion object. {e}");
+
+                        // log
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        return error::internal_server_error(err_msg);
+                    }
+                };
+
+                // return response
+                let result = Response::builder()
+                    .header("Access-Control-Allow-Origin", "*")
+                    .header("Access-Control-Allow-Methods", "*")
+                    .header("Access-Control-Allow-Headers", "*")
+                    .header("Content-Type", "application/json")
+                    .header("user", id)
+                    .body(Body::from(s));
+                match result {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let err_msg = e.to_string();
+
+                        // log
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        error::internal_server_error(err_msg)
+                    }
                 }
             }
-        }
-        Err(e) => {
-            let err_msg = e.to_string();
+            Err(e) => {
+                let err_msg = e.to_string();
 
-            // log
-            error!(target: "stdout", "{}", &err_msg);
+                // log
+                error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
-        }
-    };
+                error::llama_core_error(&e, err_msg)
+            }
+        };
 
     info!(target: "stdout", "Send the completions response.");
 
@@ -422,9 +494,16 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
 
     debug!(target: "stdout", "request:\n{}", serde_json::to_string_pretty(&chat_request).unwrap());
 
-    let res = match chat_completions::chat(&mut chat_request).await {
+    // cancel the request if the handler is dropped, e.g. the client disconnected
+    let cancel = CancellationToken::new();
+    let guard = cancel.cancel_on_drop();
+
+    let res = match chat_completions::chat_with_cancellation(&mut chat_request, cancel).await {
         Ok((result, include_tool_calls)) => match result {
             either::Left(stream) => {
+                // the stream releases the model by itself when it is dropped
+                guard.disarm();
+
                 let stream = stream.map_err(|e| e.to_string());
 
                 let result = Response::builder()
@@ -505,7 +584,7 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            error::llama_core_error(&e, err_msg)
         }
     };
 
@@ -1099,7 +1178,7 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
         // log
         error!(target: "stdout", "{}", &err_msg);
 
-        return error::internal_server_error(err_msg);
+        return error::not_found(err_msg);
     }
 
     // check if the file exists
@@ -1113,7 +1192,7 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
         // log
         error!(target: "stdout", "{}", &err_msg);
 
-        return error::internal_server_error(err_msg);
+        return error::not_found(err_msg);
     }
 
     // log
@@ -1209,7 +1288,7 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            error::llama_core_error(&e, err_msg)
         }
     };
 
@@ -1235,10 +1314,21 @@ pub(crate) async fn server_info_handler() -> Response<Body> {
             return error::internal_server_error("The server info is not set.");
         }
     };
+    let server_info = match server_info.read() {
+        Ok(server_info) => server_info,
+        Err(e) => {
+            let err_msg = format!("Fail to acquire the lock of the server info. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
 
     // serialize server info
-    let s = match serde_json::to_string(&server_info) {
-        Ok(s) => s,
+    let mut server_info = match serde_json::to_value(&*server_info) {
+        Ok(server_info) => server_info,
         Err(e) => {
             let err_msg = format!("Fail to serialize server info. {e}");
 
@@ -1249,6 +1339,93 @@ pub(crate) async fn server_info_handler() -> Response<Body> {
         }
     };
 
+    // add the status of the request queues of the chat models
+    match llama_core::scheduler::queue_statuses() {
+        Ok(queues) => {
+            if let Some(server_info) = server_info.as_object_mut() {
+                server_info.insert("queues".to_string(), serde_json::json!(queues));
+            }
+        }
+        Err(e) => {
+            let err_msg = format!("Fail to get the status of the request queues. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    }
+
+    // replace the `auto` prompt templates of the loaded chat models with the detected ones
+    match llama_core::models::chat_prompt_templates() {
+        Ok(prompt_templates) => {
+            let mut chat_models = vec![];
+            if let Some(server_info) = server_info.as_object_mut() {
+                for (key, value) in server_info.iter_mut() {
+                    match key.as_str() {
+                        "chat_model" => chat_models.push(value),
+                        "chat_models" => {
+                            if let Some(models) = value.as_array_mut() {
+                                chat_models.extend(models.iter_mut());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            for model in chat_models
+                .into_iter()
+                .filter_map(serde_json::Value::as_object_mut)
+            {
+                let loaded = model
+                    .get("name")
+                    .and_then(serde_json::Value::as_str)
+                    .and_then(|name| prompt_templates.get(name));
+                if let Some((prompt_template, detected_prompt_template)) = loaded {
+                    model.insert(
+                        "prompt_template".to_string(),
+                        serde_json::json!(prompt_template),
+                    );
+                    if let Some(detected_prompt_template) = detected_prompt_template {
+                        model.insert(
+                            "detected_prompt_template".to_string(),
+                            serde_json::json!(detected_prompt_template),
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            let err_msg = format!("Fail to get the prompt templates of the chat models. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    }
+
+    // add the status of the model pool if it is used
+    match llama_core::pool::pool_status() {
+        Ok(pool) if !pool.models.is_empty() => {
+            if let Some(server_info) = server_info.as_object_mut() {
+                server_info.insert("pool".to_string(), serde_json::json!(pool));
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            let err_msg = format!("Fail to get the status of the model pool. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    }
+
+    let s = server_info.to_string();
+
     // return response
     let result = Response::builder()
         .header("Access-Control-Allow-Origin", "*")
@@ -1351,7 +1528,7 @@ pub(crate) async fn audio_speech_handler(req: Request<Body>) -> Response<Body> {
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            return error::internal_server_error(err_msg);
+            return error::llama_core_error(&e, err_msg);
         }
     };
 
@@ -1466,9 +1643,28 @@ pub(crate) async fn responses_handler(mut req: Request<Body>) -> Response<Body>
 
     debug!(target: "stdout", "request:\n{}", serde_json::to_string_pretty(&model_response_request).unwrap());
 
-    let res = match responses::chat(&mut model_response_request).await {
+    // the background response is generated after the response is sent, and is polled by `GET /v1/responses/{id}`
+    if model_response_request.background == Some(true) {
+        let res = json_response(
+            responses::create_background_response(model_response_request),
+            "Failed to queue the background response",
+        );
+
+        info!(target: "stdout", "Send the Responses response");
+
+        return res;
+    }
+
+    // cancel the request if the handler is dropped, e.g. the client disconnected
+    let cancel = CancellationToken::new();
+    let guard = cancel.cancel_on_drop();
+
+    let res = match responses::chat_with_cancellation(&mut model_response_request, cancel).await {
         Ok((result, include_tool_calls)) => match result {
             either::Left(stream) => {
+                // the stream releases the model by itself when it is dropped
+                guard.disarm();
+
                 let stream = stream.map_err(|e| e.to_string());
 
                 let result = Response::builder()
@@ -1549,7 +1745,7 @@ pub(crate) async fn responses_handler(mut req: Request<Body>) -> Response<Body>
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            error::llama_core_error(&e, err_msg)
         }
     };
 
@@ -1558,3 +1754,1329 @@ pub(crate) async fn responses_handler(mut req: Request<Body>) -> Response<Body>
 
     res
 }
+
+/// Handle the requests for the stored responses:
+///
+/// - `GET /v1/responses/{id}` retrieves the response.
+/// - `DELETE /v1/responses/{id}` deletes the response.
+/// - `GET /v1/responses/{id}/input_items` lists the input items of the response.
+/// - `POST /v1/responses/{id}/cancel` cancels the response created in the background mode.
+pub(crate) async fn stored_responses_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming request for stored responses");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    let segments: Vec<&str> = match path.strip_prefix("/v1/responses/") {
+        Some(rest) => rest.split('/').collect(),
+        None => Vec::new(),
+    };
+    let res = match (req.method(), segments.as_slice()) {
+        (&Method::GET, [id]) => json_response(
+            store::retrieve_response(id),
+            &format!("Failed to retrieve the response `{id}`"),
+        ),
+        (&Method::DELETE, [id]) => json_response(
+            store::delete_response(id),
+            &format!("Failed to delete the response `{id}`"),
+        ),
+        (&Method::POST, [id, "cancel"]) => json_response(
+            responses::cancel_response(id),
+            &format!("Failed to cancel the response `{id}`"),
+        ),
+        (&Method::GET, [id, "input_items"]) => match parse_list_items_query(&req) {
+            Ok(query) => json_response(
+                store::list_input_items(id, &query),
+                &format!("Failed to list the input items of the response `{id}`"),
+            ),
+            Err(res) => res,
+        },
+        _ => {
+            let err_msg = format!("Invalid request: {} {}", req.method(), req.uri().path());
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::invalid_endpoint(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the response for stored responses");
+
+    res
+}
+
+/// Handle the requests for the conversations:
+///
+/// - `POST /v1/conversations` creates a conversation.
+/// - `GET /v1/conversations/{id}` retrieves the conversation.
+/// - `POST /v1/conversations/{id}` updates the metadata of the conversation.
+/// - `DELETE /v1/conversations/{id}` deletes the conversation.
+/// - `GET /v1/conversations/{id}/items` lists the items of the conversation.
+/// - `POST /v1/conversations/{id}/items` adds items to the conversation.
+/// - `GET /v1/conversations/{id}/items/{item_id}` retrieves an item of the conversation.
+/// - `DELETE /v1/conversations/{id}/items/{item_id}` deletes an item from the conversation.
+pub(crate) async fn conversations_handler(mut req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming conversations request");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    let method = req.method().clone();
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    let segments: Vec<&str> = match path.strip_prefix("/v1/conversations") {
+        Some("") => Vec::new(),
+        Some(rest) => rest[1..].split('/').collect(),
+        None => vec![""],
+    };
+    let res = match (&method, segments.as_slice()) {
+        (&Method::POST, []) => match read_json_body(&mut req, "create conversation").await {
+            Ok(request) => json_response(
+                store::create_conversation(request),
+                "Failed to create the conversation",
+            ),
+            Err(res) => res,
+        },
+        (&Method::GET, [id]) => json_response(
+            store::retrieve_conversation(id),
+            &format!("Failed to retrieve the conversation `{id}`"),
+        ),
+        (&Method::POST, [id]) => match read_json_body(&mut req, "update conversation").await {
+            Ok(request) => json_response(
+                store::update_conversation(id, request),
+                &format!("Failed to update the conversation `{id}`"),
+            ),
+            Err(res) => res,
+        },
+        (&Method::DELETE, [id]) => json_response(
+            store::delete_conversation(id),
+            &format!("Failed to delete the conversation `{id}`"),
+        ),
+        (&Method::GET, [id, "items"]) => match parse_list_items_query(&req) {
+            Ok(query) => json_response(
+                store::list_conversation_items(id, &query),
+                &format!("Failed to list the items of the conversation `{id}`"),
+            ),
+            Err(res) => res,
+        },
+        (&Method::POST, [id, "items"]) => {
+            match read_json_body(&mut req, "create conversation items").await {
+                Ok(request) => json_response(
+                    store::create_conversation_items(id, request),
+                    &format!("Failed to add the items to the conversation `{id}`"),
+                ),
+                Err(res) => res,
+            }
+        }
+        (&Method::GET, [id, "items", item_id]) => json_response(
+            store::retrieve_conversation_item(id, item_id),
+            &format!("Failed to retrieve the item `{item_id}` of the conversation `{id}`"),
+        ),
+        (&Method::DELETE, [id, "items", item_id]) => json_response(
+            store::delete_conversation_item(id, item_id),
+            &format!("Failed to delete the item `{item_id}` of the conversation `{id}`"),
+        ),
+        _ => {
+            let err_msg = format!("Invalid conversations request: {} {}", method, path);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::invalid_endpoint(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the conversations response");
+
+    res
+}
+
+/// Create images given a prompt.
+pub(crate) async fn image_generation_handler(mut req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming image generation request");
+
+    if let Err(res) = check_running_mode(
+        RunningMode::IMAGE,
+        "Image tasks are only supported in the image mode. Start the server with a stable diffusion model.",
+    ) {
+        return res;
+    }
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    // parse request
+    let body_bytes = match to_bytes(req.body_mut()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
+    let mut image_request: ImageCreateRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(image_request) => image_request,
+        Err(e) => {
+            let err_msg = format!("Fail to deserialize image create request: {e}.");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    let res = json_response(
+        llama_core::images::image_generation(&mut image_request).await,
+        "Failed to process the image generation request",
+    );
+
+    info!(target: "stdout", "Send the image generation response");
+
+    res
+}
+
+/// Create an edited or extended image given an original image and a prompt. The request is a `multipart/form-data` form.
+pub(crate) async fn image_edit_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming image edit request");
+
+    if let Err(res) = check_running_mode(
+        RunningMode::IMAGE,
+        "Image tasks are only supported in the image mode. Start the server with a stable diffusion model.",
+    ) {
+        return res;
+    }
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    let mut multipart = match read_multipart(req).await {
+        Ok(multipart) => multipart,
+        Err(res) => return res,
+    };
+
+    let mut image_request = ImageEditRequest::default();
+    let mut image = None;
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        let name = field.headers.name.to_string();
+        match name.as_str() {
+            "image" | "mask" | "control_image" => {
+                let file_object = match archive_form_file(field.headers.filename, &mut field.data) {
+                    Ok(file_object) => file_object,
+                    Err(res) => return res,
+                };
+
+                match name.as_str() {
+                    "image" => image = Some(file_object),
+                    "mask" => image_request.mask = Some(file_object),
+                    _ => image_request.control_image = Some(file_object),
+                }
+            }
+            _ => {
+                let value = match read_form_text(&name, &mut field.data) {
+                    Ok(value) => value,
+                    Err(res) => return res,
+                };
+
+                let parsed = match name.as_str() {
+                    "prompt" => {
+                        image_request.prompt = value;
+                        Ok(())
+                    }
+                    "negative_prompt" => {
+                        image_request.negative_prompt = Some(value);
+                        Ok(())
+                    }
+                    "model" => {
+                        image_request.model = value;
+                        Ok(())
+                    }
+                    "size" => {
+                        image_request.size = Some(value);
+                        Ok(())
+                    }
+                    "user" => {
+                        image_request.user = Some(value);
+                        Ok(())
+                    }
+                    "sample_method" => {
+                        image_request.sample_method = Some(SamplingMethod::from(value.as_str()));
+                        Ok(())
+                    }
+                    "scheduler" => {
+                        image_request.scheduler = Some(Scheduler::from(value.as_str()));
+                        Ok(())
+                    }
+                    "n" => parse_form_value(&name, &value).map(|v| image_request.n = Some(v)),
+                    "response_format" => parse_form_value(&name, &value)
+                        .map(|v| image_request.response_format = Some(v)),
+                    "cfg_scale" => {
+                        parse_form_value(&name, &value).map(|v| image_request.cfg_scale = Some(v))
+                    }
+                    "steps" => {
+                        parse_form_value(&name, &value).map(|v| image_request.steps = Some(v))
+                    }
+                    "height" => {
+                        parse_form_value(&name, &value).map(|v| image_request.height = Some(v))
+                    }
+                    "width" => {
+                        parse_form_value(&name, &value).map(|v| image_request.width = Some(v))
+                    }
+                    "control_strength" => parse_form_value(&name, &value)
+                        .map(|v| image_request.control_strength = Some(v)),
+                    "seed" => parse_form_value(&name, &value).map(|v| image_request.seed = Some(v)),
+                    "strength" => {
+                        parse_form_value(&name, &value).map(|v| image_request.strength = Some(v))
+                    }
+                    "apply_canny_preprocessor" => parse_form_value(&name, &value)
+                        .map(|v| image_request.apply_canny_preprocessor = Some(v)),
+                    "style_ratio" => {
+                        parse_form_value(&name, &value).map(|v| image_request.style_ratio = Some(v))
+                    }
+                    _ => {
+                        warn!(target: "stdout", "Not supported field: {name}");
+                        Ok(())
+                    }
+                };
+
+                if let Err(res) = parsed {
+                    return res;
+                }
+            }
+        }
+    }
+
+    match image {
+        Some(image) => image_request.image = image,
+        None => {
+            let err_msg = "The `image` field is required.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return error::bad_request(err_msg);
+        }
+    }
+    if image_request.prompt.is_empty() {
+        let err_msg = "The `prompt` field is required.";
+
+        // log
+        error!(target: "stdout", "{err_msg}");
+
+        return error::bad_request(err_msg);
+    }
+
+    let res = json_response(
+        llama_core::images::image_edit(&mut image_request).await,
+        "Failed to process the image edit request",
+    );
+
+    info!(target: "stdout", "Send the image edit response");
+
+    res
+}
+
+/// Create a variation of a given image. The request is a `multipart/form-data` form.
+pub(crate) async fn image_variation_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming image variation request");
+
+    if let Err(res) = check_running_mode(
+        RunningMode::IMAGE,
+        "Image tasks are only supported in the image mode. Start the server with a stable diffusion model.",
+    ) {
+        return res;
+    }
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    let mut multipart = match read_multipart(req).await {
+        Ok(multipart) => multipart,
+        Err(res) => return res,
+    };
+
+    let mut image_request = ImageVariationRequest::default();
+    let mut image = None;
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        let name = field.headers.name.to_string();
+        if name == "image" {
+            match archive_form_file(field.headers.filename, &mut field.data) {
+                Ok(file_object) => image = Some(file_object),
+                Err(res) => return res,
+            }
+
+            continue;
+        }
+
+        let value = match read_form_text(&name, &mut field.data) {
+            Ok(value) => value,
+            Err(res) => return res,
+        };
+
+        let parsed = match name.as_str() {
+            "model" => {
+                image_request.model = value;
+                Ok(())
+            }
+            "size" => {
+                image_request.size = Some(value);
+                Ok(())
+            }
+            "user" => {
+                image_request.user = Some(value);
+                Ok(())
+            }
+            "n" => parse_form_value(&name, &value).map(|v| image_request.n = Some(v)),
+            "response_format" => {
+                parse_form_value(&name, &value).map(|v| image_request.response_format = Some(v))
+            }
+            _ => {
+                warn!(target: "stdout", "Not supported field: {name}");
+                Ok(())
+            }
+        };
+
+        if let Err(res) = parsed {
+            return res;
+        }
+    }
+
+    match image {
+        Some(image) => image_request.image = image,
+        None => {
+            let err_msg = "The `image` field is required.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return error::bad_request(err_msg);
+        }
+    }
+
+    let res = json_response(
+        llama_core::images::image_variation(&mut image_request).await,
+        "Failed to process the image variation request",
+    );
+
+    info!(target: "stdout", "Send the image variation response");
+
+    res
+}
+
+/// Transcribe audio into the input language. The request is a `multipart/form-data` form.
+#[cfg(feature = "whisper")]
+pub(crate) async fn audio_transcriptions_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming audio transcription request");
+
+    if let Err(res) = check_running_mode(
+        RunningMode::AUDIO,
+        "Audio transcriptions are only supported in the audio mode. Start the server with a whisper model.",
+    ) {
+        return res;
+    }
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    let mut multipart = match read_multipart(req).await {
+        Ok(multipart) => multipart,
+        Err(res) => return res,
+    };
+
+    let mut request = TranscriptionRequest::default();
+    let mut file = None;
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        let name = field.headers.name.to_string();
+        if name == "file" {
+            match archive_form_file(field.headers.filename, &mut field.data) {
+                Ok(file_object) => file = Some(file_object),
+                Err(res) => return res,
+            }
+
+            continue;
+        }
+
+        let value = match read_form_text(&name, &mut field.data) {
+            Ok(value) => value,
+            Err(res) => return res,
+        };
+
+        let parsed = match name.as_str() {
+            "model" => {
+                request.model = Some(value);
+                Ok(())
+            }
+            "language" => {
+                request.language = Some(value);
+                Ok(())
+            }
+            "prompt" => {
+                request.prompt = Some(value);
+                Ok(())
+            }
+            "response_format" => {
+                request.response_format = Some(value);
+                Ok(())
+            }
+            "temperature" => parse_form_value(&name, &value).map(|v| request.temperature = Some(v)),
+            "detect_language" => {
+                parse_form_value(&name, &value).map(|v| request.detect_language = Some(v))
+            }
+            "offset_time" => parse_form_value(&name, &value).map(|v| request.offset_time = Some(v)),
+            "duration" => parse_form_value(&name, &value).map(|v| request.duration = Some(v)),
+            "max_context" => parse_form_value(&name, &value).map(|v| request.max_context = Some(v)),
+            "max_len" => parse_form_value(&name, &value).map(|v| request.max_len = Some(v)),
+            "split_on_word" => {
+                parse_form_value(&name, &value).map(|v| request.split_on_word = Some(v))
+            }
+            _ => {
+                warn!(target: "stdout", "Not supported field: {name}");
+                Ok(())
+            }
+        };
+
+        if let Err(res) = parsed {
+            return res;
+        }
+    }
+
+    match file {
+        Some(file) => request.file = file,
+        None => {
+            let err_msg = "The `file` field is required.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return error::bad_request(err_msg);
+        }
+    }
+
+    let res = json_response(
+        llama_core::audio::audio_transcriptions(request).await,
+        "Failed to transcribe the audio",
+    );
+
+    info!(target: "stdout", "Send the audio transcription response");
+
+    res
+}
+
+/// Translate audio into English. The request is a `multipart/form-data` form.
+#[cfg(feature = "whisper")]
+pub(crate) async fn audio_translations_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming audio translation request");
+
+    if let Err(res) = check_running_mode(
+        RunningMode::AUDIO,
+        "Audio translations are only supported in the audio mode. Start the server with a whisper model.",
+    ) {
+        return res;
+    }
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    let mut multipart = match read_multipart(req).await {
+        Ok(multipart) => multipart,
+        Err(res) => return res,
+    };
+
+    let mut request = TranslationRequest::default();
+    let mut file = None;
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        let name = field.headers.name.to_string();
+        if name == "file" {
+            match archive_form_file(field.headers.filename, &mut field.data) {
+                Ok(file_object) => file = Some(file_object),
+                Err(res) => return res,
+            }
+
+            continue;
+        }
+
+        let value = match read_form_text(&name, &mut field.data) {
+            Ok(value) => value,
+            Err(res) => return res,
+        };
+
+        let parsed = match name.as_str() {
+            "model" => {
+                request.model = Some(value);
+                Ok(())
+            }
+            "language" => {
+                request.language = Some(value);
+                Ok(())
+            }
+            "prompt" => {
+                request.prompt = Some(value);
+                Ok(())
+            }
+            "response_format" => {
+                request.response_format = Some(value);
+                Ok(())
+            }
+            "temperature" => parse_form_value(&name, &value).map(|v| request.temperature = Some(v)),
+            "detect_language" => {
+                parse_form_value(&name, &value).map(|v| request.detect_language = Some(v))
+            }
+            "offset_time" => parse_form_value(&name, &value).map(|v| request.offset_time = Some(v)),
+            "duration" => parse_form_value(&name, &value).map(|v| request.duration = Some(v)),
+            "max_context" => parse_form_value(&name, &value).map(|v| request.max_context = Some(v)),
+            "max_len" => parse_form_value(&name, &value).map(|v| request.max_len = Some(v)),
+            "split_on_word" => {
+                parse_form_value(&name, &value).map(|v| request.split_on_word = Some(v))
+            }
+            _ => {
+                warn!(target: "stdout", "Not supported field: {name}");
+                Ok(())
+            }
+        };
+
+        if let Err(res) = parsed {
+            return res;
+        }
+    }
+
+    match file {
+        Some(file) => request.file = file,
+        None => {
+            let err_msg = "The `file` field is required.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return error::bad_request(err_msg);
+        }
+    }
+
+    let res = json_response(
+        llama_core::audio::audio_translations(request).await,
+        "Failed to translate the audio",
+    );
+
+    info!(target: "stdout", "Send the audio translation response");
+
+    res
+}
+
+/// Load, replace and unload chat and embedding models at runtime.
+///
+/// - `POST /v1/admin/models` loads a model described by a [`LoadModelRequest`], or replaces the loaded model with the same name if `replace` is `true`.
+/// - `DELETE /v1/admin/models/{name}` unloads the model.
+///
+/// The requests must carry the admin API key set by the `ADMIN_API_KEY` environment variable, and the endpoints are disabled if it is not set.
+pub(crate) async fn admin_models_handler(mut req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming admin request for models");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        return options_response();
+    }
+
+    if let Err(res) = check_admin_api_key(&req) {
+        return res;
+    }
+
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    let res = match (req.method(), path.strip_prefix("/v1/admin/models")) {
+        (&Method::POST, Some("")) => {
+            // parse request
+            let body_bytes = match to_bytes(req.body_mut()).await {
+                Ok(body_bytes) => body_bytes,
+                Err(e) => {
+                    let err_msg = format!("Fail to read buffer from request body. {e}");
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return error::internal_server_error(err_msg);
+                }
+            };
+            let load_request: LoadModelRequest = match serde_json::from_slice(&body_bytes) {
+                Ok(load_request) => load_request,
+                Err(e) => {
+                    let err_msg = format!("Fail to deserialize load model request: {e}.");
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return error::bad_request(err_msg);
+                }
+            };
+
+            match load_model(load_request) {
+                Ok(model_config) => json_response(Ok(model_config), ""),
+                Err(res) => res,
+            }
+        }
+        (&Method::DELETE, Some(name)) if name.len() > 1 => unload_model(&name[1..]),
+        _ => {
+            let err_msg = format!(
+                "Invalid admin request: {} {}",
+                req.method(),
+                req.uri().path()
+            );
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::invalid_endpoint(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the admin response for models");
+
+    res
+}
+
+/// Type of the models managed by the admin endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AdminModelType {
+    Chat,
+    Embedding,
+}
+
+/// Request body of `POST /v1/admin/models`.
+#[derive(Debug, Deserialize)]
+struct LoadModelRequest {
+    /// Type of the model: `chat` or `embedding`.
+    #[serde(rename = "type")]
+    ty: AdminModelType,
+    /// Name of the model, which is used in the `model` field of the requests.
+    name: String,
+    /// Alias of the model preloaded by `--nn-preload`. Defaults to `name`. Ignored if `path` is set.
+    alias: Option<String>,
+    /// Path to the model file. If not set, the model preloaded with the alias is loaded.
+    path: Option<String>,
+    /// Prompt template of the chat model, given by the name used on the command line. Required for chat models.
+    prompt_template: Option<String>,
+    /// Path to the chat template rendered by the `jinja` prompt template: a GGUF model file, a `tokenizer_config.json` file or a Jinja file. Defaults to `path` for the `jinja` prompt template.
+    chat_template: Option<String>,
+    /// Path to the GGUF file of the chat model, whose metadata is read to detect the prompt template. Defaults to `path`. Required for the `auto` prompt template if `path` is not set.
+    model_file: Option<String>,
+    ctx_size: Option<u64>,
+    batch_size: Option<u64>,
+    ubatch_size: Option<u64>,
+    n_predict: Option<i32>,
+    n_gpu_layers: Option<u64>,
+    reverse_prompt: Option<String>,
+    /// Maximum number of requests waiting for the chat model.
+    max_queued_requests: Option<usize>,
+    /// Number of requests the chat model generates at the same time.
+    parallel_slots: Option<usize>,
+    /// How the chat history is shortened if the prompt does not fit in the context window.
+    truncation: Option<TruncationStrategy>,
+    /// Whether to reuse the KV cache of the previous prompt on the same slot of the chat model.
+    #[serde(default)]
+    cache_prompt: bool,
+    /// Replace the loaded model with the same name instead of failing.
+    #[serde(default)]
+    replace: bool,
+}
+
+/// Loads the model described by the request, and updates the server info.
+#[allow(clippy::result_large_err)]
+fn load_model(load_request: LoadModelRequest) -> Result<ModelConfig, Response<Body>> {
+    info!(target: "stdout", "Load the {:?} model named {}", load_request.ty, &load_request.name);
+
+    let prompt_template = match load_request.ty {
+        AdminModelType::Chat => match load_request
+            .prompt_template
+            .as_deref()
+            .map(str::parse::<PromptTemplateType>)
+        {
+            Some(Err(e)) => {
+                let err_msg = format!("Failed to parse the `prompt_template` field. {e}");
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(error::bad_request(err_msg));
+            }
+            Some(Ok(PromptTemplateType::Embedding)) | None => {
+                let err_msg = "The `prompt_template` field is required for chat models, and must not be `embedding`.";
+
+                // log
+                error!(target: "stdout", "{err_msg}");
+
+                return Err(error::bad_request(err_msg));
+            }
+            Some(Ok(prompt_template)) => prompt_template,
+        },
+        AdminModelType::Embedding => PromptTemplateType::Embedding,
+    };
+    if let PromptTemplateType::Custom(name) = prompt_template {
+        if chat_prompts::chat::custom_template(name).is_none() {
+            let err_msg = format!("The custom prompt template `{name}` is not declared.");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::bad_request(err_msg));
+        }
+    }
+
+    // the chat template of the `jinja` prompt template is read from the model file by default
+    let chat_template_path = match prompt_template {
+        PromptTemplateType::Jinja => load_request
+            .chat_template
+            .as_ref()
+            .or(load_request.path.as_ref()),
+        _ => load_request.chat_template.as_ref(),
+    };
+    let chat_template = match chat_template_path {
+        Some(path) => match llama_core::utils::load_chat_template(path) {
+            Ok(chat_template) => Some(chat_template),
+            Err(e) => {
+                let err_msg = format!(
+                    "Failed to load the model `{}`. Reason: {e}",
+                    &load_request.name
+                );
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(error::bad_request(err_msg));
+            }
+        },
+        None if prompt_template == PromptTemplateType::Jinja => {
+            let err_msg =
+                "The `jinja` prompt template requires the `chat_template` or `path` field.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(error::bad_request(err_msg));
+        }
+        None => None,
+    };
+
+    // the prompt template of the chat model is detected from the model file by default
+    let model_file = match load_request.ty {
+        AdminModelType::Chat => load_request
+            .model_file
+            .as_ref()
+            .or(load_request.path.as_ref())
+            .map(PathBuf::from),
+        AdminModelType::Embedding => None,
+    };
+    if prompt_template == PromptTemplateType::Auto && model_file.is_none() {
+        let err_msg = "The `auto` prompt template requires the `model_file` or `path` field.";
+
+        // log
+        error!(target: "stdout", "{err_msg}");
+
+        return Err(error::bad_request(err_msg));
+    }
+
+    let alias = load_request
+        .alias
+        .clone()
+        .unwrap_or_else(|| load_request.name.clone());
+    let mut builder = GgmlMetadataBuilder::new(load_request.name.clone(), alias, prompt_template)
+        .with_reverse_prompt(load_request.reverse_prompt.clone())
+        .with_chat_template(chat_template)
+        .with_model_file(model_file)
+        .enable_plugin_log(true)
+        .enable_debug_log(log::max_level() >= log::LevelFilter::Debug);
+    if let Some(ctx_size) = load_request.ctx_size {
+        builder = builder.with_ctx_size(ctx_size);
+    }
+    if let Some(batch_size) = load_request.batch_size {
+        builder = builder.with_batch_size(batch_size);
+    }
+    if let Some(ubatch_size) = load_request.ubatch_size {
+        builder = builder.with_ubatch_size(ubatch_size);
+    }
+    if let Some(n_predict) = load_request.n_predict {
+        builder = builder.with_n_predict(n_predict);
+    }
+    if let Some(n_gpu_layers) = load_request.n_gpu_layers {
+        builder = builder.with_n_gpu_layers(n_gpu_layers);
+    }
+    if let Some(truncation) = load_request.truncation {
+        builder = builder.with_truncation_strategy(truncation);
+    }
+    let metadata = builder
+        .enable_prompt_cache(load_request.cache_prompt)
+        .build();
+
+    let err_context = format!("Failed to load the model `{}`", &load_request.name);
+    let core_error = |e: LlamaCoreError| {
+        let err_msg = format!("{err_context}. Reason: {e}");
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        error::llama_core_error(&e, err_msg)
+    };
+
+    // load the model from the file, or from the preloaded models
+    let graph = match &load_request.path {
+        Some(path) => GraphBuilder::new(EngineType::Ggml)
+            .and_then(|builder| builder.with_config(metadata.clone()))
+            .and_then(|builder| builder.build_from_files([path])),
+        None => Graph::new(metadata.clone()),
+    }
+    .map_err(core_error)?;
+
+    let (ty, result) = match (load_request.ty, load_request.replace) {
+        (AdminModelType::Chat, false) => ("chat", models::add_chat_graph(graph)),
+        (AdminModelType::Chat, true) => ("chat", models::replace_chat_graph(graph).map(|_| ())),
+        (AdminModelType::Embedding, false) => ("embedding", models::add_embedding_graph(graph)),
+        (AdminModelType::Embedding, true) => (
+            "embedding",
+            models::replace_embedding_graph(graph).map(|_| ()),
+        ),
+    };
+    result.map_err(core_error)?;
+
+    if load_request.ty == AdminModelType::Chat {
+        llama_core::scheduler::set_max_queued_requests(
+            metadata.model_name.clone(),
+            load_request.max_queued_requests,
+        )
+        .map_err(core_error)?;
+
+        if let Some(parallel_slots) = load_request.parallel_slots {
+            llama_core::scheduler::set_parallel_slots(metadata.model_name.clone(), parallel_slots)
+                .map_err(core_error)?;
+        }
+    }
+
+    let mut model_config = ModelConfig::new(&metadata, ty);
+    // the prompt template detected when the chat model is added
+    if load_request.ty == AdminModelType::Chat {
+        let prompt_templates = models::chat_prompt_templates().map_err(core_error)?;
+        if let Some((prompt_template, detected_prompt_template)) =
+            prompt_templates.get(&metadata.model_name)
+        {
+            model_config.prompt_template = Some(*prompt_template);
+            model_config.detected_prompt_template = *detected_prompt_template;
+        }
+    }
+    update_server_info(|server_info| server_info.set_model(model_config.clone()))?;
+
+    info!(target: "stdout", "Loaded the {} model named {}", ty, &metadata.model_name);
+
+    Ok(model_config)
+}
+
+/// Unloads the chat or embedding model with the given name, and updates the server info. Chat models are looked up first.
+fn unload_model(name: &str) -> Response<Body> {
+    info!(target: "stdout", "Unload the model named {name}");
+
+    let result = match models::remove_chat_graph(name) {
+        Ok(_) => Ok("chat"),
+        Err(LlamaCoreError::ModelNotFound(_)) => {
+            models::remove_embedding_graph(name).map(|_| "embedding")
+        }
+        Err(e) => Err(e),
+    };
+
+    let ty = match result {
+        Ok(ty) => ty,
+        Err(e) => {
+            let err_msg = format!("Failed to unload the model `{name}`. Reason: {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::llama_core_error(&e, err_msg);
+        }
+    };
+
+    if let Err(res) = update_server_info(|server_info| server_info.remove_model(ty, name)) {
+        return res;
+    }
+
+    info!(target: "stdout", "Unloaded the {ty} model named {name}");
+
+    json_response(
+        Ok(serde_json::json!({
+            "id": name,
+            "object": "model",
+            "deleted": true,
+        })),
+        "",
+    )
+}
+
+/// Returns an error response if the request does not carry the admin API key.
+#[allow(clippy::result_large_err)]
+fn check_admin_api_key(req: &Request<Body>) -> Result<(), Response<Body>> {
+    let admin_api_key = match ADMIN_API_KEY.get() {
+        Some(admin_api_key) => admin_api_key,
+        None => {
+            let err_msg = "The admin endpoints are disabled. Set the `ADMIN_API_KEY` environment variable to enable them.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(error::forbidden(err_msg));
+        }
+    };
+
+    let api_key = req
+        .headers()
+        .get("authorization")
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_header| auth_header.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // compare in constant time, so that the time of the response does not reveal how much of the key matches
+    if !bool::from(api_key.as_bytes().ct_eq(admin_api_key.as_bytes())) {
+        let err_msg = "Invalid admin API key.";
+
+        // log
+        error!(target: "stdout", "{err_msg}");
+
+        return Err(error::unauthorized(err_msg));
+    }
+
+    Ok(())
+}
+
+/// Applies the change to the server info.
+#[allow(clippy::result_large_err)]
+fn update_server_info(f: impl FnOnce(&mut ApiServer)) -> Result<(), Response<Body>> {
+    let server_info = match SERVER_INFO.get() {
+        Some(server_info) => server_info,
+        None => {
+            let err_msg = "The server info is not set.";
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    match server_info.write() {
+        Ok(mut server_info) => {
+            f(&mut server_info);
+
+            Ok(())
+        }
+        Err(e) => {
+            let err_msg = format!("Fail to acquire the lock of the server info. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(error::internal_server_error(err_msg))
+        }
+    }
+}
+
+/// Returns an error response if the models required by the request are not loaded.
+#[allow(clippy::result_large_err)]
+fn check_running_mode(required: RunningMode, err_msg: &str) -> Result<(), Response<Body>> {
+    let running_mode = match llama_core::running_mode() {
+        Ok(mode) => mode,
+        Err(e) => {
+            let err_msg = format!("Failed to get running mode: {e}");
+
+            error!(target: "stdout", "{e}");
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+    if !running_mode.contains(required) {
+        error!(target: "stdout", "{err_msg}");
+
+        return Err(error::internal_server_error(err_msg));
+    }
+
+    Ok(())
+}
+
+fn options_response() -> Response<Body> {
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", "application/json")
+        .body(Body::empty());
+
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    }
+}
+
+/// Reads the JSON body of a request. `name` is the name of the request in the error messages.
+#[allow(clippy::result_large_err)]
+async fn read_json_body<T: DeserializeOwned>(
+    req: &mut Request<Body>,
+    name: &str,
+) -> Result<T, Response<Body>> {
+    let body_bytes = match to_bytes(req.body_mut()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    serde_json::from_slice(&body_bytes).map_err(|e| {
+        let err_msg = format!("Fail to deserialize {name} request: {e}.");
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        error::bad_request(err_msg)
+    })
+}
+
+/// Parses the `after`, `limit` and `order` query parameters of the requests listing items.
+#[allow(clippy::result_large_err)]
+fn parse_list_items_query(req: &Request<Body>) -> Result<ListItemsQuery, Response<Body>> {
+    let mut query = ListItemsQuery::default();
+    for (key, value) in req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+    {
+        match key {
+            "after" => query.after = Some(value.to_string()),
+            "limit" => query.limit = Some(parse_form_value(key, value)?),
+            "order" => query.order = Some(parse_form_value(key, value)?),
+            _ => {}
+        }
+    }
+
+    Ok(query)
+}
+
+/// Reads the body of a `multipart/form-data` request.
+async fn read_multipart(req: Request<Body>) -> Result<Multipart<Cursor<Vec<u8>>>, Response<Body>> {
+    let boundary = "boundary=";
+
+    let boundary = req.headers().get("content-type").and_then(|ct| {
+        let ct = ct.to_str().ok()?;
+        let idx = ct.find(boundary)?;
+        Some(ct[idx + boundary.len()..].to_string())
+    });
+    let boundary = match boundary {
+        Some(boundary) => boundary,
+        None => {
+            let err_msg = "The request should be a `multipart/form-data` form with a boundary.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(error::bad_request(err_msg));
+        }
+    };
+
+    let body_bytes = match to_bytes(req.into_body()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    Ok(Multipart::with_body(
+        Cursor::new(body_bytes.to_vec()),
+        boundary,
+    ))
+}
+
+/// Saves a file uploaded in a multipart form to the archives, and returns its file object.
+#[allow(clippy::result_large_err)]
+fn archive_form_file(
+    filename: Option<String>,
+    data: &mut impl Read,
+) -> Result<FileObject, Response<Body>> {
+    let filename = match filename {
+        Some(filename) => filename,
+        None => {
+            let err_msg = "Failed to upload the file. The filename is not provided.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(error::bad_request(err_msg));
+        }
+    };
+
+    let mut buffer = Vec::new();
+    let size_in_bytes = match data.read_to_end(&mut buffer) {
+        Ok(size_in_bytes) => size_in_bytes,
+        Err(e) => {
+            let err_msg = format!("Failed to read the uploaded file. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    // create a unique file id
+    let id = format!("file_{}", uuid::Uuid::new_v4());
+
+    // save the file
+    let file_path = Path::new(ARCHIVES_DIR).join(&id);
+    if let Err(e) = fs::create_dir_all(&file_path) {
+        let err_msg = format!("Failed to create the archive directory {id}. {e}");
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(error::internal_server_error(err_msg));
+    }
+    if let Err(e) = fs::write(file_path.join(&filename), &buffer) {
+        let err_msg = format!("Failed to create archive document {}. {}", &filename, e);
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(error::internal_server_error(err_msg));
+    }
+
+    // log
+    info!(target: "stdout", "file_id: {}, file_name: {}", &id, &filename);
+
+    let created_at = match SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => {
+            let err_msg = "Failed to get the current time.";
+
+            // log
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    Ok(FileObject {
+        id,
+        bytes: size_in_bytes as u64,
+        created_at,
+        filename,
+        object: "file".to_string(),
+        purpose: "assistants".to_string(),
+    })
+}
+
+/// Reads the value of a text field in a multipart form.
+#[allow(clippy::result_large_err)]
+fn read_form_text(name: &str, data: &mut impl Read) -> Result<String, Response<Body>> {
+    let mut value = String::new();
+    match data.read_to_string(&mut value) {
+        Ok(_) => Ok(value),
+        Err(e) => {
+            let err_msg = format!("Failed to read the `{name}` field. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(error::bad_request(err_msg))
+        }
+    }
+}
+
+/// Parses the value of a text field in a multipart form, or of a query parameter.
+#[allow(clippy::result_large_err)]
+fn parse_form_value<T>(name: &str, value: &str) -> Result<T, Response<Body>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.trim().parse().map_err(|e| {
+        let err_msg = format!("Invalid value of the `{name}` field: {value}. {e}");
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        error::bad_request(err_msg)
+    })
+}
+
+/// Returns the JSON response of the result of `llama-core`.
+fn json_response<T: Serialize>(result: Result<T, LlamaCoreError>, context: &str) -> Response<Body> {
+    let obj = match result {
+        Ok(obj) => obj,
+        Err(e) => {
+            let err_msg = format!("{context}. Reason: {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::llama_core_error(&e, err_msg);
+        }
+    };
+
+    // serialize response
+    let s = match serde_json::to_string(&obj) {
+        Ok(s) => s,
+        Err(e) => {
+            let err_msg = format!("Fail to serialize the response. {e}");
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
+
+    // return response
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", "application/json")
+        .body(Body::from(s));
+
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    }
+}
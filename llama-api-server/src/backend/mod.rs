@@ -11,11 +11,24 @@ pub(crate) async fn handle_llama_request(req: Request<Body>) -> Response<Body> {
         "/v1/embeddings" => ggml::embeddings_handler(req).await,
         "/v1/chunks" => ggml::chunks_handler(req).await,
         "/v1/audio/speech" => ggml::audio_speech_handler(req).await,
+        #[cfg(feature = "whisper")]
+        "/v1/audio/transcriptions" => ggml::audio_transcriptions_handler(req).await,
+        #[cfg(feature = "whisper")]
+        "/v1/audio/translations" => ggml::audio_translations_handler(req).await,
         "/v1/info" => ggml::server_info_handler().await,
         "/v1/responses" => ggml::responses_handler(req).await,
+        "/v1/images/generations" => ggml::image_generation_handler(req).await,
+        "/v1/images/edits" => ggml::image_edit_handler(req).await,
+        "/v1/images/variations" => ggml::image_variation_handler(req).await,
         path => {
             if path.starts_with("/v1/files") {
                 ggml::files_handler(req).await
+            } else if path.starts_with("/v1/responses/") {
+                ggml::stored_responses_handler(req).await
+            } else if path == "/v1/conversations" || path.starts_with("/v1/conversations/") {
+                ggml::conversations_handler(req).await
+            } else if path == "/v1/admin/models" || path.starts_with("/v1/admin/models/") {
+                ggml::admin_models_handler(req).await
             } else {
                 error!(target: "stdout", "Invalid endpoint: {path}");
 
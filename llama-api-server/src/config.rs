@@ -1,7 +1,10 @@
 use crate::ServerError;
-use chat_prompts::PromptTemplateType;
+use chat_prompts::{chat::CustomTemplate, PromptTemplateType};
+use endpoints::chat::TruncationStrategy;
+use llama_core::StableDiffusionTask;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs,
     net::SocketAddr,
     path::{Path, PathBuf},
@@ -10,9 +13,20 @@ use std::{
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct Config {
     pub(crate) server: ServerConfig,
-    pub(crate) chat: ChatConfig,
-    pub(crate) embedding: EmbeddingConfig,
+    #[serde(deserialize_with = "one_or_many")]
+    pub(crate) chat: Vec<ChatConfig>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub(crate) embedding: Vec<EmbeddingConfig>,
     pub(crate) tts: TtsConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) image: Option<ImageConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) whisper: Option<WhisperConfig>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) pool: Option<PoolConfig>,
+    /// The custom prompt templates declared as `[templates.<name>]` tables, which are used with the `custom:<name>` prompt template.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub(crate) templates: BTreeMap<String, CustomTemplate>,
 }
 impl Config {
     pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self, ServerError> {
@@ -24,6 +38,63 @@ impl Config {
     }
 }
 
+/// The custom prompt templates declared as `[templates.<name>]` tables of a TOML file, which is given by `--custom-templates`.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct CustomTemplates {
+    #[serde(default)]
+    pub(crate) templates: BTreeMap<String, CustomTemplate>,
+}
+impl CustomTemplates {
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self, ServerError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to read the custom templates from {}. {e}",
+                path.display()
+            ))
+        })?;
+        toml::from_str(&content).map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to parse the custom templates from {}. {e}",
+                path.display()
+            ))
+        })
+    }
+}
+
+/// Registers the custom prompt templates, so the chat models can use them with the `custom:<name>` prompt template.
+pub(crate) fn register_custom_templates(
+    templates: &BTreeMap<String, CustomTemplate>,
+) -> Result<(), ServerError> {
+    for (name, template) in templates {
+        chat_prompts::chat::register_custom_template(name, template.clone())
+            .map_err(|e| ServerError::ArgumentError(e.to_string()))?;
+
+        info!(target: "stdout", "custom prompt template: custom:{name}");
+    }
+
+    Ok(())
+}
+
+/// Deserializes a section of models from a single table, e.g. `[chat]`, or an array of tables, e.g. `[[chat]]`.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    use serde::de::Error;
+
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::Array(values) => values
+            .into_iter()
+            .map(|value| T::deserialize(value).map_err(Error::custom))
+            .collect(),
+        value => T::deserialize(value)
+            .map(|model| vec![model])
+            .map_err(Error::custom),
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub(crate) struct ServerConfig {
     pub(crate) socket_addr: SocketAddr,
@@ -67,6 +138,12 @@ pub(crate) struct ChatConfig {
     pub(crate) batch_size: u64,
     pub(crate) ubatch_size: u64,
     pub(crate) prompt_template: PromptTemplateType,
+    // path to the chat template rendered by the `jinja` prompt template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) chat_template: Option<PathBuf>,
+    // path to the GGUF file of the model, whose metadata is read to detect the prompt template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) model_file: Option<PathBuf>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) reverse_prompt: Option<String>,
     pub(crate) n_predict: i32,
@@ -77,7 +154,8 @@ pub(crate) struct ChatConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) tensor_split: Option<String>,
     pub(crate) threads: u64,
-    pub(crate) no_mmap: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) no_mmap: Option<bool>,
     pub(crate) temp: f64,
     pub(crate) top_p: f64,
     pub(crate) repeat_penalty: f64,
@@ -90,6 +168,15 @@ pub(crate) struct ChatConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) llava_mmproj: Option<PathBuf>,
     pub(crate) include_usage: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_queued_requests: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) parallel_slots: Option<usize>,
+    // estimated memory of the model in MiB, checked against the memory budget of the model pool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) model_size: Option<u64>,
+    pub(crate) truncation: TruncationStrategy,
+    pub(crate) cache_prompt: bool,
 }
 impl Default for ChatConfig {
     fn default() -> Self {
@@ -100,6 +187,8 @@ impl Default for ChatConfig {
             batch_size: 512,
             ubatch_size: 512,
             prompt_template: PromptTemplateType::Null,
+            chat_template: None,
+            model_file: None,
             reverse_prompt: None,
             n_predict: -1,
             n_gpu_layers: 100,
@@ -107,7 +196,7 @@ impl Default for ChatConfig {
             main_gpu: None,
             tensor_split: None,
             threads: 2,
-            no_mmap: true,
+            no_mmap: Some(true),
             temp: 1.0,
             top_p: 1.0,
             repeat_penalty: 1.1,
@@ -117,6 +206,11 @@ impl Default for ChatConfig {
             json_schema: None,
             llava_mmproj: None,
             include_usage: false,
+            max_queued_requests: None,
+            parallel_slots: None,
+            model_size: None,
+            truncation: TruncationStrategy::default(),
+            cache_prompt: false,
         }
     }
 }
@@ -136,6 +230,10 @@ impl<'de> Deserialize<'de> for ChatConfig {
             batch_size: u64,
             ubatch_size: u64,
             prompt_template: String,
+            #[serde(default)]
+            chat_template: Option<String>,
+            #[serde(default)]
+            model_file: Option<String>,
             reverse_prompt: Option<String>,
             n_predict: i32,
             n_gpu_layers: u64,
@@ -153,6 +251,12 @@ impl<'de> Deserialize<'de> for ChatConfig {
             json_schema: Option<String>,
             llava_mmproj: Option<String>,
             include_usage: bool,
+            max_queued_requests: Option<usize>,
+            parallel_slots: Option<usize>,
+            model_size: Option<u64>,
+            truncation: Option<String>,
+            #[serde(default)]
+            cache_prompt: bool,
         }
 
         let helper = Helper::deserialize(deserializer)?;
@@ -167,6 +271,14 @@ impl<'de> Deserialize<'de> for ChatConfig {
                 ))
             })?;
 
+        // truncation
+        let truncation = match helper.truncation {
+            Some(truncation) => truncation.parse::<TruncationStrategy>().map_err(|e| {
+                Error::custom(format!("Failed to parse truncation from config file: {e}"))
+            })?,
+            None => TruncationStrategy::default(),
+        };
+
         // grammar
         let grammar = helper.grammar.filter(|grammar| !grammar.is_empty());
 
@@ -193,6 +305,14 @@ impl<'de> Deserialize<'de> for ChatConfig {
             batch_size: helper.batch_size,
             ubatch_size: helper.ubatch_size,
             prompt_template,
+            chat_template: helper
+                .chat_template
+                .filter(|chat_template| !chat_template.is_empty())
+                .map(PathBuf::from),
+            model_file: helper
+                .model_file
+                .filter(|model_file| !model_file.is_empty())
+                .map(PathBuf::from),
             reverse_prompt: helper.reverse_prompt,
             n_predict: helper.n_predict,
             n_gpu_layers: helper.n_gpu_layers,
@@ -200,7 +320,7 @@ impl<'de> Deserialize<'de> for ChatConfig {
             main_gpu: helper.main_gpu,
             tensor_split: helper.tensor_split,
             threads: helper.threads,
-            no_mmap: helper.no_mmap,
+            no_mmap: Some(helper.no_mmap),
             temp: helper.temp,
             top_p: helper.top_p,
             repeat_penalty: helper.repeat_penalty,
@@ -210,6 +330,11 @@ impl<'de> Deserialize<'de> for ChatConfig {
             json_schema,
             llava_mmproj,
             include_usage: helper.include_usage,
+            max_queued_requests: helper.max_queued_requests,
+            parallel_slots: helper.parallel_slots,
+            model_size: helper.model_size,
+            truncation,
+            cache_prompt: helper.cache_prompt,
         })
     }
 }
@@ -354,3 +479,194 @@ impl<'de> Deserialize<'de> for TtsConfig {
         })
     }
 }
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ImageConfig {
+    pub(crate) model_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) model: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) diffusion_model: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) vae: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) clip_l: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) t5xxl: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) lora_model_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) control_net: Option<PathBuf>,
+    pub(crate) control_net_on_cpu: bool,
+    pub(crate) clip_on_cpu: bool,
+    pub(crate) vae_on_cpu: bool,
+    pub(crate) threads: i32,
+    pub(crate) task: StableDiffusionTask,
+}
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig {
+            model_name: "image".to_string(),
+            model: None,
+            diffusion_model: None,
+            vae: None,
+            clip_l: None,
+            t5xxl: None,
+            lora_model_dir: None,
+            control_net: None,
+            control_net_on_cpu: false,
+            clip_on_cpu: false,
+            vae_on_cpu: false,
+            threads: 2,
+            task: StableDiffusionTask::Full,
+        }
+    }
+}
+impl<'de> Deserialize<'de> for ImageConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        #[serde(rename = "image")]
+        struct Helper {
+            model_name: String,
+            model: String,
+            diffusion_model: String,
+            vae: String,
+            clip_l: String,
+            t5xxl: String,
+            lora_model_dir: String,
+            control_net: String,
+            control_net_on_cpu: bool,
+            clip_on_cpu: bool,
+            vae_on_cpu: bool,
+            threads: i32,
+            task: String,
+        }
+
+        let helper = Helper::deserialize(deserializer)?;
+
+        // empty paths mean not set
+        let path = |p: String| (!p.is_empty()).then(|| PathBuf::from(p));
+
+        let model = path(helper.model);
+        let diffusion_model = path(helper.diffusion_model);
+        if model.is_none() && diffusion_model.is_none() {
+            return Err(Error::custom(
+                "Either `model` or `diffusion_model` should be set in the image section of the config file",
+            ));
+        }
+
+        // task
+        let task = helper
+            .task
+            .parse::<StableDiffusionTask>()
+            .map_err(|e| Error::custom(format!("Failed to parse task from config file: {e}")))?;
+
+        Ok(ImageConfig {
+            model_name: helper.model_name,
+            model,
+            diffusion_model,
+            vae: path(helper.vae),
+            clip_l: path(helper.clip_l),
+            t5xxl: path(helper.t5xxl),
+            lora_model_dir: path(helper.lora_model_dir),
+            control_net: path(helper.control_net),
+            control_net_on_cpu: helper.control_net_on_cpu,
+            clip_on_cpu: helper.clip_on_cpu,
+            vae_on_cpu: helper.vae_on_cpu,
+            threads: helper.threads,
+            task,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct WhisperConfig {
+    pub(crate) model_name: String,
+    pub(crate) model_alias: String,
+    pub(crate) model: PathBuf,
+    pub(crate) threads: u64,
+    pub(crate) language: String,
+    pub(crate) detect_language: bool,
+    pub(crate) temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prompt: Option<String>,
+}
+impl Default for WhisperConfig {
+    fn default() -> Self {
+        WhisperConfig {
+            model_name: "whisper".to_string(),
+            model_alias: "whisper".to_string(),
+            model: PathBuf::from(""),
+            threads: 4,
+            language: "en".to_string(),
+            detect_language: false,
+            temperature: 0.0,
+            prompt: None,
+        }
+    }
+}
+impl<'de> Deserialize<'de> for WhisperConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        #[serde(rename = "whisper")]
+        struct Helper {
+            model_name: String,
+            model_alias: String,
+            model: String,
+            threads: u64,
+            language: String,
+            detect_language: bool,
+            temperature: f64,
+            prompt: String,
+        }
+
+        let helper = Helper::deserialize(deserializer)?;
+
+        if helper.model.is_empty() {
+            return Err(Error::custom(
+                "The `model` field of the `whisper` section is required.",
+            ));
+        }
+
+        let prompt = if helper.prompt.is_empty() {
+            None
+        } else {
+            Some(helper.prompt)
+        };
+
+        Ok(WhisperConfig {
+            model_name: helper.model_name,
+            model_alias: helper.model_alias,
+            model: PathBuf::from(helper.model),
+            threads: helper.threads,
+            language: helper.language,
+            detect_language: helper.detect_language,
+            temperature: helper.temperature,
+            prompt,
+        })
+    }
+}
+
+/// The limits of the pool of chat models. If the `pool` section is set, the chat models are loaded on their first requests instead of at startup.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct PoolConfig {
+    // maximum number of chat models loaded at the same time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_resident_models: Option<usize>,
+    // maximum estimated memory in MiB of the chat models loaded at the same time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) memory_budget: Option<u64>,
+    // time in seconds after which an idle chat model is unloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) idle_timeout: Option<u64>,
+}
@@ -1,93 +1,168 @@
-use hyper::{Body, Response};
+use chat_prompts::error::PromptError;
+use hyper::{Body, Response, StatusCode};
+use llama_core::error::LlamaCoreError;
 use thiserror::Error;
 
-#[allow(dead_code)]
-pub(crate) fn not_implemented() -> Response<Body> {
-    // log error
-    error!(target: "stdout", "501 Not Implemented");
-
-    Response::builder()
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "*")
-        .header("Access-Control-Allow-Headers", "*")
-        .status(hyper::StatusCode::NOT_IMPLEMENTED)
-        .body(Body::from("501 Not Implemented"))
-        .unwrap()
-}
-
-pub(crate) fn internal_server_error(msg: impl AsRef<str>) -> Response<Body> {
-    let err_msg = match msg.as_ref().is_empty() {
-        true => "500 Internal Server Error".to_string(),
-        false => format!("500 Internal Server Error: {}", msg.as_ref()),
+/// Builds an error response whose body is an OpenAI-compatible error object, i.e. `{"error": {"message", "type", "param", "code"}}`.
+fn error_response(
+    status: StatusCode,
+    ty: &str,
+    param: Option<&str>,
+    code: Option<&str>,
+    msg: impl AsRef<str>,
+) -> Response<Body> {
+    let reason = status.canonical_reason().unwrap_or_default();
+    let message = match msg.as_ref().is_empty() {
+        true => reason.to_string(),
+        false => msg.as_ref().to_string(),
     };
 
     // log error
-    error!(target: "stdout", "{}", &err_msg);
+    error!(target: "stdout", "{} {}: {}", status.as_u16(), reason, &message);
+
+    let body = serde_json::json!({
+        "error": {
+            "message": message,
+            "type": ty,
+            "param": param,
+            "code": code,
+        }
+    });
 
     Response::builder()
         .header("Access-Control-Allow-Origin", "*")
         .header("Access-Control-Allow-Methods", "*")
         .header("Access-Control-Allow-Headers", "*")
-        .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-        .body(Body::from(err_msg))
+        .header("Content-Type", "application/json")
+        .status(status)
+        .body(Body::from(body.to_string()))
         .unwrap()
 }
 
-pub(crate) fn bad_request(msg: impl AsRef<str>) -> Response<Body> {
-    let err_msg = match msg.as_ref().is_empty() {
-        true => "400 Bad Request".to_string(),
-        false => format!("400 Bad Request: {}", msg.as_ref()),
-    };
+#[allow(dead_code)]
+pub(crate) fn not_implemented() -> Response<Body> {
+    error_response(StatusCode::NOT_IMPLEMENTED, "server_error", None, None, "")
+}
 
-    // log error
-    error!(target: "stdout", "{}", &err_msg);
+pub(crate) fn internal_server_error(msg: impl AsRef<str>) -> Response<Body> {
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        None,
+        None,
+        msg,
+    )
+}
 
-    Response::builder()
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "*")
-        .header("Access-Control-Allow-Headers", "*")
-        .status(hyper::StatusCode::BAD_REQUEST)
-        .body(Body::from(err_msg))
-        .unwrap()
+pub(crate) fn bad_request(msg: impl AsRef<str>) -> Response<Body> {
+    error_response(
+        StatusCode::BAD_REQUEST,
+        "invalid_request_error",
+        None,
+        None,
+        msg,
+    )
 }
 
 pub(crate) fn unauthorized(msg: impl AsRef<str>) -> Response<Body> {
-    let err_msg = match msg.as_ref().is_empty() {
-        true => "401 Unauthorized".to_string(),
-        false => format!("401 Unauthorized: {}", msg.as_ref()),
-    };
-
-    // log error
-    error!(target: "stdout", "{}", &err_msg);
+    error_response(
+        StatusCode::UNAUTHORIZED,
+        "authentication_error",
+        None,
+        Some("invalid_api_key"),
+        msg,
+    )
+}
 
-    Response::builder()
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "*")
-        .header("Access-Control-Allow-Headers", "*")
-        .status(hyper::StatusCode::UNAUTHORIZED)
-        .body(Body::from(err_msg))
-        .unwrap()
+pub(crate) fn forbidden(msg: impl AsRef<str>) -> Response<Body> {
+    error_response(StatusCode::FORBIDDEN, "permission_error", None, None, msg)
 }
 
 pub(crate) fn invalid_endpoint(msg: impl AsRef<str>) -> Response<Body> {
     let err_msg = match msg.as_ref().is_empty() {
-        true => "404 The requested service endpoint is not found".to_string(),
+        true => "The requested service endpoint is not found".to_string(),
         false => format!(
-            "404 The requested service endpoint is not found: {}",
+            "The requested service endpoint is not found: {}",
             msg.as_ref()
         ),
     };
 
-    // log error
-    error!(target: "stdout", "{}", &err_msg);
+    error_response(
+        StatusCode::NOT_FOUND,
+        "invalid_request_error",
+        None,
+        Some("unknown_url"),
+        err_msg,
+    )
+}
 
-    Response::builder()
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "*")
-        .header("Access-Control-Allow-Headers", "*")
-        .status(hyper::StatusCode::NOT_FOUND)
-        .body(Body::from(err_msg))
-        .unwrap()
+pub(crate) fn not_found(msg: impl AsRef<str>) -> Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "invalid_request_error",
+        None,
+        None,
+        msg,
+    )
+}
+
+/// Returns the error response for an error from `llama-core`. The status code and the type of the error object depend on the kind of the error.
+///
+/// # Arguments
+///
+/// * `err` - The error returned by `llama-core`.
+///
+/// * `msg` - The message of the error object.
+pub(crate) fn llama_core_error(err: &LlamaCoreError, msg: impl AsRef<str>) -> Response<Body> {
+    match err {
+        LlamaCoreError::Prompt(
+            PromptError::NoMessages
+            | PromptError::NoUserMessage
+            | PromptError::NoAssistantMessage
+            | PromptError::NoAvailableTools
+            | PromptError::BadMessages(_)
+            | PromptError::UnknownRole(_)
+            | PromptError::UnsupportedContent(_),
+        ) => error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            Some("messages"),
+            None,
+            msg,
+        ),
+        LlamaCoreError::InvalidRequest(_) => bad_request(msg),
+        LlamaCoreError::ModelNotFound(_) => error_response(
+            StatusCode::NOT_FOUND,
+            "invalid_request_error",
+            Some("model"),
+            Some("model_not_found"),
+            msg,
+        ),
+        LlamaCoreError::PromptTooLong(_) => error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "invalid_request_error",
+            Some("messages"),
+            Some("context_length_exceeded"),
+            msg,
+        ),
+        LlamaCoreError::QueueFull(_) => error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limit_error",
+            None,
+            Some("rate_limit_exceeded"),
+            msg,
+        ),
+        LlamaCoreError::ModelConflict(_) => error_response(
+            StatusCode::CONFLICT,
+            "invalid_request_error",
+            Some("model"),
+            Some("model_conflict"),
+            msg,
+        ),
+        LlamaCoreError::NotFound(_) => not_found(msg),
+        // the other errors, including the prompt errors caused by a misconfigured prompt template, are server errors
+        _ => internal_server_error(msg),
+    }
 }
 
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
@@ -7,8 +7,13 @@ mod error;
 mod utils;
 
 use anyhow::Result;
-use chat_prompts::PromptTemplateType;
+use chat_prompts::{PromptTemplateType, PromptTemplateTypeParser};
 use clap::{ArgGroup, Parser, Subcommand};
+use config::{
+    register_custom_templates, ChatConfig, CustomTemplates, EmbeddingConfig, ImageConfig,
+    PoolConfig, WhisperConfig,
+};
+use endpoints::chat::TruncationStrategy;
 use error::ServerError;
 use hyper::{
     body::HttpBody,
@@ -17,21 +22,30 @@ use hyper::{
     service::{make_service_fn, service_fn},
     Body, Request, Response, Server, StatusCode,
 };
-use llama_core::metadata::ggml::{GgmlMetadataBuilder, GgmlTtsMetadataBuilder};
+#[cfg(feature = "whisper")]
+use llama_core::metadata::whisper::WhisperMetadataBuilder;
+use llama_core::{
+    metadata::ggml::{GgmlMetadata, GgmlMetadataBuilder, GgmlTtsMetadataBuilder},
+    pool::PoolEvent,
+    StableDiffusionTask,
+};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::RwLock, time::Duration};
 use tokio::net::TcpListener;
 use utils::LogLevel;
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 // server info
-pub(crate) static SERVER_INFO: OnceCell<ApiServer> = OnceCell::new();
+pub(crate) static SERVER_INFO: OnceCell<RwLock<ApiServer>> = OnceCell::new();
 
 // API key
 pub(crate) static LLAMA_API_KEY: OnceCell<String> = OnceCell::new();
 
+// API key of the admin endpoints
+pub(crate) static ADMIN_API_KEY: OnceCell<String> = OnceCell::new();
+
 // default port
 const DEFAULT_PORT: &str = "8080";
 
@@ -64,16 +78,24 @@ enum Commands {
         /// Use the TTS model
         #[arg(short, long, default_value = "false")]
         tts: bool,
+
+        /// Use the stable diffusion model for the image endpoints
+        #[arg(short, long, default_value = "false")]
+        image: bool,
+
+        /// Use the whisper model for the audio transcription and translation endpoints
+        #[arg(short, long, default_value = "false")]
+        whisper: bool,
     },
 }
 
 #[derive(Debug, Parser)]
 #[command(group = ArgGroup::new("socket_address_group").multiple(false).args(&["socket_addr", "port"]))]
 struct ServerArgs {
-    /// Sets names for chat and/or embedding models. To run both chat and embedding models, the names should be separated by comma without space, for example, '--model-name Llama-2-7b,all-minilm'. The first value is for the chat model, and the second is for the embedding model.
+    /// Sets names of the chat and embedding models. To run multiple models, the names should be separated by comma without space, for example, '--model-name Llama-3-8b,Qwen2-7b,all-minilm'. Each name should be the same as the name of the model preloaded by `--nn-preload`.
     #[arg(short, long, value_delimiter = ',', default_value = "default")]
     model_name: Vec<String>,
-    /// Model aliases for chat and embedding models
+    /// Sets aliases of the models, in the same order as the model names. The name of a model is used as its alias if the alias is not given.
     #[arg(
         short = 'a',
         long,
@@ -81,24 +103,32 @@ struct ServerArgs {
         default_value = "default,embedding"
     )]
     model_alias: Vec<String>,
-    /// Sets context sizes for chat and/or embedding models. To run both chat and embedding models, the sizes should be separated by comma without space, for example, '--ctx-size 4096,384'. The first value is for the chat model, and the second is for the embedding model.
+    /// Sets context sizes of the models, in the same order as the model names, for example, '--ctx-size 4096,8192,384'. A model without a context size uses 4096 if it is a chat model, or 384 if it is an embedding model.
     #[arg(
         short = 'c',
         long,
         value_delimiter = ',',
-        default_value = "4096,384",
         value_parser = clap::value_parser!(u64)
     )]
     ctx_size: Vec<u64>,
-    /// Sets logical maximum batch sizes for chat and/or embedding models. To run both chat and embedding models, the sizes should be separated by comma without space, for example, '--batch-size 128,64'. The first value is for the chat model, and the second for the embedding model.
-    #[arg(short, long, value_delimiter = ',', default_value = "512,512", value_parser = clap::value_parser!(u64))]
+    /// Sets logical maximum batch sizes of the models, in the same order as the model names, for example, '--batch-size 512,128,64'. A model without a batch size uses 512.
+    #[arg(short, long, value_delimiter = ',', value_parser = clap::value_parser!(u64))]
     batch_size: Vec<u64>,
-    /// Sets physical maximum batch sizes for chat and/or embedding models. To run both chat and embedding models, the sizes should be separated by comma without space, for example, '--ubatch-size 512,512'. The first value is for the chat model, and the second for the embedding model.
-    #[arg(short, long, value_delimiter = ',', default_value = "512,512", value_parser = clap::value_parser!(u64))]
+    /// Sets physical maximum batch sizes of the models, in the same order as the model names, for example, '--ubatch-size 512,512,512'. A model without a ubatch size uses 512.
+    #[arg(short, long, value_delimiter = ',', value_parser = clap::value_parser!(u64))]
     ubatch_size: Vec<u64>,
-    /// Sets prompt templates for chat and/or embedding models, respectively. To run both chat and embedding models, the prompt templates should be separated by comma without space, for example, '--prompt-template llama-2-chat,embedding'. The first value is for the chat model, and the second is for the embedding model.
-    #[arg(short, long, value_delimiter = ',', value_parser = clap::value_parser!(PromptTemplateType))]
+    /// Sets prompt templates of the models, in the same order as the model names, for example, '--prompt-template llama-3-chat,chatml,embedding'. The models with the `embedding` template are embedding models, and the others are chat models. A template declared in the file of `--custom-templates` is used with 'custom:<name>'. The `auto` template is detected from the model file set by `--model-file`, which is required because the server cannot read the file preloaded by `--nn-preload`.
+    #[arg(short, long, value_delimiter = ',', value_parser = PromptTemplateTypeParser)]
     prompt_template: Vec<PromptTemplateType>,
+    /// Sets paths to the chat templates of the models, in the same order as the model names, for example, '--chat-template ,Qwen3-8B-Q5_K_M.gguf'. A chat template is rendered by the `jinja` prompt template, and is read from the `tokenizer.chat_template` metadata of a GGUF model file, from a `tokenizer_config.json` file of Hugging Face, or from a file with the source of the template. The models with an empty path, or without a path, have no chat template.
+    #[arg(long, value_delimiter = ',')]
+    chat_template: Vec<String>,
+    /// Sets paths to the GGUF files of the models, in the same order as the model names, for example, '--model-file Llama-3.2-3B-Instruct-Q5_K_M.gguf,'. The prompt template of a chat model is detected from the metadata of its model file, which replaces the `auto` prompt template, and a warning is logged if the prompt template set by '--prompt-template' looks inconsistent with the model. The models with an empty path, or without a path, are not checked.
+    #[arg(long, value_delimiter = ',')]
+    model_file: Vec<String>,
+    /// Path to a TOML file declaring custom prompt templates as `[templates.<name>]` tables, which are used with '--prompt-template custom:<name>'.
+    #[arg(long)]
+    custom_templates: Option<PathBuf>,
     /// Halt generation at PROMPT, return control.
     #[arg(short, long)]
     reverse_prompt: Option<String>,
@@ -150,6 +180,75 @@ struct ServerArgs {
     /// Whether to include usage in the stream response. Defaults to false.
     #[arg(long, default_value = "false")]
     include_usage: bool,
+    /// Maximum number of requests waiting for each chat model. The requests beyond the limit are rejected with `429 Too Many Requests`. Unlimited if not set.
+    #[arg(long)]
+    max_queued_requests: Option<usize>,
+    /// Number of requests each chat model generates at the same time. Each slot has its own context of `--ctx-size` tokens in the backend, so the memory of the contexts grows with the number of slots. Defaults to 1.
+    #[arg(long)]
+    parallel_slots: Option<usize>,
+    /// Maximum number of chat models loaded at the same time. Setting any of `--max-resident-models`, `--memory-budget` and `--idle-timeout` enables the model pool, which loads the chat models on their first requests and unloads the least recently used ones to stay within the limits.
+    #[arg(long)]
+    max_resident_models: Option<usize>,
+    /// Maximum estimated memory in MiB of the chat models loaded at the same time. The memory of a model is set by `--model-size`, or is the size of its `--model-file`.
+    #[arg(long)]
+    memory_budget: Option<u64>,
+    /// Time in seconds after which an idle chat model is unloaded.
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+    /// Sets estimated memory in MiB of the models, in the same order as the model names, e.g. the sizes of the model files. Used with `--memory-budget`. A model without a size defaults to the size of its `--model-file`.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u64))]
+    model_size: Vec<u64>,
+    /// Sets truncation strategies of the chat models, in the same order as the model names, for example, '--truncation auto,last_turns:8'. The strategy controls how the chat history is shortened if the prompt does not fit in the context window: `auto` drops the oldest turns, `last_turns:<N>` keeps at most N latest turns, `middle_out` drops the turns in the middle, `summarize` replaces the oldest turns with a summary generated by the model, and `disabled` rejects the request with `413 Payload Too Large`. A model without a strategy uses `auto`.
+    #[arg(long, value_delimiter = ',')]
+    truncation: Vec<TruncationStrategy>,
+    /// Whether to reuse the KV cache of the previous prompt on the same slot of a chat model. A prompt starting with the previous prompt only evaluates the tokens after it, and the reused tokens are reported in `usage.prompt_tokens_details.cached_tokens`. Defaults to false.
+    #[arg(long, default_value = "false")]
+    cache_prompt: bool,
+    /// Sets the name of the stable diffusion model for the image endpoints
+    #[arg(long, default_value = "image")]
+    sd_model_name: String,
+    /// Path to the full stable diffusion model file for the image endpoints
+    #[arg(long, conflicts_with = "sd_diffusion_model")]
+    sd_model: Option<PathBuf>,
+    /// Path to the standalone diffusion model file for the image endpoints, e.g. a FLUX model. Use with `--sd-vae`, `--sd-clip-l` and `--sd-t5xxl`.
+    #[arg(long)]
+    sd_diffusion_model: Option<PathBuf>,
+    /// Path to the VAE model file of the standalone diffusion model
+    #[arg(long)]
+    sd_vae: Option<PathBuf>,
+    /// Path to the CLIP-L model file of the standalone diffusion model
+    #[arg(long)]
+    sd_clip_l: Option<PathBuf>,
+    /// Path to the T5-XXL model file of the standalone diffusion model
+    #[arg(long)]
+    sd_t5xxl: Option<PathBuf>,
+    /// Path to the directory of the LoRA models of the stable diffusion model
+    #[arg(long)]
+    sd_lora_model_dir: Option<PathBuf>,
+    /// Path to the ControlNet model file of the stable diffusion model
+    #[arg(long)]
+    sd_control_net: Option<PathBuf>,
+    /// Keep the ControlNet model on CPU
+    #[arg(long)]
+    sd_control_net_cpu: bool,
+    /// Keep the CLIP model on CPU
+    #[arg(long)]
+    sd_clip_on_cpu: bool,
+    /// Keep the VAE model on CPU
+    #[arg(long)]
+    sd_vae_on_cpu: bool,
+    /// Task of the stable diffusion model. Possible values: `full` (generations, edits and variations), `text2image` (generations only), `image2image` (edits and variations only)
+    #[arg(long, default_value = "full", value_parser = clap::value_parser!(StableDiffusionTask))]
+    sd_task: StableDiffusionTask,
+    /// Sets the name of the whisper model for the audio transcription and translation endpoints
+    #[arg(long, default_value = "whisper")]
+    whisper_model_name: String,
+    /// Path to the whisper model file for the audio transcription and translation endpoints. Requires the `whisper` feature.
+    #[arg(long)]
+    whisper_model: Option<PathBuf>,
+    /// The default language of the input audio in ISO-639-1 format, or `auto` for auto-detection
+    #[arg(long, default_value = "en")]
+    whisper_language: String,
     /// Socket address of LlamaEdge API Server instance. For example, `0.0.0.0:8080`.
     #[arg(long, default_value = None, value_parser = clap::value_parser!(SocketAddr), group = "socket_address_group")]
     socket_addr: Option<SocketAddr>,
@@ -199,6 +298,17 @@ async fn main() -> Result<(), ServerError> {
         }
     }
 
+    // the admin endpoints are disabled unless the admin API key is set
+    if let Ok(admin_api_key) = std::env::var("ADMIN_API_KEY") {
+        if let Err(e) = ADMIN_API_KEY.set(admin_api_key) {
+            let err_msg = format!("Failed to set admin API key. {e}");
+
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(ServerError::Operation(err_msg));
+        }
+    }
+
     info!(target: "stdout", "LOG LEVEL: {log_level}");
 
     // log the version of the server
@@ -215,9 +325,11 @@ async fn main() -> Result<(), ServerError> {
                 chat,
                 embedding,
                 tts,
+                image,
+                whisper,
             } => {
-                if !chat && !embedding && !tts {
-                    let err_msg = "Specify at least one of the following: chat, embedding, and/or TTS. by using --chat, --embedding, and/or --tts.";
+                if !chat && !embedding && !tts && !image && !whisper {
+                    let err_msg = "Specify at least one of the following: chat, embedding, TTS, image and/or whisper, by using --chat, --embedding, --tts, --image and/or --whisper.";
 
                     error!(target: "stdout", "{err_msg}");
 
@@ -227,174 +339,21 @@ async fn main() -> Result<(), ServerError> {
                 info!(target: "stdout", "CONFIG FILE: {}", file.to_string_lossy());
                 let config = config::Config::load(&file)?;
 
-                // chat model
-                let mut chat_model_config = None;
-                if chat {
-                    info!(target: "stdout", "chat model name: {}", config.chat.model_name);
-
-                    info!(target: "stdout", "chat model alias: {}", config.chat.model_alias);
-
-                    info!(target: "stdout", "chat context size: {}", config.chat.ctx_size);
-
-                    info!(target: "stdout", "chat batch size: {}", config.chat.batch_size);
-
-                    info!(target: "stdout", "chat ubatch size: {}", config.chat.ubatch_size);
-
-                    info!(target: "stdout", "chat prompt template: {}", config.chat.prompt_template);
-
-                    info!(target: "stdout", "chat split mode: {}", config.chat.split_mode);
-
-                    info!(target: "stdout", "chat main gpu: {:?}", config.chat.main_gpu);
+                // the custom prompt templates are used by the chat models
+                register_custom_templates(&config.templates)?;
 
-                    info!(target: "stdout", "chat tensor split: {:?}", config.chat.tensor_split);
-
-                    info!(target: "stdout", "chat threads: {}", config.chat.threads);
-
-                    info!(target: "stdout", "chat no_mmap: {}", config.chat.no_mmap);
-
-                    info!(target: "stdout", "chat temp: {}", config.chat.temp);
-
-                    info!(target: "stdout", "chat top_p: {}", config.chat.top_p);
-
-                    info!(target: "stdout", "chat repeat_penalty: {}", config.chat.repeat_penalty);
-
-                    info!(target: "stdout", "chat presence_penalty: {}", config.chat.presence_penalty);
-
-                    info!(target: "stdout", "chat frequency_penalty: {}", config.chat.frequency_penalty);
-
-                    info!(target: "stdout", "chat grammar: {:?}", config.chat.grammar);
-
-                    info!(target: "stdout", "chat json_schema: {:?}", config.chat.json_schema);
-
-                    info!(target: "stdout", "chat llava_mmproj: {:?}", config.chat.llava_mmproj);
-
-                    info!(target: "stdout", "chat include_usage: {}", config.chat.include_usage);
-
-                    // create a Metadata instance
-                    let metadata_chat = GgmlMetadataBuilder::new(
-                        config.chat.model_name,
-                        config.chat.model_alias,
-                        config.chat.prompt_template,
-                    )
-                    .with_ctx_size(config.chat.ctx_size)
-                    .with_batch_size(config.chat.batch_size)
-                    .with_ubatch_size(config.chat.ubatch_size)
-                    .with_n_predict(config.chat.n_predict)
-                    .with_n_gpu_layers(config.chat.n_gpu_layers)
-                    .with_split_mode(config.chat.split_mode)
-                    .with_main_gpu(config.chat.main_gpu)
-                    .with_tensor_split(config.chat.tensor_split)
-                    .with_threads(config.chat.threads)
-                    .disable_mmap(Some(config.chat.no_mmap))
-                    .with_temperature(config.chat.temp)
-                    .with_top_p(config.chat.top_p)
-                    .with_repeat_penalty(config.chat.repeat_penalty)
-                    .with_presence_penalty(config.chat.presence_penalty)
-                    .with_frequency_penalty(config.chat.frequency_penalty)
-                    .with_grammar(config.chat.grammar.unwrap_or_default())
-                    .with_json_schema(config.chat.json_schema)
-                    .with_reverse_prompt(config.chat.reverse_prompt)
-                    .with_mmproj(
-                        config
-                            .chat
-                            .llava_mmproj
-                            .map(|p| p.to_string_lossy().to_string()),
-                    )
-                    .enable_plugin_log(true)
-                    .enable_debug_log(plugin_debug)
-                    .include_usage(config.chat.include_usage)
-                    .build();
-
-                    // set the chat model config
-                    chat_model_config = Some(ModelConfig {
-                        name: metadata_chat.model_name.clone(),
-                        ty: "chat".to_string(),
-                        ctx_size: metadata_chat.ctx_size,
-                        batch_size: metadata_chat.batch_size,
-                        ubatch_size: metadata_chat.ubatch_size,
-                        prompt_template: Some(metadata_chat.prompt_template),
-                        n_predict: Some(metadata_chat.n_predict),
-                        reverse_prompt: metadata_chat.reverse_prompt.clone(),
-                        n_gpu_layers: Some(metadata_chat.n_gpu_layers),
-                        use_mmap: metadata_chat.use_mmap,
-                        temperature: Some(metadata_chat.temperature),
-                        top_p: Some(metadata_chat.top_p),
-                        repeat_penalty: Some(metadata_chat.repeat_penalty),
-                        presence_penalty: Some(metadata_chat.presence_penalty),
-                        frequency_penalty: Some(metadata_chat.frequency_penalty),
-                        split_mode: Some(metadata_chat.split_mode.clone()),
-                        main_gpu: metadata_chat.main_gpu,
-                        tensor_split: metadata_chat.tensor_split.clone(),
-                    });
-
-                    // initialize the chat context
-                    llama_core::init_ggml_chat_context(&[metadata_chat])
-                        .map_err(|e| ServerError::Operation(format!("{e}")))?;
+                // chat models
+                let mut chat_model_configs = vec![];
+                if chat {
+                    chat_model_configs =
+                        init_chat_models(&config.chat, config.pool.as_ref(), plugin_debug)?;
                 }
 
-                // embedding model
-                let mut embedding_model_config = None;
+                // embedding models
+                let mut embedding_model_configs = vec![];
                 if embedding {
-                    info!(target: "stdout", "embedding model name: {}", config.embedding.model_name);
-
-                    info!(target: "stdout", "embedding model alias: {}", config.embedding.model_alias);
-
-                    info!(target: "stdout", "embedding context size: {}", config.embedding.ctx_size);
-
-                    info!(target: "stdout", "embedding batch size: {}", config.embedding.batch_size);
-
-                    info!(target: "stdout", "embedding ubatch size: {}", config.embedding.ubatch_size);
-
-                    info!(target: "stdout", "embedding split mode: {}", config.embedding.split_mode);
-
-                    info!(target: "stdout", "embedding main gpu: {:?}", config.embedding.main_gpu);
-
-                    info!(target: "stdout", "embedding tensor split: {:?}", config.embedding.tensor_split);
-
-                    info!(target: "stdout", "embedding threads: {}", config.embedding.threads);
-
-                    // create a Metadata instance
-                    let metadata_embedding = GgmlMetadataBuilder::new(
-                        config.embedding.model_name,
-                        config.embedding.model_alias,
-                        PromptTemplateType::Embedding,
-                    )
-                    .with_ctx_size(config.embedding.ctx_size)
-                    .with_batch_size(config.embedding.batch_size)
-                    .with_ubatch_size(config.embedding.ubatch_size)
-                    .with_split_mode(config.embedding.split_mode)
-                    .with_main_gpu(config.embedding.main_gpu)
-                    .with_tensor_split(config.embedding.tensor_split)
-                    .with_threads(config.embedding.threads)
-                    .enable_plugin_log(true)
-                    .enable_debug_log(plugin_debug)
-                    .build();
-
-                    // set the embedding model config
-                    embedding_model_config = Some(ModelConfig {
-                        name: metadata_embedding.model_name.clone(),
-                        ty: "embedding".to_string(),
-                        ctx_size: metadata_embedding.ctx_size,
-                        batch_size: metadata_embedding.batch_size,
-                        ubatch_size: metadata_embedding.ubatch_size,
-                        prompt_template: Some(PromptTemplateType::Embedding),
-                        n_predict: Some(cli.server_args.n_predict),
-                        reverse_prompt: metadata_embedding.reverse_prompt.clone(),
-                        n_gpu_layers: Some(metadata_embedding.n_gpu_layers),
-                        use_mmap: metadata_embedding.use_mmap,
-                        temperature: Some(metadata_embedding.temperature),
-                        top_p: Some(metadata_embedding.top_p),
-                        repeat_penalty: Some(metadata_embedding.repeat_penalty),
-                        presence_penalty: Some(metadata_embedding.presence_penalty),
-                        frequency_penalty: Some(metadata_embedding.frequency_penalty),
-                        split_mode: Some(metadata_embedding.split_mode.clone()),
-                        main_gpu: metadata_embedding.main_gpu,
-                        tensor_split: metadata_embedding.tensor_split.clone(),
-                    });
-
-                    // initialize the embeddings context
-                    llama_core::init_ggml_embeddings_context(&[metadata_embedding])
-                        .map_err(|e| ServerError::Operation(format!("{e}")))?;
+                    embedding_model_configs =
+                        init_embedding_models(&config.embedding, plugin_debug)?;
                 }
 
                 // tts model
@@ -448,6 +407,7 @@ async fn main() -> Result<(), ServerError> {
                         batch_size: metadata_tts.batch_size,
                         ubatch_size: metadata_tts.ubatch_size,
                         prompt_template: Some(PromptTemplateType::Tts),
+                        detected_prompt_template: None,
                         n_predict: Some(metadata_tts.n_predict),
                         reverse_prompt: None,
                         n_gpu_layers: None,
@@ -460,6 +420,8 @@ async fn main() -> Result<(), ServerError> {
                         split_mode: None,
                         main_gpu: None,
                         tensor_split: None,
+                        truncation: None,
+                        cache_prompt: None,
                     });
 
                     // initialize the tts context
@@ -467,6 +429,40 @@ async fn main() -> Result<(), ServerError> {
                         .map_err(|e| ServerError::Operation(format!("{e}")))?;
                 }
 
+                // image model
+                let mut image_model_config = None;
+                if image {
+                    let image_config = match config.image {
+                        Some(image_config) => image_config,
+                        None => {
+                            let err_msg = "The `image` section is not found in the config file.";
+
+                            error!(target: "stdout", "{err_msg}");
+
+                            return Err(ServerError::Operation(err_msg.to_string()));
+                        }
+                    };
+
+                    image_model_config = Some(init_image_model(&image_config)?);
+                }
+
+                // whisper model
+                let mut audio_model_config = None;
+                if whisper {
+                    let whisper_config = match config.whisper {
+                        Some(whisper_config) => whisper_config,
+                        None => {
+                            let err_msg = "The `whisper` section is not found in the config file.";
+
+                            error!(target: "stdout", "{err_msg}");
+
+                            return Err(ServerError::Operation(err_msg.to_string()));
+                        }
+                    };
+
+                    audio_model_config = Some(init_whisper_model(&whisper_config)?);
+                }
+
                 // get running mode
                 let running_mode = llama_core::running_mode()
                     .map_err(|e| ServerError::Operation(e.to_string()))?;
@@ -500,12 +496,16 @@ async fn main() -> Result<(), ServerError> {
                     version: env!("CARGO_PKG_VERSION").to_string(),
                     plugin_version,
                     port,
-                    chat_model: chat_model_config,
-                    embedding_model: embedding_model_config,
+                    chat_model: chat_model_configs.first().cloned(),
+                    embedding_model: embedding_model_configs.first().cloned(),
+                    chat_models: chat_model_configs,
+                    embedding_models: embedding_model_configs,
                     tts_model: tts_model_config,
+                    image_model: image_model_config,
+                    audio_model: audio_model_config,
                     extras: HashMap::new(),
                 };
-                SERVER_INFO.set(server_info).map_err(|_| {
+                SERVER_INFO.set(RwLock::new(server_info)).map_err(|_| {
                     ServerError::Operation("Failed to set `SERVER_INFO`.".to_string())
                 })?;
 
@@ -536,84 +536,56 @@ async fn main() -> Result<(), ServerError> {
         }
     } else {
         // log model names
-        if cli.server_args.model_name.is_empty() && cli.server_args.model_name.len() > 2 {
-            return Err(ServerError::ArgumentError(
-            "Invalid setting for model name. For running chat or embedding model, please specify a single model name. For running both chat and embedding models, please specify two model names: the first one for chat model, the other for embedding model.".to_owned(),
-        ));
-        }
         info!(target: "stdout", "model_name: {}", cli.server_args.model_name.join(","));
 
         // log model alias
-        let mut model_alias = String::new();
-        if cli.server_args.model_name.len() == 1 {
-            model_alias.clone_from(&cli.server_args.model_alias[0]);
-        } else if cli.server_args.model_alias.len() == 2 {
-            model_alias = cli.server_args.model_alias.join(",").to_string();
-        }
-        info!(target: "stdout", "model_alias: {model_alias}");
+        info!(target: "stdout", "model_alias: {}", cli.server_args.model_alias.join(","));
 
         // log context size
-        if cli.server_args.ctx_size.is_empty() && cli.server_args.ctx_size.len() > 2 {
-            return Err(ServerError::ArgumentError(
-            "Invalid setting for context size. For running chat or embedding model, please specify a single context size. For running both chat and embedding models, please specify two context sizes: the first one for chat model, the other for embedding model.".to_owned(),
-        ));
-        }
-        let mut ctx_sizes_str = String::new();
-        if cli.server_args.model_name.len() == 1 {
-            ctx_sizes_str = cli.server_args.ctx_size[0].to_string();
-        } else if cli.server_args.model_name.len() == 2 {
-            ctx_sizes_str = cli
+        if !cli.server_args.ctx_size.is_empty() {
+            let ctx_sizes_str = cli
                 .server_args
                 .ctx_size
                 .iter()
                 .map(|n| n.to_string())
                 .collect::<Vec<String>>()
                 .join(",");
+            info!(target: "stdout", "ctx_size: {ctx_sizes_str}");
         }
-        info!(target: "stdout", "ctx_size: {ctx_sizes_str}");
 
         // log batch size
-        if cli.server_args.batch_size.is_empty() && cli.server_args.batch_size.len() > 2 {
-            return Err(ServerError::ArgumentError(
-            "Invalid setting for batch size. For running chat or embedding model, please specify a single batch size. For running both chat and embedding models, please specify two batch sizes: the first one for chat model, the other for embedding model.".to_owned(),
-        ));
-        }
-        let mut batch_sizes_str = String::new();
-        if cli.server_args.model_name.len() == 1 {
-            batch_sizes_str = cli.server_args.batch_size[0].to_string();
-        } else if cli.server_args.model_name.len() == 2 {
-            batch_sizes_str = cli
+        if !cli.server_args.batch_size.is_empty() {
+            let batch_sizes_str = cli
                 .server_args
                 .batch_size
                 .iter()
                 .map(|n| n.to_string())
                 .collect::<Vec<String>>()
                 .join(",");
+            info!(target: "stdout", "batch_size: {batch_sizes_str}");
         }
-        info!(target: "stdout", "batch_size: {batch_sizes_str}");
 
         // log ubatch size
-        let mut ubatch_sizes_str = String::new();
-        if cli.server_args.model_name.len() == 1 {
-            ubatch_sizes_str = cli.server_args.ubatch_size[0].to_string();
-        } else if cli.server_args.model_name.len() == 2 {
-            ubatch_sizes_str = cli
+        if !cli.server_args.ubatch_size.is_empty() {
+            let ubatch_sizes_str = cli
                 .server_args
                 .ubatch_size
                 .iter()
                 .map(|n| n.to_string())
                 .collect::<Vec<String>>()
                 .join(",");
+            info!(target: "stdout", "ubatch_size: {ubatch_sizes_str}");
         }
-        info!(target: "stdout", "ubatch_size: {ubatch_sizes_str}");
 
-        // log prompt template
-        if cli.server_args.prompt_template.is_empty() && cli.server_args.prompt_template.len() > 2 {
-            return Err(ServerError::ArgumentError(
-                "LlamaEdge API server requires prompt templates. For running chat or embedding model, please specify a single prompt template. For running both chat and embedding models, please specify two prompt templates: the first one for chat model, the other for embedding model.".to_owned(),
-            ));
+        // register the custom prompt templates before checking the prompt templates of the models
+        if let Some(path) = &cli.server_args.custom_templates {
+            info!(target: "stdout", "custom_templates: {}", path.display());
+
+            let custom_templates = CustomTemplates::load(path)?;
+            register_custom_templates(&custom_templates.templates)?;
         }
 
+        // log prompt template
         let prompt_template_str: String = cli
             .server_args
             .prompt_template
@@ -622,11 +594,37 @@ async fn main() -> Result<(), ServerError> {
             .collect::<Vec<String>>()
             .join(",");
         info!(target: "stdout", "prompt_template: {prompt_template_str}");
-        if cli.server_args.model_name.len() != cli.server_args.prompt_template.len() {
+
+        // check the per-model options
+        let num_models = cli.server_args.model_name.len();
+        if num_models != cli.server_args.prompt_template.len() {
             return Err(ServerError::ArgumentError(
                 "The number of model names and prompt templates must be the same.".to_owned(),
             ));
         }
+        for (option, len) in [
+            ("model aliases", cli.server_args.model_alias.len()),
+            ("context sizes", cli.server_args.ctx_size.len()),
+            ("batch sizes", cli.server_args.batch_size.len()),
+            ("ubatch sizes", cli.server_args.ubatch_size.len()),
+            ("model sizes", cli.server_args.model_size.len()),
+            ("chat templates", cli.server_args.chat_template.len()),
+            ("model files", cli.server_args.model_file.len()),
+            ("truncation strategies", cli.server_args.truncation.len()),
+        ] {
+            if len > num_models {
+                return Err(ServerError::ArgumentError(format!(
+                    "The number of {option} ({len}) is greater than the number of model names ({num_models})."
+                )));
+            }
+        }
+        for (i, model_name) in cli.server_args.model_name.iter().enumerate() {
+            if cli.server_args.model_name[..i].contains(model_name) {
+                return Err(ServerError::ArgumentError(format!(
+                    "The model name `{model_name}` is used by more than one model."
+                )));
+            }
+        }
 
         // log reverse prompt
         if let Some(reverse_prompt) = &cli.server_args.reverse_prompt {
@@ -693,213 +691,199 @@ async fn main() -> Result<(), ServerError> {
         // log include_usage
         info!(target: "stdout", "include_usage: {}", cli.server_args.include_usage);
 
-        // initialize the core context
-        let mut chat_model_config = None;
-        let mut embedding_model_config = None;
-        if cli.server_args.prompt_template.len() == 1 {
-            match cli.server_args.prompt_template[0] {
-                PromptTemplateType::Embedding => {
-                    // create a Metadata instance
-                    let metadata_embedding = GgmlMetadataBuilder::new(
-                        cli.server_args.model_name[0].clone(),
-                        cli.server_args.model_alias[0].clone(),
-                        cli.server_args.prompt_template[0],
-                    )
-                    .with_ctx_size(cli.server_args.ctx_size[0])
-                    .with_batch_size(cli.server_args.batch_size[0])
-                    .with_ubatch_size(cli.server_args.ubatch_size[0])
-                    .with_split_mode(cli.server_args.split_mode)
-                    .with_main_gpu(cli.server_args.main_gpu)
-                    .with_tensor_split(cli.server_args.tensor_split)
-                    .with_threads(cli.server_args.threads)
-                    .enable_plugin_log(true)
-                    .enable_debug_log(plugin_debug)
-                    .build();
+        // log cache_prompt
+        info!(target: "stdout", "cache_prompt: {}", cli.server_args.cache_prompt);
 
-                    // set the embedding model config
-                    embedding_model_config = Some(ModelConfig {
-                        name: metadata_embedding.model_name.clone(),
-                        ty: "embedding".to_string(),
-                        ctx_size: metadata_embedding.ctx_size,
-                        batch_size: metadata_embedding.batch_size,
-                        ubatch_size: metadata_embedding.ubatch_size,
-                        prompt_template: Some(PromptTemplateType::Embedding),
-                        n_predict: Some(cli.server_args.n_predict),
-                        reverse_prompt: metadata_embedding.reverse_prompt.clone(),
-                        n_gpu_layers: Some(metadata_embedding.n_gpu_layers),
-                        use_mmap: metadata_embedding.use_mmap,
-                        temperature: Some(metadata_embedding.temperature),
-                        top_p: Some(metadata_embedding.top_p),
-                        repeat_penalty: Some(metadata_embedding.repeat_penalty),
-                        presence_penalty: Some(metadata_embedding.presence_penalty),
-                        frequency_penalty: Some(metadata_embedding.frequency_penalty),
-                        split_mode: Some(metadata_embedding.split_mode.clone()),
-                        main_gpu: metadata_embedding.main_gpu,
-                        tensor_split: metadata_embedding.tensor_split.clone(),
-                    });
+        // log max_queued_requests
+        if let Some(max_queued_requests) = &cli.server_args.max_queued_requests {
+            info!(target: "stdout", "max_queued_requests: {max_queued_requests}");
+        }
 
-                    // initialize the embeddings context
-                    llama_core::init_ggml_embeddings_context(&[metadata_embedding])
-                        .map_err(|e| ServerError::Operation(format!("{e}")))?;
-                }
-                _ => {
-                    // create a Metadata instance
-                    let metadata_chat = GgmlMetadataBuilder::new(
-                        cli.server_args.model_name[0].clone(),
-                        cli.server_args.model_alias[0].clone(),
-                        cli.server_args.prompt_template[0],
-                    )
-                    .with_ctx_size(cli.server_args.ctx_size[0])
-                    .with_batch_size(cli.server_args.batch_size[0])
-                    .with_ubatch_size(cli.server_args.ubatch_size[0])
-                    .with_n_predict(cli.server_args.n_predict)
-                    .with_n_gpu_layers(cli.server_args.n_gpu_layers)
-                    .with_split_mode(cli.server_args.split_mode)
-                    .with_main_gpu(cli.server_args.main_gpu)
-                    .with_tensor_split(cli.server_args.tensor_split)
-                    .with_threads(cli.server_args.threads)
-                    .disable_mmap(cli.server_args.no_mmap)
-                    .with_temperature(cli.server_args.temp)
-                    .with_top_p(cli.server_args.top_p)
-                    .with_repeat_penalty(cli.server_args.repeat_penalty)
-                    .with_presence_penalty(cli.server_args.presence_penalty)
-                    .with_frequency_penalty(cli.server_args.frequency_penalty)
-                    .with_grammar(cli.server_args.grammar)
-                    .with_json_schema(cli.server_args.json_schema)
-                    .with_reverse_prompt(cli.server_args.reverse_prompt)
-                    .with_mmproj(cli.server_args.llava_mmproj.clone())
-                    .enable_plugin_log(true)
-                    .enable_debug_log(plugin_debug)
-                    .include_usage(cli.server_args.include_usage)
-                    .build();
+        // log parallel_slots
+        if let Some(parallel_slots) = &cli.server_args.parallel_slots {
+            info!(target: "stdout", "parallel_slots: {parallel_slots}");
+        }
 
-                    // set the chat model config
-                    chat_model_config = Some(ModelConfig {
-                        name: metadata_chat.model_name.clone(),
-                        ty: "chat".to_string(),
-                        ctx_size: metadata_chat.ctx_size,
-                        batch_size: metadata_chat.batch_size,
-                        ubatch_size: metadata_chat.ubatch_size,
-                        prompt_template: Some(metadata_chat.prompt_template),
-                        n_predict: Some(metadata_chat.n_predict),
-                        reverse_prompt: metadata_chat.reverse_prompt.clone(),
-                        n_gpu_layers: Some(metadata_chat.n_gpu_layers),
-                        use_mmap: metadata_chat.use_mmap,
-                        temperature: Some(metadata_chat.temperature),
-                        top_p: Some(metadata_chat.top_p),
-                        repeat_penalty: Some(metadata_chat.repeat_penalty),
-                        presence_penalty: Some(metadata_chat.presence_penalty),
-                        frequency_penalty: Some(metadata_chat.frequency_penalty),
-                        split_mode: Some(metadata_chat.split_mode.clone()),
-                        main_gpu: metadata_chat.main_gpu,
-                        tensor_split: metadata_chat.tensor_split.clone(),
-                    });
+        // the model pool is enabled by any of its limits
+        let pool_config = match (
+            cli.server_args.max_resident_models,
+            cli.server_args.memory_budget,
+            cli.server_args.idle_timeout,
+        ) {
+            (None, None, None) => None,
+            (max_resident_models, memory_budget, idle_timeout) => {
+                info!(target: "stdout", "max_resident_models: {max_resident_models:?}, memory_budget: {memory_budget:?}, idle_timeout: {idle_timeout:?}");
+
+                Some(PoolConfig {
+                    max_resident_models,
+                    memory_budget,
+                    idle_timeout,
+                })
+            }
+        };
 
-                    // initialize the chat context
-                    llama_core::init_ggml_chat_context(&[metadata_chat])
-                        .map_err(|e| ServerError::Operation(format!("{e}")))?;
+        // the models are classified by their prompt templates
+        let mut chat_configs = vec![];
+        let mut embedding_configs = vec![];
+        for (i, model_name) in cli.server_args.model_name.iter().enumerate() {
+            let model_alias = cli
+                .server_args
+                .model_alias
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| model_name.clone());
+
+            match cli.server_args.prompt_template[i] {
+                PromptTemplateType::Embedding => {
+                    let default_config = EmbeddingConfig::default();
+
+                    embedding_configs.push(EmbeddingConfig {
+                        model_name: model_name.clone(),
+                        model_alias,
+                        ctx_size: cli
+                            .server_args
+                            .ctx_size
+                            .get(i)
+                            .copied()
+                            .unwrap_or(default_config.ctx_size),
+                        batch_size: cli
+                            .server_args
+                            .batch_size
+                            .get(i)
+                            .copied()
+                            .unwrap_or(default_config.batch_size),
+                        ubatch_size: cli
+                            .server_args
+                            .ubatch_size
+                            .get(i)
+                            .copied()
+                            .unwrap_or(default_config.ubatch_size),
+                        split_mode: cli.server_args.split_mode.clone(),
+                        main_gpu: cli.server_args.main_gpu,
+                        tensor_split: cli.server_args.tensor_split.clone(),
+                        threads: cli.server_args.threads,
+                    });
+                }
+                prompt_template => {
+                    let default_config = ChatConfig::default();
+
+                    chat_configs.push(ChatConfig {
+                        model_name: model_name.clone(),
+                        model_alias,
+                        ctx_size: cli
+                            .server_args
+                            .ctx_size
+                            .get(i)
+                            .copied()
+                            .unwrap_or(default_config.ctx_size),
+                        batch_size: cli
+                            .server_args
+                            .batch_size
+                            .get(i)
+                            .copied()
+                            .unwrap_or(default_config.batch_size),
+                        ubatch_size: cli
+                            .server_args
+                            .ubatch_size
+                            .get(i)
+                            .copied()
+                            .unwrap_or(default_config.ubatch_size),
+                        prompt_template,
+                        chat_template: cli
+                            .server_args
+                            .chat_template
+                            .get(i)
+                            .filter(|path| !path.is_empty())
+                            .map(PathBuf::from),
+                        model_file: cli
+                            .server_args
+                            .model_file
+                            .get(i)
+                            .filter(|path| !path.is_empty())
+                            .map(PathBuf::from),
+                        reverse_prompt: cli.server_args.reverse_prompt.clone(),
+                        n_predict: cli.server_args.n_predict,
+                        n_gpu_layers: cli.server_args.n_gpu_layers,
+                        split_mode: cli.server_args.split_mode.clone(),
+                        main_gpu: cli.server_args.main_gpu,
+                        tensor_split: cli.server_args.tensor_split.clone(),
+                        threads: cli.server_args.threads,
+                        no_mmap: cli.server_args.no_mmap,
+                        temp: cli.server_args.temp,
+                        top_p: cli.server_args.top_p,
+                        repeat_penalty: cli.server_args.repeat_penalty,
+                        presence_penalty: cli.server_args.presence_penalty,
+                        frequency_penalty: cli.server_args.frequency_penalty,
+                        grammar: Some(cli.server_args.grammar.clone()),
+                        json_schema: cli.server_args.json_schema.clone(),
+                        llava_mmproj: cli.server_args.llava_mmproj.as_ref().map(PathBuf::from),
+                        include_usage: cli.server_args.include_usage,
+                        max_queued_requests: cli.server_args.max_queued_requests,
+                        parallel_slots: cli.server_args.parallel_slots,
+                        model_size: cli.server_args.model_size.get(i).copied(),
+                        truncation: cli
+                            .server_args
+                            .truncation
+                            .get(i)
+                            .copied()
+                            .unwrap_or_default(),
+                        cache_prompt: cli.server_args.cache_prompt,
+                    });
                 }
             }
-        } else if cli.server_args.prompt_template.len() == 2 {
-            // create a Metadata instance
-            let metadata_chat = GgmlMetadataBuilder::new(
-                cli.server_args.model_name[0].clone(),
-                cli.server_args.model_alias[0].clone(),
-                cli.server_args.prompt_template[0],
-            )
-            .with_ctx_size(cli.server_args.ctx_size[0])
-            .with_batch_size(cli.server_args.batch_size[0])
-            .with_ubatch_size(cli.server_args.ubatch_size[0])
-            .with_n_predict(cli.server_args.n_predict)
-            .with_n_gpu_layers(cli.server_args.n_gpu_layers)
-            .with_split_mode(cli.server_args.split_mode.clone())
-            .with_main_gpu(cli.server_args.main_gpu)
-            .with_tensor_split(cli.server_args.tensor_split.clone())
-            .with_threads(cli.server_args.threads)
-            .disable_mmap(cli.server_args.no_mmap)
-            .with_temperature(cli.server_args.temp)
-            .with_top_p(cli.server_args.top_p)
-            .with_repeat_penalty(cli.server_args.repeat_penalty)
-            .with_presence_penalty(cli.server_args.presence_penalty)
-            .with_frequency_penalty(cli.server_args.frequency_penalty)
-            .with_grammar(cli.server_args.grammar)
-            .with_json_schema(cli.server_args.json_schema)
-            .with_reverse_prompt(cli.server_args.reverse_prompt)
-            .with_mmproj(cli.server_args.llava_mmproj.clone())
-            .enable_plugin_log(true)
-            .enable_debug_log(plugin_debug)
-            .include_usage(cli.server_args.include_usage)
-            .build();
-
-            // set the chat model config
-            chat_model_config = Some(ModelConfig {
-                name: metadata_chat.model_name.clone(),
-                ty: "chat".to_string(),
-                ctx_size: metadata_chat.ctx_size,
-                batch_size: metadata_chat.batch_size,
-                ubatch_size: metadata_chat.ubatch_size,
-                prompt_template: Some(metadata_chat.prompt_template),
-                n_predict: Some(metadata_chat.n_predict),
-                reverse_prompt: metadata_chat.reverse_prompt.clone(),
-                n_gpu_layers: Some(metadata_chat.n_gpu_layers),
-                use_mmap: metadata_chat.use_mmap,
-                temperature: Some(metadata_chat.temperature),
-                top_p: Some(metadata_chat.top_p),
-                repeat_penalty: Some(metadata_chat.repeat_penalty),
-                presence_penalty: Some(metadata_chat.presence_penalty),
-                frequency_penalty: Some(metadata_chat.frequency_penalty),
-                split_mode: Some(metadata_chat.split_mode.clone()),
-                main_gpu: metadata_chat.main_gpu,
-                tensor_split: metadata_chat.tensor_split.clone(),
-            });
+        }
 
-            // initialize the chat context
-            llama_core::init_ggml_chat_context(&[metadata_chat])
-                .map_err(|e| ServerError::Operation(format!("{e}")))?;
+        // initialize the core context
+        let chat_model_configs =
+            init_chat_models(&chat_configs, pool_config.as_ref(), plugin_debug)?;
+        let embedding_model_configs = init_embedding_models(&embedding_configs, plugin_debug)?;
 
-            // create a Metadata instance
-            let metadata_embedding = GgmlMetadataBuilder::new(
-                cli.server_args.model_name[1].clone(),
-                cli.server_args.model_alias[1].clone(),
-                cli.server_args.prompt_template[1],
-            )
-            .with_ctx_size(cli.server_args.ctx_size[1])
-            .with_batch_size(cli.server_args.batch_size[1])
-            .with_ubatch_size(cli.server_args.ubatch_size[1])
-            .with_split_mode(cli.server_args.split_mode)
-            .with_main_gpu(cli.server_args.main_gpu)
-            .with_tensor_split(cli.server_args.tensor_split)
-            .with_threads(cli.server_args.threads)
-            .enable_plugin_log(true)
-            .enable_debug_log(plugin_debug)
-            .build();
-
-            // set the embedding model config
-            embedding_model_config = Some(ModelConfig {
-                name: metadata_embedding.model_name.clone(),
-                ty: "embedding".to_string(),
-                ctx_size: metadata_embedding.ctx_size,
-                batch_size: metadata_embedding.batch_size,
-                ubatch_size: metadata_embedding.ubatch_size,
-                prompt_template: Some(PromptTemplateType::Embedding),
-                n_predict: Some(cli.server_args.n_predict),
-                reverse_prompt: metadata_embedding.reverse_prompt.clone(),
-                n_gpu_layers: Some(metadata_embedding.n_gpu_layers),
-                use_mmap: metadata_embedding.use_mmap,
-                temperature: Some(metadata_embedding.temperature),
-                top_p: Some(metadata_embedding.top_p),
-                repeat_penalty: Some(metadata_embedding.repeat_penalty),
-                presence_penalty: Some(metadata_embedding.presence_penalty),
-                frequency_penalty: Some(metadata_embedding.frequency_penalty),
-                split_mode: Some(metadata_embedding.split_mode.clone()),
-                main_gpu: metadata_embedding.main_gpu,
-                tensor_split: metadata_embedding.tensor_split.clone(),
-            });
-
-            // initialize the embeddings context
-            llama_core::init_ggml_embeddings_context(&[metadata_embedding])
-                .map_err(|e| ServerError::Operation(format!("{e}")))?;
-        }
+        // initialize the stable diffusion contexts for the image endpoints
+        let image_model_config = match cli
+            .server_args
+            .sd_model
+            .as_ref()
+            .or(cli.server_args.sd_diffusion_model.as_ref())
+        {
+            Some(_) => {
+                let image_config = ImageConfig {
+                    model_name: cli.server_args.sd_model_name.clone(),
+                    model: cli.server_args.sd_model.clone(),
+                    diffusion_model: cli.server_args.sd_diffusion_model.clone(),
+                    vae: cli.server_args.sd_vae.clone(),
+                    clip_l: cli.server_args.sd_clip_l.clone(),
+                    t5xxl: cli.server_args.sd_t5xxl.clone(),
+                    lora_model_dir: cli.server_args.sd_lora_model_dir.clone(),
+                    control_net: cli.server_args.sd_control_net.clone(),
+                    control_net_on_cpu: cli.server_args.sd_control_net_cpu,
+                    clip_on_cpu: cli.server_args.sd_clip_on_cpu,
+                    vae_on_cpu: cli.server_args.sd_vae_on_cpu,
+                    threads: cli.server_args.threads as i32,
+                    task: cli.server_args.sd_task,
+                };
+
+                Some(init_image_model(&image_config)?)
+            }
+            None => None,
+        };
+
+        // initialize the whisper context for the audio transcription and translation endpoints
+        let audio_model_config = match &cli.server_args.whisper_model {
+            Some(model) => {
+                let whisper_config = WhisperConfig {
+                    model_name: cli.server_args.whisper_model_name.clone(),
+                    model_alias: cli.server_args.whisper_model_name.clone(),
+                    model: model.clone(),
+                    threads: cli.server_args.threads,
+                    language: cli.server_args.whisper_language.clone(),
+                    detect_language: cli.server_args.whisper_language == "auto",
+                    ..Default::default()
+                };
+
+                Some(init_whisper_model(&whisper_config)?)
+            }
+            None => None,
+        };
 
         // get running mode
         let running_mode =
@@ -929,13 +913,17 @@ async fn main() -> Result<(), ServerError> {
             version: env!("CARGO_PKG_VERSION").to_string(),
             plugin_version,
             port,
-            chat_model: chat_model_config,
-            embedding_model: embedding_model_config,
+            chat_model: chat_model_configs.first().cloned(),
+            embedding_model: embedding_model_configs.first().cloned(),
+            chat_models: chat_model_configs,
+            embedding_models: embedding_model_configs,
             tts_model: None,
+            image_model: image_model_config,
+            audio_model: audio_model_config,
             extras: HashMap::new(),
         };
         SERVER_INFO
-            .set(server_info)
+            .set(RwLock::new(server_info))
             .map_err(|_| ServerError::Operation("Failed to set `SERVER_INFO`.".to_string()))?;
 
         let new_service = make_service_fn(move |conn: &AddrStream| {
@@ -973,8 +961,13 @@ async fn handle_request(
     let root_path = path_iter.next().unwrap_or_default();
     let root_path = "/".to_owned() + root_path.to_str().unwrap_or_default();
 
-    // check if the API key is valid
-    if let Some(auth_header) = req.headers().get("authorization") {
+    // check if the API key is valid. The admin endpoints check the admin API key instead.
+    let is_admin_path = path_str.starts_with("/v1/admin/");
+    if let Some(auth_header) = req
+        .headers()
+        .get("authorization")
+        .filter(|_| !is_admin_path)
+    {
         if !auth_header.is_empty() {
             let auth_header = match auth_header.to_str() {
                 Ok(auth_header) => auth_header,
@@ -1091,12 +1084,52 @@ pub(crate) struct ApiServer {
     chat_model: Option<ModelConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     embedding_model: Option<ModelConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    chat_models: Vec<ModelConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    embedding_models: Vec<ModelConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tts_model: Option<ModelConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_model: Option<ImageModelConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio_model: Option<AudioModelConfig>,
     extras: HashMap<String, String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+impl ApiServer {
+    /// Adds the config of a chat or embedding model loaded at runtime, or replaces the config of the model with the same name.
+    pub(crate) fn set_model(&mut self, config: ModelConfig) {
+        let models = match config.ty.as_str() {
+            "embedding" => &mut self.embedding_models,
+            _ => &mut self.chat_models,
+        };
+        match models.iter_mut().find(|model| model.name == config.name) {
+            Some(model) => *model = config,
+            None => models.push(config),
+        }
+
+        self.sync_default_models();
+    }
+
+    /// Removes the config of the chat or embedding model with the given name.
+    pub(crate) fn remove_model(&mut self, ty: &str, name: &str) {
+        let models = match ty {
+            "embedding" => &mut self.embedding_models,
+            _ => &mut self.chat_models,
+        };
+        models.retain(|model| model.name != name);
+
+        self.sync_default_models();
+    }
+
+    fn sync_default_models(&mut self) {
+        self.chat_model = self.chat_models.first().cloned();
+        self.embedding_model = self.embedding_models.first().cloned();
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct ModelConfig {
     // model name
     name: String,
@@ -1108,6 +1141,9 @@ pub(crate) struct ModelConfig {
     pub ubatch_size: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prompt_template: Option<PromptTemplateType>,
+    // the prompt template detected from the model file when the model is loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected_prompt_template: Option<PromptTemplateType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub n_predict: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -1132,4 +1168,503 @@ pub(crate) struct ModelConfig {
     pub main_gpu: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tensor_split: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<TruncationStrategy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_prompt: Option<bool>,
+}
+
+impl ModelConfig {
+    /// Creates the config of a chat or embedding model shown in the server info.
+    pub(crate) fn new(metadata: &GgmlMetadata, ty: impl Into<String>) -> Self {
+        let ty = ty.into();
+
+        Self {
+            name: metadata.model_name.clone(),
+            truncation: (ty == "chat").then_some(metadata.truncation),
+            cache_prompt: (ty == "chat").then_some(metadata.cache_prompt),
+            ty,
+            ctx_size: metadata.ctx_size,
+            batch_size: metadata.batch_size,
+            ubatch_size: metadata.ubatch_size,
+            prompt_template: Some(metadata.prompt_template),
+            detected_prompt_template: metadata.detected_prompt_template,
+            n_predict: Some(metadata.n_predict),
+            reverse_prompt: metadata.reverse_prompt.clone(),
+            n_gpu_layers: Some(metadata.n_gpu_layers),
+            use_mmap: metadata.use_mmap,
+            temperature: Some(metadata.temperature),
+            top_p: Some(metadata.top_p),
+            repeat_penalty: Some(metadata.repeat_penalty),
+            presence_penalty: Some(metadata.presence_penalty),
+            frequency_penalty: Some(metadata.frequency_penalty),
+            split_mode: Some(metadata.split_mode.clone()),
+            main_gpu: metadata.main_gpu,
+            tensor_split: metadata.tensor_split.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct ImageModelConfig {
+    // model name
+    name: String,
+    // type: image
+    #[serde(rename = "type")]
+    ty: String,
+    // task: full, text2image or image2image
+    task: String,
+}
+
+/// Initializes the contexts of the chat models, or registers them in the model pool if `pool_config` is set, and returns the configs of the models shown in the server info.
+fn init_chat_models(
+    chat_configs: &[ChatConfig],
+    pool_config: Option<&PoolConfig>,
+    plugin_debug: bool,
+) -> Result<Vec<ModelConfig>, ServerError> {
+    if chat_configs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut metadata_chats = vec![];
+    let mut chat_model_configs = vec![];
+    for chat_config in chat_configs {
+        info!(target: "stdout", "chat model name: {}", chat_config.model_name);
+
+        info!(target: "stdout", "chat model alias: {}", chat_config.model_alias);
+
+        info!(target: "stdout", "chat context size: {}", chat_config.ctx_size);
+
+        info!(target: "stdout", "chat batch size: {}", chat_config.batch_size);
+
+        info!(target: "stdout", "chat ubatch size: {}", chat_config.ubatch_size);
+
+        info!(target: "stdout", "chat prompt template: {}", chat_config.prompt_template);
+
+        info!(target: "stdout", "chat template: {:?}", chat_config.chat_template);
+
+        info!(target: "stdout", "chat model file: {:?}", chat_config.model_file);
+
+        info!(target: "stdout", "chat split mode: {}", chat_config.split_mode);
+
+        info!(target: "stdout", "chat main gpu: {:?}", chat_config.main_gpu);
+
+        info!(target: "stdout", "chat tensor split: {:?}", chat_config.tensor_split);
+
+        info!(target: "stdout", "chat threads: {}", chat_config.threads);
+
+        info!(target: "stdout", "chat no_mmap: {:?}", chat_config.no_mmap);
+
+        info!(target: "stdout", "chat temp: {}", chat_config.temp);
+
+        info!(target: "stdout", "chat top_p: {}", chat_config.top_p);
+
+        info!(target: "stdout", "chat repeat_penalty: {}", chat_config.repeat_penalty);
+
+        info!(target: "stdout", "chat presence_penalty: {}", chat_config.presence_penalty);
+
+        info!(target: "stdout", "chat frequency_penalty: {}", chat_config.frequency_penalty);
+
+        info!(target: "stdout", "chat grammar: {:?}", chat_config.grammar);
+
+        info!(target: "stdout", "chat json_schema: {:?}", chat_config.json_schema);
+
+        info!(target: "stdout", "chat llava_mmproj: {:?}", chat_config.llava_mmproj);
+
+        info!(target: "stdout", "chat include_usage: {}", chat_config.include_usage);
+
+        info!(target: "stdout", "chat max_queued_requests: {:?}", chat_config.max_queued_requests);
+
+        info!(target: "stdout", "chat parallel_slots: {:?}", chat_config.parallel_slots);
+
+        info!(target: "stdout", "chat truncation: {}", chat_config.truncation);
+
+        info!(target: "stdout", "chat cache_prompt: {}", chat_config.cache_prompt);
+
+        // the custom prompt template must be registered before the model is loaded
+        if let PromptTemplateType::Custom(name) = chat_config.prompt_template {
+            if chat_prompts::chat::custom_template(name).is_none() {
+                let err_msg = format!(
+                    "The custom prompt template `{name}` of the chat model `{}` is not declared. Declare it as a `[templates.{name}]` table of the file given by `--custom-templates`, or of the config file.",
+                    chat_config.model_name
+                );
+
+                error!(target: "stdout", "{err_msg}");
+
+                return Err(ServerError::ArgumentError(err_msg));
+            }
+        }
+
+        // load the chat template rendered by the `jinja` prompt template
+        let chat_template = match (chat_config.prompt_template, &chat_config.chat_template) {
+            (_, Some(path)) => Some(
+                llama_core::utils::load_chat_template(path)
+                    .map_err(|e| ServerError::Operation(e.to_string()))?,
+            ),
+            (PromptTemplateType::Jinja, None) => {
+                let err_msg = format!(
+                    "The `jinja` prompt template of the chat model `{}` requires a chat template. Set `--chat-template` to the GGUF file of the model, or to a `tokenizer_config.json` or Jinja file.",
+                    chat_config.model_name
+                );
+
+                error!(target: "stdout", "{err_msg}");
+
+                return Err(ServerError::ArgumentError(err_msg));
+            }
+            (_, None) => None,
+        };
+
+        // the `auto` prompt template is detected from the model file when the model is loaded
+        if chat_config.prompt_template == PromptTemplateType::Auto
+            && chat_config.model_file.is_none()
+        {
+            let err_msg = format!(
+                "The `auto` prompt template of the chat model `{}` requires `--model-file`. The server cannot read the file preloaded by `--nn-preload`, so set `--model-file` to the same GGUF file.",
+                chat_config.model_name
+            );
+
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(ServerError::ArgumentError(err_msg));
+        }
+
+        // create a Metadata instance
+        let metadata_chat = GgmlMetadataBuilder::new(
+            chat_config.model_name.clone(),
+            chat_config.model_alias.clone(),
+            chat_config.prompt_template,
+        )
+        .with_ctx_size(chat_config.ctx_size)
+        .with_batch_size(chat_config.batch_size)
+        .with_ubatch_size(chat_config.ubatch_size)
+        .with_n_predict(chat_config.n_predict)
+        .with_n_gpu_layers(chat_config.n_gpu_layers)
+        .with_split_mode(chat_config.split_mode.clone())
+        .with_main_gpu(chat_config.main_gpu)
+        .with_tensor_split(chat_config.tensor_split.clone())
+        .with_threads(chat_config.threads)
+        .disable_mmap(chat_config.no_mmap)
+        .with_temperature(chat_config.temp)
+        .with_top_p(chat_config.top_p)
+        .with_repeat_penalty(chat_config.repeat_penalty)
+        .with_presence_penalty(chat_config.presence_penalty)
+        .with_frequency_penalty(chat_config.frequency_penalty)
+        .with_grammar(chat_config.grammar.clone().unwrap_or_default())
+        .with_json_schema(chat_config.json_schema.clone())
+        .with_reverse_prompt(chat_config.reverse_prompt.clone())
+        .with_chat_template(chat_template)
+        .with_model_file(chat_config.model_file.clone())
+        .with_mmproj(
+            chat_config
+                .llava_mmproj
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string()),
+        )
+        .enable_plugin_log(true)
+        .enable_debug_log(plugin_debug)
+        .include_usage(chat_config.include_usage)
+        .with_truncation_strategy(chat_config.truncation)
+        .enable_prompt_cache(chat_config.cache_prompt)
+        .build();
+
+        // set the chat model config
+        chat_model_configs.push(ModelConfig::new(&metadata_chat, "chat"));
+
+        // limit the number of requests waiting for the chat model
+        llama_core::scheduler::set_max_queued_requests(
+            metadata_chat.model_name.clone(),
+            chat_config.max_queued_requests,
+        )
+        .map_err(|e| ServerError::Operation(format!("{e}")))?;
+
+        // the number of requests generated by the chat model at the same time
+        if let Some(parallel_slots) = chat_config.parallel_slots {
+            llama_core::scheduler::set_parallel_slots(
+                metadata_chat.model_name.clone(),
+                parallel_slots,
+            )
+            .map_err(|e| ServerError::Operation(format!("{e}")))?;
+        }
+
+        metadata_chats.push(metadata_chat);
+    }
+
+    match pool_config {
+        Some(pool_config) => {
+            init_model_pool(pool_config)?;
+
+            // the chat models are loaded on their first requests
+            for (metadata_chat, chat_config) in metadata_chats.into_iter().zip(chat_configs) {
+                let memory = model_memory(chat_config, pool_config.memory_budget.is_some())?;
+
+                llama_core::pool::register_chat_model(metadata_chat, memory)
+                    .map_err(|e| ServerError::Operation(format!("{e}")))?;
+            }
+        }
+        None => {
+            // initialize the chat context
+            llama_core::init_ggml_chat_context(&metadata_chats)
+                .map_err(|e| ServerError::Operation(format!("{e}")))?;
+        }
+    }
+
+    Ok(chat_model_configs)
+}
+
+/// Returns the estimated memory of the chat model in bytes: `--model-size` if it is set, otherwise the size of the model file. Fails if the memory budget is set and the size of the model is unknown, since the model would not count against the budget.
+fn model_memory(chat_config: &ChatConfig, memory_budget: bool) -> Result<u64, ServerError> {
+    if let Some(model_size) = chat_config.model_size {
+        return Ok(model_size * 1024 * 1024);
+    }
+
+    let file_size = chat_config
+        .model_file
+        .as_ref()
+        .and_then(|model_file| std::fs::metadata(model_file).ok())
+        .map(|metadata| metadata.len());
+
+    match file_size {
+        Some(file_size) => {
+            info!(target: "stdout", "chat model size of {}: {} MiB from the model file", chat_config.model_name, file_size / 1024 / 1024);
+
+            Ok(file_size)
+        }
+        None if memory_budget => {
+            let err_msg = format!(
+                "The size of the chat model `{}` is unknown, so it cannot be counted against `--memory-budget`. Set its `--model-size`, or its `--model-file` to the GGUF file of the model.",
+                chat_config.model_name
+            );
+
+            error!(target: "stdout", "{err_msg}");
+
+            Err(ServerError::ArgumentError(err_msg))
+        }
+        None => Ok(0),
+    }
+}
+
+/// Sets the limits of the model pool, logs its events, and starts unloading the idle models periodically if the idle timeout is set.
+fn init_model_pool(pool_config: &PoolConfig) -> Result<(), ServerError> {
+    let idle_timeout = pool_config.idle_timeout.map(Duration::from_secs);
+
+    llama_core::pool::set_pool_config(llama_core::pool::PoolConfig {
+        max_resident: pool_config.max_resident_models,
+        memory_budget: pool_config
+            .memory_budget
+            .map(|memory_budget| memory_budget * 1024 * 1024),
+        idle_timeout,
+    })
+    .map_err(|e| ServerError::Operation(format!("{e}")))?;
+
+    llama_core::pool::set_event_handler(|event| match event {
+        PoolEvent::Loaded {
+            model_name,
+            elapsed,
+        } => {
+            info!(target: "stdout", "model pool: loaded {model_name} in {elapsed:?}");
+        }
+        PoolEvent::Evicted {
+            model_name,
+            reason,
+            idle,
+        } => {
+            info!(target: "stdout", "model pool: evicted {model_name} after idle for {idle:?}, reason: {reason:?}");
+        }
+    })
+    .map_err(|e| ServerError::Operation(format!("{e}")))?;
+
+    if let Some(idle_timeout) = idle_timeout {
+        let period = (idle_timeout / 2).max(Duration::from_secs(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = llama_core::pool::evict_idle_models() {
+                    error!(target: "stdout", "Failed to evict the idle models. {e}");
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Initializes the contexts of the embedding models, and returns the configs of the models shown in the server info.
+fn init_embedding_models(
+    embedding_configs: &[EmbeddingConfig],
+    plugin_debug: bool,
+) -> Result<Vec<ModelConfig>, ServerError> {
+    if embedding_configs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut metadata_embeddings = vec![];
+    let mut embedding_model_configs = vec![];
+    for embedding_config in embedding_configs {
+        info!(target: "stdout", "embedding model name: {}", embedding_config.model_name);
+
+        info!(target: "stdout", "embedding model alias: {}", embedding_config.model_alias);
+
+        info!(target: "stdout", "embedding context size: {}", embedding_config.ctx_size);
+
+        info!(target: "stdout", "embedding batch size: {}", embedding_config.batch_size);
+
+        info!(target: "stdout", "embedding ubatch size: {}", embedding_config.ubatch_size);
+
+        info!(target: "stdout", "embedding split mode: {}", embedding_config.split_mode);
+
+        info!(target: "stdout", "embedding main gpu: {:?}", embedding_config.main_gpu);
+
+        info!(target: "stdout", "embedding tensor split: {:?}", embedding_config.tensor_split);
+
+        info!(target: "stdout", "embedding threads: {}", embedding_config.threads);
+
+        // create a Metadata instance
+        let metadata_embedding = GgmlMetadataBuilder::new(
+            embedding_config.model_name.clone(),
+            embedding_config.model_alias.clone(),
+            PromptTemplateType::Embedding,
+        )
+        .with_ctx_size(embedding_config.ctx_size)
+        .with_batch_size(embedding_config.batch_size)
+        .with_ubatch_size(embedding_config.ubatch_size)
+        .with_split_mode(embedding_config.split_mode.clone())
+        .with_main_gpu(embedding_config.main_gpu)
+        .with_tensor_split(embedding_config.tensor_split.clone())
+        .with_threads(embedding_config.threads)
+        .enable_plugin_log(true)
+        .enable_debug_log(plugin_debug)
+        .build();
+
+        // set the embedding model config
+        embedding_model_configs.push(ModelConfig::new(&metadata_embedding, "embedding"));
+
+        metadata_embeddings.push(metadata_embedding);
+    }
+
+    // initialize the embeddings context
+    llama_core::init_ggml_embeddings_context(&metadata_embeddings)
+        .map_err(|e| ServerError::Operation(format!("{e}")))?;
+
+    Ok(embedding_model_configs)
+}
+
+/// Initializes the stable diffusion contexts for the image endpoints, and returns the config of the image model shown in the server info.
+fn init_image_model(image_config: &ImageConfig) -> Result<ImageModelConfig, ServerError> {
+    info!(target: "stdout", "image model name: {}", image_config.model_name);
+
+    info!(target: "stdout", "image task: {}", image_config.task);
+
+    info!(target: "stdout", "image threads: {}", image_config.threads);
+
+    let path_str = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let lora_model_dir = path_str(&image_config.lora_model_dir);
+    let control_net = path_str(&image_config.control_net);
+
+    match (&image_config.model, &image_config.diffusion_model) {
+        (Some(model), _) => {
+            info!(target: "stdout", "image model: {}", model.to_string_lossy());
+
+            llama_core::init_sd_context_with_full_model(
+                model.to_string_lossy(),
+                Some(lora_model_dir.as_str()),
+                Some(control_net.as_str()),
+                image_config.control_net_on_cpu,
+                image_config.clip_on_cpu,
+                image_config.vae_on_cpu,
+                image_config.threads,
+                image_config.task,
+            )
+            .map_err(|e| ServerError::Operation(format!("{e}")))?;
+        }
+        (None, Some(diffusion_model)) => {
+            info!(target: "stdout", "image diffusion model: {}", diffusion_model.to_string_lossy());
+
+            llama_core::init_sd_context_with_standalone_model(
+                diffusion_model.to_string_lossy(),
+                path_str(&image_config.vae),
+                path_str(&image_config.clip_l),
+                path_str(&image_config.t5xxl),
+                Some(lora_model_dir.as_str()),
+                Some(control_net.as_str()),
+                image_config.control_net_on_cpu,
+                image_config.clip_on_cpu,
+                image_config.vae_on_cpu,
+                image_config.threads,
+                image_config.task,
+            )
+            .map_err(|e| ServerError::Operation(format!("{e}")))?;
+        }
+        (None, None) => {
+            let err_msg = "Either the full model or the standalone diffusion model is required for the image endpoints.";
+
+            error!(target: "stdout", "{err_msg}");
+
+            return Err(ServerError::Operation(err_msg.to_string()));
+        }
+    }
+
+    Ok(ImageModelConfig {
+        name: image_config.model_name.clone(),
+        ty: "image".to_string(),
+        task: image_config.task.to_string(),
+    })
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct AudioModelConfig {
+    // model name
+    name: String,
+    // type: whisper
+    #[serde(rename = "type")]
+    ty: String,
+    // the default language of the input audio
+    language: String,
+}
+
+/// Initializes the whisper context for the audio transcription and translation endpoints, and returns the config of the audio model shown in the server info.
+#[cfg(feature = "whisper")]
+fn init_whisper_model(whisper_config: &WhisperConfig) -> Result<AudioModelConfig, ServerError> {
+    info!(target: "stdout", "whisper model name: {}", whisper_config.model_name);
+
+    info!(target: "stdout", "whisper model: {}", whisper_config.model.to_string_lossy());
+
+    info!(target: "stdout", "whisper threads: {}", whisper_config.threads);
+
+    info!(target: "stdout", "whisper language: {}", whisper_config.language);
+
+    let metadata = WhisperMetadataBuilder::new(
+        whisper_config.model_name.clone(),
+        whisper_config.model_alias.clone(),
+    )
+    .with_model_path(&whisper_config.model)
+    .with_threads(whisper_config.threads)
+    .with_language(whisper_config.language.clone())
+    .detect_language(whisper_config.detect_language)
+    .with_temperature(whisper_config.temperature)
+    .with_prompt(whisper_config.prompt.clone().unwrap_or_default())
+    .build();
+
+    llama_core::init_whisper_context(&metadata)
+        .map_err(|e| ServerError::Operation(format!("{e}")))?;
+
+    Ok(AudioModelConfig {
+        name: whisper_config.model_name.clone(),
+        ty: "whisper".to_string(),
+        language: whisper_config.language.clone(),
+    })
+}
+
+#[cfg(not(feature = "whisper"))]
+fn init_whisper_model(_whisper_config: &WhisperConfig) -> Result<AudioModelConfig, ServerError> {
+    let err_msg = "The whisper model requires the server to be built with the `whisper` feature.";
+
+    error!(target: "stdout", "{err_msg}");
+
+    Err(ServerError::Operation(err_msg.to_string()))
 }
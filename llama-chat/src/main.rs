@@ -1,5 +1,5 @@
 use anyhow::bail;
-use chat_prompts::PromptTemplateType;
+use chat_prompts::{chat::CustomTemplate, PromptTemplateType, PromptTemplateTypeParser};
 use clap::Parser;
 use either::{Left, Right};
 use endpoints::chat::{
@@ -9,7 +9,11 @@ use endpoints::chat::{
 use futures::TryStreamExt;
 use llama_core::metadata::ggml::GgmlMetadataBuilder;
 use serde::{Deserialize, Serialize};
-use std::io::{self, Write};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::PathBuf,
+};
 
 #[derive(Debug, Parser)]
 #[command(author, about, version, long_about=None)]
@@ -65,9 +69,18 @@ struct Cli {
     /// JSON schema to constrain generations (<https://json-schema.org/>), e.g. `{}` for any JSON object. For schemas w/ external $refs, use --grammar + example/json_schema_to_grammar.py instead.
     #[arg(long)]
     pub json_schema: Option<String>,
-    /// Sets the prompt template.
-    #[arg(short, long, value_parser = clap::value_parser!(PromptTemplateType), required = true)]
+    /// Sets the prompt template. A template declared in the file of `--custom-templates` is used with 'custom:<name>'. The `auto` template is detected from the model file set by `--model-file`, which is required because llama-chat cannot read the file preloaded by `--nn-preload`.
+    #[arg(short, long, value_parser = PromptTemplateTypeParser, required = true)]
     prompt_template: PromptTemplateType,
+    /// Path to the chat template rendered by the `jinja` prompt template: a GGUF model file with the `tokenizer.chat_template` metadata, a `tokenizer_config.json` file of Hugging Face, or a file with the source of the template.
+    #[arg(long)]
+    chat_template: Option<String>,
+    /// Path to the GGUF file of the model. The prompt template is detected from its metadata, which replaces the `auto` prompt template, and a warning is logged if the prompt template looks inconsistent with the model.
+    #[arg(long)]
+    model_file: Option<String>,
+    /// Path to a TOML file declaring custom prompt templates as `[templates.<name>]` tables.
+    #[arg(long)]
+    custom_templates: Option<String>,
     /// Halt generation at PROMPT, return control.
     #[arg(short, long)]
     reverse_prompt: Option<String>,
@@ -112,6 +125,14 @@ async fn main() -> anyhow::Result<()> {
     log(format!("[INFO] Model name: {}", &cli.model_name));
     log(format!("[INFO] Model alias: {}", &cli.model_alias));
     log(format!("[INFO] Prompt template: {}", &cli.prompt_template));
+    // chat template
+    if let Some(chat_template) = &cli.chat_template {
+        log(format!("[INFO] Chat template: {chat_template}"));
+    }
+    // custom templates
+    if let Some(custom_templates) = &cli.custom_templates {
+        log(format!("[INFO] Custom templates: {custom_templates}"));
+    }
     // ctx size
     log(format!("[INFO] Context size: {}", &cli.ctx_size));
     // reverse prompt
@@ -188,6 +209,38 @@ async fn main() -> anyhow::Result<()> {
     // log statistics
     log(format!("[INFO] Enable plugin log: {}", &cli.log_stat));
 
+    // register the custom prompt templates
+    if let Some(path) = &cli.custom_templates {
+        #[derive(Deserialize)]
+        struct CustomTemplates {
+            #[serde(default)]
+            templates: BTreeMap<String, CustomTemplate>,
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        let custom_templates: CustomTemplates = toml::from_str(&content)?;
+        for (name, template) in custom_templates.templates {
+            chat_prompts::chat::register_custom_template(name, template)?;
+        }
+    }
+    if let PromptTemplateType::Custom(name) = cli.prompt_template {
+        if chat_prompts::chat::custom_template(name).is_none() {
+            bail!("The custom prompt template `{name}` is not declared in `--custom-templates`.")
+        }
+    }
+
+    // load the chat template rendered by the `jinja` prompt template
+    let chat_template = match &cli.chat_template {
+        Some(path) => Some(llama_core::utils::load_chat_template(path)?),
+        None if cli.prompt_template == PromptTemplateType::Jinja => {
+            anyhow::bail!("The `jinja` prompt template requires `--chat-template`.")
+        }
+        None => None,
+    };
+    if cli.prompt_template == PromptTemplateType::Auto && cli.model_file.is_none() {
+        bail!("The `auto` prompt template requires `--model-file`. The file preloaded by `--nn-preload` cannot be read by llama-chat, so set `--model-file` to the same GGUF file.")
+    }
+
     // create a MetadataBuilder instance
     let builder = GgmlMetadataBuilder::new(&cli.model_name, &cli.model_alias, cli.prompt_template)
         .with_ctx_size(cli.ctx_size)
@@ -204,6 +257,8 @@ async fn main() -> anyhow::Result<()> {
         .with_grammar(cli.grammar)
         .with_json_schema(cli.json_schema)
         .with_reverse_prompt(cli.reverse_prompt)
+        .with_chat_template(chat_template)
+        .with_model_file(cli.model_file.map(PathBuf::from))
         .enable_prompts_log(cli.log_prompts || cli.log_all)
         .enable_plugin_log(cli.log_stat || cli.log_all)
         .enable_debug_log(plugin_debug);